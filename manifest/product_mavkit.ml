@@ -592,11 +592,11 @@ let mavkit_risc_v_pvm =
          * copying any build artifacts. *)
         [S "source_tree"; S "../kernel_loader"];
         [S "source_tree"; S "../interpreter"];
+        [S "source_tree"; S "../../kernel_sdk/constants"];
+        [S "source_tree"; S "../../kernel_sdk/core"];
         (* We have to include all the locally mentioned Cargo.toml files
          * within the workspace (including transitively). *)
         [S "file"; S "../sandbox/Cargo.toml"];
-        [S "file"; S "../../kernel_sdk/constants/Cargo.toml"];
-        [S "file"; S "../../kernel_sdk/core/Cargo.toml"];
         [S "file"; S "../../kernel_sdk/host/Cargo.toml"];
         [S "file"; S "../../kernel_sdk/encoding/Cargo.toml"];
         extra_dep;
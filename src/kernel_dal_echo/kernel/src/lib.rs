@@ -119,3 +119,48 @@ pub fn entry(host: &mut impl Runtime) {
 }
 
 kernel_entry!(entry);
+
+#[cfg(test)]
+mod tests {
+    use super::entry;
+    use mavryk_smart_rollup_host::dal_parameters::RollupDalParameters;
+    use mavryk_smart_rollup_host::path::RefPath;
+    use mavryk_smart_rollup_host::runtime::Runtime;
+    use mavryk_smart_rollup_mock::MockHost;
+
+    const OUTPUT_PATH: RefPath = RefPath::assert_from(b"/output/slot-0");
+
+    fn host_with_slot(attested: bool) -> MockHost {
+        let mut host = MockHost::default();
+        host.set_dal_parameters(RollupDalParameters {
+            number_of_slots: 4,
+            attestation_lag: 2,
+            slot_size: 8,
+            page_size: 4,
+        });
+
+        // Published so that it is attested at the level the kernel runs at.
+        let published_level = host.level() - 2;
+        host.publish_dal_slot(published_level, 0, vec![1, 2, 3, 4, 5], attested);
+        host
+    }
+
+    #[test]
+    fn attested_slot_is_echoed() {
+        let mut host = host_with_slot(true);
+
+        host.run_level(entry);
+
+        let output = host.store_read_all(&OUTPUT_PATH).unwrap();
+        assert_eq!(output, vec![1, 2, 3, 4, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn unattested_slot_is_ignored() {
+        let mut host = host_with_slot(false);
+
+        host.run_level(entry);
+
+        assert_eq!(host.store_has(&OUTPUT_PATH).unwrap(), None);
+    }
+}
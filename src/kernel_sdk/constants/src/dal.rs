@@ -0,0 +1,17 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Parameters of the Data Availability Layer of Mavryk mainnet
+
+/// Number of DAL slots per level
+pub const MAINNET_NUMBER_OF_SLOTS: u64 = 32;
+
+/// Number of levels between the publication of a slot and its attestation
+pub const MAINNET_ATTESTATION_LAG: u64 = 8;
+
+/// Size of a DAL slot in bytes
+pub const MAINNET_SLOT_SIZE: u64 = 126_944;
+
+/// Size of a page of a DAL slot in bytes
+pub const MAINNET_PAGE_SIZE: u64 = 3967;
@@ -4,4 +4,5 @@
 
 #![no_std]
 
+pub mod dal;
 pub mod riscv;
@@ -29,3 +29,42 @@ pub const SBI_MAVRYK_ED25519_SIGN: u64 = 0x06;
 
 /// Function ID for `sbi_mavryk_blake2b_hash256`
 pub const SBI_MAVRYK_BLAKE2B_HASH256: u64 = 0x07;
+
+/// Function ID for `sbi_mavryk_store_has`
+pub const SBI_MAVRYK_STORE_HAS: u64 = 0x08;
+
+/// Function ID for `sbi_mavryk_store_read`
+pub const SBI_MAVRYK_STORE_READ: u64 = 0x09;
+
+/// Function ID for `sbi_mavryk_store_write`
+pub const SBI_MAVRYK_STORE_WRITE: u64 = 0x0A;
+
+/// Function ID for `sbi_mavryk_store_delete`
+pub const SBI_MAVRYK_STORE_DELETE: u64 = 0x0B;
+
+/// Function ID for `sbi_mavryk_store_delete_value`
+pub const SBI_MAVRYK_STORE_DELETE_VALUE: u64 = 0x0C;
+
+/// Function ID for `sbi_mavryk_store_list_size`
+pub const SBI_MAVRYK_STORE_LIST_SIZE: u64 = 0x0D;
+
+/// Function ID for `sbi_mavryk_store_move`
+pub const SBI_MAVRYK_STORE_MOVE: u64 = 0x0E;
+
+/// Function ID for `sbi_mavryk_store_copy`
+pub const SBI_MAVRYK_STORE_COPY: u64 = 0x0F;
+
+/// Function ID for `sbi_mavryk_store_value_size`
+pub const SBI_MAVRYK_STORE_VALUE_SIZE: u64 = 0x10;
+
+/// Function ID for `sbi_mavryk_reveal_preimage`
+pub const SBI_MAVRYK_REVEAL_PREIMAGE: u64 = 0x11;
+
+/// Function ID for `sbi_mavryk_reveal_dal_page`
+pub const SBI_MAVRYK_REVEAL_DAL_PAGE: u64 = 0x12;
+
+/// Function ID for `sbi_mavryk_reveal_dal_parameters`
+pub const SBI_MAVRYK_REVEAL_DAL_PARAMETERS: u64 = 0x13;
+
+/// Function ID for `sbi_mavryk_store_get_nth_key`
+pub const SBI_MAVRYK_STORE_GET_NTH_KEY: u64 = 0x14;
@@ -65,6 +65,9 @@ pub const STORE_NOT_A_NODE: i32 = -10;
 /// The outbox is full an cannot accept new messages at this level.
 pub const FULL_OUTBOX: i32 = -11;
 
+/// The index of a subkey is greater than or equal to the number of subkeys.
+pub const STORE_INVALID_SUBKEY_INDEX: i32 = -12;
+
 /// None ValueType discriminant.
 pub const VALUE_TYPE_NONE: i32 = 0;
 
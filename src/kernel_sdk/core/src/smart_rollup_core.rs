@@ -75,6 +75,25 @@ extern "C" {
     /// Get the number of subkeys of the prefix given by `path`.
     pub fn store_list_size(path: *const u8, path_len: usize) -> i64;
 
+    /// Write the name of the `index`-th subkey of the prefix given by `path`
+    /// to memory, truncated to `max_size` bytes.
+    ///
+    /// Returns the number of bytes written. The value at `path`, if any, is
+    /// listed among the subkeys with an empty name. The order of the subkeys
+    /// is left to the host.
+    ///
+    /// Returns [`STORE_INVALID_SUBKEY_INDEX`] if `index` is not smaller than
+    /// the number of subkeys.
+    ///
+    /// [`STORE_INVALID_SUBKEY_INDEX`]: crate::STORE_INVALID_SUBKEY_INDEX
+    pub fn store_get_nth_key(
+        path: *const u8,
+        path_len: usize,
+        index: i64,
+        dst: *mut u8,
+        max_size: usize,
+    ) -> i32;
+
     /// Moves the value and/or subkeys of `from_path` to `to_path`.
     ///
     /// Overwrites the destination, if it already exists.
@@ -257,6 +276,27 @@ pub unsafe trait SmartRollupCore {
     /// - `path_len` must be the length of that slice.
     unsafe fn store_list_size(&self, path: *const u8, path_len: usize) -> i64;
 
+    /// See [store_get_nth_key].
+    ///
+    /// Hosts which don't implement it fail with [`GENERIC_INVALID_ACCESS`].
+    ///
+    /// # Safety
+    /// - `path` must be a ptr to a correctly path-encoded slice of bytes.
+    /// - `path_len` must be the length of that slice.
+    /// - `dst` must point to a mutable slice of bytes with `capacity >= max_size`.
+    ///
+    /// [`GENERIC_INVALID_ACCESS`]: crate::GENERIC_INVALID_ACCESS
+    unsafe fn store_get_nth_key(
+        &self,
+        _path: *const u8,
+        _path_len: usize,
+        _index: i64,
+        _dst: *mut u8,
+        _max_size: usize,
+    ) -> i32 {
+        crate::GENERIC_INVALID_ACCESS
+    }
+
     /// See [store_move] above.
     ///
     /// # Safety
@@ -347,9 +387,15 @@ pub struct ReadInputMessageInfo {
 mod riscv64_hermit {
     extern crate std;
     use crate::smart_rollup_core::ReadInputMessageInfo;
+    use crate::{GENERIC_INVALID_ACCESS, PREIMAGE_HASH_SIZE};
     use mavryk_smart_rollup_constants::riscv::{
         SBI_FIRMWARE_MAVRYK, SBI_MAVRYK_INBOX_NEXT, SBI_MAVRYK_META_ADDRESS,
-        SBI_MAVRYK_META_ORIGINATION_LEVEL,
+        SBI_MAVRYK_META_ORIGINATION_LEVEL, SBI_MAVRYK_REVEAL_DAL_PAGE,
+        SBI_MAVRYK_REVEAL_DAL_PARAMETERS, SBI_MAVRYK_REVEAL_PREIMAGE,
+        SBI_MAVRYK_STORE_COPY, SBI_MAVRYK_STORE_DELETE, SBI_MAVRYK_STORE_DELETE_VALUE,
+        SBI_MAVRYK_STORE_GET_NTH_KEY, SBI_MAVRYK_STORE_HAS, SBI_MAVRYK_STORE_LIST_SIZE,
+        SBI_MAVRYK_STORE_MOVE, SBI_MAVRYK_STORE_READ, SBI_MAVRYK_STORE_VALUE_SIZE,
+        SBI_MAVRYK_STORE_WRITE,
     };
     use std::{
         io::{self, Write},
@@ -422,6 +468,27 @@ mod riscv64_hermit {
         result
     }
 
+    /// Perform a durable storage or reveal call. The arguments are passed in
+    /// order in registers `a0` to `a4`, the result is returned in `a0`.
+    #[inline(always)]
+    unsafe fn sbi_mavryk_call(function: u64, args: [usize; 5]) -> i64 {
+        let result: i64;
+
+        core::arch::asm!(
+            "ecall",
+            in("a0") args[0],
+            in("a1") args[1],
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a7") SBI_FIRMWARE_MAVRYK,
+            in("a6") function,
+            lateout("a0") result
+        );
+
+        result
+    }
+
     pub unsafe fn read_input(
         message_info: *mut ReadInputMessageInfo,
         dst: *mut u8,
@@ -451,81 +518,176 @@ mod riscv64_hermit {
             .expect("Writing to stdout failed");
     }
 
-    pub unsafe fn store_has(_path: *const u8, _path_len: usize) -> i32 {
-        unimplemented!()
+    pub unsafe fn store_has(path: *const u8, path_len: usize) -> i32 {
+        sbi_mavryk_call(SBI_MAVRYK_STORE_HAS, [path as usize, path_len, 0, 0, 0]) as i32
     }
 
     pub unsafe fn store_read(
-        _path: *const u8,
-        _path_len: usize,
-        _offset: usize,
-        _dst: *mut u8,
-        _max_bytes: usize,
+        path: *const u8,
+        path_len: usize,
+        offset: usize,
+        dst: *mut u8,
+        max_bytes: usize,
     ) -> i32 {
-        unimplemented!()
+        sbi_mavryk_call(
+            SBI_MAVRYK_STORE_READ,
+            [path as usize, path_len, offset, dst as usize, max_bytes],
+        ) as i32
     }
 
     pub unsafe fn store_write(
-        _path: *const u8,
-        _path_len: usize,
-        _offset: usize,
-        _src: *const u8,
-        _num_bytes: usize,
+        path: *const u8,
+        path_len: usize,
+        offset: usize,
+        src: *const u8,
+        num_bytes: usize,
     ) -> i32 {
-        unimplemented!()
+        sbi_mavryk_call(
+            SBI_MAVRYK_STORE_WRITE,
+            [path as usize, path_len, offset, src as usize, num_bytes],
+        ) as i32
     }
 
-    pub unsafe fn store_delete(_path: *const u8, _len: usize) -> i32 {
-        unimplemented!()
+    pub unsafe fn store_delete(path: *const u8, len: usize) -> i32 {
+        sbi_mavryk_call(SBI_MAVRYK_STORE_DELETE, [path as usize, len, 0, 0, 0]) as i32
     }
 
-    pub unsafe fn store_delete_value(_path: *const u8, _len: usize) -> i32 {
-        unimplemented!()
+    pub unsafe fn store_delete_value(path: *const u8, len: usize) -> i32 {
+        sbi_mavryk_call(SBI_MAVRYK_STORE_DELETE_VALUE, [path as usize, len, 0, 0, 0])
+            as i32
     }
 
-    pub unsafe fn store_list_size(_path: *const u8, _path_len: usize) -> i64 {
-        unimplemented!()
+    pub unsafe fn store_list_size(path: *const u8, path_len: usize) -> i64 {
+        sbi_mavryk_call(
+            SBI_MAVRYK_STORE_LIST_SIZE,
+            [path as usize, path_len, 0, 0, 0],
+        )
+    }
+
+    pub unsafe fn store_get_nth_key(
+        path: *const u8,
+        path_len: usize,
+        index: i64,
+        dst: *mut u8,
+        max_size: usize,
+    ) -> i32 {
+        sbi_mavryk_call(
+            SBI_MAVRYK_STORE_GET_NTH_KEY,
+            [
+                path as usize,
+                path_len,
+                index as usize,
+                dst as usize,
+                max_size,
+            ],
+        ) as i32
     }
 
     pub unsafe fn store_move(
-        _from_path: *const u8,
-        _from_path_len: usize,
-        _to_path: *const u8,
-        _to_path_len: usize,
+        from_path: *const u8,
+        from_path_len: usize,
+        to_path: *const u8,
+        to_path_len: usize,
     ) -> i32 {
-        unimplemented!()
+        sbi_mavryk_call(
+            SBI_MAVRYK_STORE_MOVE,
+            [
+                from_path as usize,
+                from_path_len,
+                to_path as usize,
+                to_path_len,
+                0,
+            ],
+        ) as i32
     }
 
     pub unsafe fn store_copy(
-        _from_path: *const u8,
-        _from_path_len: usize,
-        _to_path: *const u8,
-        _to_path_len: usize,
+        from_path: *const u8,
+        from_path_len: usize,
+        to_path: *const u8,
+        to_path_len: usize,
     ) -> i32 {
-        unimplemented!()
+        sbi_mavryk_call(
+            SBI_MAVRYK_STORE_COPY,
+            [
+                from_path as usize,
+                from_path_len,
+                to_path as usize,
+                to_path_len,
+                0,
+            ],
+        ) as i32
     }
 
     pub unsafe fn reveal_preimage(
-        _hash_addr: *const u8,
-        _hash_len: usize,
-        _destination_addr: *mut u8,
-        _max_bytes: usize,
+        hash_addr: *const u8,
+        hash_len: usize,
+        destination_addr: *mut u8,
+        max_bytes: usize,
     ) -> i32 {
-        unimplemented!()
+        sbi_mavryk_call(
+            SBI_MAVRYK_REVEAL_PREIMAGE,
+            [
+                hash_addr as usize,
+                hash_len,
+                destination_addr as usize,
+                max_bytes,
+                0,
+            ],
+        ) as i32
     }
 
+    /// Decode a reveal request, following the encoding of the Mavryk
+    /// protocol, and forward it to the matching SBI call.
     #[cfg(feature = "proto-alpha")]
     pub unsafe fn reveal(
-        _payload_addr: *const u8,
-        _payload_len: usize,
-        _destination_addr: *mut u8,
-        _max_bytes: usize,
+        payload_addr: *const u8,
+        payload_len: usize,
+        destination_addr: *mut u8,
+        max_bytes: usize,
     ) -> i32 {
-        unimplemented!()
+        let payload = from_raw_parts(payload_addr, payload_len);
+
+        match payload {
+            // Raw data, revealed by hash
+            [0, hash @ ..] if hash.len() == PREIMAGE_HASH_SIZE => {
+                reveal_preimage(hash.as_ptr(), hash.len(), destination_addr, max_bytes)
+            }
+
+            // Metadata
+            [1] => reveal_metadata(destination_addr, max_bytes),
+
+            // DAL page: published level, slot index and page index
+            [2, l0, l1, l2, l3, slot_index, p0, p1] => {
+                let published_level = i32::from_be_bytes([*l0, *l1, *l2, *l3]);
+                let page_index = i16::from_be_bytes([*p0, *p1]);
+                sbi_mavryk_call(
+                    SBI_MAVRYK_REVEAL_DAL_PAGE,
+                    [
+                        published_level as usize,
+                        *slot_index as usize,
+                        page_index as usize,
+                        destination_addr as usize,
+                        max_bytes,
+                    ],
+                ) as i32
+            }
+
+            // DAL parameters
+            [3] => sbi_mavryk_call(
+                SBI_MAVRYK_REVEAL_DAL_PARAMETERS,
+                [destination_addr as usize, max_bytes, 0, 0, 0],
+            ) as i32,
+
+            _ => GENERIC_INVALID_ACCESS,
+        }
     }
 
-    pub unsafe fn store_value_size(_path: *const u8, _path_len: usize) -> i32 {
-        unimplemented!()
+    pub unsafe fn store_value_size(path: *const u8, path_len: usize) -> i32 {
+        sbi_mavryk_call(
+            SBI_MAVRYK_STORE_VALUE_SIZE,
+            [path as usize, path_len, 0, 0, 0],
+        ) as i32
     }
 
     pub unsafe fn reveal_metadata(buffer: *mut u8, max_bytes: usize) -> i32 {
@@ -233,10 +233,15 @@ mod test {
     use super::ExternalMessageFrame;
     use super::InboxMessage;
     use super::InternalInboxMessage;
+    use super::Transfer;
     use crate::michelson::Michelson;
+    use crate::michelson::MichelsonExpr;
     use crate::michelson::MichelsonUnit;
+    use crate::public_key_hash::PublicKeyHash;
     use crate::smart_rollup::SmartRollupAddress;
+    use crypto::hash::ContractKt1Hash;
     use mavryk_data_encoding::enc::BinWriter;
+    use mavryk_data_encoding::nom::NomReader;
 
     #[test]
     fn test_encode_decode_sol() {
@@ -321,6 +326,36 @@ mod test {
         assert!(input_remaining.is_empty());
     }
 
+    #[test]
+    fn test_encode_transfer_expr() {
+        let transfer = |payload| Transfer {
+            payload,
+            sender: ContractKt1Hash::from_b58check(
+                "KT1EfTusMLoeCAAGd9MZJn5yKzFr6kJU5U91",
+            )
+            .unwrap(),
+            source: PublicKeyHash::from_b58check("mv1Q4RbUk6tX1J43AGg4ScnisAEhLnpHNyux")
+                .unwrap(),
+            destination: SmartRollupAddress::from_b58check(
+                "sr163Lv22CdE8QagCwf48PWDTquk6isQwv57",
+            )
+            .unwrap(),
+        };
+
+        let mut expected = Vec::new();
+        InboxMessage::Internal(InternalInboxMessage::Transfer(transfer(MichelsonUnit)))
+            .serialize(&mut expected)
+            .unwrap();
+
+        // Unit, as generic micheline
+        let (remaining, unit) = MichelsonExpr::nom_read(&[3, 11]).unwrap();
+        assert!(remaining.is_empty());
+        let message =
+            InboxMessage::Internal(InternalInboxMessage::Transfer(transfer(unit)));
+
+        test_encode_decode(expected, message);
+    }
+
     #[test]
     fn test_encode_decode_external_framing_targetted() {
         let contents = "Hello, world! (But only in one rollup)";
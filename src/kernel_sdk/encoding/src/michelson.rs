@@ -95,6 +95,7 @@ impl Michelson for MichelsonInt {}
 impl Michelson for MichelsonNat {}
 impl Michelson for MichelsonString {}
 impl Michelson for MichelsonBytes {}
+impl Michelson for MichelsonExpr {}
 impl<Arg0, Arg1> Michelson for MichelsonPair<Arg0, Arg1>
 where
     Arg0: Michelson,
@@ -156,6 +157,13 @@ pub struct MichelsonBytes(pub Vec<u8>);
 #[derive(Debug, PartialEq, Eq)]
 pub struct MichelsonInt(pub Zarith);
 
+/// Michelson expression of any type, encoded as generic micheline.
+///
+/// Useful when the type of an expression isn't known statically, e.g. for
+/// payloads provided by users.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MichelsonExpr(Node);
+
 /// Michelson Nat encoding.
 #[derive(Debug, PartialEq, Eq)]
 pub struct MichelsonNat(Zarith);
@@ -275,6 +283,12 @@ impl HasEncoding for MichelsonNat {
     }
 }
 
+impl HasEncoding for MichelsonExpr {
+    fn encoding() -> Encoding {
+        Encoding::Custom
+    }
+}
+
 // --------
 // DECODING
 // --------
@@ -664,6 +678,12 @@ impl NomReader for MichelsonInt {
     }
 }
 
+impl NomReader for MichelsonExpr {
+    fn nom_read(input: &[u8]) -> NomResult<Self> {
+        map(Node::nom_read, MichelsonExpr)(input)
+    }
+}
+
 impl NomReader for MichelsonNat {
     fn nom_read(input: &[u8]) -> NomResult<Self> {
         use mavryk_data_encoding::nom::error::*;
@@ -805,6 +825,12 @@ impl BinWriter for MichelsonInt {
         bin_write_micheline_int(&self.0, output)
     }
 }
+
+impl BinWriter for MichelsonExpr {
+    fn bin_write(&self, output: &mut Vec<u8>) -> BinResult {
+        self.0.bin_write(output)
+    }
+}
 impl BinWriter for MichelsonNat {
     fn bin_write(&self, output: &mut Vec<u8>) -> BinResult {
         bin_write_micheline_int(&self.0, output)
@@ -59,6 +59,8 @@ pub enum Error {
     StoreNotANode = mavryk_smart_rollup_core::STORE_NOT_A_NODE,
     /// The outbox is full
     FullOutbox = mavryk_smart_rollup_core::FULL_OUTBOX,
+    /// The index of a subkey is out of bounds.
+    StoreInvalidSubkeyIndex = mavryk_smart_rollup_core::STORE_INVALID_SUBKEY_INDEX,
 }
 
 impl core::fmt::Display for Error {
@@ -75,6 +77,7 @@ impl core::fmt::Display for Error {
             Self::StoreReadonlyValue => write!(f, "StoreReadonlyValue"),
             Self::StoreNotANode => write!(f, "StoreNotANode"),
             Self::FullOutbox => write!(f, "FullOutbox"),
+            Self::StoreInvalidSubkeyIndex => write!(f, "StoreInvalidSubkeyIndex"),
         }
     }
 }
@@ -97,6 +100,9 @@ impl From<i32> for Error {
             mavryk_smart_rollup_core::STORE_READONLY_VALUE => Self::StoreReadonlyValue,
             mavryk_smart_rollup_core::STORE_NOT_A_NODE => Self::StoreNotANode,
             mavryk_smart_rollup_core::FULL_OUTBOX => Self::FullOutbox,
+            mavryk_smart_rollup_core::STORE_INVALID_SUBKEY_INDEX => {
+                Self::StoreInvalidSubkeyIndex
+            }
             _ => Error::GenericInvalidAccess,
         }
     }
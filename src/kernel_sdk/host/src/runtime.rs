@@ -161,6 +161,45 @@ pub trait Runtime {
     /// See [SmartRollupCore::store_list_size].
     fn store_count_subkeys<T: Path>(&self, prefix: &T) -> Result<u64, RuntimeError>;
 
+    /// Get the name of the subkey of `prefix` at `index`.
+    ///
+    /// The value at `prefix`, if any, is listed as a subkey with an empty
+    /// name. The order of the subkeys is left to the host.
+    ///
+    /// See [SmartRollupCore::store_get_nth_key].
+    #[cfg(feature = "alloc")]
+    fn store_get_nth_key<T: Path>(
+        &self,
+        prefix: &T,
+        index: u64,
+    ) -> Result<String, RuntimeError>;
+
+    /// List the names of the subkeys under `prefix`, one
+    /// [`store_get_nth_key`] call at a time, in the order of the host.
+    ///
+    /// Contrary to `store_count_subkeys`, the value at `prefix` is not
+    /// included.
+    ///
+    /// [`store_get_nth_key`]: Runtime::store_get_nth_key
+    #[cfg(feature = "alloc")]
+    fn store_list_subkeys<T: Path>(
+        &self,
+        prefix: &T,
+    ) -> Result<Vec<String>, RuntimeError> {
+        let count = Runtime::store_count_subkeys(self, prefix)?;
+        let mut subkeys = Vec::with_capacity(count as usize);
+
+        for index in 0..count {
+            let subkey = Runtime::store_get_nth_key(self, prefix, index)?;
+            // The value at `prefix` is listed with an empty name.
+            if !subkey.is_empty() {
+                subkeys.push(subkey);
+            }
+        }
+
+        Ok(subkeys)
+    }
+
     /// Move one part of durable storage to a different location
     ///
     /// See [SmartRollupCore::store_move].
@@ -516,6 +555,39 @@ where
         }
     }
 
+    #[cfg(feature = "alloc")]
+    fn store_get_nth_key<T: Path>(
+        &self,
+        prefix: &T,
+        index: u64,
+    ) -> Result<String, RuntimeError> {
+        use crate::path::PATH_MAX_SIZE;
+
+        let index =
+            i64::try_from(index).map_err(|_| RuntimeError::StoreListIndexOutOfBounds)?;
+        let mut buffer = [0u8; PATH_MAX_SIZE];
+
+        let result = unsafe {
+            SmartRollupCore::store_get_nth_key(
+                self,
+                prefix.as_ptr(),
+                prefix.size(),
+                index,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+            )
+        };
+
+        match Error::wrap(result) {
+            Ok(size) => String::from_utf8(buffer[..size].to_vec())
+                .map_err(|_| RuntimeError::DecodingError),
+            Err(Error::StoreInvalidSubkeyIndex) => {
+                Err(RuntimeError::StoreListIndexOutOfBounds)
+            }
+            Err(e) => Err(RuntimeError::HostErr(e)),
+        }
+    }
+
     fn store_move(
         &mut self,
         from_path: &impl Path,
@@ -1266,6 +1338,50 @@ mod tests {
         assert_eq!(Ok(subkey_count.try_into().unwrap()), result);
     }
 
+    #[test]
+    fn store_list_subkeys() {
+        // Arrange
+        const PATH: RefPath<'static> = RefPath::assert_from(b"/prefix/of/other/keys");
+        const SUBKEYS: [&str; 3] = ["", "a", "b.c"];
+
+        let mut mock = MockSmartRollupCore::new();
+
+        mock.expect_store_list_size()
+            .withf(|ptr, size| {
+                let slice = unsafe { from_raw_parts(*ptr, *size) };
+
+                PATH.as_bytes() == slice
+            })
+            .return_const(SUBKEYS.len() as i64);
+
+        mock.expect_store_get_nth_key()
+            .withf(|ptr, size, _, _, _| {
+                let slice = unsafe { from_raw_parts(*ptr, *size) };
+
+                PATH.as_bytes() == slice
+            })
+            .returning(|_, _, index, dst, max_size| {
+                let Some(subkey) = SUBKEYS.get(index as usize) else {
+                    return Error::StoreInvalidSubkeyIndex.code();
+                };
+                assert!(subkey.len() <= max_size);
+
+                let buffer = unsafe { from_raw_parts_mut(dst, subkey.len()) };
+                buffer.copy_from_slice(subkey.as_bytes());
+                subkey.len() as i32
+            });
+
+        // Act
+        let subkey = mock.store_get_nth_key(&PATH, 2);
+        let out_of_bounds = mock.store_get_nth_key(&PATH, 3);
+        let subkeys = mock.store_list_subkeys(&PATH);
+
+        // Assert
+        assert_eq!(Ok("b.c".to_string()), subkey);
+        assert_eq!(Err(RuntimeError::StoreListIndexOutOfBounds), out_of_bounds);
+        assert_eq!(Ok(vec!["a".to_string(), "b.c".to_string()]), subkeys);
+    }
+
     #[test]
     fn reveal_preimage_ok() {
         let mut mock = MockSmartRollupCore::new();
@@ -9,6 +9,7 @@
 //! _not_ compiling to **wasm**.
 
 use crate::state::{HostState, NextInput};
+use crate::ticks::{bytes_read, bytes_written, tree_access};
 use crate::MockHost;
 use core::{
     cell::RefCell,
@@ -37,6 +38,14 @@ impl AsMut<HostState> for MockHost {
     }
 }
 
+impl MockHost {
+    /// Charge `ticks` for a host function call, unless its `result` is an error.
+    fn charge_ticks(&self, result: impl Into<i64>, ticks: u64) {
+        let ticks = if result.into() < 0 { 0 } else { ticks };
+        self.state.borrow_mut().charge_ticks(ticks);
+    }
+}
+
 unsafe impl SmartRollupCore for MockHost {
     unsafe fn read_input(
         &self,
@@ -44,9 +53,9 @@ unsafe impl SmartRollupCore for MockHost {
         dst: *mut u8,
         max_bytes: usize,
     ) -> i32 {
-        if let Some(NextInput { level, id, payload }) =
-            self.state.borrow_mut().handle_read_input(max_bytes)
-        {
+        let next_input = self.state.borrow_mut().handle_read_input(max_bytes);
+
+        if let Some(NextInput { level, id, payload }) = next_input {
             let input_message_info = ReadInputMessageInfo {
                 level: level as i32,
                 id: id as i32,
@@ -57,13 +66,17 @@ unsafe impl SmartRollupCore for MockHost {
             let slice = from_raw_parts_mut(dst, payload.len());
             slice.copy_from_slice(payload.as_slice());
 
+            self.charge_ticks(0, bytes_written(payload.len()));
             payload.len().try_into().unwrap()
         } else {
+            self.charge_ticks(0, 0);
             0_i32
         }
     }
 
     unsafe fn write_debug(&self, src: *const u8, num_bytes: usize) {
+        self.charge_ticks(0, 0);
+
         let debug_out = from_raw_parts(src, num_bytes).to_vec();
 
         let debug = String::from_utf8(debug_out).expect("unexpected non-utf8 debug log");
@@ -74,15 +87,21 @@ unsafe impl SmartRollupCore for MockHost {
     unsafe fn write_output(&self, src: *const u8, num_bytes: usize) -> i32 {
         let output = from_raw_parts(src, num_bytes).to_vec();
 
-        self.state
+        let result = self
+            .state
             .borrow_mut()
             .handle_write_output(output)
             .map(|_| 0)
-            .unwrap_or_else(Error::code)
+            .unwrap_or_else(Error::code);
+
+        self.charge_ticks(result, bytes_read(num_bytes));
+        result
     }
 
     unsafe fn store_has(&self, path: *const u8, len: usize) -> i32 {
-        self.state.borrow().store.store_has(path, len)
+        let result = self.state.borrow().store.store_has(path, len);
+        self.charge_ticks(result, tree_access(len));
+        result
     }
 
     unsafe fn store_read(
@@ -93,10 +112,17 @@ unsafe impl SmartRollupCore for MockHost {
         dst: *mut u8,
         max_bytes: usize,
     ) -> i32 {
-        self.state
+        let result = self
+            .state
             .borrow()
             .store
-            .store_read(path, len, offset, dst, max_bytes)
+            .store_read(path, len, offset, dst, max_bytes);
+
+        self.charge_ticks(
+            result,
+            tree_access(len) + bytes_written(result.max(0) as usize),
+        );
+        result
     }
 
     unsafe fn store_write(
@@ -107,22 +133,53 @@ unsafe impl SmartRollupCore for MockHost {
         src: *const u8,
         num_bytes: usize,
     ) -> i32 {
-        self.state
+        let result = self
+            .state
             .borrow_mut()
             .store
-            .store_write(path, len, offset, src, num_bytes)
+            .store_write(path, len, offset, src, num_bytes);
+
+        self.charge_ticks(result, tree_access(len) + bytes_read(num_bytes));
+        result
     }
 
     unsafe fn store_delete(&self, path: *const u8, len: usize) -> i32 {
-        self.state.borrow_mut().store.store_delete(path, len)
+        let result = self.state.borrow_mut().store.store_delete(path, len);
+        self.charge_ticks(result, tree_access(len));
+        result
     }
 
     unsafe fn store_delete_value(&self, path: *const u8, len: usize) -> i32 {
-        self.state.borrow_mut().store.store_delete_value(path, len)
+        let result = self.state.borrow_mut().store.store_delete_value(path, len);
+        self.charge_ticks(result, tree_access(len));
+        result
     }
 
     unsafe fn store_list_size(&self, path: *const u8, len: usize) -> i64 {
-        self.state.borrow().store.store_list_size(path, len)
+        let result = self.state.borrow().store.store_list_size(path, len);
+        self.charge_ticks(result, tree_access(len));
+        result
+    }
+
+    unsafe fn store_get_nth_key(
+        &self,
+        path: *const u8,
+        len: usize,
+        index: i64,
+        dst: *mut u8,
+        max_size: usize,
+    ) -> i32 {
+        let result = self
+            .state
+            .borrow()
+            .store
+            .store_get_nth_key(path, len, index, dst, max_size);
+
+        self.charge_ticks(
+            result,
+            tree_access(len) + bytes_written(result.max(0) as usize),
+        );
+        result
     }
 
     unsafe fn store_move(
@@ -132,12 +189,15 @@ unsafe impl SmartRollupCore for MockHost {
         to_path: *const u8,
         to_path_len: usize,
     ) -> i32 {
-        self.state.borrow_mut().store.store_move(
+        let result = self.state.borrow_mut().store.store_move(
             from_path,
             from_path_len,
             to_path,
             to_path_len,
-        )
+        );
+
+        self.charge_ticks(result, tree_access(from_path_len) + bytes_read(to_path_len));
+        result
     }
 
     unsafe fn store_copy(
@@ -147,12 +207,15 @@ unsafe impl SmartRollupCore for MockHost {
         to_path: *const u8,
         to_path_len: usize,
     ) -> i32 {
-        self.state.borrow_mut().store.store_copy(
+        let result = self.state.borrow_mut().store.store_copy(
             from_path,
             from_path_len,
             to_path,
             to_path_len,
-        )
+        );
+
+        self.charge_ticks(result, tree_access(from_path_len) + bytes_read(to_path_len));
+        result
     }
 
     unsafe fn reveal_preimage(
@@ -178,11 +241,14 @@ unsafe impl SmartRollupCore for MockHost {
         let slice = from_raw_parts_mut(destination_addr, bytes.len());
         slice.copy_from_slice(bytes.as_slice());
 
+        self.charge_ticks(0, bytes_read(hash_len) + bytes_written(bytes.len()));
         bytes.len().try_into().unwrap()
     }
 
     unsafe fn store_value_size(&self, path: *const u8, path_len: usize) -> i32 {
-        self.state.borrow().store.store_value_size(path, path_len)
+        let result = self.state.borrow().store.store_value_size(path, path_len);
+        self.charge_ticks(result, tree_access(path_len));
+        result
     }
 
     unsafe fn reveal_metadata(&self, destination_addr: *mut u8, max_bytes: usize) -> i32 {
@@ -191,19 +257,31 @@ unsafe impl SmartRollupCore for MockHost {
             self.state.borrow().get_metadata().clone().into();
         let slice = from_raw_parts_mut(destination_addr, metadata.len());
         slice.copy_from_slice(metadata.as_slice());
+
+        self.charge_ticks(0, bytes_written(metadata.len()));
         metadata.len().try_into().unwrap()
     }
 
     #[cfg(feature = "proto-alpha")]
     unsafe fn reveal(
         &self,
-        _payload_addr: *const u8,
-        _payload_len: usize,
-        _destination_addr: *mut u8,
-        _max_bytes: usize,
+        payload_addr: *const u8,
+        payload_len: usize,
+        destination_addr: *mut u8,
+        max_bytes: usize,
     ) -> i32 {
-        // TODO: https://gitlab.com/tezos/tezos/-/issues/6171
-        unimplemented!("The `reveal` host function is not yet mocked.")
+        let payload = from_raw_parts(payload_addr, payload_len);
+
+        let bytes = match self.state.borrow().handle_reveal(payload, max_bytes) {
+            Ok(bytes) => bytes,
+            Err(e) => return e.code(),
+        };
+
+        let slice = from_raw_parts_mut(destination_addr, bytes.len());
+        slice.copy_from_slice(bytes.as_slice());
+
+        self.charge_ticks(0, bytes_read(payload_len) + bytes_written(bytes.len()));
+        bytes.len().try_into().unwrap()
     }
 }
 
@@ -399,4 +477,160 @@ mod tests {
         assert_ne!(new_value_in_store, initial_value_in_store);
         assert_eq!(new_value_in_store, smaller_value);
     }
+
+    #[cfg(feature = "proto-alpha")]
+    #[test]
+    fn test_reveal_raw_data_and_metadata() {
+        use mavryk_smart_rollup_core::smart_rollup_core::SmartRollupCore;
+
+        // Arrange
+        let mut mock_host = MockHost::default();
+        let hash = mock_host.set_preimage(vec![b'a'; 3 * 1024]);
+
+        let mut buffer = [0; 300];
+        let payload = [&[0u8], hash.as_slice()].concat();
+
+        // Act
+        let size = unsafe {
+            SmartRollupCore::reveal(
+                &mock_host,
+                payload.as_ptr(),
+                payload.len(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+            )
+        };
+
+        // Assert
+        assert_eq!(size, 300);
+        assert_eq!(buffer, [b'a'; 300]);
+
+        let mut metadata = [0; 24];
+        let size = unsafe {
+            SmartRollupCore::reveal(
+                &mock_host,
+                [1u8].as_ptr(),
+                1,
+                metadata.as_mut_ptr(),
+                metadata.len(),
+            )
+        };
+        assert_eq!(size, 24);
+        assert_eq!(
+            RollupMetadata::from(metadata),
+            Runtime::reveal_metadata(&mock_host)
+        );
+
+        // Unknown preimages are an error, not a panic
+        let payload = [&[0u8], [1u8; 33].as_slice()].concat();
+        let result = unsafe {
+            SmartRollupCore::reveal(
+                &mock_host,
+                payload.as_ptr(),
+                payload.len(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+            )
+        };
+        assert_eq!(
+            result,
+            mavryk_smart_rollup_host::Error::GenericInvalidAccess.code()
+        );
+    }
+
+    #[cfg(feature = "proto-alpha")]
+    #[test]
+    fn test_reveal_dal_parameters() {
+        use mavryk_smart_rollup_host::dal_parameters::RollupDalParameters;
+
+        // Arrange
+        let mut mock_host = MockHost::default();
+        let parameters = RollupDalParameters {
+            number_of_slots: 4,
+            attestation_lag: 2,
+            slot_size: 10,
+            page_size: 4,
+        };
+        mock_host.set_dal_parameters(parameters.clone());
+
+        // Act
+        let result = mock_host.reveal_dal_parameters();
+
+        // Assert
+        assert_eq!(result, parameters);
+    }
+
+    #[cfg(feature = "proto-alpha")]
+    #[test]
+    fn test_reveal_dal_page() {
+        use mavryk_smart_rollup_host::dal_parameters::RollupDalParameters;
+
+        // Arrange
+        let mut mock_host = MockHost::default();
+        mock_host.set_dal_parameters(RollupDalParameters {
+            number_of_slots: 4,
+            attestation_lag: 2,
+            slot_size: 10,
+            page_size: 4,
+        });
+
+        let published_level = mock_host.level();
+        mock_host.publish_dal_slot(published_level, 0, vec![1, 2, 3, 4, 5, 6], true);
+        mock_host.publish_dal_slot(published_level, 1, vec![1, 2, 3, 4, 5, 6], false);
+        mock_host.publish_dal_page(published_level, 2, 1, &[7, 8]);
+        mock_host.attest_dal_slot(published_level, 2);
+        mock_host.publish_dal_page(published_level, 3, 0, &[9]);
+
+        let reveal_page = |host: &MockHost, slot_index, page_index| {
+            let mut buffer = [0; 4];
+            host.reveal_dal_page(
+                published_level as i32,
+                slot_index,
+                page_index,
+                &mut buffer,
+            )
+            .map(|size| buffer[..size].to_vec())
+        };
+
+        // Act & Assert
+
+        // Not attested yet.
+        assert_eq!(reveal_page(&mock_host, 0, 0), Ok(vec![]));
+
+        mock_host.run_level(|_| {});
+        mock_host.run_level(|_| {});
+
+        assert_eq!(reveal_page(&mock_host, 0, 0), Ok(vec![1, 2, 3, 4]));
+        assert_eq!(reveal_page(&mock_host, 0, 1), Ok(vec![5, 6, 0, 0]));
+        assert_eq!(reveal_page(&mock_host, 0, 2), Ok(vec![0, 0]));
+        assert_eq!(reveal_page(&mock_host, 1, 0), Ok(vec![]));
+        assert_eq!(reveal_page(&mock_host, 2, 0), Ok(vec![0, 0, 0, 0]));
+        assert_eq!(reveal_page(&mock_host, 2, 1), Ok(vec![7, 8, 0, 0]));
+        assert_eq!(reveal_page(&mock_host, 3, 0), Ok(vec![]));
+
+        // Out of range.
+        assert_eq!(
+            reveal_page(&mock_host, 0, 3),
+            Err(RuntimeError::HostErr(
+                mavryk_smart_rollup_host::Error::GenericInvalidAccess
+            ))
+        );
+        assert!(reveal_page(&mock_host, 4, 0).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "DAL page index out of range")]
+    fn test_publish_dal_page_out_of_range() {
+        use mavryk_smart_rollup_host::dal_parameters::RollupDalParameters;
+
+        let mut mock_host = MockHost::default();
+        mock_host.set_dal_parameters(RollupDalParameters {
+            number_of_slots: 4,
+            attestation_lag: 2,
+            slot_size: 10,
+            page_size: 4,
+        });
+
+        mock_host.publish_dal_page(0, 0, 3, &[1]);
+    }
 }
@@ -8,6 +8,7 @@
 
 mod host;
 mod state;
+mod ticks;
 
 extern crate mavryk_crypto_rs as crypto;
 
@@ -22,10 +23,12 @@ use mavryk_smart_rollup_encoding::michelson::MichelsonUnit;
 use mavryk_smart_rollup_encoding::public_key_hash::PublicKeyHash;
 use mavryk_smart_rollup_encoding::smart_rollup::SmartRollupAddress;
 use mavryk_smart_rollup_encoding::timestamp::Timestamp;
+use mavryk_smart_rollup_host::dal_parameters::RollupDalParameters;
 use mavryk_smart_rollup_host::metadata::RollupMetadata;
 
 use state::HostState;
 use std::cell::RefCell;
+use ticks::TickCounter;
 
 const MAXIMUM_REBOOTS_PER_INPUT: i32 = 1000;
 
@@ -35,6 +38,9 @@ const REBOOT_FLAG_KEY: &str = "/kernel/env/reboot";
 
 const TOO_MANY_REBOOT_FLAG_KEY: &str = "/readonly/kernel/env/too_many_reboot";
 
+/// Flag set by the WASM PVM when the previous `kernel_run` call was aborted.
+const STUCK_FLAG_KEY: &str = "/readonly/kernel/env/stuck";
+
 /// The path where the WASM PVM exposes the remaining reboots a kernel can do
 /// with a given inbox. Written as i32 (little-endian).
 const REBOOT_COUNTER_KEY: &str = "/readonly/kernel/env/reboot_counter";
@@ -45,6 +51,7 @@ const NAIROBI_BLOCK_TIME: i64 = 15;
 const NAIROBI_ACTIVATION_TIMESTAMP: i64 = 1_687_561_630;
 
 pub use state::InMemoryStore;
+pub use ticks::{TickModel, MAX_TICKS_PER_KERNEL_RUN};
 
 /// The runtime host when _not_ running in **wasm**.
 #[derive(Debug)]
@@ -168,16 +175,66 @@ impl MockHost {
         self.add_inbox_message(external_message);
     }
 
+    /// Append an inbox message to the current inbox, serialized as in the
+    /// rollup inbox.
+    pub fn add_raw_message(&mut self, message: Vec<u8>) {
+        self.as_mut().add_input(message);
+    }
+
     /// Make a preimage available to the _reveal_data_ channel.
     pub fn set_preimage(&mut self, preimage: Vec<u8>) -> [u8; PREIMAGE_HASH_SIZE] {
         self.as_mut().set_preimage(preimage)
     }
 
+    /// Set the parameters of the Data Availability Layer, which default to
+    /// those of Mavryk mainnet.
+    pub fn set_dal_parameters(&mut self, parameters: RollupDalParameters) {
+        self.as_mut().dal_parameters = parameters;
+    }
+
+    /// Publish `contents` in the DAL slot `slot_index` at `published_level`.
+    ///
+    /// - The slot is padded with zeros up to the slot size.
+    /// - Pages of slots which are not `attested` are revealed as empty, as
+    ///   are the pages of slots less than `attestation_lag` levels old.
+    pub fn publish_dal_slot(
+        &mut self,
+        published_level: u32,
+        slot_index: u8,
+        contents: Vec<u8>,
+        attested: bool,
+    ) {
+        self.as_mut()
+            .publish_dal_slot(published_level, slot_index, contents, attested);
+    }
+
+    /// Publish `page` as page `page_index` of the DAL slot `slot_index` at
+    /// `published_level`, leaving its other pages and its attestation status
+    /// untouched. Slots are not attested until [`MockHost::attest_dal_slot`]
+    /// is called.
+    pub fn publish_dal_page(
+        &mut self,
+        published_level: u32,
+        slot_index: u8,
+        page_index: u16,
+        page: &[u8],
+    ) {
+        self.as_mut()
+            .publish_dal_page(published_level, slot_index, page_index, page);
+    }
+
+    /// Attest the DAL slot `slot_index` published at `published_level`.
+    pub fn attest_dal_slot(&mut self, published_level: u32, slot_index: u8) {
+        self.as_mut().attest_dal_slot(published_level, slot_index);
+    }
+
     /// Runs `kernel_run` against the current level's inbox.
     ///
     /// - Includes the `StartOfLevel`, `InfoPerLevel` & `EndOfLevel` messages.
+    /// - Discards the calls which run out of ticks or are aborted, if a
+    ///   [`TickModel`] is set.
     /// - Returns the level the kernel was run at.
-    pub fn run_level(&mut self, kernel_run: fn(&mut Self)) -> u32 {
+    pub fn run_level(&mut self, mut kernel_run: impl FnMut(&mut Self)) -> u32 {
         self.finalise_inputs();
 
         let mut reboots = MAXIMUM_REBOOTS_PER_INPUT;
@@ -186,17 +243,20 @@ impl MockHost {
             let bytes = reboots.to_le_bytes().to_vec();
             self.as_mut().store.0.set_value(REBOOT_COUNTER_KEY, bytes);
 
+            let snapshot = self.start_run();
             kernel_run(self);
+            let out_of_ticks = self.end_run(snapshot);
             self.as_mut().store.0.node_delete(TOO_MANY_REBOOT_FLAG_KEY);
 
             reboots -= 1;
 
-            let reboot_requested = self
-                .as_mut()
-                .store
-                .0
-                .maybe_get_value(REBOOT_FLAG_KEY)
-                .is_some();
+            let reboot_requested = out_of_ticks
+                || self
+                    .as_mut()
+                    .store
+                    .0
+                    .maybe_get_value(REBOOT_FLAG_KEY)
+                    .is_some();
 
             if reboot_requested {
                 self.as_mut().store.0.node_delete(REBOOT_FLAG_KEY);
@@ -219,6 +279,50 @@ impl MockHost {
         level_ran_at
     }
 
+    /// Raise or clear the flag read by `Runtime::last_run_aborted`, which the
+    /// WASM PVM raises when a `kernel_run` call traps.
+    pub fn set_last_run_aborted(&mut self, aborted: bool) {
+        let store = &mut self.as_mut().store.0;
+        if aborted {
+            store.set_value(STUCK_FLAG_KEY, vec![]);
+        } else {
+            store.node_delete(STUCK_FLAG_KEY);
+        }
+    }
+
+    /// Charge ticks for the host functions called by `kernel_run`, and discard
+    /// the calls exceeding the budget of `model`, as described in [`TickModel`].
+    pub fn set_tick_model(&mut self, model: TickModel) {
+        self.as_mut().ticks = Some(TickCounter::new(model));
+    }
+
+    /// Charge `ticks` to the current `kernel_run` call, in addition to the
+    /// ticks of its host function calls, e.g. for the instructions executed
+    /// by a kernel which is not linked into the test binary. Has no effect if
+    /// no [`TickModel`] is set.
+    pub fn charge_ticks(&mut self, ticks: u64) {
+        if let Some(counter) = self.as_mut().ticks.as_mut() {
+            counter.consumed = counter.consumed.saturating_add(ticks);
+        }
+    }
+
+    /// Discard the current `kernel_run` call once it returns, as the PVM does
+    /// when a call traps: the durable storage and outbox are restored and
+    /// `Runtime::last_run_aborted` holds. Unlike running out of ticks, this
+    /// does not reboot the kernel. Has no effect if no [`TickModel`] is set.
+    pub fn abort_run(&mut self) {
+        if let Some(counter) = self.as_mut().ticks.as_mut() {
+            counter.trapped = true;
+        }
+    }
+
+    /// Returns the ticks consumed by the current (or last) `kernel_run`, or 0
+    /// if no [`TickModel`] is set.
+    pub fn ticks_consumed(&self) -> u64 {
+        let state = self.state.borrow();
+        state.ticks.as_ref().map_or(0, |counter| counter.consumed)
+    }
+
     /// Returns the level of the next `kernel_run`.
     pub fn level(&self) -> u32 {
         self.state.borrow().curr_level
@@ -255,6 +359,37 @@ impl MockHost {
         self.info = curr_info;
     }
 
+    /// Reset the tick counter before a `kernel_run` call. Returns the store
+    /// to restore if the call runs out of ticks.
+    fn start_run(&mut self) -> Option<InMemoryStore> {
+        let state = self.as_mut();
+        state.ticks.as_mut()?.consumed = 0;
+        Some(state.store.clone())
+    }
+
+    /// Discard a `kernel_run` call which ran out of ticks, as the PVM would
+    /// with a forced reboot, or which trapped. Returns whether it ran out of
+    /// ticks.
+    fn end_run(&mut self, snapshot: Option<InMemoryStore>) -> bool {
+        let state = self.as_mut();
+        let (Some(snapshot), Some(counter)) = (snapshot, state.ticks.as_mut()) else {
+            return false;
+        };
+
+        let out_of_ticks = counter.exhausted();
+        let discarded = out_of_ticks || std::mem::take(&mut counter.trapped);
+        let was_aborted = std::mem::replace(&mut counter.aborted, discarded);
+
+        if discarded {
+            state.store = snapshot;
+            self.set_last_run_aborted(true);
+        } else if was_aborted {
+            self.set_last_run_aborted(false);
+        }
+
+        out_of_ticks
+    }
+
     fn finalise_inputs(&mut self) {
         let eol = inbox::InboxMessage::<MichelsonUnit>::Internal(
             inbox::InternalInboxMessage::EndOfLevel,
@@ -7,10 +7,15 @@
 //! Mock runtime state & state transitions
 
 use crypto::hash::SmartRollupHash;
+use mavryk_smart_rollup_constants::dal;
 use mavryk_smart_rollup_core::{
     MAX_INPUT_MESSAGE_SIZE, MAX_OUTPUT_SIZE, PREIMAGE_HASH_SIZE,
 };
-use mavryk_smart_rollup_host::{metadata::RollupMetadata, Error};
+use mavryk_smart_rollup_host::{
+    dal_parameters::RollupDalParameters, metadata::RollupMetadata, Error,
+};
+
+use crate::ticks::TickCounter;
 
 pub(crate) mod in_memory_store;
 pub(crate) mod store;
@@ -19,6 +24,16 @@ pub use self::in_memory_store::InMemoryStore;
 
 const MAX_OUTPUTS_PER_LEVEL: usize = 100;
 
+// Tags of the reveal requests, as encoded in the Mavryk protocol.
+#[cfg(feature = "proto-alpha")]
+const REVEAL_RAW_DATA_TAG: u8 = 0;
+#[cfg(feature = "proto-alpha")]
+const REVEAL_METADATA_TAG: u8 = 1;
+#[cfg(feature = "proto-alpha")]
+const REQUEST_DAL_PAGE_TAG: u8 = 2;
+#[cfg(feature = "proto-alpha")]
+const REVEAL_DAL_PARAMETERS_TAG: u8 = 3;
+
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) struct NextInput {
     pub level: u32,
@@ -32,10 +47,13 @@ pub(crate) struct HostState {
     /// Key-value store of runtime state.
     pub store: InMemoryStore,
     pub metadata: RollupMetadata,
+    pub dal_parameters: RollupDalParameters,
     // Inbox metadata
     pub(crate) curr_level: u32,
     pub(crate) curr_input_id: usize,
     pub(crate) input: Vec<Vec<u8>>,
+    // Ticks of the current `kernel_run`, when a tick model is set
+    pub(crate) ticks: Option<TickCounter>,
 }
 
 impl Default for HostState {
@@ -52,12 +70,21 @@ impl Default for HostState {
             raw_rollup_address,
             origination_level: crate::NAIROBI_ACTIVATION_LEVEL,
         };
+        let dal_parameters = RollupDalParameters {
+            number_of_slots: dal::MAINNET_NUMBER_OF_SLOTS,
+            attestation_lag: dal::MAINNET_ATTESTATION_LAG,
+            slot_size: dal::MAINNET_SLOT_SIZE,
+            page_size: dal::MAINNET_PAGE_SIZE,
+        };
+
         Self {
             store,
             metadata,
+            dal_parameters,
             curr_level: crate::NAIROBI_ACTIVATION_LEVEL,
             curr_input_id: 0,
             input: vec![],
+            ticks: None,
         }
     }
 }
@@ -133,6 +160,176 @@ impl HostState {
     pub(crate) fn get_metadata(&self) -> &RollupMetadata {
         &self.metadata
     }
+
+    /// The DAL slot `slot_index` published at `published_level`, which must
+    /// be within the number of slots.
+    fn dal_slot_mut(
+        &mut self,
+        published_level: u32,
+        slot_index: u8,
+    ) -> &mut store::DalSlot {
+        let number_of_slots = self.dal_parameters.number_of_slots;
+        if u64::from(slot_index) >= number_of_slots {
+            panic!(
+                "DAL slot index out of range -index:{} -slots:{}",
+                slot_index, number_of_slots
+            );
+        }
+
+        self.store.0.dal_slot_mut(published_level, slot_index)
+    }
+
+    pub(crate) fn publish_dal_slot(
+        &mut self,
+        published_level: u32,
+        slot_index: u8,
+        contents: Vec<u8>,
+        attested: bool,
+    ) {
+        let slot_size = self.dal_parameters.slot_size as usize;
+        if contents.len() > slot_size {
+            panic!(
+                "DAL slot too big -size:{} -max:{}",
+                contents.len(),
+                slot_size
+            );
+        }
+
+        let slot = self.dal_slot_mut(published_level, slot_index);
+        slot.contents = contents;
+        slot.attested = attested;
+    }
+
+    pub(crate) fn publish_dal_page(
+        &mut self,
+        published_level: u32,
+        slot_index: u8,
+        page_index: u16,
+        page: &[u8],
+    ) {
+        let params = &self.dal_parameters;
+        let pages_per_slot = params.slot_size.div_ceil(params.page_size.max(1));
+        if u64::from(page_index) >= pages_per_slot {
+            panic!(
+                "DAL page index out of range -index:{} -pages:{}",
+                page_index, pages_per_slot
+            );
+        }
+
+        // The last page of the slot may be shorter.
+        let start = page_index as usize * params.page_size as usize;
+        let page_size =
+            usize::min(params.page_size as usize, params.slot_size as usize - start);
+        if page.len() > page_size {
+            panic!("DAL page too big -size:{} -max:{}", page.len(), page_size);
+        }
+
+        let slot = self.dal_slot_mut(published_level, slot_index);
+        if slot.contents.len() < start + page.len() {
+            slot.contents.resize(start + page.len(), 0);
+        }
+        slot.contents[start..start + page.len()].copy_from_slice(page);
+    }
+
+    pub(crate) fn attest_dal_slot(&mut self, published_level: u32, slot_index: u8) {
+        self.dal_slot_mut(published_level, slot_index).attested = true;
+    }
+
+    /// Page `page_index` of the slot `slot_index` published at
+    /// `published_level`. Pages of slots which were not attested, or not yet,
+    /// are empty.
+    #[cfg(feature = "proto-alpha")]
+    fn handle_reveal_dal_page(
+        &self,
+        published_level: i32,
+        slot_index: u8,
+        page_index: i16,
+    ) -> Result<Vec<u8>, Error> {
+        let params = &self.dal_parameters;
+        let pages_per_slot = params.slot_size.div_ceil(params.page_size.max(1));
+
+        if published_level < 0
+            || u64::from(slot_index) >= params.number_of_slots
+            || page_index < 0
+            || page_index as u64 >= pages_per_slot
+        {
+            return Err(Error::GenericInvalidAccess);
+        }
+
+        // Slots are attested `attestation_lag` levels after their publication.
+        if published_level as u64 + params.attestation_lag > self.curr_level as u64 {
+            return Ok(vec![]);
+        }
+
+        let slot = match self.store.0.dal_slot(published_level as u32, slot_index) {
+            Some(slot) if slot.attested => slot,
+            _ => return Ok(vec![]),
+        };
+
+        // Slots are padded with zeros up to the slot size.
+        let start = page_index as usize * params.page_size as usize;
+        let end =
+            usize::min(start + params.page_size as usize, params.slot_size as usize);
+        let mut page = vec![0; end - start];
+        if let Some(contents) = slot.contents.get(start..) {
+            let len = usize::min(contents.len(), page.len());
+            page[..len].copy_from_slice(&contents[..len]);
+        }
+
+        Ok(page)
+    }
+
+    /// Decode and serve a reveal request, trimmed to `max_bytes`.
+    #[cfg(feature = "proto-alpha")]
+    pub(crate) fn handle_reveal(
+        &self,
+        payload: &[u8],
+        max_bytes: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let mut revealed = match *payload {
+            [REVEAL_RAW_DATA_TAG, ref hash @ ..] => {
+                let hash = hash.try_into().map_err(|_| Error::GenericInvalidAccess)?;
+                self.store
+                    .0
+                    .find_preimage(hash)
+                    .ok_or(Error::GenericInvalidAccess)?
+                    .to_vec()
+            }
+            [REVEAL_METADATA_TAG] => {
+                let metadata: [u8; mavryk_smart_rollup_host::metadata::METADATA_SIZE] =
+                    self.metadata.clone().into();
+                metadata.to_vec()
+            }
+            [REQUEST_DAL_PAGE_TAG, l0, l1, l2, l3, slot_index, p0, p1] => self
+                .handle_reveal_dal_page(
+                    i32::from_be_bytes([l0, l1, l2, l3]),
+                    slot_index,
+                    i16::from_be_bytes([p0, p1]),
+                )?,
+            [REVEAL_DAL_PARAMETERS_TAG] => {
+                let params = &self.dal_parameters;
+                [
+                    params.number_of_slots,
+                    params.attestation_lag,
+                    params.slot_size,
+                    params.page_size,
+                ]
+                .iter()
+                .flat_map(|param| param.to_be_bytes())
+                .collect()
+            }
+            _ => return Err(Error::GenericInvalidAccess),
+        };
+
+        revealed.truncate(max_bytes);
+        Ok(revealed)
+    }
+
+    pub(crate) fn charge_ticks(&mut self, ticks: u64) {
+        if let Some(counter) = &mut self.ticks {
+            counter.charge(ticks);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -412,6 +609,34 @@ mod tests {
         assert_eq!(11, with_value, "Expected 10 subkeys of prefix, plus value");
     }
 
+    #[test]
+    fn store_get_nth_key() {
+        // Arrange
+        let mut state = HostState::default();
+        for path in ["/a/c/d", "/a/b", "/a", "/ab"] {
+            state
+                .store
+                .handle_store_write(path.as_bytes(), 0, &[])
+                .unwrap();
+        }
+
+        // Act
+        let keys = (0..3)
+            .map(|i| state.store.handle_store_get_nth_key(b"/a", i).unwrap())
+            .collect::<Vec<_>>();
+
+        // Assert
+        assert_eq!(vec!["", "b", "c"], keys, "Expected value, then subkeys");
+        assert_eq!(
+            Err(Error::StoreInvalidSubkeyIndex),
+            state.store.handle_store_get_nth_key(b"/a", 3)
+        );
+        assert_eq!(
+            Err(Error::StoreNotANode),
+            state.store.handle_store_get_nth_key(b"/b", 0)
+        );
+    }
+
     #[test]
     fn store_move() {
         // Arrange
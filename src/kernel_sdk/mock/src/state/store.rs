@@ -13,6 +13,7 @@ use std::rc::Rc;
 pub(crate) struct Store {
     pub(crate) durable: Rc<Node>,
     preimages: HashMap<[u8; PREIMAGE_HASH_SIZE], Vec<u8>>,
+    dal_slots: HashMap<(u32, u8), DalSlot>,
     outbox: HashMap<u32, Vec<Vec<u8>>>,
     inbox: HashMap<u32, Vec<Vec<u8>>>,
 }
@@ -23,6 +24,13 @@ pub(crate) struct Node {
     pub(crate) inner: Rc<HashMap<String, Rc<Self>>>,
 }
 
+/// Slot published on the Data Availability Layer.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct DalSlot {
+    pub(crate) contents: Vec<u8>,
+    pub(crate) attested: bool,
+}
+
 pub(crate) const VALUE_NAME: &str = "@";
 
 impl Node {
@@ -182,10 +190,22 @@ impl Store {
     }
 
     pub fn retrieve_preimage(&self, hash: &[u8; PREIMAGE_HASH_SIZE]) -> &[u8] {
-        self.preimages
-            .get(hash)
-            .expect("Cannot retrieve preimage")
-            .as_ref()
+        self.find_preimage(hash).expect("Cannot retrieve preimage")
+    }
+
+    pub fn find_preimage(&self, hash: &[u8; PREIMAGE_HASH_SIZE]) -> Option<&[u8]> {
+        self.preimages.get(hash).map(Vec::as_slice)
+    }
+
+    #[cfg(feature = "proto-alpha")]
+    pub fn dal_slot(&self, published_level: u32, slot_index: u8) -> Option<&DalSlot> {
+        self.dal_slots.get(&(published_level, slot_index))
+    }
+
+    pub fn dal_slot_mut(&mut self, published_level: u32, slot_index: u8) -> &mut DalSlot {
+        self.dal_slots
+            .entry((published_level, slot_index))
+            .or_default()
     }
 }
 
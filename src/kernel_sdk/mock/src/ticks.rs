@@ -0,0 +1,207 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Tick accounting of the mock runtime.
+//!
+//! Host functions are charged as in the cost model of the WASM PVM: by the
+//! bytes they read from and write to the kernel memory, plus a tick per
+//! access to the durable storage. Failing host functions are free.
+
+/// Maximum number of ticks of a `kernel_run` call in the WASM PVM.
+pub const MAX_TICKS_PER_KERNEL_RUN: u64 = 11_000_000_000;
+
+/// Ticks per byte read from the kernel memory by a host function.
+const TICKS_PER_BYTE_READ: u64 = 42;
+
+/// Ticks per byte written to the kernel memory by a host function.
+const TICKS_PER_BYTE_WRITTEN: u64 = 42;
+
+/// Ticks per access to the durable storage.
+const TICKS_PER_TREE_ACCESS: u64 = 1;
+
+/// Tick budget of `kernel_run` calls made with [`MockHost::run_level`].
+///
+/// A kernel linked into the test binary can't be interrupted. Instead, the
+/// run is discarded once it returns if it exceeded its budget: the durable
+/// storage and outbox are restored, the kernel is rebooted and
+/// `Runtime::last_run_aborted` holds, as after a forced reboot of the PVM.
+///
+/// [`MockHost::run_level`]: crate::MockHost::run_level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickModel {
+    /// Ticks available to each `kernel_run` call.
+    pub max_ticks: u64,
+
+    /// Estimate of the ticks spent executing the kernel between two host
+    /// function calls, charged on every host function call.
+    pub ticks_per_step: u64,
+}
+
+impl Default for TickModel {
+    fn default() -> Self {
+        Self {
+            max_ticks: MAX_TICKS_PER_KERNEL_RUN,
+            ticks_per_step: 0,
+        }
+    }
+}
+
+/// Ticks consumed by the current `kernel_run` call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TickCounter {
+    pub(crate) model: TickModel,
+    pub(crate) consumed: u64,
+    // Whether the previous call was discarded
+    pub(crate) aborted: bool,
+    // Whether the current call trapped, see `MockHost::abort_run`
+    pub(crate) trapped: bool,
+}
+
+impl TickCounter {
+    pub(crate) fn new(model: TickModel) -> Self {
+        Self {
+            model,
+            consumed: 0,
+            aborted: false,
+            trapped: false,
+        }
+    }
+
+    /// Charge a host function call which cost `ticks`.
+    pub(crate) fn charge(&mut self, ticks: u64) {
+        self.consumed = self
+            .consumed
+            .saturating_add(self.model.ticks_per_step)
+            .saturating_add(ticks);
+    }
+
+    pub(crate) fn exhausted(&self) -> bool {
+        self.consumed > self.model.max_ticks
+    }
+}
+
+/// Cost of reading `bytes` from the kernel memory.
+pub(crate) fn bytes_read(bytes: usize) -> u64 {
+    bytes as u64 * TICKS_PER_BYTE_READ
+}
+
+/// Cost of writing `bytes` to the kernel memory.
+pub(crate) fn bytes_written(bytes: usize) -> u64 {
+    bytes as u64 * TICKS_PER_BYTE_WRITTEN
+}
+
+/// Cost of accessing the durable storage at a path of `path_len` bytes.
+pub(crate) fn tree_access(path_len: usize) -> u64 {
+    bytes_read(path_len) + TICKS_PER_TREE_ACCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TickModel;
+    use crate::MockHost;
+    use mavryk_smart_rollup_core::smart_rollup_core::SmartRollupCore;
+    use mavryk_smart_rollup_host::path::RefPath;
+    use mavryk_smart_rollup_host::runtime::Runtime;
+
+    const PATH: RefPath<'static> = RefPath::assert_from(b"/counter");
+
+    // Charges 4 storage accesses, and `2 * len + 1` bytes.
+    fn increment<Host: Runtime>(host: &mut Host) {
+        let counter = host.store_read_all(&PATH).unwrap_or_default().len();
+        host.store_write_all(&PATH, &vec![0; counter + 1]).unwrap();
+    }
+
+    #[test]
+    fn host_functions_charge_ticks() {
+        let mut host = MockHost::default();
+        host.set_tick_model(TickModel::default());
+        let path = b"/counter";
+        let access = 8 * 42 + 1;
+
+        // Path & value read.
+        let result = unsafe {
+            SmartRollupCore::store_write(&host, path.as_ptr(), 8, 0, [1u8; 3].as_ptr(), 3)
+        };
+        assert_eq!(result, 0);
+        assert_eq!(host.ticks_consumed(), access + 3 * 42);
+
+        // Path read, value written.
+        let mut buffer = [0u8; 2];
+        let result = unsafe {
+            SmartRollupCore::store_read(
+                &host,
+                path.as_ptr(),
+                8,
+                0,
+                buffer.as_mut_ptr(),
+                2,
+            )
+        };
+        assert_eq!(result, 2);
+        assert_eq!(host.ticks_consumed(), 2 * access + 5 * 42);
+
+        // Failing host functions are free.
+        let missing = b"/missing";
+        let result =
+            unsafe { SmartRollupCore::store_value_size(&host, missing.as_ptr(), 8) };
+        assert!(result < 0);
+        assert_eq!(host.ticks_consumed(), 2 * access + 5 * 42);
+    }
+
+    #[test]
+    fn step_estimate_is_charged_per_host_call() {
+        let mut host = MockHost::default();
+        host.set_tick_model(TickModel {
+            max_ticks: 1_000_000,
+            ticks_per_step: 1_000,
+        });
+
+        Runtime::store_has(&host, &PATH).unwrap();
+        assert_eq!(host.ticks_consumed(), 1_000 + 8 * 42 + 1);
+    }
+
+    #[test]
+    fn run_is_discarded_when_out_of_ticks() {
+        let mut host = MockHost::default();
+        host.set_tick_model(TickModel {
+            max_ticks: 10_000,
+            ticks_per_step: 0,
+        });
+
+        host.run_level(increment);
+        assert_eq!(host.store_read_all(&PATH).unwrap().len(), 1);
+        assert!(!host.last_run_aborted().unwrap());
+
+        // Incrementing a 200 bytes counter takes more than the budget.
+        host.store_write_all(&PATH, &[0; 200]).unwrap();
+        host.run_level(increment);
+
+        // Every run of the level was discarded.
+        assert_eq!(host.store_read_all(&PATH).unwrap().len(), 200);
+        assert!(host.last_run_aborted().unwrap());
+        assert!(host.restart_forced().unwrap());
+    }
+
+    #[test]
+    fn aborted_run_is_discarded() {
+        let mut host = MockHost::default();
+        host.set_tick_model(TickModel::default());
+
+        let mut runs = 0;
+        host.run_level(|host| {
+            runs += 1;
+            increment(host);
+            host.abort_run();
+        });
+
+        // The run was discarded, but the kernel was not rebooted.
+        assert_eq!(runs, 1);
+        assert!(Runtime::store_has(&host, &PATH).unwrap().is_none());
+        assert!(host.last_run_aborted().unwrap());
+
+        host.run_level(increment);
+        assert_eq!(host.store_read_all(&PATH).unwrap().len(), 1);
+        assert!(!host.last_run_aborted().unwrap());
+    }
+}
@@ -0,0 +1,397 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! The `smart_rollup_core` host functions, as imported by WASM kernels.
+//!
+//! Arguments pointing into the kernel memory are copied to host buffers,
+//! which are handed to the [`SmartRollupCore`] implementation of the
+//! [`MockHost`]. Results are copied back into the kernel memory. Accesses
+//! outside of the kernel memory fail with [`MEMORY_INVALID_ACCESS`], as in
+//! the WASM PVM.
+
+use mavryk_smart_rollup_core::smart_rollup_core::{
+    ReadInputMessageInfo, SmartRollupCore,
+};
+use mavryk_smart_rollup_core::MEMORY_INVALID_ACCESS;
+use mavryk_smart_rollup_mock::MockHost;
+use wasmi::{core::Trap, Caller, Extern, Linker, Memory};
+
+/// Name of the module from which kernels import the host functions
+pub(crate) const HOST_MODULE: &str = "smart_rollup_core";
+
+/// Name of the memory kernels must export
+const MEMORY_EXPORT: &str = "memory";
+
+type HostResult<T> = Result<T, Trap>;
+
+/// Memory exported by the kernel
+fn memory(caller: &Caller<'_, MockHost>) -> HostResult<Memory> {
+    caller
+        .get_export(MEMORY_EXPORT)
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("The kernel does not export its memory"))
+}
+
+/// Copy `len` bytes of the kernel memory starting at `ptr`.
+fn read_memory(
+    caller: &Caller<'_, MockHost>,
+    ptr: i32,
+    len: i32,
+) -> HostResult<Option<Vec<u8>>> {
+    let memory = memory(caller)?;
+    let data = memory.data(caller);
+    let start = ptr as u32 as usize;
+    let bytes = start
+        .checked_add(len as u32 as usize)
+        .and_then(|end| data.get(start..end))
+        .map(<[u8]>::to_vec);
+    Ok(bytes)
+}
+
+/// Copy `bytes` to the kernel memory starting at `ptr`. Returns `false` if
+/// they don't fit.
+fn write_memory(
+    caller: &mut Caller<'_, MockHost>,
+    ptr: i32,
+    bytes: &[u8],
+) -> HostResult<bool> {
+    let memory = memory(caller)?;
+    Ok(memory.write(caller, ptr as u32 as usize, bytes).is_ok())
+}
+
+/// Buffer for at most `max_bytes` bytes to be written to the kernel memory
+/// at `ptr`. It never exceeds the memory, which bounds the allocation.
+fn output_buffer(
+    caller: &Caller<'_, MockHost>,
+    ptr: i32,
+    max_bytes: i32,
+) -> HostResult<Vec<u8>> {
+    let memory_size = memory(caller)?.data(caller).len();
+    let available = memory_size.saturating_sub(ptr as u32 as usize);
+    Ok(vec![0; available.min(max_bytes as u32 as usize)])
+}
+
+/// Copy the first `size` bytes of `buffer` to the kernel memory at `ptr` if
+/// `size` is not an error code. Returns `size` or the error code.
+fn write_result(
+    caller: &mut Caller<'_, MockHost>,
+    ptr: i32,
+    buffer: &[u8],
+    size: i32,
+) -> HostResult<i32> {
+    if size > 0 && !write_memory(caller, ptr, &buffer[..size as usize])? {
+        return Ok(MEMORY_INVALID_ACCESS);
+    }
+    Ok(size)
+}
+
+/// Read the `len` bytes at `ptr`, e.g. a path, and pass them to `f`.
+fn with_input<R: From<i32>>(
+    caller: &Caller<'_, MockHost>,
+    ptr: i32,
+    len: i32,
+    f: impl FnOnce(&MockHost, &[u8]) -> R,
+) -> HostResult<R> {
+    Ok(match read_memory(caller, ptr, len)? {
+        Some(input) => f(caller.data(), &input),
+        None => MEMORY_INVALID_ACCESS.into(),
+    })
+}
+
+/// Define the host functions in `linker`.
+pub(crate) fn define(linker: &mut Linker<MockHost>) -> Result<(), wasmi::Error> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_input",
+        |mut caller: Caller<'_, MockHost>,
+         info_addr: i32,
+         dst: i32,
+         max_bytes: i32|
+         -> HostResult<i32> {
+            let mut buffer = output_buffer(&caller, dst, max_bytes)?;
+            let mut info = ReadInputMessageInfo { level: 0, id: 0 };
+            let size = unsafe {
+                caller
+                    .data()
+                    .read_input(&mut info, buffer.as_mut_ptr(), buffer.len())
+            };
+            if size <= 0 {
+                return Ok(size);
+            }
+
+            let info = [info.level.to_le_bytes(), info.id.to_le_bytes()].concat();
+            if !write_memory(&mut caller, info_addr, &info)? {
+                return Ok(MEMORY_INVALID_ACCESS);
+            }
+            write_result(&mut caller, dst, &buffer, size)
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "write_output",
+        |caller: Caller<'_, MockHost>, src: i32, num_bytes: i32| -> HostResult<i32> {
+            Ok(match read_memory(&caller, src, num_bytes)? {
+                Some(output) => unsafe {
+                    caller.data().write_output(output.as_ptr(), output.len())
+                },
+                None => MEMORY_INVALID_ACCESS,
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "write_debug",
+        |caller: Caller<'_, MockHost>, src: i32, num_bytes: i32| -> HostResult<()> {
+            // Debug messages which are not valid UTF-8 are not an error.
+            if let Some(bytes) = read_memory(&caller, src, num_bytes)? {
+                let message = String::from_utf8_lossy(&bytes);
+                unsafe { caller.data().write_debug(message.as_ptr(), message.len()) };
+            }
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "store_has",
+        |caller: Caller<'_, MockHost>, path: i32, path_len: i32| -> HostResult<i32> {
+            with_input(&caller, path, path_len, |host, path| unsafe {
+                host.store_has(path.as_ptr(), path.len())
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "store_read",
+        |mut caller: Caller<'_, MockHost>,
+         path: i32,
+         path_len: i32,
+         offset: i32,
+         dst: i32,
+         max_bytes: i32|
+         -> HostResult<i32> {
+            let mut buffer = output_buffer(&caller, dst, max_bytes)?;
+            let size = with_input(&caller, path, path_len, |host, path| unsafe {
+                host.store_read(
+                    path.as_ptr(),
+                    path.len(),
+                    offset as u32 as usize,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                )
+            })?;
+            write_result(&mut caller, dst, &buffer, size)
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "store_write",
+        |caller: Caller<'_, MockHost>,
+         path: i32,
+         path_len: i32,
+         offset: i32,
+         src: i32,
+         num_bytes: i32|
+         -> HostResult<i32> {
+            let Some(bytes) = read_memory(&caller, src, num_bytes)? else {
+                return Ok(MEMORY_INVALID_ACCESS);
+            };
+            with_input(&caller, path, path_len, |host, path| unsafe {
+                host.store_write(
+                    path.as_ptr(),
+                    path.len(),
+                    offset as u32 as usize,
+                    bytes.as_ptr(),
+                    bytes.len(),
+                )
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "store_delete",
+        |caller: Caller<'_, MockHost>, path: i32, path_len: i32| -> HostResult<i32> {
+            with_input(&caller, path, path_len, |host, path| unsafe {
+                host.store_delete(path.as_ptr(), path.len())
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "store_delete_value",
+        |caller: Caller<'_, MockHost>, path: i32, path_len: i32| -> HostResult<i32> {
+            with_input(&caller, path, path_len, |host, path| unsafe {
+                host.store_delete_value(path.as_ptr(), path.len())
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "store_list_size",
+        |caller: Caller<'_, MockHost>, path: i32, path_len: i32| -> HostResult<i64> {
+            with_input(&caller, path, path_len, |host, path| unsafe {
+                host.store_list_size(path.as_ptr(), path.len())
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "store_get_nth_key",
+        |mut caller: Caller<'_, MockHost>,
+         path: i32,
+         path_len: i32,
+         index: i64,
+         dst: i32,
+         max_size: i32|
+         -> HostResult<i32> {
+            let mut buffer = output_buffer(&caller, dst, max_size)?;
+            let size = with_input(&caller, path, path_len, |host, path| unsafe {
+                host.store_get_nth_key(
+                    path.as_ptr(),
+                    path.len(),
+                    index,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                )
+            })?;
+            write_result(&mut caller, dst, &buffer, size)
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "store_move",
+        |caller: Caller<'_, MockHost>,
+         from_path: i32,
+         from_path_len: i32,
+         to_path: i32,
+         to_path_len: i32|
+         -> HostResult<i32> {
+            let Some(to_path) = read_memory(&caller, to_path, to_path_len)? else {
+                return Ok(MEMORY_INVALID_ACCESS);
+            };
+            with_input(
+                &caller,
+                from_path,
+                from_path_len,
+                |host, from_path| unsafe {
+                    host.store_move(
+                        from_path.as_ptr(),
+                        from_path.len(),
+                        to_path.as_ptr(),
+                        to_path.len(),
+                    )
+                },
+            )
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "store_copy",
+        |caller: Caller<'_, MockHost>,
+         from_path: i32,
+         from_path_len: i32,
+         to_path: i32,
+         to_path_len: i32|
+         -> HostResult<i32> {
+            let Some(to_path) = read_memory(&caller, to_path, to_path_len)? else {
+                return Ok(MEMORY_INVALID_ACCESS);
+            };
+            with_input(
+                &caller,
+                from_path,
+                from_path_len,
+                |host, from_path| unsafe {
+                    host.store_copy(
+                        from_path.as_ptr(),
+                        from_path.len(),
+                        to_path.as_ptr(),
+                        to_path.len(),
+                    )
+                },
+            )
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "store_value_size",
+        |caller: Caller<'_, MockHost>, path: i32, path_len: i32| -> HostResult<i32> {
+            with_input(&caller, path, path_len, |host, path| unsafe {
+                host.store_value_size(path.as_ptr(), path.len())
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "reveal_preimage",
+        |mut caller: Caller<'_, MockHost>,
+         hash_addr: i32,
+         hash_len: i32,
+         dst: i32,
+         max_bytes: i32|
+         -> HostResult<i32> {
+            let mut buffer = output_buffer(&caller, dst, max_bytes)?;
+            let size = with_input(&caller, hash_addr, hash_len, |host, hash| unsafe {
+                host.reveal_preimage(
+                    hash.as_ptr(),
+                    hash.len(),
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                )
+            })?;
+            write_result(&mut caller, dst, &buffer, size)
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "reveal_metadata",
+        |mut caller: Caller<'_, MockHost>, dst: i32, max_bytes: i32| -> HostResult<i32> {
+            let mut buffer = output_buffer(&caller, dst, max_bytes)?;
+            let size = unsafe {
+                caller
+                    .data()
+                    .reveal_metadata(buffer.as_mut_ptr(), buffer.len())
+            };
+            write_result(&mut caller, dst, &buffer, size)
+        },
+    )?;
+
+    #[cfg(feature = "proto-alpha")]
+    linker.func_wrap(
+        HOST_MODULE,
+        "reveal",
+        |mut caller: Caller<'_, MockHost>,
+         payload_addr: i32,
+         payload_len: i32,
+         dst: i32,
+         max_bytes: i32|
+         -> HostResult<i32> {
+            let mut buffer = output_buffer(&caller, dst, max_bytes)?;
+            let size =
+                with_input(&caller, payload_addr, payload_len, |host, payload| unsafe {
+                    host.reveal(
+                        payload.as_ptr(),
+                        payload.len(),
+                        buffer.as_mut_ptr(),
+                        buffer.len(),
+                    )
+                })?;
+            write_result(&mut caller, dst, &buffer, size)
+        },
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Inbox files
+//!
+//! An inbox file contains a list of levels, each of which is a list of
+//! messages. It is parsed as YAML, hence JSON files are accepted too.
+//!
+//! ```yaml
+//! - - external: "01020304"
+//!   - transfer:
+//!       payload: "030b" # Unit
+//!       sender: KT1EfTusMLoeCAAGd9MZJn5yKzFr6kJU5U91
+//!       source: mv1Q4RbUk6tX1J43AGg4ScnisAEhLnpHNyux
+//! - - external: "0101"
+//! ```
+//!
+//! The `StartOfLevel`, `InfoPerLevel` and `EndOfLevel` messages are not part
+//! of the file, the [`MockHost`] adds them to each level.
+//!
+//! [`MockHost`]: mavryk_smart_rollup_mock::MockHost
+
+use crypto::hash::ContractKt1Hash;
+use mavryk_data_encoding::nom::NomReader;
+use mavryk_smart_rollup_encoding::{
+    inbox::{InboxMessage, InternalInboxMessage, Transfer},
+    michelson::{MichelsonExpr, MichelsonUnit},
+    public_key_hash::PublicKeyHash,
+    smart_rollup::SmartRollupAddress,
+};
+use serde::Deserialize;
+use std::path::Path;
+use thiserror::Error;
+
+/// Serialized messages of a level, excluding the messages added by the
+/// [`MockHost`].
+///
+/// [`MockHost`]: mavryk_smart_rollup_mock::MockHost
+pub type Level = Vec<Vec<u8>>;
+
+/// Errors when loading an inbox file
+#[derive(Debug, Error)]
+pub enum InboxError {
+    /// The file could not be read
+    #[error("Unable to read the inbox file: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The file is not a list of levels of messages
+    #[error("Invalid inbox file: {0}")]
+    Parse(#[from] serde_yaml::Error),
+
+    /// A message contains an invalid hex string
+    #[error("Invalid hex string {0:?}")]
+    Hex(String),
+
+    /// A transfer payload is not a binary Micheline expression
+    #[error("Invalid Micheline expression {0:?}")]
+    Micheline(String),
+
+    /// A transfer contains an invalid address
+    #[error("Invalid address {0:?}")]
+    Address(String),
+}
+
+/// Message of an inbox file
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FileMessage {
+    /// External message with hex-encoded contents
+    External { external: String },
+
+    /// Transfer from a smart contract
+    Transfer { transfer: FileTransfer },
+}
+
+/// Transfer of an inbox file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileTransfer {
+    /// Hex-encoded binary Micheline payload
+    payload: String,
+
+    /// Sending smart contract
+    sender: String,
+
+    /// Implicit account originating the transfer
+    source: String,
+
+    /// Destination rollup, defaults to the rollup being run
+    destination: Option<String>,
+}
+
+/// Decode a hex string found in an inbox file.
+fn decode_hex(data: &str) -> Result<Vec<u8>, InboxError> {
+    hex::decode(data).map_err(|_| InboxError::Hex(data.to_owned()))
+}
+
+/// Decode a hex-encoded binary Micheline expression found in an inbox file.
+fn decode_micheline(data: &str) -> Result<MichelsonExpr, InboxError> {
+    match MichelsonExpr::nom_read(&decode_hex(data)?) {
+        Ok(([], expr)) => Ok(expr),
+        _ => Err(InboxError::Micheline(data.to_owned())),
+    }
+}
+
+/// Serialize an external message.
+fn external(payload: &[u8]) -> Vec<u8> {
+    let message = InboxMessage::<MichelsonUnit>::External(payload);
+    let mut data = Vec::new();
+    message
+        .serialize(&mut data)
+        .expect("Failed to serialise external message");
+    data
+}
+
+/// Serialize a transfer.
+fn transfer(
+    sender: ContractKt1Hash,
+    source: PublicKeyHash,
+    destination: SmartRollupAddress,
+    payload: MichelsonExpr,
+) -> Vec<u8> {
+    let message = InboxMessage::Internal(InternalInboxMessage::Transfer(Transfer {
+        payload,
+        sender,
+        source,
+        destination,
+    }));
+    let mut data = Vec::new();
+    message
+        .serialize(&mut data)
+        .expect("Failed to serialise transfer");
+    data
+}
+
+/// Load the levels of the inbox file at `path`. Transfers without a
+/// destination are addressed to `rollup`.
+pub fn load_file(
+    path: impl AsRef<Path>,
+    rollup: &SmartRollupAddress,
+) -> Result<Vec<Level>, InboxError> {
+    let contents = std::fs::read(path)?;
+    let levels: Vec<Vec<FileMessage>> = serde_yaml::from_slice(&contents)?;
+
+    levels
+        .into_iter()
+        .map(|level| {
+            level
+                .into_iter()
+                .map(|message| match message {
+                    FileMessage::External { external: payload } => {
+                        Ok(external(&decode_hex(&payload)?))
+                    }
+
+                    FileMessage::Transfer { transfer: message } => {
+                        let destination = match message.destination {
+                            Some(destination) => {
+                                SmartRollupAddress::from_b58check(&destination)
+                                    .map_err(|_| InboxError::Address(destination))?
+                            }
+                            None => rollup.clone(),
+                        };
+                        let sender = ContractKt1Hash::from_base58_check(&message.sender)
+                            .map_err(|_| InboxError::Address(message.sender))?;
+                        let source = PublicKeyHash::from_b58check(&message.source)
+                            .map_err(|_| InboxError::Address(message.source))?;
+                        Ok(transfer(
+                            sender,
+                            source,
+                            destination,
+                            decode_micheline(&message.payload)?,
+                        ))
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
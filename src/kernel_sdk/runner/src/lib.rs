@@ -0,0 +1,413 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+#![doc = include_str!("../README.md")]
+#![deny(missing_docs)]
+#![deny(rustdoc::broken_intra_doc_links)]
+
+mod host_funcs;
+pub mod inbox;
+
+extern crate mavryk_crypto_rs as crypto;
+
+use mavryk_smart_rollup_host::runtime::{Runtime, RuntimeError};
+use mavryk_smart_rollup_host::KERNEL_BOOT_PATH;
+use mavryk_smart_rollup_mock::{MockHost, TickModel};
+use std::sync::Arc;
+use thiserror::Error;
+use wasmi::core::TrapCode;
+use wasmi::{Config, Engine, Linker, Module, StackLimits, Store};
+
+pub use mavryk_smart_rollup_mock::MAX_TICKS_PER_KERNEL_RUN;
+
+/// Name of the function kernels export as their entrypoint
+const KERNEL_ENTRYPOINT: &str = "kernel_run";
+
+/// Maximum depth of the call stack, as in the WASM PVM
+const MAX_RECURSION_DEPTH: usize = 60_000;
+
+/// Maximum height of the value stack. It accommodates the maximum call stack
+/// of functions with a few locals.
+const MAX_VALUE_STACK_HEIGHT: usize = 16 * 1024 * 1024;
+
+/// Errors when setting up a [`WasmRunner`]
+#[derive(Debug, Error)]
+pub enum RunnerError {
+    /// The kernel is not a valid WASM module
+    #[error("Invalid kernel: {0}")]
+    InvalidKernel(wasmi::Error),
+
+    /// Installing the kernel in the durable storage failed
+    #[error("Unable to install the kernel: {0}")]
+    Install(RuntimeError),
+}
+
+/// How a `kernel_run` call ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KernelRunOutcome {
+    /// The call returned.
+    Finished,
+
+    /// The call exceeded the tick limit and was interrupted. As in the WASM
+    /// PVM, its changes to the durable storage and outbox are discarded, and
+    /// the kernel is rebooted with `Runtime::last_run_aborted` raised.
+    TooManyTicks,
+
+    /// The kernel trapped. As in the WASM PVM, the changes of the call to the
+    /// durable storage and outbox are discarded, which the kernel can check
+    /// on its next call with `Runtime::last_run_aborted`.
+    Trapped(String),
+
+    /// The kernel in the durable storage could not be loaded, linked or
+    /// instantiated.
+    InvalidKernel(String),
+}
+
+/// Summary of a `kernel_run` call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelRun {
+    /// Ticks consumed by the call: the ticks of its host function calls, as
+    /// charged by the [`TickModel`] of the mock host, plus a tick per unit of
+    /// fuel consumed by its WASM instructions. The WASM PVM spends at least a
+    /// tick per instruction, hence this underestimates its ticks.
+    pub ticks: u64,
+
+    /// How the call ended
+    pub outcome: KernelRunOutcome,
+}
+
+/// Summary of a level
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelSummary {
+    /// Level the kernel was run at
+    pub level: u32,
+
+    /// The `kernel_run` calls of the level, one more than the number of
+    /// reboots
+    pub runs: Vec<KernelRun>,
+}
+
+/// Kernel loaded from the durable storage
+struct Kernel {
+    linker: Linker<MockHost>,
+    max_ticks: u64,
+
+    /// Code of the last loaded kernel, and its compiled module. Kernels are
+    /// only compiled again after an upgrade.
+    loaded: Option<(Vec<u8>, Arc<Module>)>,
+}
+
+impl Kernel {
+    /// Compile `code`, unless it was loaded already.
+    fn load(&mut self, code: Vec<u8>) -> Result<Arc<Module>, wasmi::Error> {
+        match &self.loaded {
+            Some((loaded, module)) if *loaded == code => Ok(module.clone()),
+            _ => {
+                let module =
+                    Arc::new(Module::new(self.linker.engine(), code.as_slice())?);
+                self.loaded = Some((code, module.clone()));
+                Ok(module)
+            }
+        }
+    }
+
+    /// Instantiate the kernel and call its entrypoint. Like the WASM PVM,
+    /// the module is instantiated again for every call: the memory of the
+    /// kernel does not survive reboots.
+    fn call(&mut self, store: &mut Store<MockHost>, module: &Module) -> KernelRunOutcome {
+        let result = self
+            .linker
+            .instantiate(&mut *store, module)
+            .and_then(|instance| instance.start(&mut *store))
+            .and_then(|instance| {
+                instance.get_typed_func::<(), ()>(&*store, KERNEL_ENTRYPOINT)
+            });
+        let entrypoint = match result {
+            Ok(entrypoint) => entrypoint,
+            Err(err) => return KernelRunOutcome::InvalidKernel(err.to_string()),
+        };
+
+        match entrypoint.call(&mut *store, ()) {
+            Ok(()) => KernelRunOutcome::Finished,
+            Err(trap) if matches!(trap.trap_code(), Some(TrapCode::OutOfFuel)) => {
+                KernelRunOutcome::TooManyTicks
+            }
+            Err(trap) => KernelRunOutcome::Trapped(trap.to_string()),
+        }
+    }
+
+    /// Charge the host functions called by the kernel to its tick budget. The
+    /// WASM instructions are charged separately, as fuel.
+    fn set_tick_model(&self, host: &mut MockHost) {
+        host.set_tick_model(TickModel {
+            max_ticks: self.max_ticks,
+            ticks_per_step: 0,
+        });
+    }
+
+    /// Run the kernel installed in the durable storage of `host` once.
+    fn run(&mut self, host: &mut MockHost) -> KernelRun {
+        let module = match host.store_read_all(&KERNEL_BOOT_PATH) {
+            Ok(code) => self.load(code).map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        let module = match module {
+            Ok(module) => module,
+            Err(err) => {
+                return KernelRun {
+                    ticks: 0,
+                    outcome: KernelRunOutcome::InvalidKernel(err),
+                }
+            }
+        };
+
+        // The host state is owned by the store for the duration of the call.
+        // Ticks of host function calls are not charged as fuel, they only
+        // count once the call returns.
+        let fuel = self.max_ticks.saturating_add(1);
+        let mut store = Store::new(self.linker.engine(), std::mem::take(host));
+        store.add_fuel(fuel).expect("Fuel metering is enabled");
+
+        let outcome = self.call(&mut store, &module);
+        let fuel_consumed = match outcome {
+            // The remaining fuel may be too little for the next instruction.
+            KernelRunOutcome::TooManyTicks => fuel,
+            _ => store.fuel_consumed().unwrap_or_default(),
+        };
+        *host = store.into_data();
+
+        // The mock host discards the call once it returns if it ran out of
+        // ticks or trapped.
+        host.charge_ticks(fuel_consumed);
+        let ticks = host.ticks_consumed();
+        let outcome = match outcome {
+            KernelRunOutcome::Trapped(_) => {
+                host.abort_run();
+                outcome
+            }
+            _ if ticks > self.max_ticks => KernelRunOutcome::TooManyTicks,
+            _ => outcome,
+        };
+
+        KernelRun { ticks, outcome }
+    }
+}
+
+/// Runs a WASM kernel level by level against a [`MockHost`]
+pub struct WasmRunner {
+    host: MockHost,
+    kernel: Kernel,
+}
+
+impl WasmRunner {
+    /// Install `kernel`, a WASM module, in the durable storage of `host`. The
+    /// tick model of `host` is replaced by the limits of the WASM PVM.
+    pub fn new(mut host: MockHost, kernel: &[u8]) -> Result<Self, RunnerError> {
+        let mut config = Config::default();
+        config.consume_fuel(true).floats(false).set_stack_limits(
+            StackLimits::new(1024, MAX_VALUE_STACK_HEIGHT, MAX_RECURSION_DEPTH)
+                .expect("Valid stack limits"),
+        );
+
+        let engine = Engine::new(&config);
+        let mut linker = Linker::new(&engine);
+        host_funcs::define(&mut linker).expect("Host functions are defined once");
+
+        let mut kernel_state = Kernel {
+            linker,
+            max_ticks: MAX_TICKS_PER_KERNEL_RUN,
+            loaded: None,
+        };
+        kernel_state.set_tick_model(&mut host);
+        kernel_state
+            .load(kernel.to_vec())
+            .map_err(RunnerError::InvalidKernel)?;
+
+        host.store_write_all(&KERNEL_BOOT_PATH, kernel)
+            .map_err(RunnerError::Install)?;
+
+        Ok(Self {
+            host,
+            kernel: kernel_state,
+        })
+    }
+
+    /// Limit `kernel_run` calls to `max_ticks` ticks instead of
+    /// [`MAX_TICKS_PER_KERNEL_RUN`].
+    pub fn with_max_ticks(mut self, max_ticks: u64) -> Self {
+        self.kernel.max_ticks = max_ticks;
+        self.kernel.set_tick_model(&mut self.host);
+        self
+    }
+
+    /// The host the kernel runs against
+    pub fn host(&self) -> &MockHost {
+        &self.host
+    }
+
+    /// The host the kernel runs against, e.g. to add messages to the inbox
+    /// of the next level.
+    pub fn host_mut(&mut self) -> &mut MockHost {
+        &mut self.host
+    }
+
+    /// Stop running the kernel and return the host.
+    pub fn into_host(self) -> MockHost {
+        self.host
+    }
+
+    /// Run the kernel against the current level's inbox, rebooting it as
+    /// requested up to the limit of the WASM PVM.
+    pub fn run_level(&mut self) -> LevelSummary {
+        let kernel = &mut self.kernel;
+        let mut runs = Vec::new();
+        let level = self.host.run_level(|host| runs.push(kernel.run(host)));
+        LevelSummary { level, runs }
+    }
+
+    /// Run the kernel for each of `levels`, after adding their messages to
+    /// the inbox.
+    pub fn run_inbox(&mut self, levels: Vec<inbox::Level>) -> Vec<LevelSummary> {
+        levels
+            .into_iter()
+            .map(|level| {
+                for message in level {
+                    self.host.add_raw_message(message);
+                }
+                self.run_level()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KernelRunOutcome, WasmRunner};
+    use mavryk_smart_rollup_host::runtime::Runtime;
+    use mavryk_smart_rollup_mock::MockHost;
+
+    // (module
+    //   (import "smart_rollup_core" "read_input"
+    //     (func (param i32 i32 i32) (result i32)))
+    //   (import "smart_rollup_core" "write_output"
+    //     (func (param i32 i32) (result i32)))
+    //   (memory (export "memory") 1)
+    //   (func (export "kernel_run") (local i32)
+    //     (block
+    //       (loop
+    //         (local.set 0 (call 0 (i32.const 0) (i32.const 8) (i32.const 4096)))
+    //         (br_if 1 (i32.le_s (local.get 0) (i32.const 0)))
+    //         (drop (call 1 (i32.const 8) (local.get 0)))
+    //         (br 0)))))
+    const ECHO_KERNEL: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x11, 0x03, 0x60, 0x03,
+        0x7f, 0x7f, 0x7f, 0x01, 0x7f, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, 0x60, 0x00,
+        0x00, 0x02, 0x41, 0x02, 0x11, 0x73, 0x6d, 0x61, 0x72, 0x74, 0x5f, 0x72, 0x6f,
+        0x6c, 0x6c, 0x75, 0x70, 0x5f, 0x63, 0x6f, 0x72, 0x65, 0x0a, 0x72, 0x65, 0x61,
+        0x64, 0x5f, 0x69, 0x6e, 0x70, 0x75, 0x74, 0x00, 0x00, 0x11, 0x73, 0x6d, 0x61,
+        0x72, 0x74, 0x5f, 0x72, 0x6f, 0x6c, 0x6c, 0x75, 0x70, 0x5f, 0x63, 0x6f, 0x72,
+        0x65, 0x0c, 0x77, 0x72, 0x69, 0x74, 0x65, 0x5f, 0x6f, 0x75, 0x74, 0x70, 0x75,
+        0x74, 0x00, 0x01, 0x03, 0x02, 0x01, 0x02, 0x05, 0x03, 0x01, 0x00, 0x01, 0x07,
+        0x17, 0x02, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00, 0x0a, 0x6b,
+        0x65, 0x72, 0x6e, 0x65, 0x6c, 0x5f, 0x72, 0x75, 0x6e, 0x00, 0x02, 0x0a, 0x27,
+        0x01, 0x25, 0x01, 0x01, 0x7f, 0x02, 0x40, 0x03, 0x40, 0x41, 0x00, 0x41, 0x08,
+        0x41, 0x80, 0x20, 0x10, 0x00, 0x21, 0x00, 0x20, 0x00, 0x41, 0x00, 0x4c, 0x0d,
+        0x01, 0x41, 0x08, 0x20, 0x00, 0x10, 0x01, 0x1a, 0x0c, 0x00, 0x0b, 0x0b, 0x0b,
+    ];
+
+    // (module
+    //   (memory (export "memory") 1)
+    //   (func (export "kernel_run") (loop (br 0))))
+    const LOOP_KERNEL: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00,
+        0x00, 0x03, 0x02, 0x01, 0x00, 0x05, 0x03, 0x01, 0x00, 0x01, 0x07, 0x17, 0x02,
+        0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00, 0x0a, 0x6b, 0x65, 0x72,
+        0x6e, 0x65, 0x6c, 0x5f, 0x72, 0x75, 0x6e, 0x00, 0x00, 0x0a, 0x09, 0x01, 0x07,
+        0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b,
+    ];
+
+    // (module
+    //   (import "smart_rollup_core" "write_output"
+    //     (func (param i32 i32) (result i32)))
+    //   (memory (export "memory") 1)
+    //   (func (export "kernel_run")
+    //     (drop (call 0 (i32.const 0) (i32.const 4)))
+    //     unreachable))
+    const TRAP_KERNEL: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x0a, 0x02, 0x60, 0x02,
+        0x7f, 0x7f, 0x01, 0x7f, 0x60, 0x00, 0x00, 0x02, 0x22, 0x01, 0x11, 0x73, 0x6d,
+        0x61, 0x72, 0x74, 0x5f, 0x72, 0x6f, 0x6c, 0x6c, 0x75, 0x70, 0x5f, 0x63, 0x6f,
+        0x72, 0x65, 0x0c, 0x77, 0x72, 0x69, 0x74, 0x65, 0x5f, 0x6f, 0x75, 0x74, 0x70,
+        0x75, 0x74, 0x00, 0x00, 0x03, 0x02, 0x01, 0x01, 0x05, 0x03, 0x01, 0x00, 0x01,
+        0x07, 0x17, 0x02, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00, 0x0a,
+        0x6b, 0x65, 0x72, 0x6e, 0x65, 0x6c, 0x5f, 0x72, 0x75, 0x6e, 0x00, 0x01, 0x0a,
+        0x0c, 0x01, 0x0a, 0x00, 0x41, 0x00, 0x41, 0x04, 0x10, 0x00, 0x1a, 0x00, 0x0b,
+    ];
+
+    #[test]
+    fn test_echo_kernel() {
+        // Arrange
+        let mut runner = WasmRunner::new(MockHost::default(), ECHO_KERNEL).unwrap();
+        let message = vec![1, 2, 3, 4];
+        runner.host_mut().add_raw_message(message.clone());
+
+        // Act
+        let summary = runner.run_level();
+
+        // Assert
+        assert_eq!(summary.runs.len(), 1);
+        assert_eq!(summary.runs[0].outcome, KernelRunOutcome::Finished);
+        assert!(summary.runs[0].ticks > 0);
+
+        // StartOfLevel, InfoPerLevel, the message and EndOfLevel are echoed.
+        let outbox = runner.host().outbox_at(summary.level);
+        assert_eq!(outbox.len(), 4);
+        assert_eq!(outbox[2], message);
+    }
+
+    #[test]
+    fn test_too_many_ticks_reboots() {
+        // Arrange
+        let mut runner = WasmRunner::new(MockHost::default(), LOOP_KERNEL)
+            .unwrap()
+            .with_max_ticks(1000);
+
+        // Act
+        let summary = runner.run_level();
+
+        // Assert
+        assert_eq!(summary.runs.len(), 1000);
+        assert!(summary.runs.iter().all(|run| {
+            run.outcome == KernelRunOutcome::TooManyTicks && run.ticks > 1000
+        }));
+        assert!(runner.host().last_run_aborted().unwrap());
+    }
+
+    #[test]
+    fn test_trap_aborts_run() {
+        // Arrange
+        let mut runner = WasmRunner::new(MockHost::default(), TRAP_KERNEL).unwrap();
+        assert!(!runner.host().last_run_aborted().unwrap());
+
+        // Act
+        let summary = runner.run_level();
+
+        // Assert
+        assert_eq!(summary.runs.len(), 1);
+        assert!(matches!(
+            summary.runs[0].outcome,
+            KernelRunOutcome::Trapped(_)
+        ));
+        assert!(runner.host().last_run_aborted().unwrap());
+
+        // The output written before the trap was discarded.
+        assert!(runner.host().outbox_at(summary.level).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_kernel() {
+        let result = WasmRunner::new(MockHost::default(), b"not a kernel");
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,101 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+use clap::Parser;
+use mavryk_smart_rollup_encoding::smart_rollup::SmartRollupAddress;
+use mavryk_smart_rollup_host::runtime::Runtime;
+use mavryk_smart_rollup_mock::MockHost;
+use mavryk_smart_rollup_runner::inbox::{self, InboxError};
+use mavryk_smart_rollup_runner::{
+    KernelRunOutcome, LevelSummary, RunnerError, WasmRunner, MAX_TICKS_PER_KERNEL_RUN,
+};
+use std::ffi::OsString;
+use thiserror::Error;
+
+#[derive(Parser)]
+#[command(long_about = None)]
+struct Cli {
+    /// WASM kernel to run
+    #[arg(short, long, value_name = "KERNEL_WASM")]
+    kernel: OsString,
+
+    /// Inbox file with the messages of each level to run. Without it, a
+    /// single level with an empty inbox is run.
+    #[arg(short, long, value_name = "INBOX_FILE")]
+    inputs: Option<OsString>,
+
+    /// Address of the rollup
+    #[arg(short, long, value_name = "ROLLUP_ADDRESS")]
+    address: Option<String>,
+
+    /// Maximum number of ticks of a `kernel_run` call
+    #[arg(long, value_name = "TICKS", default_value_t = MAX_TICKS_PER_KERNEL_RUN)]
+    max_ticks: u64,
+}
+
+#[derive(Debug, Error)]
+enum ClientError {
+    #[error("Unable to read the kernel: {0}")]
+    ReadKernel(std::io::Error),
+    #[error("Invalid rollup address {0:?}")]
+    Address(String),
+    #[error(transparent)]
+    Inbox(#[from] InboxError),
+    #[error(transparent)]
+    Runner(#[from] RunnerError),
+}
+
+/// Print the `kernel_run` calls of a level and the size of its outbox.
+fn print_summary(summary: &LevelSummary, outbox_size: usize) {
+    let ticks: u64 = summary.runs.iter().map(|run| run.ticks).sum();
+    println!(
+        "Level {}: {} kernel_run calls, {} ticks, {} outbox messages",
+        summary.level,
+        summary.runs.len(),
+        ticks,
+        outbox_size
+    );
+
+    for (index, run) in summary.runs.iter().enumerate() {
+        match &run.outcome {
+            KernelRunOutcome::Finished => {}
+            KernelRunOutcome::TooManyTicks => {
+                println!("  Call {index}: too many ticks, forced reboot")
+            }
+            KernelRunOutcome::Trapped(reason) => {
+                println!("  Call {index}: trapped: {reason}")
+            }
+            KernelRunOutcome::InvalidKernel(reason) => {
+                println!("  Call {index}: invalid kernel: {reason}")
+            }
+        }
+    }
+}
+
+fn main() -> Result<(), ClientError> {
+    let cli = Cli::parse();
+
+    let host = match cli.address {
+        Some(address) => match SmartRollupAddress::from_b58check(&address) {
+            Ok(address) => MockHost::with_address(&address),
+            Err(_) => return Err(ClientError::Address(address)),
+        },
+        None => MockHost::default(),
+    };
+    let rollup = SmartRollupAddress::new(host.reveal_metadata().address());
+
+    let levels = match &cli.inputs {
+        Some(path) => inbox::load_file(path, &rollup)?,
+        None => vec![vec![]],
+    };
+
+    let kernel = std::fs::read(&cli.kernel).map_err(ClientError::ReadKernel)?;
+    let mut runner = WasmRunner::new(host, &kernel)?.with_max_ticks(cli.max_ticks);
+
+    for summary in runner.run_inbox(levels) {
+        print_summary(&summary, runner.host().outbox_at(summary.level).len());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Benchmarks of the interpreter
+//!
+//! The hot loop benchmarks run without any setup. They compare fetching
+//! instructions through the instruction cache with decoding them from memory,
+//! which is what every step did before the cache existed.
+//!
+//! The kernel benchmarks need the kernels to be built first (try: make -C
+//! src/risc_v build). To measure the effect of a change on them, save a
+//! baseline before the change using `cargo bench -- --save-baseline before`
+//! and compare against it afterwards using `cargo bench -- --baseline before`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use risc_v_interpreter::{
+    machine_state::{
+        bus::{
+            main_memory::{MainMemoryLayout, M1K},
+            start_of_main_memory, Address, Addressable,
+        },
+        mode::Mode,
+        MachineState, MachineStateLayout,
+    },
+    parser::parse,
+    state_backend::{memory_backend::InMemoryBackend, Backend},
+    Interpreter,
+};
+use std::{fs, hint::black_box};
+
+const DUMMY_KERNEL: &str = "../risc-v-dummy.elf";
+const JSMV_KERNEL: &str = "../jsmv/target/riscv64gc-unknown-hermit/release/jsmv";
+const MAX_STEPS: usize = 1_000_000;
+
+/// Straight-line code: ADDI t0, t0, 1; XOR t1, t1, t0; SLLI t2, t1, 3;
+/// ADD t0, t0, t2
+const HOT_LOOP_BODY: [u32; 4] = [0x00128293, 0x00534333, 0x00331393, 0x007282B3];
+
+/// Number of instructions in the hot loop
+const HOT_LOOP_LEN: usize = M1K::BYTES / 4;
+
+fn bench_hot_loop(c: &mut Criterion) {
+    type L = MachineStateLayout<M1K>;
+
+    let (mut backend, placed) = InMemoryBackend::<L>::new();
+    let mut state = MachineState::<M1K, _>::bind(backend.allocate(placed));
+    state.reset();
+
+    let start = start_of_main_memory::<M1K>();
+    let program = HOT_LOOP_BODY.repeat(HOT_LOOP_LEN / HOT_LOOP_BODY.len());
+    state.bus.write_all(start, &program).unwrap();
+    let addresses = (0..HOT_LOOP_LEN as Address)
+        .map(|i| start + 4 * i)
+        .collect::<Vec<_>>();
+
+    c.bench_function("hot loop: decode from memory", |b| {
+        b.iter(|| {
+            for &addr in addresses.iter() {
+                let bus = &state.bus;
+                black_box(parse(bus.read(addr).unwrap(), || bus.read(addr + 2)).unwrap());
+            }
+        })
+    });
+
+    c.bench_function("hot loop: fetch from instruction cache", |b| {
+        b.iter(|| {
+            for &addr in addresses.iter() {
+                black_box(state.bus.read_instr(addr).unwrap());
+            }
+        })
+    });
+
+    c.bench_function("hot loop: step", |b| {
+        b.iter(|| {
+            state.hart.pc.write(start);
+            for _ in 0..HOT_LOOP_LEN {
+                state.step().unwrap();
+            }
+        })
+    });
+}
+
+fn bench_kernel(c: &mut Criterion, name: &str, path: &str) {
+    let Ok(contents) = fs::read(path) else {
+        eprintln!("Skipping {name}: failed to read {path} (try: make -C src/risc_v build)");
+        return;
+    };
+
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            let mut backend = Interpreter::create_backend();
+            let mut interpreter =
+                Interpreter::new(&mut backend, &contents, None, Mode::User).expect("Boot failed");
+            black_box(interpreter.run(MAX_STEPS));
+        })
+    });
+}
+
+fn bench_kernels(c: &mut Criterion) {
+    bench_kernel(c, "dummy kernel", DUMMY_KERNEL);
+    bench_kernel(c, "jsmv", JSMV_KERNEL);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench_hot_loop, bench_kernels
+}
+criterion_main!(benches);
@@ -6,7 +6,15 @@
 //!   - https://elinux.org/Device_Tree_Usage
 //!   - https://github.com/devicetree-org/devicetree-specification/releases/tag/v0.4
 
-use crate::machine_state::bus::{self, main_memory::MainMemoryLayout};
+use crate::machine_state::bus::{
+    self,
+    devices::{
+        clint::{CLINT_LENGTH, CLINT_START},
+        uart::{UART_CLOCK_FREQUENCY, UART_LENGTH, UART_START},
+        DEVICES_ADDRESS_SPACE_LENGTH,
+    },
+    main_memory::MainMemoryLayout,
+};
 use vm_fdt::FdtWriter;
 
 /// Information about the initial ramdisk.
@@ -18,6 +26,9 @@ pub struct InitialRamDisk {
     pub length: u64,
 }
 
+/// Phandle of the interrupt controller of the hart
+const CPU_INTC_PHANDLE: u32 = 0x2;
+
 /// Create a new node scope in the device tree.
 macro_rules! node {
     ( $i:ident, $name:expr, $inner:block ) => {
@@ -70,6 +81,9 @@ pub fn generate_custom(
 
         // /chosen
         node!(fdt, "chosen", {
+            // Early console output goes to the UART.
+            fdt.property_string("stdout-path", &format!("/soc/serial@{UART_START:x}"))?;
+
             // HermitOS loader wants an initial ramdisk.
             if let Some(initrd) = initrd {
                 // End pointer is exclusive (i.e. after the initrd).
@@ -101,6 +115,42 @@ pub fn generate_custom(
                 fdt.property_u32("reg", 0x0)?;
                 fdt.property_string("status", "okay")?;
                 fdt.property_string("compatible", "riscv")?;
+
+                // /cpus/cpu@0/interrupt-controller
+                node!(fdt, "interrupt-controller", {
+                    fdt.property_phandle(CPU_INTC_PHANDLE)?;
+                    fdt.property_u32("#interrupt-cells", 1)?;
+                    fdt.property_null("interrupt-controller")?;
+                    fdt.property_string("compatible", "riscv,cpu-intc")?;
+                });
+            });
+        });
+
+        // /soc
+        node!(fdt, "soc", {
+            fdt.property_u32("#address-cells", 2)?;
+            fdt.property_u32("#size-cells", 2)?;
+            fdt.property_string("compatible", "simple-bus")?;
+            fdt.property_array_u64("ranges", &[0, 0, DEVICES_ADDRESS_SPACE_LENGTH])?;
+
+            // /soc/clint@?
+            node!(fdt, "clint", CLINT_START, {
+                fdt.property_string_list(
+                    "compatible",
+                    vec!["sifive,clint0".to_owned(), "riscv,clint0".to_owned()],
+                )?;
+                fdt.property_array_u64("reg", &[CLINT_START, CLINT_LENGTH])?;
+                fdt.property_array_u32(
+                    "interrupts-extended",
+                    &[CPU_INTC_PHANDLE, 3, CPU_INTC_PHANDLE, 7],
+                )?;
+            });
+
+            // /soc/serial@?
+            node!(fdt, "serial", UART_START, {
+                fdt.property_string("compatible", "ns16550a")?;
+                fdt.property_array_u64("reg", &[UART_START, UART_LENGTH])?;
+                fdt.property_u32("clock-frequency", UART_CLOCK_FREQUENCY)?;
             });
         });
     });
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: MIT
 
 pub mod posix;
+pub mod sbi;
 
 use self::posix::PosixState;
 use crate::{
@@ -5,20 +5,125 @@
 use super::{EcallOutcome, ExecutionEnvironment, ExecutionEnvironmentState};
 use crate::{
     machine_state::{
-        bus::main_memory::MainMemoryLayout,
+        bus::{self, main_memory::MainMemoryLayout, Address, Addressable},
         mode::{Mode, ModeCell, ModeLayout},
-        registers::{a0, a7},
-        MachineState,
+        registers::{a0, a1, a2, a3, a7, sp},
+        AccessType, MachineError, MachineState,
     },
+    program::Program,
     state_backend::{AllocatedOf, Atom, Cell, Manager},
     traps::EnvironException,
 };
 
+/// Linux system call numbers of the RISC-V ABI
+mod syscall {
+    pub const IOCTL: u64 = 29;
+    pub const WRITE: u64 = 64;
+    pub const WRITEV: u64 = 66;
+    pub const PPOLL: u64 = 73;
+    pub const EXIT: u64 = 93;
+    pub const EXIT_GROUP: u64 = 94;
+    pub const SET_TID_ADDRESS: u64 = 96;
+    pub const CLOCK_GETTIME: u64 = 113;
+    pub const SIGALTSTACK: u64 = 132;
+    pub const RT_SIGACTION: u64 = 134;
+    pub const RT_SIGPROCMASK: u64 = 135;
+    pub const BRK: u64 = 214;
+    pub const MUNMAP: u64 = 215;
+    pub const MMAP: u64 = 222;
+    pub const MPROTECT: u64 = 226;
+    pub const GETRANDOM: u64 = 278;
+}
+
+/// Linux error numbers
+mod errno {
+    pub const EBADF: u64 = 9;
+    pub const ENOMEM: u64 = 12;
+    pub const EFAULT: u64 = 14;
+    pub const EINVAL: u64 = 22;
+    pub const ENOTTY: u64 = 25;
+}
+
+/// Types of the entries of the auxiliary vector passed on the initial stack
+mod auxv {
+    pub const AT_NULL: u64 = 0;
+    pub const AT_PHDR: u64 = 3;
+    pub const AT_PHENT: u64 = 4;
+    pub const AT_PHNUM: u64 = 5;
+    pub const AT_PAGESZ: u64 = 6;
+    pub const AT_ENTRY: u64 = 9;
+    pub const AT_RANDOM: u64 = 25;
+}
+
+/// Flags of the `mmap` system call
+mod mmap_flags {
+    pub const MAP_PRIVATE: u64 = 0x02;
+    pub const MAP_FIXED: u64 = 0x10;
+    pub const MAP_ANONYMOUS: u64 = 0x20;
+}
+
+/// Page size used for `brk` and `mmap`
+const PAGE_SIZE: u64 = 4096;
+
+/// Memory at the end of main memory which `mmap` leaves to the stack
+const STACK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Nanoseconds by which the clock advances each time it is read
+const CLOCK_TICK_NS: u64 = 1000;
+
+/// Number of supported clocks, e.g. `CLOCK_REALTIME` or `CLOCK_MONOTONIC`
+const CLOCK_COUNT: u64 = 8;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Process and thread identifier of the program
+const PID: u64 = 1;
+
+/// Size of an ELF64 program header
+const PROGRAM_HEADER_SIZE: u64 = 56;
+
+/// Name of the program in its argument vector
+const PROGRAM_NAME: &[u8] = b"program\0";
+
+/// Highest signal number
+const SIGNAL_MAX: u64 = 64;
+
+/// Size of a signal set, the only size accepted for `sigsetsize` arguments
+const SIGSET_SIZE: u64 = 8;
+
+/// Size of `struct sigaction` as seen by the kernel
+const SIGACTION_SIZE: u64 = 24;
+
+/// Value of `ss_flags` for a disabled alternate signal stack
+const SS_DISABLE: u32 = 2;
+
+/// Maximum number of buffers passed to `writev`
+const IOV_MAX: u64 = 1024;
+
+/// Maximum number of file descriptors passed to `ppoll`
+const POLL_MAX: u64 = 1024;
+
+/// `revents` of a file descriptor passed to `ppoll` which isn't open
+const POLLNVAL: u16 = 0x20;
+
+/// Maximum number of bytes returned by a single `getrandom` call
+const GETRANDOM_MAX: u64 = 0x1ff_ffff;
+
+/// Number of random bytes generated and written to guest memory at once
+const RANDOM_CHUNK_SIZE: usize = 256;
+
+/// Result of a system call, either its return value or an error number
+type SyscallResult = Result<u64, u64>;
+
 /// Posix execution environment
 pub enum Posix {}
 
+/// Layout of the system call state: start of the heap, program break, lowest
+/// address mapped by `mmap`, clock and random number generator state
+pub type SyscallLayout = (Atom<u64>, Atom<u64>, Atom<u64>, Atom<u64>, Atom<u64>);
+
 impl ExecutionEnvironment for Posix {
-    type Layout = (Atom<u64>, Atom<u8>, ModeLayout);
+    type Layout = (Atom<u64>, Atom<u8>, ModeLayout, SyscallLayout);
 
     type State<M: Manager> = PosixState<M>;
 }
@@ -28,6 +133,15 @@ pub struct PosixState<M: Manager> {
     code: Cell<u64, M>,
     exited: Cell<u8, M>,
     exit_mode: ModeCell<M>,
+    heap_start: Cell<u64, M>,
+    program_break: Cell<u64, M>,
+    mmap_bottom: Cell<u64, M>,
+    clock: Cell<u64, M>,
+    random: Cell<u64, M>,
+
+    // Output isn't part of the state. It is only collected for inspection.
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
 }
 
 impl<M: Manager> PosixState<M> {
@@ -46,6 +160,488 @@ impl<M: Manager> PosixState<M> {
     pub fn set_exit_mode(&mut self, mode: Mode) {
         self.exit_mode.write(mode);
     }
+
+    /// Configures the start of the heap managed by the `brk` system call.
+    /// The address is rounded up to the next page boundary.
+    pub fn set_program_break(&mut self, addr: Address) {
+        let addr = round_up_to_page(addr);
+        self.heap_start.write(addr);
+        self.program_break.write(addr);
+    }
+
+    /// Lay out the initial stack of a Linux process for [program] at the end
+    /// of main memory and point `sp` at it. The stack holds the argument count,
+    /// the argument vector naming the program, an empty environment and the
+    /// auxiliary vector.
+    pub fn setup_process_stack<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &mut MachineState<ML, M>,
+        program: &Program<ML>,
+    ) -> Result<(), MachineError> {
+        use auxv::*;
+
+        let memory_end = bus::start_of_main_memory::<ML>() + ML::BYTES as u64;
+        let name_addr = memory_end - 16;
+        let random_addr = name_addr - 16;
+
+        let mut random = [0u8; 16];
+        random[..8].copy_from_slice(&self.next_random().to_le_bytes());
+        random[8..].copy_from_slice(&self.next_random().to_le_bytes());
+        machine.bus.write_all(name_addr, PROGRAM_NAME)?;
+        machine.bus.write_all(random_addr, &random)?;
+
+        // argc, argv and envp
+        let mut words = vec![1, name_addr, 0, 0];
+        if let Some(headers) = program.program_headers {
+            words.extend([
+                AT_PHDR,
+                headers.addr,
+                AT_PHENT,
+                PROGRAM_HEADER_SIZE,
+                AT_PHNUM,
+                headers.count,
+            ]);
+        }
+        words.extend([
+            AT_PAGESZ,
+            PAGE_SIZE,
+            AT_ENTRY,
+            program.entrypoint,
+            AT_RANDOM,
+            random_addr,
+            AT_NULL,
+            0,
+        ]);
+
+        // The stack pointer must be 16-byte aligned.
+        let stack_pointer = (random_addr - 8 * words.len() as u64) & !0xf;
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        machine.bus.write_all(stack_pointer, &bytes)?;
+        machine.hart.xregisters.write(sp, stack_pointer);
+
+        Ok(())
+    }
+
+    /// Bytes written to the standard output
+    pub fn stdout(&self) -> &[u8] {
+        &self.stdout
+    }
+
+    /// Bytes written to the standard error
+    pub fn stderr(&self) -> &[u8] {
+        &self.stderr
+    }
+
+    /// Handle a Linux system call. The system call number is in `a7` and its
+    /// arguments are in `a0` to `a5`. Returns `None` if the system call is not
+    /// implemented.
+    fn handle_syscall<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &mut MachineState<ML, M>,
+        number: u64,
+    ) -> Option<SyscallResult> {
+        let [arg0, arg1, arg2, arg3] =
+            [a0, a1, a2, a3].map(|reg| machine.hart.xregisters.read(reg));
+
+        let result = match number {
+            syscall::IOCTL => sys_ioctl(arg0),
+            syscall::WRITE => self.sys_write(machine, arg0, arg1, arg2),
+            syscall::WRITEV => self.sys_writev(machine, arg0, arg1, arg2),
+            syscall::PPOLL => sys_ppoll(machine, arg0, arg1),
+            syscall::SET_TID_ADDRESS => Ok(PID),
+            syscall::CLOCK_GETTIME => self.sys_clock_gettime(machine, arg0, arg1),
+            syscall::SIGALTSTACK => sys_sigaltstack(machine, arg1),
+            syscall::RT_SIGACTION => sys_rt_sigaction(machine, arg0, arg2, arg3),
+            syscall::RT_SIGPROCMASK => sys_rt_sigprocmask(machine, arg2, arg3),
+            syscall::BRK => self.sys_brk(machine, arg0),
+            syscall::MUNMAP => self.sys_munmap(machine, arg0, arg1),
+            syscall::MMAP => self.sys_mmap(machine, arg1, arg3),
+            syscall::MPROTECT => sys_mprotect(arg0),
+            syscall::GETRANDOM => self.sys_getrandom(machine, arg0, arg1),
+            _ => return None,
+        };
+
+        Some(result)
+    }
+
+    /// `write(fd, buf, count)`, only for the standard output and error
+    fn sys_write<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &MachineState<ML, M>,
+        fd: u64,
+        buf: Address,
+        count: u64,
+    ) -> SyscallResult {
+        let output = match fd {
+            1 => &mut self.stdout,
+            2 => &mut self.stderr,
+            _ => return Err(errno::EBADF),
+        };
+
+        output.extend(
+            machine
+                .read_guest_bytes(buf, count)
+                .map_err(|_| errno::EFAULT)?,
+        );
+        Ok(count)
+    }
+
+    /// `writev(fd, iov, iovcnt)`, only for the standard output and error
+    fn sys_writev<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &MachineState<ML, M>,
+        fd: u64,
+        iov: Address,
+        iovcnt: u64,
+    ) -> SyscallResult {
+        if iovcnt > IOV_MAX {
+            return Err(errno::EINVAL);
+        }
+
+        let mut written = 0u64;
+        for i in 0..iovcnt {
+            // Each `struct iovec` holds the address and length of a buffer.
+            let entry = iov.wrapping_add(16 * i);
+            let base = read_guest_u64(machine, entry)?;
+            let len = read_guest_u64(machine, entry.wrapping_add(8))?;
+            written = written.saturating_add(self.sys_write(machine, fd, base, len)?);
+        }
+
+        Ok(written)
+    }
+
+    /// `clock_gettime(clockid, tp)`
+    ///
+    /// All clocks read the same deterministic time which starts at zero and
+    /// advances by [`CLOCK_TICK_NS`] with every read.
+    fn sys_clock_gettime<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &mut MachineState<ML, M>,
+        clock_id: u64,
+        tp: Address,
+    ) -> SyscallResult {
+        if clock_id >= CLOCK_COUNT {
+            return Err(errno::EINVAL);
+        }
+
+        let now = self.clock.read().saturating_add(CLOCK_TICK_NS);
+        self.clock.write(now);
+
+        let mut timespec = [0u8; 16];
+        timespec[..8].copy_from_slice(&(now / NANOS_PER_SEC).to_le_bytes());
+        timespec[8..].copy_from_slice(&(now % NANOS_PER_SEC).to_le_bytes());
+        machine
+            .write_guest_bytes(tp, &timespec)
+            .map_err(|_| errno::EFAULT)?;
+
+        Ok(0)
+    }
+
+    /// `brk(addr)`
+    ///
+    /// The heap lies between the start configured using
+    /// [`PosixState::set_program_break`] and the memory mapped by `mmap`.
+    /// Requests outside these bounds leave the program break unchanged.
+    fn sys_brk<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &mut MachineState<ML, M>,
+        addr: Address,
+    ) -> SyscallResult {
+        let current = self.program_break.read();
+        let heap_start = self.heap_start.read();
+
+        // Without a configured heap, `brk` always fails.
+        if heap_start == 0 || addr < heap_start || addr > self.mmap_bottom::<ML>() {
+            return Ok(current);
+        }
+
+        // Memory handed out by the heap must be zeroed.
+        if addr > current {
+            zero_guest_memory(machine, current, addr - current)?;
+        }
+
+        self.program_break.write(addr);
+        Ok(addr)
+    }
+
+    /// `mmap(addr, length, prot, flags, fd, offset)`, only for private
+    /// anonymous mappings
+    ///
+    /// Mappings are placed top-down below the stack. The address hint and
+    /// protection are ignored.
+    fn sys_mmap<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &mut MachineState<ML, M>,
+        length: u64,
+        flags: u64,
+    ) -> SyscallResult {
+        use mmap_flags::*;
+
+        if flags & MAP_ANONYMOUS == 0 || flags & MAP_PRIVATE == 0 || flags & MAP_FIXED != 0 {
+            return Err(errno::EINVAL);
+        }
+
+        if length == 0 {
+            return Err(errno::EINVAL);
+        }
+
+        let length = length.checked_add(PAGE_SIZE - 1).ok_or(errno::ENOMEM)? & !(PAGE_SIZE - 1);
+        let addr = self
+            .mmap_bottom::<ML>()
+            .checked_sub(length)
+            .filter(|addr| *addr >= self.program_break.read())
+            .ok_or(errno::ENOMEM)?;
+
+        zero_guest_memory(machine, addr, length)?;
+        self.mmap_bottom.write(addr);
+
+        Ok(addr)
+    }
+
+    /// `munmap(addr, length)`
+    ///
+    /// Only the lowest mapping is given back, other unmapped memory is not
+    /// reused.
+    fn sys_munmap<ML: MainMemoryLayout>(
+        &mut self,
+        _machine: &mut MachineState<ML, M>,
+        addr: Address,
+        length: u64,
+    ) -> SyscallResult {
+        if addr % PAGE_SIZE != 0 || length == 0 {
+            return Err(errno::EINVAL);
+        }
+
+        if addr == self.mmap_bottom::<ML>() {
+            let end = round_up_to_page(addr.saturating_add(length)).min(mmap_top::<ML>());
+            self.mmap_bottom.write(end);
+        }
+
+        Ok(0)
+    }
+
+    /// `getrandom(buf, buflen, flags)`
+    ///
+    /// The bytes come from a deterministic pseudo-random number generator.
+    fn sys_getrandom<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &mut MachineState<ML, M>,
+        buf: Address,
+        length: u64,
+    ) -> SyscallResult {
+        let length = length.min(GETRANDOM_MAX);
+
+        let mut written = 0;
+        while written < length {
+            let mut chunk = [0u8; RANDOM_CHUNK_SIZE];
+            for word in chunk.chunks_exact_mut(8) {
+                word.copy_from_slice(&self.next_random().to_le_bytes());
+            }
+
+            let len = (length - written).min(RANDOM_CHUNK_SIZE as u64);
+            machine
+                .write_guest_bytes(buf.wrapping_add(written), &chunk[..len as usize])
+                .map_err(|_| errno::EFAULT)?;
+            written += len;
+        }
+
+        Ok(length)
+    }
+
+    /// Generate the next pseudo-random number using SplitMix64.
+    fn next_random(&mut self) -> u64 {
+        let state = self.random.read().wrapping_add(0x9E3779B97F4A7C15);
+        self.random.write(state);
+
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Lowest address mapped by `mmap`
+    fn mmap_bottom<ML: MainMemoryLayout>(&self) -> Address {
+        match self.mmap_bottom.read() {
+            0 => mmap_top::<ML>(),
+            bottom => bottom,
+        }
+    }
+}
+
+/// `ioctl(fd, request, arg)`
+///
+/// None of the standard streams is a terminal.
+fn sys_ioctl(fd: u64) -> SyscallResult {
+    match fd {
+        0..=2 => Err(errno::ENOTTY),
+        _ => Err(errno::EBADF),
+    }
+}
+
+/// `ppoll(fds, nfds, timeout, sigmask, sigsetsize)`
+///
+/// No events are ever pending on the standard streams, hence the call returns
+/// immediately. Other file descriptors aren't open.
+fn sys_ppoll<ML: MainMemoryLayout, M: Manager>(
+    machine: &mut MachineState<ML, M>,
+    fds: Address,
+    nfds: u64,
+) -> SyscallResult {
+    if nfds > POLL_MAX {
+        return Err(errno::EINVAL);
+    }
+
+    let mut ready = 0;
+    for i in 0..nfds {
+        // Each `struct pollfd` holds a 32-bit file descriptor followed by the
+        // 16-bit requested and returned events.
+        let entry = fds.wrapping_add(8 * i);
+        let fd = i32::from_le_bytes(
+            machine
+                .read_guest_bytes(entry, 4)
+                .map_err(|_| errno::EFAULT)?
+                .try_into()
+                .map_err(|_| errno::EFAULT)?,
+        );
+        let revents = match fd {
+            0..=2 => 0,
+            fd if fd < 0 => 0,
+            _ => POLLNVAL,
+        };
+        if revents != 0 {
+            ready += 1;
+        }
+        machine
+            .write_guest_bytes(entry.wrapping_add(6), &revents.to_le_bytes())
+            .map_err(|_| errno::EFAULT)?;
+    }
+
+    Ok(ready)
+}
+
+/// `sigaltstack(ss, old_ss)`
+///
+/// Signals are never delivered, so no alternate signal stack is ever in use.
+fn sys_sigaltstack<ML: MainMemoryLayout, M: Manager>(
+    machine: &mut MachineState<ML, M>,
+    old_ss: Address,
+) -> SyscallResult {
+    if old_ss != 0 {
+        // `struct stack_t` holds the stack address, the flags and the size.
+        let mut stack = [0u8; 24];
+        stack[8..12].copy_from_slice(&SS_DISABLE.to_le_bytes());
+        machine
+            .write_guest_bytes(old_ss, &stack)
+            .map_err(|_| errno::EFAULT)?;
+    }
+
+    Ok(0)
+}
+
+/// `rt_sigaction(signum, act, oldact, sigsetsize)`
+///
+/// Signals are never delivered, so new actions are accepted but never run.
+/// Every signal reports the default action.
+fn sys_rt_sigaction<ML: MainMemoryLayout, M: Manager>(
+    machine: &mut MachineState<ML, M>,
+    signum: u64,
+    oldact: Address,
+    sigsetsize: u64,
+) -> SyscallResult {
+    if signum == 0 || signum > SIGNAL_MAX || sigsetsize != SIGSET_SIZE {
+        return Err(errno::EINVAL);
+    }
+
+    if oldact != 0 {
+        machine
+            .write_guest_bytes(oldact, &[0; SIGACTION_SIZE as usize])
+            .map_err(|_| errno::EFAULT)?;
+    }
+
+    Ok(0)
+}
+
+/// `rt_sigprocmask(how, set, oldset, sigsetsize)`
+///
+/// Signals are never delivered, so the mask has no effect and is always
+/// reported as empty.
+fn sys_rt_sigprocmask<ML: MainMemoryLayout, M: Manager>(
+    machine: &mut MachineState<ML, M>,
+    oldset: Address,
+    sigsetsize: u64,
+) -> SyscallResult {
+    if sigsetsize != SIGSET_SIZE {
+        return Err(errno::EINVAL);
+    }
+
+    if oldset != 0 {
+        machine
+            .write_guest_bytes(oldset, &[0; SIGSET_SIZE as usize])
+            .map_err(|_| errno::EFAULT)?;
+    }
+
+    Ok(0)
+}
+
+/// `mprotect(addr, len, prot)`
+///
+/// Memory protection isn't enforced, e.g. guard pages remain accessible.
+fn sys_mprotect(addr: Address) -> SyscallResult {
+    if addr % PAGE_SIZE != 0 {
+        return Err(errno::EINVAL);
+    }
+
+    Ok(0)
+}
+
+/// Highest address available to `mmap`, below the stack
+fn mmap_top<ML: MainMemoryLayout>() -> Address {
+    let memory_end = bus::start_of_main_memory::<ML>() + ML::BYTES as u64;
+    memory_end.saturating_sub(STACK_SIZE)
+}
+
+fn round_up_to_page(addr: Address) -> Address {
+    addr.saturating_add(PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// Read a little-endian 64-bit integer from guest memory at virtual address
+/// `addr`.
+fn read_guest_u64<ML: MainMemoryLayout, M: Manager>(
+    machine: &MachineState<ML, M>,
+    addr: Address,
+) -> Result<u64, u64> {
+    let bytes = machine
+        .read_guest_bytes(addr, 8)
+        .map_err(|_| errno::EFAULT)?;
+    Ok(u64::from_le_bytes(
+        bytes.try_into().map_err(|_| errno::EFAULT)?,
+    ))
+}
+
+/// Zero `len` bytes of guest memory starting at virtual address `addr`, one
+/// page at a time.
+fn zero_guest_memory<ML: MainMemoryLayout, M: Manager>(
+    machine: &mut MachineState<ML, M>,
+    addr: Address,
+    len: u64,
+) -> Result<(), u64> {
+    const ZEROES: [u8; PAGE_SIZE as usize] = [0; PAGE_SIZE as usize];
+
+    let end = addr.checked_add(len).ok_or(errno::EFAULT)?;
+    let mut start = addr;
+    while start < end {
+        let chunk_end = end.min((start / PAGE_SIZE + 1) * PAGE_SIZE);
+        let phys_addr = machine
+            .translate(start, AccessType::Store)
+            .map_err(|_| errno::EFAULT)?;
+        machine
+            .bus
+            .write_all(phys_addr, &ZEROES[..(chunk_end - start) as usize])
+            .map_err(|_| errno::ENOMEM)?;
+        start = chunk_end;
+    }
+
+    Ok(())
 }
 
 /// Implements a posix-ish environment for the RISC-V test suite.
@@ -60,6 +656,13 @@ where
             code: space.0,
             exited: space.1,
             exit_mode: ModeCell::bind(space.2),
+            heap_start: space.3 .0,
+            program_break: space.3 .1,
+            mmap_bottom: space.3 .2,
+            clock: space.3 .3,
+            random: space.3 .4,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
         }
     }
 
@@ -67,6 +670,13 @@ where
         self.exited.write(0);
         self.code.write(0);
         self.exit_mode.write(Mode::Machine);
+        self.heap_start.write(0);
+        self.program_break.write(0);
+        self.mmap_bottom.write(0);
+        self.clock.write(0);
+        self.random.write(0);
+        self.stdout.clear();
+        self.stderr.clear();
     }
 
     fn handle_call<ML: MainMemoryLayout>(
@@ -119,13 +729,373 @@ where
         let a0_val = machine.hart.xregisters.read(a0);
         match (a7_val, a0_val) {
             // Exit (test pass, physical | virtual)
-            (93, 0) | (0, 1) => handle_exit(0),
+            (syscall::EXIT, 0) | (0, 1) => handle_exit(0),
 
             // Exit (test fail, physical | virtual)
-            (93, code) | (0, code) => handle_exit(code),
+            (syscall::EXIT, code) | (syscall::EXIT_GROUP, code) | (0, code) => handle_exit(code),
+
+            // Other Linux system calls return their result in a0
+            (number, _) => {
+                let result = match self.handle_syscall(machine, number) {
+                    Some(Ok(value)) => value,
+                    Some(Err(errno)) => errno.wrapping_neg(),
+
+                    // Unimplemented
+                    None => return EcallOutcome::Fatal,
+                };
+                machine.hart.xregisters.write(a0, result);
 
-            // Unimplemented
-            _ => EcallOutcome::Fatal,
+                // Handled system calls resume after the ECALL instruction
+                let pc = machine.hart.pc.read();
+                machine.hart.pc.write(pc + 4);
+
+                EcallOutcome::Handled {
+                    continue_eval: true,
+                }
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        machine_state::{bus::main_memory::Sizes, registers::a4, MachineStateLayout},
+        state_backend::{memory_backend::InMemoryBackend, Backend},
+    };
+    use kernel_loader::ProgramHeaders;
+    use std::collections::BTreeMap;
+
+    type M16M = Sizes<{ 16 * 1024 * 1024 }>;
+    type L = (
+        <Posix as ExecutionEnvironment>::Layout,
+        MachineStateLayout<M16M>,
+    );
+
+    /// Perform a system call and return its result.
+    fn syscall<M: Manager>(
+        posix: &mut PosixState<M>,
+        machine: &mut MachineState<M16M, M>,
+        number: u64,
+        args: &[u64],
+    ) -> Option<u64> {
+        for (reg, arg) in [a0, a1, a2, a3, a4].into_iter().zip(args) {
+            machine.hart.xregisters.write(reg, *arg);
+        }
+        machine.hart.xregisters.write(a7, number);
+
+        let pc = machine.hart.pc.read();
+        match posix.handle_call(machine, EnvironException::EnvCallFromMMode) {
+            EcallOutcome::Fatal => None,
+            EcallOutcome::Handled { continue_eval } => {
+                assert!(continue_eval);
+                assert_eq!(machine.hart.pc.read(), pc + 4);
+                Some(machine.hart.xregisters.read(a0))
+            }
+        }
+    }
+
+    #[test]
+    fn test_syscalls() {
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        let (mut posix, mut machine) = {
+            let space = backend.allocate(placed);
+            (PosixState::bind(space.0), MachineState::bind(space.1))
+        };
+        posix.reset();
+        machine.reset();
+
+        let memory = bus::start_of_main_memory::<M16M>();
+        let neg = |errno: u64| errno.wrapping_neg();
+
+        // write
+        machine.bus.write_all(memory, b"hello").unwrap();
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::WRITE, &[1, memory, 5]),
+            Some(5)
+        );
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::WRITE, &[2, memory, 4]),
+            Some(4)
+        );
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::WRITE, &[3, memory, 5]),
+            Some(neg(errno::EBADF))
+        );
+        assert_eq!(posix.stdout(), b"hello");
+        assert_eq!(posix.stderr(), b"hell");
+
+        // brk
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::BRK, &[0]),
+            Some(0)
+        );
+        posix.set_program_break(memory + 0x10001);
+        let heap = memory + 0x11000;
+        machine.bus.write(heap, u64::MAX).unwrap();
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::BRK, &[0]),
+            Some(heap)
+        );
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::BRK, &[heap + 0x2000]),
+            Some(heap + 0x2000)
+        );
+        assert_eq!(machine.bus.read(heap), Ok(0u64));
+
+        // mmap & munmap
+        let flags = mmap_flags::MAP_PRIVATE | mmap_flags::MAP_ANONYMOUS;
+        let top = mmap_top::<M16M>();
+        let mmap = |posix: &mut PosixState<_>, machine: &mut MachineState<_, _>, len, flags| {
+            syscall(posix, machine, syscall::MMAP, &[0, len, 3, flags, u64::MAX])
+        };
+        assert_eq!(
+            mmap(&mut posix, &mut machine, 5000, flags),
+            Some(top - 0x2000)
+        );
+        assert_eq!(mmap(&mut posix, &mut machine, 1, flags), Some(top - 0x3000));
+        assert_eq!(
+            mmap(&mut posix, &mut machine, 1, mmap_flags::MAP_PRIVATE),
+            Some(neg(errno::EINVAL))
+        );
+        assert_eq!(
+            mmap(&mut posix, &mut machine, top, flags),
+            Some(neg(errno::ENOMEM))
+        );
+        assert_eq!(
+            syscall(
+                &mut posix,
+                &mut machine,
+                syscall::MUNMAP,
+                &[top - 0x3000, 1]
+            ),
+            Some(0)
+        );
+        assert_eq!(mmap(&mut posix, &mut machine, 1, flags), Some(top - 0x3000));
+
+        // The heap can't grow into the mapped memory.
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::BRK, &[top - 0x2000]),
+            Some(heap + 0x2000)
+        );
+
+        // clock_gettime
+        let clock_gettime = |posix: &mut PosixState<_>, machine: &mut MachineState<_, _>| {
+            assert_eq!(
+                syscall(posix, machine, syscall::CLOCK_GETTIME, &[1, memory]),
+                Some(0)
+            );
+            let secs: u64 = machine.bus.read(memory).unwrap();
+            let nanos: u64 = machine.bus.read(memory + 8).unwrap();
+            secs * NANOS_PER_SEC + nanos
+        };
+        let first = clock_gettime(&mut posix, &mut machine);
+        let second = clock_gettime(&mut posix, &mut machine);
+        assert_eq!(second - first, CLOCK_TICK_NS);
+
+        // getrandom
+        assert_eq!(
+            syscall(
+                &mut posix,
+                &mut machine,
+                syscall::GETRANDOM,
+                &[memory, 13, 0]
+            ),
+            Some(13)
+        );
+        let mut random = [0u8; 16];
+        random.iter_mut().enumerate().for_each(|(i, byte)| {
+            *byte = machine.bus.read(memory + i as u64).unwrap();
+        });
+        assert_ne!(random[..13], [0; 13]);
+        assert_eq!(random[13..], [0; 3]);
+
+        // Large requests are served without allocating their length.
+        assert_eq!(
+            syscall(
+                &mut posix,
+                &mut machine,
+                syscall::GETRANDOM,
+                &[memory, u64::MAX, 0]
+            ),
+            Some(neg(errno::EFAULT))
+        );
+
+        // writev
+        let iov = memory + 0x100;
+        machine.bus.write_all(memory, b"hello").unwrap();
+        machine
+            .bus
+            .write_all(iov, &[memory, 2, memory + 2, 3])
+            .unwrap();
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::WRITEV, &[1, iov, 2]),
+            Some(5)
+        );
+        assert_eq!(posix.stdout(), b"hellohello");
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::WRITEV, &[1, iov, 1025]),
+            Some(neg(errno::EINVAL))
+        );
+
+        // ioctl
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::IOCTL, &[1, 0x5413, 0]),
+            Some(neg(errno::ENOTTY))
+        );
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::IOCTL, &[5, 0x5413, 0]),
+            Some(neg(errno::EBADF))
+        );
+
+        // ppoll
+        let fds = memory + 0x200;
+        machine
+            .bus
+            .write_all(fds, &[0xffff_0004_0000_0001u64, 0xffff_0004_0000_0007])
+            .unwrap();
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::PPOLL, &[fds, 2, 0, 0, 8]),
+            Some(1)
+        );
+        assert_eq!(machine.bus.read(fds + 6), Ok(0u16));
+        assert_eq!(machine.bus.read(fds + 14), Ok(POLLNVAL));
+
+        // set_tid_address
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::SET_TID_ADDRESS, &[fds]),
+            Some(PID)
+        );
+
+        // Signals
+        let old = memory + 0x300;
+        machine.bus.write_all(old, &[u64::MAX; 3]).unwrap();
+        assert_eq!(
+            syscall(
+                &mut posix,
+                &mut machine,
+                syscall::RT_SIGACTION,
+                &[11, memory, old, 8]
+            ),
+            Some(0)
+        );
+        assert_eq!(machine.bus.read(old + 16), Ok(0u64));
+        assert_eq!(
+            syscall(
+                &mut posix,
+                &mut machine,
+                syscall::RT_SIGACTION,
+                &[0, memory, old, 8]
+            ),
+            Some(neg(errno::EINVAL))
+        );
+        machine.bus.write(old, u64::MAX).unwrap();
+        assert_eq!(
+            syscall(
+                &mut posix,
+                &mut machine,
+                syscall::RT_SIGPROCMASK,
+                &[0, memory, old, 8]
+            ),
+            Some(0)
+        );
+        assert_eq!(machine.bus.read(old), Ok(0u64));
+        assert_eq!(
+            syscall(&mut posix, &mut machine, syscall::SIGALTSTACK, &[0, old]),
+            Some(0)
+        );
+        assert_eq!(machine.bus.read(old + 8), Ok(SS_DISABLE));
+
+        // mprotect
+        assert_eq!(
+            syscall(
+                &mut posix,
+                &mut machine,
+                syscall::MPROTECT,
+                &[memory, 4096, 0]
+            ),
+            Some(0)
+        );
+        assert_eq!(
+            syscall(
+                &mut posix,
+                &mut machine,
+                syscall::MPROTECT,
+                &[memory + 1, 4096, 0]
+            ),
+            Some(neg(errno::EINVAL))
+        );
+
+        // Unimplemented system calls are fatal
+        assert_eq!(syscall(&mut posix, &mut machine, 1234, &[]), None);
+    }
+
+    #[test]
+    fn test_process_stack() {
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        let (mut posix, mut machine) = {
+            let space = backend.allocate(placed);
+            (PosixState::bind(space.0), MachineState::bind(space.1))
+        };
+        posix.reset();
+        machine.reset();
+
+        let memory = bus::start_of_main_memory::<M16M>();
+        let mut program = Program::<M16M>::from_raw(&[]);
+        program.program_headers = Some(ProgramHeaders {
+            addr: memory + 0x40,
+            count: 3,
+        });
+        posix.setup_process_stack(&mut machine, &program).unwrap();
+
+        let stack = machine.hart.xregisters.read(sp);
+        assert_eq!(stack % 16, 0);
+        let word = |i: u64| -> u64 { machine.bus.read(stack + 8 * i).unwrap() };
+
+        // argc, argv and envp
+        assert_eq!(word(0), 1);
+        let name = (0..PROGRAM_NAME.len() as u64)
+            .map(|i| machine.bus.read(word(1) + i).unwrap())
+            .collect::<Vec<u8>>();
+        assert_eq!(name, PROGRAM_NAME);
+        assert_eq!(word(2), 0);
+        assert_eq!(word(3), 0);
+
+        // Auxiliary vector
+        let auxv: BTreeMap<u64, u64> = (0..)
+            .map(|i| (word(4 + 2 * i), word(5 + 2 * i)))
+            .take_while(|(key, _)| *key != auxv::AT_NULL)
+            .collect();
+        assert_eq!(auxv[&auxv::AT_PHDR], memory + 0x40);
+        assert_eq!(auxv[&auxv::AT_PHENT], PROGRAM_HEADER_SIZE);
+        assert_eq!(auxv[&auxv::AT_PHNUM], 3);
+        assert_eq!(auxv[&auxv::AT_PAGESZ], PAGE_SIZE);
+        assert_eq!(auxv[&auxv::AT_ENTRY], memory);
+        assert!(auxv[&auxv::AT_RANDOM] > stack);
+    }
+
+    #[test]
+    fn test_getrandom_deterministic() {
+        let random_bytes = || {
+            let (mut backend, placed) = InMemoryBackend::<L>::new();
+            let space = backend.allocate(placed);
+            let mut posix = PosixState::bind(space.0);
+            let mut machine = MachineState::bind(space.1);
+            posix.reset();
+            machine.reset();
+
+            let memory = bus::start_of_main_memory::<M16M>();
+            syscall(
+                &mut posix,
+                &mut machine,
+                syscall::GETRANDOM,
+                &[memory, 8, 0],
+            );
+            let random: u64 = machine.bus.read(memory).unwrap();
+            random
+        };
+
+        assert_eq!(random_bytes(), random_bytes());
+    }
+}
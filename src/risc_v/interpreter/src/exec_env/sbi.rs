@@ -0,0 +1,605 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Supervisor binary interface (SBI) execution environment
+//!
+//! Kernels run in supervisor mode and call into the execution environment
+//! using `ecall`. The extension is selected by `a7` and the function by `a6`,
+//! arguments are passed in `a0` to `a5`. Environment calls from other modes
+//! are forwarded to the kernel as traps.
+//!
+//! Besides the legacy console, timer and shutdown extensions, the base, timer,
+//! hart state management, system reset and debug console extensions of the
+//! SBI specification are implemented for a single hart. The
+//! [`SBI_FIRMWARE_MAVRYK`] extension provides the inbox, the rollup metadata
+//! and cryptographic primitives to the kernel.
+//!
+//! More information on the SBI specification:
+//!   - https://github.com/riscv-non-isa/riscv-sbi-doc
+
+use super::{EcallOutcome, ExecutionEnvironment, ExecutionEnvironmentState};
+use crate::{
+    machine_state::{
+        bus::{main_memory::MainMemoryLayout, Address, Addressable},
+        registers::{a0, a1, a2, a3, a6, a7},
+        MachineState,
+    },
+    state_backend::{AllocatedOf, Atom, Cell, Manager},
+    traps::EnvironException,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use mavryk_smart_rollup_constants::riscv::{
+    SBI_CONSOLE_PUTCHAR, SBI_FIRMWARE_MAVRYK, SBI_MAVRYK_BLAKE2B_HASH256, SBI_MAVRYK_ED25519_SIGN,
+    SBI_MAVRYK_ED25519_VERIFY, SBI_MAVRYK_INBOX_NEXT, SBI_MAVRYK_META_ADDRESS,
+    SBI_MAVRYK_META_ORIGINATION_LEVEL, SBI_SHUTDOWN,
+};
+
+/// SBI extension IDs
+mod extension {
+    pub const LEGACY_SET_TIMER: u64 = 0x00;
+    pub const LEGACY_CONSOLE_GETCHAR: u64 = 0x02;
+    pub const BASE: u64 = 0x10;
+    pub const TIME: u64 = 0x54494D45;
+    pub const HSM: u64 = 0x48534D;
+    pub const SRST: u64 = 0x53525354;
+    pub const DBCN: u64 = 0x4442434E;
+}
+
+/// SBI error codes
+mod error {
+    pub const FAILED: i64 = -1;
+    pub const NOT_SUPPORTED: i64 = -2;
+    pub const INVALID_PARAM: i64 = -3;
+    pub const ALREADY_AVAILABLE: i64 = -6;
+}
+
+/// Version of the SBI specification: 2.0
+const SPEC_VERSION: u64 = 2 << 24;
+
+/// Implementation ID, which is not registered with the specification
+const IMPL_ID: u64 = 0x4D415652;
+
+/// ID of the only hart
+const HART_ID: u64 = 0;
+
+/// Status of a started hart, as returned by `sbi_hart_get_status`
+const HART_STARTED: u64 = 0;
+
+/// Shutdown reset type of `sbi_system_reset`
+const RESET_SHUTDOWN: u64 = 0;
+
+/// Highest reset type defined by the specification
+const RESET_WARM_REBOOT: u64 = 2;
+
+/// Start of the vendor or platform specific reset types
+const RESET_VENDOR: u64 = 0xF0000000;
+
+/// Result of a call to a standard extension, either its value or an error
+/// code
+type SbiResult = Result<u64, i64>;
+
+/// Inbox message as revealed to the kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboxMessage {
+    /// Level the message was included at
+    pub level: u32,
+
+    /// Index of the message within its level
+    pub id: u32,
+
+    /// Encoded message
+    pub payload: Vec<u8>,
+}
+
+/// Metadata of the rollup
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RollupMetadata {
+    /// Level at which the rollup was originated
+    pub origination_level: u64,
+
+    /// Raw address of the rollup
+    pub address: Vec<u8>,
+}
+
+/// SBI execution environment
+pub enum Sbi {}
+
+impl ExecutionEnvironment for Sbi {
+    type Layout = (Atom<u64>, Atom<u8>, Atom<u64>, Atom<u64>);
+
+    type State<M: Manager> = SbiState<M>;
+}
+
+/// SBI execution environment state
+pub struct SbiState<M: Manager> {
+    code: Cell<u64, M>,
+    exited: Cell<u8, M>,
+    timer: Cell<u64, M>,
+
+    /// Number of calls to `inbox_next`, including those after the inbox has
+    /// been drained
+    inbox_reads: Cell<u64, M>,
+
+    // The inbox and the metadata are inputs, they aren't part of the state.
+    inbox: Vec<InboxMessage>,
+    metadata: RollupMetadata,
+
+    // Output isn't part of the state. It is only collected for inspection.
+    console: Vec<u8>,
+}
+
+impl<M: Manager> SbiState<M> {
+    /// If an exit has been requested, return the exit code.
+    pub fn exit_code(&self) -> Option<u64> {
+        self.exited().then(|| self.code.read())
+    }
+
+    /// Has an exit been requested?
+    pub fn exited(&self) -> bool {
+        self.exited.read() > 0
+    }
+
+    /// Provide the messages returned by `inbox_next`, in order.
+    pub fn set_inbox(&mut self, inbox: Vec<InboxMessage>) {
+        self.inbox = inbox;
+    }
+
+    /// Provide the metadata of the rollup.
+    pub fn set_metadata(&mut self, metadata: RollupMetadata) {
+        self.metadata = metadata;
+    }
+
+    /// Number of times `inbox_next` found the inbox drained
+    pub fn inbox_none_count(&self) -> u64 {
+        self.inbox_reads
+            .read()
+            .saturating_sub(self.inbox.len() as u64)
+    }
+
+    /// Deadline of the supervisor timer, as set by the kernel. Timer
+    /// interrupts are not raised yet.
+    pub fn timer(&self) -> u64 {
+        self.timer.read()
+    }
+
+    /// Bytes the kernel has written to the console
+    pub fn console(&self) -> &[u8] {
+        &self.console
+    }
+
+    /// Request an exit with `code`.
+    fn exit(&mut self, code: u64) -> Option<bool> {
+        let exited = self.exited.read();
+        self.exited.write(exited.saturating_add(1));
+        self.code.write(code);
+        Some(false)
+    }
+
+    /// Handle an SBI call. Returns whether evaluation should continue, or
+    /// `None` if the call is fatal.
+    fn handle_sbi_call<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &mut MachineState<ML, M>,
+    ) -> Option<bool> {
+        let extension = machine.hart.xregisters.read(a7);
+        let function = machine.hart.xregisters.read(a6);
+        let [arg0, arg1, arg2] = [a0, a1, a2].map(|reg| machine.hart.xregisters.read(reg));
+
+        // Legacy extensions return their result in a0 only.
+        let legacy = |machine: &mut MachineState<ML, M>, value: u64| {
+            machine.hart.xregisters.write(a0, value);
+            Some(true)
+        };
+
+        let result = match extension {
+            extension::LEGACY_SET_TIMER => {
+                self.timer.write(arg0);
+                return legacy(machine, 0);
+            }
+            SBI_CONSOLE_PUTCHAR => {
+                self.console.push(arg0 as u8);
+                return legacy(machine, 0);
+            }
+            extension::LEGACY_CONSOLE_GETCHAR => return legacy(machine, error::FAILED as u64),
+            SBI_SHUTDOWN => return self.exit(0),
+            SBI_FIRMWARE_MAVRYK => return self.handle_mavryk_call(machine, function),
+
+            extension::BASE => sbi_base(function, arg0),
+            extension::TIME if function == 0 => {
+                self.timer.write(arg0);
+                Ok(0)
+            }
+            extension::HSM => match function {
+                // hart_start
+                0 if arg0 == HART_ID => Err(error::ALREADY_AVAILABLE),
+                // hart_stop of the only hart halts the machine.
+                1 => return self.exit(0),
+                // hart_get_status
+                2 if arg0 == HART_ID => Ok(HART_STARTED),
+                0 | 2 => Err(error::INVALID_PARAM),
+                _ => Err(error::NOT_SUPPORTED),
+            },
+            extension::SRST if function == 0 => match arg0 {
+                RESET_SHUTDOWN => return self.exit(arg1),
+                ..=RESET_WARM_REBOOT | RESET_VENDOR.. => Err(error::NOT_SUPPORTED),
+                _ => Err(error::INVALID_PARAM),
+            },
+            extension::DBCN => self.sbi_debug_console(machine, function, arg0, arg1, arg2),
+            _ => Err(error::NOT_SUPPORTED),
+        };
+
+        // Standard extensions return an error code in a0 and a value in a1.
+        let (error, value) = match result {
+            Ok(value) => (0, value),
+            Err(error) => (error as u64, 0),
+        };
+        machine.hart.xregisters.write(a0, error);
+        machine.hart.xregisters.write(a1, value);
+        Some(true)
+    }
+
+    /// Debug console extension. Buffers are given by physical addresses.
+    fn sbi_debug_console<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &mut MachineState<ML, M>,
+        function: u64,
+        num_bytes: u64,
+        base_addr_lo: u64,
+        base_addr_hi: u64,
+    ) -> SbiResult {
+        match function {
+            // console_write
+            0 if base_addr_hi == 0 => {
+                let bytes = (0..num_bytes)
+                    .map(|i| machine.bus.read(base_addr_lo.wrapping_add(i)))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|_| error::INVALID_PARAM)?;
+                self.console.extend(bytes);
+                Ok(num_bytes)
+            }
+            // console_read, there is no input
+            1 => Ok(0),
+            // console_write_byte
+            2 => {
+                self.console.push(num_bytes as u8);
+                Ok(0)
+            }
+            0 => Err(error::INVALID_PARAM),
+            _ => Err(error::NOT_SUPPORTED),
+        }
+    }
+
+    /// Handle a call to the [`SBI_FIRMWARE_MAVRYK`] extension. Buffers are
+    /// given by virtual addresses.
+    fn handle_mavryk_call<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &mut MachineState<ML, M>,
+        function: u64,
+    ) -> Option<bool> {
+        let [arg0, arg1, arg2, arg3] =
+            [a0, a1, a2, a3].map(|reg| machine.hart.xregisters.read(reg));
+
+        match function {
+            SBI_MAVRYK_INBOX_NEXT => return self.sbi_mavryk_inbox_next(machine, arg0, arg1),
+
+            SBI_MAVRYK_META_ORIGINATION_LEVEL => machine
+                .hart
+                .xregisters
+                .write(a0, self.metadata.origination_level),
+
+            SBI_MAVRYK_META_ADDRESS => {
+                let address = &self.metadata.address;
+                let length = arg1.min(address.len() as u64);
+                machine
+                    .write_guest_bytes(arg0, &address[..length as usize])
+                    .ok()?;
+                machine.hart.xregisters.write(a0, length);
+            }
+
+            SBI_MAVRYK_ED25519_SIGN => {
+                let sk = machine.read_guest_bytes(arg0, 32).ok()?;
+                let sk = SigningKey::try_from(sk.as_slice()).ok()?;
+                let msg = machine.read_guest_bytes(arg1, arg2).ok()?;
+                let sig = sk.sign(msg.as_slice());
+                machine.write_guest_bytes(arg3, &sig.to_bytes()).ok()?;
+            }
+
+            SBI_MAVRYK_ED25519_VERIFY => {
+                let pk = machine.read_guest_bytes(arg0, 32).ok()?;
+                let sig = machine.read_guest_bytes(arg1, 64).ok()?;
+                let msg = machine.read_guest_bytes(arg2, arg3).ok()?;
+
+                let pk = VerifyingKey::try_from(pk.as_slice()).ok()?;
+                let sig = Signature::from_slice(sig.as_slice()).ok()?;
+                let valid = pk.verify_strict(msg.as_slice(), &sig).is_ok();
+                machine.hart.xregisters.write(a0, valid as u64);
+            }
+
+            SBI_MAVRYK_BLAKE2B_HASH256 => {
+                let msg = machine.read_guest_bytes(arg1, arg2).ok()?;
+                let hash = mavryk_crypto_rs::blake2b::digest_256(msg.as_slice()).ok()?;
+                machine.write_guest_bytes(arg0, hash.as_slice()).ok()?;
+            }
+
+            // Unimplemented, e.g. durable storage calls which are served by
+            // the PVM
+            _ => return None,
+        }
+
+        Some(true)
+    }
+
+    /// Write the next inbox message to `dest` and place its level, its index
+    /// and its length in `a0` to `a2`. Once the inbox has been drained, all
+    /// three are zero and the evaluation pauses.
+    fn sbi_mavryk_inbox_next<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &mut MachineState<ML, M>,
+        dest: Address,
+        max_bytes: u64,
+    ) -> Option<bool> {
+        let reads = self.inbox_reads.read();
+        self.inbox_reads.write(reads.saturating_add(1));
+
+        let Some(message) = self.inbox.get(reads as usize) else {
+            for reg in [a0, a1, a2] {
+                machine.hart.xregisters.write(reg, 0);
+            }
+            return Some(false);
+        };
+
+        let length = max_bytes.min(message.payload.len() as u64);
+        machine
+            .write_guest_bytes(dest, &message.payload[..length as usize])
+            .ok()?;
+
+        machine.hart.xregisters.write(a0, message.level as u64);
+        machine.hart.xregisters.write(a1, message.id as u64);
+        machine.hart.xregisters.write(a2, length);
+        Some(true)
+    }
+}
+
+/// Base extension
+fn sbi_base(function: u64, arg0: u64) -> SbiResult {
+    match function {
+        // get_sbi_spec_version
+        0 => Ok(SPEC_VERSION),
+        // get_sbi_impl_id
+        1 => Ok(IMPL_ID),
+        // get_sbi_impl_version
+        2 => Ok(0),
+        // probe_extension
+        3 => Ok(is_supported(arg0) as u64),
+        // get_mvendorid, get_marchid and get_mimpid
+        4..=6 => Ok(0),
+        _ => Err(error::NOT_SUPPORTED),
+    }
+}
+
+/// Is the extension `extension` implemented?
+fn is_supported(extension: u64) -> bool {
+    matches!(
+        extension,
+        extension::LEGACY_SET_TIMER
+            | SBI_CONSOLE_PUTCHAR
+            | extension::LEGACY_CONSOLE_GETCHAR
+            | SBI_SHUTDOWN
+            | extension::BASE
+            | extension::TIME
+            | extension::HSM
+            | extension::SRST
+            | extension::DBCN
+            | SBI_FIRMWARE_MAVRYK
+    )
+}
+
+impl<M: Manager> ExecutionEnvironmentState<M> for SbiState<M> {
+    type ExecutionEnvironment = Sbi;
+
+    fn bind(space: AllocatedOf<<Sbi as ExecutionEnvironment>::Layout, M>) -> Self {
+        Self {
+            code: space.0,
+            exited: space.1,
+            timer: space.2,
+            inbox_reads: space.3,
+            inbox: Vec::new(),
+            metadata: RollupMetadata::default(),
+            console: Vec::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.code.write(0);
+        self.exited.write(0);
+        self.timer.write(0);
+        self.inbox_reads.write(0);
+        self.console.clear();
+    }
+
+    fn handle_call<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &mut MachineState<ML, M>,
+        exception: EnvironException,
+    ) -> EcallOutcome {
+        if exception != EnvironException::EnvCallFromSMode {
+            // Only the kernel calls into the SBI, other environment calls are
+            // the kernel's to handle.
+            let return_pc = machine.hart.pc.read();
+            let new_pc = machine.hart.take_trap(exception.as_exception(), return_pc);
+            machine.hart.pc.write(new_pc);
+
+            return EcallOutcome::Handled {
+                continue_eval: true,
+            };
+        }
+
+        if self.exited() {
+            // Can't exit twice
+            return EcallOutcome::Fatal;
+        }
+
+        match self.handle_sbi_call(machine) {
+            Some(continue_eval) => {
+                // Handled calls resume after the ECALL instruction
+                let pc = machine.hart.pc.read();
+                machine.hart.pc.write(pc + 4);
+
+                EcallOutcome::Handled { continue_eval }
+            }
+            None => EcallOutcome::Fatal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        machine_state::{
+            bus::{self, main_memory::Sizes},
+            mode::Mode,
+            MachineStateLayout,
+        },
+        state_backend::{memory_backend::InMemoryBackend, Backend},
+    };
+
+    type M1M = Sizes<{ 1024 * 1024 }>;
+    type L = (
+        <Sbi as ExecutionEnvironment>::Layout,
+        MachineStateLayout<M1M>,
+    );
+
+    /// Perform an SBI call and return `a0` and `a1`.
+    fn sbi_call<M: Manager>(
+        sbi: &mut SbiState<M>,
+        machine: &mut MachineState<M1M, M>,
+        extension: u64,
+        function: u64,
+        args: &[u64],
+    ) -> Option<(u64, u64)> {
+        for (reg, arg) in [a0, a1, a2, a3].into_iter().zip(args) {
+            machine.hart.xregisters.write(reg, *arg);
+        }
+        machine.hart.xregisters.write(a7, extension);
+        machine.hart.xregisters.write(a6, function);
+
+        match sbi.handle_call(machine, EnvironException::EnvCallFromSMode) {
+            EcallOutcome::Fatal => None,
+            EcallOutcome::Handled { .. } => Some((
+                machine.hart.xregisters.read(a0),
+                machine.hart.xregisters.read(a1),
+            )),
+        }
+    }
+
+    #[test]
+    fn test_sbi_calls() {
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        let (mut sbi, mut machine) = {
+            let space = backend.allocate(placed);
+            (SbiState::bind(space.0), MachineState::bind(space.1))
+        };
+        sbi.reset();
+        machine.reset();
+        machine.hart.mode.write(Mode::Supervisor);
+
+        let memory = bus::start_of_main_memory::<M1M>();
+        let neg = |error: i64| error as u64;
+
+        // Base extension
+        assert_eq!(
+            sbi_call(&mut sbi, &mut machine, extension::BASE, 0, &[]),
+            Some((0, SPEC_VERSION))
+        );
+        assert_eq!(
+            sbi_call(
+                &mut sbi,
+                &mut machine,
+                extension::BASE,
+                3,
+                &[extension::HSM]
+            ),
+            Some((0, 1))
+        );
+        assert_eq!(
+            sbi_call(&mut sbi, &mut machine, extension::BASE, 3, &[0x12345678]),
+            Some((0, 0))
+        );
+        assert_eq!(
+            sbi_call(&mut sbi, &mut machine, 0x12345678, 0, &[]),
+            Some((neg(error::NOT_SUPPORTED), 0))
+        );
+
+        // Console
+        machine.bus.write_all(memory, b"ello").unwrap();
+        sbi_call(
+            &mut sbi,
+            &mut machine,
+            SBI_CONSOLE_PUTCHAR,
+            0,
+            &[b'h' as u64],
+        );
+        assert_eq!(
+            sbi_call(&mut sbi, &mut machine, extension::DBCN, 0, &[4, memory, 0]),
+            Some((0, 4))
+        );
+        assert_eq!(sbi.console(), b"hello");
+
+        // Timer and harts
+        sbi_call(&mut sbi, &mut machine, extension::TIME, 0, &[1234]);
+        assert_eq!(sbi.timer(), 1234);
+        assert_eq!(
+            sbi_call(&mut sbi, &mut machine, extension::HSM, 2, &[HART_ID]),
+            Some((0, HART_STARTED))
+        );
+        assert_eq!(
+            sbi_call(&mut sbi, &mut machine, extension::HSM, 0, &[1]),
+            Some((neg(error::INVALID_PARAM), 0))
+        );
+
+        // Inbox
+        sbi.set_inbox(vec![InboxMessage {
+            level: 3,
+            id: 1,
+            payload: b"message".to_vec(),
+        }]);
+        let inbox_next = |sbi: &mut SbiState<_>, machine: &mut MachineState<_, _>| {
+            sbi_call(
+                sbi,
+                machine,
+                SBI_FIRMWARE_MAVRYK,
+                SBI_MAVRYK_INBOX_NEXT,
+                &[memory, 4],
+            )?;
+            let regs = [a0, a1, a2].map(|reg| machine.hart.xregisters.read(reg));
+            Some(regs)
+        };
+        assert_eq!(inbox_next(&mut sbi, &mut machine), Some([3, 1, 4]));
+        let revealed: [u8; 4] = machine.bus.read(memory).unwrap();
+        assert_eq!(&revealed, b"mess");
+        assert_eq!(inbox_next(&mut sbi, &mut machine), Some([0, 0, 0]));
+        assert_eq!(sbi.inbox_none_count(), 1);
+
+        // Hashing
+        machine.bus.write_all(memory, b"abc").unwrap();
+        sbi_call(
+            &mut sbi,
+            &mut machine,
+            SBI_FIRMWARE_MAVRYK,
+            SBI_MAVRYK_BLAKE2B_HASH256,
+            &[memory + 0x100, memory, 3],
+        );
+        let hash: [u8; 32] = machine.bus.read(memory + 0x100).unwrap();
+        assert_eq!(
+            hash.as_slice(),
+            mavryk_crypto_rs::blake2b::digest_256(b"abc").unwrap()
+        );
+
+        // Shutdown
+        assert!(sbi_call(&mut sbi, &mut machine, extension::SRST, 0, &[0, 1]).is_some());
+        assert_eq!(sbi.exit_code(), Some(1));
+        assert_eq!(sbi_call(&mut sbi, &mut machine, SBI_SHUTDOWN, 0, &[]), None);
+    }
+}
@@ -12,6 +12,7 @@ use crate::{
     state_backend as backend,
     traps::Exception,
 };
+use std::mem;
 
 impl<ML, M> MachineState<ML, M>
 where
@@ -21,6 +22,11 @@ where
     /// Generic read function for loading `mem::size_of<T>` bytes from `address`
     pub(super) fn read_from_address<T: backend::Elem>(&self, address: u64) -> Result<T, Exception> {
         let address = self.translate(address, AccessType::Load)?;
+        self.check_pmp(address, mem::size_of::<T>() as u64, AccessType::Load)?;
+        self.record_event(|events| {
+            events.loads += 1;
+            events.loaded_bytes += mem::size_of::<T>() as u64;
+        });
 
         self.bus
             .read(address)
@@ -44,6 +50,11 @@ where
         value: T,
     ) -> Result<(), Exception> {
         let address = self.translate(address, AccessType::Store)?;
+        self.check_pmp(address, mem::size_of::<T>() as u64, AccessType::Store)?;
+        self.record_event(|events| {
+            events.stores += 1;
+            events.stored_bytes += mem::size_of::<T>() as u64;
+        });
 
         self.bus
             .write(address, value)
@@ -7,7 +7,7 @@ use crate::{
         },
         hart_state::HartState,
         mode::Mode,
-        registers::XRegister,
+        registers::{x0, XRegister},
         MachineState,
     },
     state_backend as backend,
@@ -122,7 +122,7 @@ where
     ///
     /// Section 5.2.1: It is always legal to over-fence.
     #[inline(always)]
-    pub fn sfence_vma(&self, _asid: XRegister, _vaddr: XRegister) -> Result<(), Exception> {
+    pub fn sfence_vma(&mut self, asid: XRegister, vaddr: XRegister) -> Result<(), Exception> {
         let mode = self.hart.mode.read();
         let mstatus = self.hart.csregisters.read(CSRegister::mstatus);
         let tvm = xstatus::get_TVM(mstatus);
@@ -131,8 +131,15 @@ where
             return Err(Exception::IllegalInstruction);
         }
 
-        // Even if we over-fence, thus ignoring asid and vaddr, this instruction
-        // is still no-op since memory loads/stores are not cached currently.
+        // Section 5.2.1: rs1 = x0 fences all addresses and rs2 = x0 fences
+        // all address spaces. Only the low ASIDLEN bits of rs2 are used.
+        let read_operand = |reg: XRegister| (reg != x0).then(|| self.hart.xregisters.read(reg));
+        let vaddr = read_operand(vaddr);
+        let asid = read_operand(asid).map(|asid| asid & 0xFFFF);
+        self.bus.tlb().flush(asid, vaddr);
+
+        // We over-fence the instruction cache and drop it entirely.
+        self.bus.invalidate_instruction_cache();
         Ok(())
     }
 }
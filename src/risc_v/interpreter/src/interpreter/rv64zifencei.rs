@@ -21,6 +21,6 @@ where
         // memory are seen by the Hart. The Hart will cache instruction
         // memory and therefore won't bother re-reading the cache from memory
         // until `fence.i` is called.
-        // Because we have no instruction cache yet, this operation is a no-op.
+        self.bus.invalidate_instruction_cache();
     }
 }
@@ -14,19 +14,23 @@ pub mod traps;
 
 use crate::{
     machine_state::{
-        bus::main_memory::M1G, mode, registers::XRegister, MachineError, MachineState,
-        MachineStateLayout, StepManyResult,
+        bus::{main_memory::M1G, Addressable},
+        mode,
+        registers::{self, XRegister},
+        AccessType, MachineError, MachineState, MachineStateLayout, StepManyResult,
     },
     program::Program,
     state_backend::{
         memory_backend::{InMemoryBackend, SliceManager},
-        Backend, Layout,
+        snapshot::SnapshotError,
+        Atom, Backend, Cell, Layout,
     },
     traps::EnvironException,
 };
 use derive_more::{Error, From};
 use exec_env::{
     posix::{Posix, PosixState},
+    sbi::{InboxMessage, RollupMetadata, Sbi, SbiState},
     ExecutionEnvironment, ExecutionEnvironmentState,
 };
 use machine_state::{
@@ -39,10 +43,34 @@ use InterpreterResult::*;
 type StateLayout = (
     <Posix as ExecutionEnvironment>::Layout,
     MachineStateLayout<M1G>,
+    <Sbi as ExecutionEnvironment>::Layout,
+    Atom<u8>,
 );
 
+/// Backend holding the state of an [Interpreter]. Copies of the state, e.g.
+/// checkpoints, are taken and restored on the backend while no [Interpreter]
+/// is bound to it, see [Interpreter::resume].
+pub type InterpreterBackend = InMemoryBackend<StateLayout>;
+
+/// Execution environment serving the environment calls of the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecEnv {
+    /// POSIX-style system calls, exiting from the given mode
+    Posix(mode::Mode),
+    /// Supervisor binary interface for kernels booting in supervisor mode
+    Sbi,
+}
+
+/// Encoding of [ExecEnv::Posix] in the state
+const EXEC_ENV_POSIX: u8 = 0;
+
+/// Encoding of [ExecEnv::Sbi] in the state
+const EXEC_ENV_SBI: u8 = 1;
+
 pub struct Interpreter<'a> {
     posix_state: PosixState<SliceManager<'a>>,
+    sbi_state: SbiState<SliceManager<'a>>,
+    exec_env: Cell<u8, SliceManager<'a>>,
     machine_state: MachineState<M1G, SliceManager<'a>>,
 }
 
@@ -70,16 +98,71 @@ impl<'a> Interpreter<'a> {
         InMemoryBackend::<StateLayout>::new().0
     }
 
-    fn bind_states(
-        backend: &'a mut InMemoryBackend<StateLayout>,
-    ) -> (
-        PosixState<SliceManager<'a>>,
-        MachineState<M1G, SliceManager<'a>>,
-    ) {
+    /// Place the heap of the POSIX execution environment after the device
+    /// tree, which [MachineState::setup_boot] places after the program and the
+    /// initial ramdisk.
+    fn setup_program_break(
+        posix_state: &mut PosixState<SliceManager<'a>>,
+        machine_state: &MachineState<M1G, SliceManager<'a>>,
+    ) -> Result<(), InterpreterError> {
+        let dtb_addr = machine_state.hart.xregisters.read(registers::a1);
+        // The total size of the device tree is a big-endian integer at offset 4
+        let dtb_size: u32 = machine_state
+            .bus
+            .read(dtb_addr + 4)
+            .map_err(MachineError::from)?;
+        posix_state.set_program_break(dtb_addr + u32::from_be(dtb_size) as u64);
+        Ok(())
+    }
+
+    /// Restore a memory backend from a snapshot of a previous run. The snapshot
+    /// is obtained using [InMemoryBackend::snapshot] once the [Interpreter] is done.
+    pub fn load_snapshot(snapshot: &[u8]) -> Result<InMemoryBackend<StateLayout>, SnapshotError> {
+        InMemoryBackend::from_snapshot(snapshot)
+    }
+
+    /// Bind the states to [backend].
+    fn bind(backend: &'a mut InMemoryBackend<StateLayout>) -> Self {
         let alloc = backend.allocate(StateLayout::placed().into_location());
-        let posix_state = PosixState::bind(alloc.0);
-        let machine_state = MachineState::<M1G, SliceManager<'a>>::bind(alloc.1);
-        (posix_state, machine_state)
+        Self {
+            posix_state: PosixState::bind(alloc.0),
+            machine_state: MachineState::<M1G, SliceManager<'a>>::bind(alloc.1),
+            sbi_state: SbiState::bind(alloc.2),
+            exec_env: alloc.3,
+        }
+    }
+
+    /// Bind the states to [backend] and boot [program] in [exec_env].
+    fn boot(
+        backend: &'a mut InMemoryBackend<StateLayout>,
+        program: &Program<M1G>,
+        initrd: Option<&[u8]>,
+        exec_env: ExecEnv,
+    ) -> Result<Self, InterpreterError> {
+        let mut interpreter = Self::bind(backend);
+        match exec_env {
+            ExecEnv::Posix(mode) => {
+                interpreter.exec_env.write(EXEC_ENV_POSIX);
+                interpreter.posix_state.set_exit_mode(mode);
+                interpreter
+                    .machine_state
+                    .setup_boot(program, initrd, mode::Mode::Machine)?;
+                Self::setup_program_break(
+                    &mut interpreter.posix_state,
+                    &interpreter.machine_state,
+                )?;
+                interpreter
+                    .posix_state
+                    .setup_process_stack(&mut interpreter.machine_state, program)?;
+            }
+            ExecEnv::Sbi => {
+                interpreter.exec_env.write(EXEC_ENV_SBI);
+                interpreter
+                    .machine_state
+                    .setup_boot(program, initrd, mode::Mode::Supervisor)?;
+            }
+        }
+        Ok(interpreter)
     }
 
     /// Initialise an interpreter with a given [program], starting execution in [mode].
@@ -90,14 +173,39 @@ impl<'a> Interpreter<'a> {
         initrd: Option<&[u8]>,
         mode: mode::Mode,
     ) -> Result<Self, InterpreterError> {
-        let (mut posix_state, mut machine_state) = Self::bind_states(backend);
-        posix_state.set_exit_mode(mode);
+        Self::new_with_exec_env(backend, program, initrd, ExecEnv::Posix(mode))
+    }
+
+    /// Initialise an interpreter with a given [program] running in [exec_env].
+    /// An initial ramdisk can also optionally be passed.
+    pub fn new_with_exec_env(
+        backend: &'a mut InMemoryBackend<StateLayout>,
+        program: &[u8],
+        initrd: Option<&[u8]>,
+        exec_env: ExecEnv,
+    ) -> Result<Self, InterpreterError> {
         let elf_program = Program::<M1G>::from_elf(program)?;
-        machine_state.setup_boot(&elf_program, initrd, mode::Mode::Machine)?;
-        Ok(Self {
-            posix_state,
-            machine_state,
-        })
+        Self::boot(backend, &elf_program, initrd, exec_env)
+    }
+
+    /// Resume an interpreter from the state in [backend], e.g. one restored
+    /// using [Interpreter::load_snapshot].
+    pub fn resume(backend: &'a mut InMemoryBackend<StateLayout>) -> Self {
+        Self::bind(backend)
+    }
+
+    /// Is the program running in the SBI execution environment?
+    fn is_sbi(&self) -> bool {
+        self.exec_env.read() == EXEC_ENV_SBI
+    }
+
+    /// If an exit has been requested, return the exit code.
+    fn exit_code(&self) -> Option<u64> {
+        if self.is_sbi() {
+            self.sbi_state.exit_code()
+        } else {
+            self.posix_state.exit_code()
+        }
     }
 
     fn handle_step_result<F>(
@@ -110,14 +218,18 @@ impl<'a> Interpreter<'a> {
         F: FnMut(&MachineState<M1G, SliceManager<'a>>) -> bool,
     {
         match result.exception {
-            Some(exc) => match self.posix_state.handle_call(&mut self.machine_state, exc) {
+            Some(exc) => match if self.is_sbi() {
+                self.sbi_state.handle_call(&mut self.machine_state, exc)
+            } else {
+                self.posix_state.handle_call(&mut self.machine_state, exc)
+            } {
                 exec_env::EcallOutcome::Fatal => Exception(exc, result.steps),
                 exec_env::EcallOutcome::Handled { continue_eval } => {
                     // Handling the ECall marks the completion of a step
                     result.steps = result.steps.saturating_add(1);
 
                     let steps_left = max.saturating_sub(result.steps);
-                    if let Some(code) = self.posix_state.exit_code() {
+                    if let Some(code) = self.exit_code() {
                         Exit {
                             code: code as usize,
                             steps: result.steps,
@@ -181,6 +293,57 @@ impl<'a> Interpreter<'a> {
     pub fn read_mode(&self) -> mode::Mode {
         self.machine_state.hart.mode.read()
     }
+
+    /// Read `len` bytes of memory starting at physical address `addr`.
+    pub fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>, MachineError> {
+        (0..len as u64)
+            .map(|offset| -> Result<u8, MachineError> {
+                Ok(self.machine_state.bus.read(addr.wrapping_add(offset))?)
+            })
+            .collect()
+    }
+
+    /// Bytes the program has written to the standard output, or to the SBI
+    /// console
+    pub fn stdout(&self) -> &[u8] {
+        if self.is_sbi() {
+            self.sbi_state.console()
+        } else {
+            self.posix_state.stdout()
+        }
+    }
+
+    /// Bytes the program has written to the standard error
+    pub fn stderr(&self) -> &[u8] {
+        self.posix_state.stderr()
+    }
+
+    /// Provide the inbox messages of the SBI execution environment.
+    pub fn set_inbox(&mut self, inbox: Vec<InboxMessage>) {
+        self.sbi_state.set_inbox(inbox)
+    }
+
+    /// Provide the rollup metadata of the SBI execution environment.
+    pub fn set_rollup_metadata(&mut self, metadata: RollupMetadata) {
+        self.sbi_state.set_metadata(metadata)
+    }
+
+    /// Number of times the program found the inbox drained. Reading from a
+    /// drained inbox pauses the evaluation.
+    pub fn inbox_none_count(&self) -> u64 {
+        self.sbi_state.inbox_none_count()
+    }
+
+    /// Bytes the program has written to the UART console
+    pub fn console(&self) -> &[u8] {
+        self.machine_state.bus.devices().uart.output()
+    }
+
+    /// Take the bytes the program has written to the UART console so far.
+    /// This allows draining the console output while the program runs.
+    pub fn take_console(&mut self) -> Vec<u8> {
+        self.machine_state.bus.devices_mut().uart.take_output()
+    }
 }
 
 /// Debugger-specific functions
@@ -194,17 +357,32 @@ impl<'a> Interpreter<'a> {
         initrd: Option<&[u8]>,
         mode: mode::Mode,
     ) -> Result<(Self, BTreeMap<u64, String>), InterpreterError> {
-        let (mut posix_state, mut machine_state) = Self::bind_states(backend);
-        posix_state.set_exit_mode(mode);
         let elf_program = Program::<M1G>::from_elf(program)?;
-        machine_state.setup_boot(&elf_program, initrd, mode::Mode::Machine)?;
-        Ok((
-            Self {
-                posix_state,
-                machine_state,
-            },
-            elf_program.parsed(),
-        ))
+        let interpreter = Self::boot(backend, &elf_program, initrd, ExecEnv::Posix(mode))?;
+        Ok((interpreter, elf_program.parsed()))
+    }
+
+    pub fn write_xregister(&mut self, reg: XRegister, value: u64) {
+        self.machine_state.hart.xregisters.write(reg, value)
+    }
+
+    pub fn write_fregister(&mut self, reg: FRegister, value: FValue) {
+        self.machine_state.hart.fregisters.write(reg, value)
+    }
+
+    pub fn write_pc(&mut self, pc: u64) {
+        self.machine_state.hart.pc.write(pc)
+    }
+
+    /// Write `data` to memory starting at physical address `addr`.
+    pub fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<(), MachineError> {
+        Ok(self.machine_state.bus.write_all(addr, data)?)
+    }
+
+    /// Translate the virtual address `addr` for an access of the given type
+    /// in the current privilege mode.
+    pub fn translate(&self, addr: u64, access_type: AccessType) -> Result<u64, traps::Exception> {
+        self.machine_state.translate(addr, access_type)
     }
 }
 
@@ -7,6 +7,7 @@
 
 mod address_translation;
 pub mod bus;
+pub mod cost;
 pub mod csregisters;
 pub mod hart_state;
 pub mod mode;
@@ -19,10 +20,11 @@ use crate::{
     devicetree,
     machine_state::{
         bus::{main_memory, Address, Addressable, Bus, OutOfBounds},
-        csregisters::CSRegister,
+        cost::{CostModel, StepEvents, UniformCost},
+        csregisters::{CSRValue, CSRegister},
         hart_state::{HartState, HartStateLayout},
     },
-    parser::{instruction::Instr, parse},
+    parser::instruction::Instr,
     program::Program,
     state_backend as backend,
     traps::{EnvironException, Exception, Interrupt, TrapContext},
@@ -37,6 +39,10 @@ pub type MachineStateLayout<ML> = (HartStateLayout, bus::BusLayout<ML>);
 pub struct MachineState<ML: main_memory::MainMemoryLayout, M: backend::Manager> {
     pub hart: HartState<M>,
     pub bus: Bus<ML, M>,
+
+    /// Events of the current step, see [`cost`]. This is not part of the
+    /// machine state.
+    events: std::cell::Cell<StepEvents>,
 }
 
 /// How to modify the program counter
@@ -53,6 +59,10 @@ enum ProgramCounterUpdate {
 pub struct StepManyResult {
     pub steps: usize,
     pub exception: Option<EnvironException>,
+
+    /// Ticks spent according to the cost model, including the step raising
+    /// the exception if any
+    pub ticks: u64,
 }
 
 /// Runs an R-type instruction over [`XRegisters`]
@@ -115,20 +125,18 @@ macro_rules! run_store_instr {
 /// Runs a CSR instruction
 macro_rules! run_csr_instr {
     ($state: ident, $instr: ident, $args: ident, $run_fn: ident) => {{
-        $state
-            .hart
-            .$run_fn($args.csr, $args.rs1, $args.rd)
-            .map(|_| Add($instr.width()))
+        $state.hart.$run_fn($args.csr, $args.rs1, $args.rd)?;
+        $state.csr_written($args.csr);
+        Ok(Add($instr.width()))
     }};
 }
 
 /// Runs a CSR imm instruction
 macro_rules! run_csr_imm_instr {
     ($state: ident, $instr: ident, $args: ident, $run_fn: ident) => {{
-        $state
-            .hart
-            .$run_fn($args.csr, $args.imm as u64, $args.rd)
-            .map(|_| Add($instr.width()))
+        $state.hart.$run_fn($args.csr, $args.imm as u64, $args.rd)?;
+        $state.csr_written($args.csr);
+        Ok(Add($instr.width()))
     }};
 }
 
@@ -190,6 +198,7 @@ impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> MachineState<ML, M>
         Self {
             hart: HartState::bind(space.0),
             bus: Bus::bind(space.1),
+            events: std::cell::Cell::default(),
         }
     }
 
@@ -199,20 +208,64 @@ impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> MachineState<ML, M>
         self.bus.reset();
     }
 
+    /// Drop the decoded instructions and cached translations, which are not
+    /// part of the state. This must be called whenever the bound state is
+    /// loaded or restored from elsewhere, as the caches may belong to the
+    /// previous state.
+    pub fn flush_caches(&mut self) {
+        self.bus.flush_caches();
+    }
+
+    /// Read `len` bytes of guest memory starting at virtual address `addr`,
+    /// on behalf of the execution environment. The access is translated and
+    /// checked against PMP in the mode of the caller. The bytes read are
+    /// recorded for the cost of the environment call.
+    pub fn read_guest_bytes(&self, addr: Address, len: u64) -> Result<Vec<u8>, Exception> {
+        (0..len)
+            .map(|i| {
+                let addr = self.translate(addr.wrapping_add(i), AccessType::Load)?;
+                self.check_pmp(addr, 1, AccessType::Load)?;
+                let byte = self
+                    .bus
+                    .read(addr)
+                    .map_err(|_: OutOfBounds| Exception::LoadAccessFault(addr))?;
+                self.record_environ_bytes(1);
+                Ok(byte)
+            })
+            .collect()
+    }
+
+    /// Write `bytes` to guest memory starting at virtual address `addr`, on
+    /// behalf of the execution environment. The access is translated and
+    /// checked against PMP in the mode of the caller. The bytes written are
+    /// recorded for the cost of the environment call.
+    pub fn write_guest_bytes(&mut self, addr: Address, bytes: &[u8]) -> Result<(), Exception> {
+        for (i, byte) in bytes.iter().enumerate() {
+            let addr = self.translate(addr.wrapping_add(i as u64), AccessType::Store)?;
+            self.check_pmp(addr, 1, AccessType::Store)?;
+            self.bus
+                .write(addr, *byte)
+                .map_err(|_: OutOfBounds| Exception::StoreAccessFault(addr))?;
+            self.record_environ_bytes(1);
+        }
+
+        Ok(())
+    }
+
     /// Fetch instruction from the address given by program counter
-    fn fetch_instr(&self, pc: Address) -> Result<Instr, Exception> {
+    fn fetch_instr(&mut self, pc: Address) -> Result<Instr, Exception> {
         let pc = self.translate(pc, AccessType::Instruction)?;
-        // The resons to provide the second half in the lambda is
-        // because those bytes may be inaccessible or may trigger an exception when read.
-        // Hence we can't read eagerly all 4 bytes.
-        let fetch_result = (|| {
-            let half_instr = self.bus.read(pc)?;
-            parse(half_instr, || self.bus.read(pc + 2))
-        })();
 
         // Transform the out of bounds read error into a
         // RISC-V instruction access fault exception
-        fetch_result.map_err(|_: OutOfBounds| Exception::InstructionAccessFault(pc))
+        let instr = self
+            .bus
+            .read_instr(pc)
+            .map_err(|_: OutOfBounds| Exception::InstructionAccessFault(pc))?;
+
+        self.check_pmp(pc, instr.width(), AccessType::Instruction)?;
+
+        Ok(instr)
     }
 
     /// Advance [`MachineState`] by executing an [`Instr`]
@@ -394,6 +447,7 @@ impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> MachineState<ML, M>
     /// Fetch & run the instruction located at address `instr_pc`
     fn run_instr_at(&mut self, instr_pc: u64) -> Result<ProgramCounterUpdate, Exception> {
         let instr = self.fetch_instr(instr_pc)?;
+        self.record_event(|events| events.instr = Some(instr));
         self.run_instr(instr)
     }
 
@@ -443,6 +497,22 @@ impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> MachineState<ML, M>
         None
     }
 
+    /// Reflect the pending machine timer and software interrupts of the CLINT
+    /// in the MTIP and MSIP bits of `mip`.
+    #[inline(always)]
+    fn update_timer_interrupts(&mut self) {
+        let pending = self.bus.devices().clint.pending_interrupts();
+
+        const CLINT_INTERRUPTS: CSRValue = 1 << Interrupt::MachineTimer.exception_code_const()
+            | 1 << Interrupt::MachineSoftware.exception_code_const();
+
+        let mip = self.hart.csregisters.read(CSRegister::mip);
+        let updated = (mip & !CLINT_INTERRUPTS) | pending;
+        if updated != mip {
+            self.hart.csregisters.write(CSRegister::mip, updated);
+        }
+    }
+
     /// Handle interrupts (also known as asynchronous exceptions)
     /// by taking a trap for the given interrupt.
     ///
@@ -459,6 +529,7 @@ impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> MachineState<ML, M>
         );
 
         let new_pc = self.hart.take_trap(interrupt, current_pc);
+        self.record_event(|events| events.traps += 1);
         Ok(new_pc)
     }
 
@@ -480,6 +551,7 @@ impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> MachineState<ML, M>
             return Err(exc);
         }
 
+        self.record_event(|events| events.traps += 1);
         Ok(self.hart.take_trap(exception, current_pc))
     }
 
@@ -489,6 +561,9 @@ impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> MachineState<ML, M>
     /// The [`Err`] case represents an [`Exception`] to be handled by
     /// the execution environment, narrowed down by the type [`EnvironException`].
     pub fn step(&mut self) -> Result<(), EnvironException> {
+        self.events.set(StepEvents::default());
+        self.update_timer_interrupts();
+
         // Try to take an interrupt if available, and then
         // obtain the pc for the next instruction to be executed
         let instr_pc = match self.get_pending_interrupt() {
@@ -499,10 +574,14 @@ impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> MachineState<ML, M>
         // Fetch & run the instruction
         let instr_result = self.run_instr_at(instr_pc);
 
-        // Take exception if needed
+        // Take exception if needed. The CLINT timer only advances when the
+        // instruction retires.
         let pc_update = match instr_result {
             Err(exc) => ProgramCounterUpdate::Set(self.address_on_exception(exc, instr_pc)?),
-            Ok(upd) => upd,
+            Ok(upd) => {
+                self.bus.devices_mut().clint.tick();
+                upd
+            }
         };
 
         // Update program couter
@@ -517,28 +596,50 @@ impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> MachineState<ML, M>
     /// Perform at most `max` instructions. Returns the number of retired instructions.
     ///
     /// See `mavkit_risc_v_pvm::state::Pvm`
-    pub fn step_many<F>(&mut self, max: usize, mut should_continue: F) -> StepManyResult
+    pub fn step_many<F>(&mut self, max: usize, should_continue: F) -> StepManyResult
     where
         F: FnMut(&Self) -> bool,
     {
-        let mut steps_done = 0;
+        self.step_many_with_budget(max, &UniformCost, u64::MAX, should_continue)
+    }
 
-        while steps_done < max && should_continue(self) {
-            match self.step() {
-                Ok(_) => {}
-                Err(e) => {
-                    return StepManyResult {
-                        steps: steps_done,
-                        exception: Some(e),
-                    }
-                }
-            };
+    /// Perform at most `max` instructions, stopping once `budget` ticks have
+    /// been spent according to `cost_model`. The step exhausting the budget
+    /// is completed, hence the ticks spent may exceed the budget by the cost
+    /// of one step. Returns the number of retired instructions and the ticks
+    /// spent.
+    pub fn step_many_with_budget<C, F>(
+        &mut self,
+        max: usize,
+        cost_model: &C,
+        budget: u64,
+        mut should_continue: F,
+    ) -> StepManyResult
+    where
+        C: CostModel + ?Sized,
+        F: FnMut(&Self) -> bool,
+    {
+        let mut steps_done = 0;
+        let mut ticks = 0u64;
+
+        while steps_done < max && ticks < budget && should_continue(self) {
+            let result = self.step();
+            ticks = ticks.saturating_add(cost_model.step(&self.events.get()));
+
+            if let Err(e) = result {
+                return StepManyResult {
+                    steps: steps_done,
+                    exception: Some(e),
+                    ticks,
+                };
+            }
             steps_done += 1;
         }
 
         StepManyResult {
             steps: steps_done,
             exception: None,
+            ticks,
         }
     }
 
@@ -919,4 +1020,116 @@ mod tests {
             assert_eq!(state.hart.csregisters.read(CSRegister::mip), mip ^ 1 << 9);
         });
     });
+
+    backend_test!(test_step_timer_interrupt, F, {
+        use crate::machine_state::bus::{
+            devices::clint::CLINT_START, main_memory::MainMemoryLayout, Addressable,
+        };
+
+        let mut backend = create_backend!(MachineStateLayout<T1K>, F);
+        let mut state = create_state!(MachineState, MachineStateLayout<T1K>, F, backend, T1K);
+        state.reset();
+
+        // Fill the memory with NOPs (ADDI x0, x0, 0)
+        let start = bus::start_of_main_memory::<T1K>();
+        const NOP: u32 = 0x13;
+        state.bus.write_all(start, &[NOP; T1K::BYTES / 4]).unwrap();
+
+        let mtvec_addr = start + 0x100;
+        state.hart.csregisters.write(CSRegister::mtvec, mtvec_addr);
+        state.hart.csregisters.write(
+            CSRegister::mie,
+            1 << Interrupt::MachineTimer.exception_code(),
+        );
+        let mstatus = xstatus::set_MIE(state.hart.csregisters.read(CSRegister::mstatus), true);
+        state.hart.csregisters.write(CSRegister::mstatus, mstatus);
+        state.hart.mode.write(Mode::Machine);
+        state.hart.pc.write(start);
+
+        // The timer interrupt fires once mtime reaches mtimecmp
+        state.bus.write(CLINT_START + 0x4000, 3u64).unwrap();
+        for _ in 0..3 {
+            state.step().unwrap();
+        }
+        assert_eq!(state.hart.pc.read(), start + 12);
+
+        state.step().unwrap();
+        assert_eq!(state.hart.pc.read(), mtvec_addr + 4);
+        assert_eq!(state.hart.csregisters.read(CSRegister::mepc), start + 12);
+        assert_eq!(
+            state.hart.csregisters.read(CSRegister::mcause),
+            Interrupt::MachineTimer.xcause()
+        );
+
+        // The interrupt stays pending until mtimecmp is updated
+        let mtip = 1 << Interrupt::MachineTimer.exception_code();
+        state.step().unwrap();
+        assert_eq!(state.hart.csregisters.read(CSRegister::mip) & mtip, mtip);
+        state.bus.write(CLINT_START + 0x4000, u64::MAX).unwrap();
+        state.step().unwrap();
+        assert_eq!(state.hart.csregisters.read(CSRegister::mip) & mtip, 0);
+
+        // mtime only advances for retired instructions, not for faulting ones
+        assert_eq!(state.bus.devices().clint.mtime(), 6);
+        const ECALL: u32 = 0x73;
+        state.bus.write(state.hart.pc.read(), ECALL).unwrap();
+        assert!(state.step().is_err());
+        assert_eq!(state.bus.devices().clint.mtime(), 6);
+    });
+
+    #[test]
+    fn test_step_proof() {
+        use crate::state_backend::{
+            memory_backend::InMemoryBackend,
+            proof_backend::{prove, verify, Proof},
+            AllocatedOf, Backend, Manager,
+        };
+
+        type L = MachineStateLayout<T1K>;
+
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        let init_pc_addr = bus::start_of_main_memory::<T1K>();
+        {
+            let mut state: MachineState<T1K, _> = MachineState::bind(backend.allocate(placed));
+            state.reset();
+
+            // ADDI a1, a1, 42
+            state
+                .hart
+                .xregisters
+                .write(a1, 42 << 20 | 0b01011 << 15 | 0b01011 << 7 | 0b0010011);
+            state.hart.xregisters.write(a2, init_pc_addr);
+            state
+                .run_sw(0, a2, a1)
+                .expect("Storing instruction should succeed");
+            state.hart.xregisters.write(a1, 0);
+            state.hart.pc.write(init_pc_addr);
+        }
+
+        fn step<M: Manager>(space: AllocatedOf<L, M>) -> (u64, u64) {
+            let mut state: MachineState<T1K, _> = MachineState::bind(space);
+            state.step().expect("should not raise trap to EE");
+            (state.hart.pc.read(), state.hart.xregisters.read(a1))
+        }
+
+        let initial_hash = backend.root_hash();
+        let (result, proof) = prove(&mut backend, |space| step(space));
+        let final_hash = backend.root_hash();
+
+        assert_eq!(result, (init_pc_addr + 4, 42));
+        assert_eq!(proof.initial_root_hash(), initial_hash);
+
+        let proof = Proof::from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(
+            verify::<L, _>(&proof, |space| step(space)),
+            Some((result, final_hash))
+        );
+
+        // A proof for a different transition doesn't suffice
+        let (_, other_proof) = prove(&mut backend, |space| {
+            let state: MachineState<T1K, _> = MachineState::bind(space);
+            state.hart.pc.read()
+        });
+        assert_eq!(verify::<L, _>(&other_proof, |space| step(space)), None);
+    }
 }
@@ -3,13 +3,15 @@
 // SPDX-License-Identifier: MIT
 
 use crate::{state_backend as backend, traps::Exception};
+use tlb::TlbEntry;
 
 use super::{
-    bus::{main_memory, Address, Addressable, Bus, OutOfBounds},
+    bus::{self, main_memory, Address, Addressable, Bus, OutOfBounds},
     csregisters::{
         fields::FieldValue,
+        pmp,
         satp::{self, SvLength, TranslationAlgorithm},
-        CSRValue, CSRegister,
+        xstatus, CSRValue, CSRegister, CSRegisters,
     },
     mode::Mode,
     MachineState,
@@ -17,6 +19,7 @@ use super::{
 
 mod physical_address;
 mod pte;
+pub(super) mod tlb;
 mod virtual_address;
 
 /// Offset of the `page offset` field in virtual and physical addresses.
@@ -40,6 +43,14 @@ impl AccessType {
             AccessType::Store => Exception::StoreAMOPageFault(addr),
         }
     }
+
+    fn access_fault(&self, addr: Address) -> Exception {
+        match self {
+            AccessType::Instruction => Exception::InstructionAccessFault(addr),
+            AccessType::Load => Exception::LoadAccessFault(addr),
+            AccessType::Store => Exception::StoreAccessFault(addr),
+        }
+    }
 }
 
 pub struct SvConstants {
@@ -67,14 +78,29 @@ impl SvLength {
     }
 }
 
-/// Implementation of the virtual address translation as explained in section 5.3.2.
-fn sv_translate_impl<ML, M>(
+/// Maximum number of page table levels of all translation algorithms
+const MAX_LEVELS: usize = 5;
+
+/// Leaf page table entry found by a page table walk
+struct Walk {
+    entry: TlbEntry,
+
+    /// Physical addresses of the page table entries read during the walk
+    table_addrs: [Address; MAX_LEVELS],
+    tables: usize,
+}
+
+/// Page table walk of the virtual address translation as explained in
+/// section 5.3.2. The permission and A/D checks of steps 5 and 7 are done by
+/// [`check_leaf`], as they also apply to translations cached in the TLB.
+fn sv_walk<ML, M>(
     bus: &Bus<ML, M>,
+    csregisters: &CSRegisters<M>,
     v_addr: Address,
     satp: CSRValue,
     sv_length: SvLength,
     access_type: &AccessType,
-) -> Result<Address, Exception>
+) -> Result<Walk, Exception>
 where
     ML: main_memory::MainMemoryLayout,
     M: backend::Manager,
@@ -84,6 +110,9 @@ where
 
     let SvConstants { levels, pte_size } = sv_length.algorithm_constants();
 
+    let mut table_addrs = [0; MAX_LEVELS];
+    let mut tables = 0;
+
     // 1. Let a be satp.ppn × PAGESIZE, and let i = LEVELS − 1.
     let mut i = levels.saturating_sub(1);
     let mut a: Address = satp::get_PPN(satp).value() * PAGE_SIZE;
@@ -91,14 +120,24 @@ where
     let mut pte: u64;
     loop {
         // 2. Let pte be the value of the PTE at address a + va.vpn[i] × PTESIZE.
-        // TODO: If accessing pte violates a PMA or PMP check, raise an access-fault exception corresponding
-        // to the original access type.
+        //    If accessing pte violates a PMA or PMP check, raise an access-fault
+        //    exception corresponding to the original access type.
         let vpn_i =
             v_addr::get_VPN_IDX(v_addr, &sv_length, i).ok_or(access_type.exception(v_addr))?;
         let addr = a + vpn_i * pte_size;
+
+        // Page tables must reside in main memory. Page table accesses are
+        // checked against PMP as S-mode loads.
+        if !bus::is_main_memory::<ML>(addr, pte_size)
+            || !csregisters.pmp_check(Mode::Supervisor, addr, pte_size, &AccessType::Load)
+        {
+            return Err(access_type.access_fault(v_addr));
+        }
         pte = bus
             .read(addr)
-            .map_err(|_: OutOfBounds| Exception::LoadAccessFault(addr))?;
+            .map_err(|_: OutOfBounds| access_type.access_fault(v_addr))?;
+        table_addrs[tables] = addr;
+        tables += 1;
 
         // 3. If pte.v = 0, or if pte.r = 0 and pte.w = 1, stop and raise a page-fault
         //    exception corresponding to the original access type.
@@ -126,12 +165,8 @@ where
         a = pte::get_PPN(pte).raw_bits() * PAGE_SIZE;
     }
 
-    // TODO: implement step 5 (MXR & SUM aware translation)
-    // 5. A leaf PTE has been found. Determine if the requested memory access is
-    //    allowed by the pte.r, pte.w, pte.x, and pte.u bits, given the current
-    //    privilege mode and the value of the SUM and MXR fields of the mstatus
-    //    register. If not, stop and raise a page-fault exception corresponding
-    //    to the original access type.
+    // 5. A leaf PTE has been found. The permission checks are done by
+    //    `check_leaf`.
 
     // 6. If i > 0 and pte.ppn[i−1:0] != 0, this is a misaligned superpage; stop and
     //    raise a page-fault exception corresponding to the original access type.
@@ -142,22 +177,7 @@ where
         }
     }
 
-    // 7. If pte.a = 0, or if the memory access is a store and pte.d = 0, either raise
-    //    a page-fault exception corresponding to the original access type, or:
-    //    • Set pte.a to 1 and, if the memory access is a store, also set pte.d to 1.
-    //    • If this access violates a PMA or PMP check, raise an access exception
-    //    corresponding to the original access type.
-    //    • Perform atomically:
-    //      – Compare pte to the value of the PTE at address a + va.vpn[i] × PTESIZE.
-    //      – If the values match, set pte.a to 1 and, if the original memory access is a store, also
-    //      set pte.d to 1.
-    //      – If the comparison fails, return to step 2
-    let pte_a = pte::get_FLAG_A(pte);
-    let pte_d = pte::get_FLAG_D(pte);
-    if !pte_a || (*access_type == AccessType::Store && !pte_d) {
-        // Trying first case for now
-        return Err(access_type.exception(v_addr));
-    }
+    // 7. The A/D checks are done by `check_leaf`.
 
     // 8. The translation is successful. The translated physical address is given as
     //    follows:
@@ -165,9 +185,9 @@ where
     //    • If i > 0, then this is a superpage translation and pa.ppn[i−1:0] =
     //    va.vpn[i−1:0].
     //    • pa.ppn[LEVELS−1:i] = pte.ppn[LEVELS−1:i].
-    let va_page_offset = v_addr::get_PAGE_OFFSET(v_addr);
-    let p_addr = (|| {
-        let mut pa = p_addr::set_PAGE_OFFSET(0, va_page_offset);
+    //    The page offset is added when the translation is used.
+    let p_page = (|| {
+        let mut pa = 0;
         for idx in 0..i {
             let va_vpn_i = v_addr::get_VPN_IDX(v_addr, &sv_length, idx)?;
             pa = p_addr::set_PPN_IDX(pa, &sv_length, idx, va_vpn_i)?;
@@ -177,13 +197,80 @@ where
             pa = p_addr::set_PPN_IDX(pa, &sv_length, idx, pte_ppn_i)?;
         }
         Some(pa)
-    })();
+    })()
+    .ok_or(access_type.exception(v_addr))?;
 
-    p_addr.ok_or(access_type.exception(v_addr))
+    Ok(Walk {
+        entry: TlbEntry { pte, p_page },
+        table_addrs,
+        tables,
+    })
+}
+
+/// Steps 5 and 7 of the virtual address translation as explained in section
+/// 5.3.2: determine whether the leaf page table entry allows the access.
+fn check_leaf(pte: u64, access_type: &AccessType, mode: Mode, mstatus: CSRValue) -> bool {
+    // 5. A leaf PTE has been found. Determine if the requested memory access is
+    //    allowed by the pte.r, pte.w, pte.x, and pte.u bits, given the current
+    //    privilege mode and the value of the SUM and MXR fields of the mstatus
+    //    register. If not, stop and raise a page-fault exception corresponding
+    //    to the original access type.
+    let allowed = match access_type {
+        AccessType::Instruction => pte::get_FLAG_X(pte),
+        // Section 3.1.6.3: MXR makes loads from executable pages succeed.
+        AccessType::Load => {
+            pte::get_FLAG_R(pte) || (xstatus::get_MXR(mstatus) && pte::get_FLAG_X(pte))
+        }
+        AccessType::Store => pte::get_FLAG_W(pte),
+    };
+
+    // Section 5.3.1: U-mode may only access pages with pte.u = 1. S-mode may
+    // only load from and store to such pages if SUM is set, and may never
+    // execute them.
+    let user_page = pte::get_FLAG_U(pte);
+    let privilege_ok = match mode {
+        Mode::User => user_page,
+        Mode::Supervisor => {
+            !user_page || (*access_type != AccessType::Instruction && xstatus::get_SUM(mstatus))
+        }
+        Mode::Machine => true,
+    };
+
+    // 7. If pte.a = 0, or if the memory access is a store and pte.d = 0, either raise
+    //    a page-fault exception corresponding to the original access type, or
+    //    update the A/D bits. We always raise the page-fault exception.
+    let pte_a = pte::get_FLAG_A(pte);
+    let pte_d = pte::get_FLAG_D(pte);
+    let accessed_ok = pte_a && (*access_type != AccessType::Store || pte_d);
+
+    allowed && privilege_ok && accessed_ok
 }
 
 impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> MachineState<ML, M> {
+    /// Privilege mode in which an access of `access_type` is performed.
+    /// Section 3.1.6.3: when `mstatus.MPRV` is set, loads and stores of M-mode
+    /// are translated and protected as though the mode were `mstatus.MPP`.
+    pub fn effective_mode(&self, access_type: &AccessType) -> Mode {
+        let mode = self.hart.mode.read();
+        if mode != Mode::Machine || *access_type == AccessType::Instruction {
+            return mode;
+        }
+
+        let mstatus = self.hart.csregisters.read(CSRegister::mstatus);
+        if !xstatus::get_MPRV(mstatus) {
+            return mode;
+        }
+
+        match xstatus::get_MPP(mstatus) {
+            xstatus::MPPValue::User => Mode::User,
+            xstatus::MPPValue::Supervisor => Mode::Supervisor,
+            xstatus::MPPValue::Machine => Mode::Machine,
+        }
+    }
+
     /// Translate a virtual address to a physical address as described in section 5.3.2
+    ///
+    /// Translations are cached in the TLB of the bus, see [`tlb`].
     pub fn translate(
         &self,
         v_addr: Address,
@@ -192,21 +279,268 @@ impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> MachineState<ML, M>
         // 1. Let a be satp.ppn × PAGESIZE, and let i = LEVELS − 1.
         //    The satp register must be active, i.e.,
         //    the effective privilege mode must be S-mode or U-mode.
-        match self.hart.mode.read() {
-            Mode::User | Mode::Supervisor => (),
-            Mode::Machine => return Ok(v_addr),
-        };
+        let mode = self.effective_mode(&access_type);
+        if let Mode::Machine = mode {
+            return Ok(v_addr);
+        }
         let satp = self.hart.csregisters.read(CSRegister::satp);
-        let mode = satp::get_MODE(satp);
 
-        match mode {
+        let sv_length = match satp::get_MODE(satp) {
             // An invalid mode should not be writable, but it is treated as BARE
-            None => Ok(v_addr),
-            Some(TranslationAlgorithm::Bare) => Ok(v_addr),
-            Some(TranslationAlgorithm::Sv(length)) => {
-                sv_translate_impl(&self.bus, v_addr, satp, length, &access_type)
-                    .map_err(|_e| access_type.exception(v_addr))
+            None | Some(TranslationAlgorithm::Bare) => return Ok(v_addr),
+            Some(TranslationAlgorithm::Sv(length)) => length,
+        };
+
+        let levels = sv_length.algorithm_constants().levels as u64;
+        self.record_event(|events| events.page_table_levels += levels);
+
+        let mut tlb = self.bus.tlb();
+        let entry = match tlb.lookup(satp, v_addr) {
+            Some(entry) => entry,
+            None => {
+                let walk = sv_walk(
+                    &self.bus,
+                    &self.hart.csregisters,
+                    v_addr,
+                    satp,
+                    sv_length,
+                    &access_type,
+                )?;
+                tlb.insert(satp, v_addr, walk.entry, &walk.table_addrs[..walk.tables]);
+                walk.entry
             }
+        };
+
+        let mstatus = self.hart.csregisters.read(CSRegister::mstatus);
+        if !check_leaf(entry.pte, &access_type, mode, mstatus) {
+            return Err(access_type.exception(v_addr));
         }
+
+        Ok(entry.p_page | (v_addr % PAGE_SIZE))
     }
+
+    /// Check an access of `len` bytes to the physical address `addr` against
+    /// PMP, raising an access-fault exception if it is not allowed.
+    #[inline(always)]
+    pub fn check_pmp(
+        &self,
+        addr: Address,
+        len: u64,
+        access_type: AccessType,
+    ) -> Result<(), Exception> {
+        let mode = self.effective_mode(&access_type);
+        if self
+            .hart
+            .csregisters
+            .pmp_check(mode, addr, len, &access_type)
+        {
+            Ok(())
+        } else {
+            Err(access_type.access_fault(addr))
+        }
+    }
+
+    /// Notify the translation caches of a write to a CSR. PMP checks of
+    /// page table accesses are cached in the TLB, hence it is flushed when
+    /// the PMP configuration changes.
+    #[inline(always)]
+    pub(crate) fn csr_written(&mut self, csr: CSRegister) {
+        if pmp::is_pmp_register(csr) {
+            self.bus.tlb().flush_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessType, PAGE_SIZE};
+    use crate::{
+        backend_test, create_backend, create_state,
+        machine_state::{
+            bus::{main_memory::Sizes, start_of_main_memory, Addressable},
+            csregisters::{xstatus, CSRValue, CSRegister},
+            mode::Mode,
+            registers::{a0, a1, x0},
+            MachineState, MachineStateLayout,
+        },
+        traps::Exception,
+    };
+
+    type M64K = Sizes<{ 64 * 1024 }>;
+
+    const V: u64 = 1 << 0;
+    const R: u64 = 1 << 1;
+    const W: u64 = 1 << 2;
+    const X: u64 = 1 << 3;
+    const U: u64 = 1 << 4;
+    const G: u64 = 1 << 5;
+    const A: u64 = 1 << 6;
+    const D: u64 = 1 << 7;
+
+    backend_test!(test_translate, F, {
+        type L = MachineStateLayout<M64K>;
+        let mut backend = create_backend!(L, F);
+        let mut state = create_state!(MachineState, L, F, backend, M64K);
+
+        let mem = start_of_main_memory::<M64K>();
+        let (root, l1, l0) = (mem + PAGE_SIZE, mem + 2 * PAGE_SIZE, mem + 3 * PAGE_SIZE);
+        let (page, other_page) = (mem + 4 * PAGE_SIZE, mem + 5 * PAGE_SIZE);
+        let make_pte = |addr: u64, flags: u64| ((addr / PAGE_SIZE) << 10) | flags;
+
+        // Sv39 mapping of v_addr to page through root[0], l1[2] and l0[0]
+        let v_addr = 2 << 21;
+        state.bus.write(root, make_pte(l1, V)).unwrap();
+        state.bus.write(l1 + 2 * 8, make_pte(l0, V)).unwrap();
+        let leaf = l0;
+
+        let satp = (8 << 60) | (root / PAGE_SIZE);
+        state.hart.csregisters.write(CSRegister::satp, satp);
+
+        let set_leaf = |state: &mut MachineState<_, _>, flags: u64| {
+            state
+                .bus
+                .write(leaf, make_pte(page, V | A | flags))
+                .unwrap();
+        };
+        let set_mstatus = |state: &mut MachineState<_, _>, bits: CSRValue| {
+            state.hart.csregisters.write(CSRegister::mstatus, bits);
+        };
+        let sum = 1 << xstatus::SUM.offset;
+        let mxr = 1 << xstatus::MXR.offset;
+
+        // U-mode only accesses user pages
+        set_leaf(&mut state, R | W | X | D);
+        state.hart.mode.write(Mode::User);
+        assert_eq!(
+            state.translate(v_addr + 8, AccessType::Load),
+            Err(Exception::LoadPageFault(v_addr + 8))
+        );
+        set_leaf(&mut state, R | W | X | D | U);
+        assert_eq!(state.translate(v_addr + 8, AccessType::Load), Ok(page + 8));
+        assert_eq!(state.translate(v_addr, AccessType::Instruction), Ok(page));
+
+        // S-mode accesses user pages only with SUM, and never executes them
+        state.hart.mode.write(Mode::Supervisor);
+        assert_eq!(
+            state.translate(v_addr, AccessType::Store),
+            Err(Exception::StoreAMOPageFault(v_addr))
+        );
+        set_mstatus(&mut state, sum);
+        assert_eq!(state.translate(v_addr, AccessType::Store), Ok(page));
+        assert_eq!(
+            state.translate(v_addr, AccessType::Instruction),
+            Err(Exception::InstructionPageFault(v_addr))
+        );
+
+        // Loads from execute-only pages need MXR
+        set_leaf(&mut state, X);
+        assert_eq!(
+            state.translate(v_addr, AccessType::Load),
+            Err(Exception::LoadPageFault(v_addr))
+        );
+        set_mstatus(&mut state, mxr);
+        assert_eq!(state.translate(v_addr, AccessType::Load), Ok(page));
+
+        // Stores to clean pages fault
+        set_leaf(&mut state, R | W);
+        assert_eq!(state.translate(v_addr, AccessType::Load), Ok(page));
+        assert_eq!(
+            state.translate(v_addr, AccessType::Store),
+            Err(Exception::StoreAMOPageFault(v_addr))
+        );
+
+        // Writing to the page tables is picked up by cached translations
+        state
+            .bus
+            .write(leaf, make_pte(other_page, V | A | D | R | W))
+            .unwrap();
+        assert_eq!(state.translate(v_addr, AccessType::Store), Ok(other_page));
+
+        // Page tables outside of main memory cause access faults
+        state.hart.csregisters.write(CSRegister::satp, 8 << 60);
+        assert_eq!(
+            state.translate(v_addr, AccessType::Instruction),
+            Err(Exception::InstructionAccessFault(v_addr))
+        );
+        assert_eq!(
+            state.translate(v_addr, AccessType::Store),
+            Err(Exception::StoreAccessFault(v_addr))
+        );
+        state.hart.csregisters.write(CSRegister::satp, satp);
+        assert_eq!(state.translate(v_addr, AccessType::Load), Ok(other_page));
+
+        // SFENCE.VMA flushes the TLB
+        let cached = |state: &MachineState<_, _>| state.bus.tlb().lookup(satp, v_addr).is_some();
+        assert!(cached(&state));
+        state.hart.xregisters.write(a0, v_addr + PAGE_SIZE);
+        state.sfence_vma(x0, a0).unwrap();
+        assert!(cached(&state));
+        state.hart.xregisters.write(a0, v_addr);
+        state.sfence_vma(x0, a0).unwrap();
+        assert!(!cached(&state));
+
+        // Flushing an address space keeps global translations
+        state
+            .bus
+            .write(leaf, make_pte(page, V | A | R | G))
+            .unwrap();
+        state.translate(v_addr, AccessType::Load).unwrap();
+        state.hart.xregisters.write(a1, 0);
+        state.sfence_vma(a1, x0).unwrap();
+        assert!(cached(&state));
+        state.sfence_vma(x0, x0).unwrap();
+        assert!(!cached(&state));
+
+        // Loading or restoring the state flushes the TLB
+        state.translate(v_addr, AccessType::Load).unwrap();
+        assert!(cached(&state));
+        state.flush_caches();
+        assert!(!cached(&state));
+    });
+
+    backend_test!(test_mprv_pmp, F, {
+        type L = MachineStateLayout<M64K>;
+        let mut backend = create_backend!(L, F);
+        let mut state = create_state!(MachineState, L, F, backend, M64K);
+
+        // A single read-only TOR entry covers the first page of main memory
+        let mem = start_of_main_memory::<M64K>();
+        state.hart.csregisters.write(CSRegister::pmpaddr0, mem >> 2);
+        state
+            .hart
+            .csregisters
+            .write(CSRegister::pmpaddr1, (mem + PAGE_SIZE) >> 2);
+        let pmpcfg0 = CSRValue::from_le_bytes([0, (1 << 3) | 1, 0, 0, 0, 0, 0, 0]);
+        state.hart.csregisters.write(CSRegister::pmpcfg0, pmpcfg0);
+
+        // M-mode is not restricted by the unlocked entry
+        state.hart.mode.write(Mode::Machine);
+        assert_eq!(state.check_pmp(mem, 8, AccessType::Store), Ok(()));
+        assert_eq!(state.write_guest_bytes(mem, &[1, 2]), Ok(()));
+
+        // With MPRV, loads and stores are checked in the mode given by MPP,
+        // but instruction fetches are not
+        let mstatus = (1 << xstatus::MPRV.offset) | (0b01 << xstatus::MPP.offset);
+        state.hart.csregisters.write(CSRegister::mstatus, mstatus);
+        assert_eq!(state.effective_mode(&AccessType::Load), Mode::Supervisor);
+        assert_eq!(state.check_pmp(mem, 8, AccessType::Load), Ok(()));
+        assert_eq!(
+            state.check_pmp(mem, 8, AccessType::Store),
+            Err(Exception::StoreAccessFault(mem))
+        );
+        assert_eq!(state.check_pmp(mem, 4, AccessType::Instruction), Ok(()));
+
+        // The environment accesses guest memory in the mode of the caller
+        state.hart.csregisters.write(CSRegister::mstatus, 0);
+        state.hart.mode.write(Mode::User);
+        assert_eq!(state.read_guest_bytes(mem, 2), Ok(vec![1, 2]));
+        assert_eq!(
+            state.read_guest_bytes(mem + PAGE_SIZE - 1, 2),
+            Err(Exception::LoadAccessFault(mem + PAGE_SIZE))
+        );
+        assert_eq!(
+            state.write_guest_bytes(mem, &[3]),
+            Err(Exception::StoreAccessFault(mem))
+        );
+    });
 }
@@ -0,0 +1,132 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Translation look-aside buffer
+//!
+//! The TLB caches the leaf page table entries found by page table walks for
+//! the address space selected by `satp`. Changing `satp` flushes the TLB, as
+//! does `SFENCE.VMA` for the given address space and virtual address.
+//!
+//! The TLB also keeps track of the physical pages holding the page table
+//! entries read during the walks. Writing to any of these pages flushes the
+//! TLB. Hence a cached translation is always the one a page table walk would
+//! yield, which keeps translation deterministic even though the TLB isn't
+//! part of the machine state.
+//!
+//! As the TLB lives outside the state, it starts empty whenever a state is
+//! bound and is flushed whenever the bound state is reset, loaded or
+//! restored, see [`crate::machine_state::MachineState::flush_caches`].
+
+use super::{pte, PAGE_SIZE};
+use crate::machine_state::{
+    bus::Address,
+    csregisters::{satp, CSRValue},
+};
+use std::collections::{HashMap, HashSet};
+
+/// Maximum number of cached translations. The TLB is flushed when full.
+const TLB_CAPACITY: usize = 4096;
+
+/// Cached translation of a virtual page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlbEntry {
+    /// Leaf page table entry
+    pub pte: u64,
+
+    /// Physical address of the start of the virtual page
+    pub p_page: Address,
+}
+
+/// Translation look-aside buffer
+#[derive(Default)]
+pub struct Tlb {
+    satp: CSRValue,
+    entries: HashMap<Address, TlbEntry>,
+    table_pages: HashSet<Address>,
+}
+
+impl Tlb {
+    /// Look up the translation of the virtual page containing `v_addr` in the
+    /// address space given by `satp`.
+    #[inline(always)]
+    pub fn lookup(&mut self, satp: CSRValue, v_addr: Address) -> Option<TlbEntry> {
+        if self.satp != satp {
+            self.flush_all();
+            self.satp = satp;
+            return None;
+        }
+
+        self.entries.get(&(v_addr / PAGE_SIZE)).copied()
+    }
+
+    /// Cache the translation of the virtual page containing `v_addr` in the
+    /// address space given by `satp`. `table_addrs` are the physical
+    /// addresses of the page table entries read to find the leaf entry.
+    pub fn insert(
+        &mut self,
+        satp: CSRValue,
+        v_addr: Address,
+        entry: TlbEntry,
+        table_addrs: &[Address],
+    ) {
+        if self.satp != satp || self.entries.len() >= TLB_CAPACITY {
+            self.flush_all();
+            self.satp = satp;
+        }
+
+        self.entries.insert(v_addr / PAGE_SIZE, entry);
+        self.table_pages
+            .extend(table_addrs.iter().map(|addr| addr / PAGE_SIZE));
+    }
+
+    /// Flush the translations as `SFENCE.VMA` does. `asid` and `v_addr`
+    /// restrict the flush to an address space and a virtual page.
+    /// Translations of global pages are kept when flushing an address space.
+    pub fn flush(&mut self, asid: Option<CSRValue>, v_addr: Option<Address>) {
+        match asid {
+            // Cached translations only belong to the current address space.
+            Some(asid) if asid != satp::get_ASID(self.satp).value() => {}
+
+            Some(_) => {
+                let is_global = |entry: &TlbEntry| pte::get_FLAG_G(entry.pte);
+                match v_addr {
+                    Some(v_addr) => {
+                        let v_page = v_addr / PAGE_SIZE;
+                        if self.entries.get(&v_page).is_some_and(|e| !is_global(e)) {
+                            self.entries.remove(&v_page);
+                        }
+                    }
+                    None => self.entries.retain(|_, entry| is_global(entry)),
+                }
+            }
+
+            None => match v_addr {
+                Some(v_addr) => {
+                    self.entries.remove(&(v_addr / PAGE_SIZE));
+                }
+                None => self.flush_all(),
+            },
+        }
+    }
+
+    /// Flush all cached translations.
+    pub fn flush_all(&mut self) {
+        self.entries.clear();
+        self.table_pages.clear();
+    }
+
+    /// Notify the TLB of a write of `len` bytes to physical address `addr`.
+    #[inline(always)]
+    pub fn invalidate(&mut self, addr: Address, len: usize) {
+        if self.table_pages.is_empty() || len == 0 {
+            return;
+        }
+
+        let first = addr / PAGE_SIZE;
+        let last = addr.saturating_add(len as Address - 1) / PAGE_SIZE;
+        if (first..=last).any(|page| self.table_pages.contains(&page)) {
+            self.flush_all();
+        }
+    }
+}
@@ -3,11 +3,19 @@
 // SPDX-License-Identifier: MIT
 
 pub mod devices;
+mod instruction_cache;
 pub mod main_memory;
 
-use crate::machine_state::{backend, registers};
+use crate::{
+    machine_state::{address_translation::tlb::Tlb, backend, registers},
+    parser::{instruction::Instr, parse},
+};
 use derive_more::Error;
-use std::mem;
+use instruction_cache::InstructionCache;
+use std::{
+    cell::{RefCell, RefMut},
+    mem,
+};
 
 /// Bus address
 pub type Address = registers::XValue;
@@ -78,6 +86,8 @@ pub type BusLayout<ML> = (devices::DevicesLayout, ML);
 pub struct Bus<ML: main_memory::MainMemoryLayout, M: backend::Manager> {
     devices: devices::Devices<M>,
     memory: main_memory::MainMemory<ML, M>,
+    instruction_cache: InstructionCache,
+    tlb: RefCell<Tlb>,
 }
 
 impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> Bus<ML, M> {
@@ -86,6 +96,8 @@ impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> Bus<ML, M> {
         Self {
             devices: devices::Devices::bind(space.0),
             memory: main_memory::MainMemory::bind(space.1),
+            instruction_cache: InstructionCache::default(),
+            tlb: RefCell::default(),
         }
     }
 
@@ -93,7 +105,75 @@ impl<ML: main_memory::MainMemoryLayout, M: backend::Manager> Bus<ML, M> {
     pub fn reset(&mut self) {
         self.devices.reset();
         self.memory.reset();
+        self.instruction_cache.invalidate_all();
+        self.tlb.get_mut().flush_all();
     }
+
+    /// Fetch and decode the instruction at the given physical address.
+    /// Instructions in main memory are served from the instruction cache when
+    /// possible.
+    #[inline(always)]
+    pub fn read_instr(&mut self, addr: Address) -> Result<Instr, OutOfBounds> {
+        if let Some(instr) = self.instruction_cache.get(addr) {
+            return Ok(instr);
+        }
+
+        // The second half of the instruction is only read if needed because
+        // those bytes may be inaccessible.
+        let instr = parse(self.read(addr)?, || self.read(addr + 2))?;
+
+        if let (AddressSpace::MainMemory, _) = AddressSpace::locate::<ML>(addr) {
+            self.instruction_cache.insert(addr, instr);
+        }
+
+        Ok(instr)
+    }
+
+    /// Drop all decoded instructions from the instruction cache.
+    pub fn invalidate_instruction_cache(&mut self) {
+        self.instruction_cache.invalidate_all();
+    }
+
+    /// Drop all decoded instructions and cached translations, see
+    /// [`super::MachineState::flush_caches`].
+    pub(crate) fn flush_caches(&mut self) {
+        self.instruction_cache.invalidate_all();
+        self.tlb.get_mut().flush_all();
+    }
+
+    /// Access the devices.
+    pub fn devices(&self) -> &devices::Devices<M> {
+        &self.devices
+    }
+
+    /// Access the devices mutably.
+    pub fn devices_mut(&mut self) -> &mut devices::Devices<M> {
+        &mut self.devices
+    }
+
+    /// Access the translation look-aside buffer.
+    pub(crate) fn tlb(&self) -> RefMut<'_, Tlb> {
+        self.tlb.borrow_mut()
+    }
+
+    /// Drop all cached decodings of the written bytes.
+    #[inline(always)]
+    fn invalidate_caches(&mut self, addr: Address, len: usize) {
+        self.instruction_cache.invalidate(addr, len);
+        self.tlb.get_mut().invalidate(addr, len);
+    }
+}
+
+/// Check whether the `len` bytes starting at `addr` lie in main memory.
+pub fn is_main_memory<ML: main_memory::MainMemoryLayout>(addr: Address, len: u64) -> bool {
+    let end_addr = addr.saturating_add(len.saturating_sub(1));
+    matches!(
+        AddressSpace::locate::<ML>(addr),
+        (AddressSpace::MainMemory, _)
+    ) && matches!(
+        AddressSpace::locate::<ML>(end_addr),
+        (AddressSpace::MainMemory, _)
+    )
 }
 
 /// Address of where the main memory starts.
@@ -124,7 +204,10 @@ where
         let (addr_space, local_address) = AddressSpace::locate::<ML>(addr);
         match addr_space {
             AddressSpace::Devices => self.devices.write(local_address, value),
-            AddressSpace::MainMemory => self.memory.write(local_address, value),
+            AddressSpace::MainMemory => {
+                self.invalidate_caches(addr, mem::size_of::<E>());
+                self.memory.write(local_address, value)
+            }
             AddressSpace::OutOfBounds => Err(OutOfBounds),
         }
     }
@@ -143,7 +226,10 @@ where
 
         match addr_space {
             AddressSpace::Devices => self.devices.write_all(local_addr, values),
-            AddressSpace::MainMemory => self.memory.write_all(local_addr, values),
+            AddressSpace::MainMemory => {
+                self.invalidate_caches(addr, mem::size_of_val(values));
+                self.memory.write_all(local_addr, values)
+            }
             AddressSpace::OutOfBounds => Err(OutOfBounds),
         }
     }
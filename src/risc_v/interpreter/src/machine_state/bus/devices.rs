@@ -5,8 +5,12 @@
 //! We reserve an address space of size [DEVICES_ADDRESS_SPACE_LENGTH] dedicated
 //! to devices.
 
-use super::Addressable;
-use crate::machine_state::backend::{self, Region};
+pub mod clint;
+pub mod uart;
+
+use super::{Address, Addressable, OutOfBounds};
+use crate::machine_state::backend;
+use std::mem;
 
 /// Length of the devices address space
 pub const DEVICES_ADDRESS_SPACE_LENGTH: u64 = 2 * 1024 * 1024 * 1024;
@@ -15,42 +19,124 @@ pub const DEVICES_ADDRESS_SPACE_LENGTH: u64 = 2 * 1024 * 1024 * 1024;
 // Note, the [DevicesLayout] is not required to have the same size as the
 // address space it represents. The address space is an interface detail,
 // the layout size is a state implementation detail.
-pub type DevicesLayout = backend::Array<u8, 1>;
+pub type DevicesLayout = (clint::ClintLayout, uart::UartLayout);
 
 /// Devices state
 pub struct Devices<M: backend::Manager> {
-    _placeholder: M::Region<u8, 1>,
+    pub clint: clint::Clint<M>,
+    pub uart: uart::Uart<M>,
 }
 
 impl<M: backend::Manager> Devices<M> {
     /// Bind the devices state.
     pub fn bind(space: backend::AllocatedOf<DevicesLayout, M>) -> Self {
         Self {
-            _placeholder: space,
+            clint: clint::Clint::bind(space.0),
+            uart: uart::Uart::bind(space.1),
         }
     }
 
     /// Reset the devices state.
     pub fn reset(&mut self) {
-        self._placeholder.write(0, 0);
+        self.clint.reset();
+        self.uart.reset();
+    }
+}
+
+/// Read `E` at byte `offset` of a device register holding `value`.
+/// The access must lie within the register.
+fn read_register<E: backend::Elem, R: backend::Elem>(
+    value: R,
+    offset: Address,
+) -> Result<E, OutOfBounds> {
+    let offset = offset as usize;
+    if offset + mem::size_of::<E>() > mem::size_of::<R>() {
+        return Err(OutOfBounds);
     }
+
+    let mut stored = value;
+    stored.to_stored_in_place();
+
+    // SAFETY: The bytes read lie within `stored` as checked above.
+    let mut result = unsafe {
+        (&stored as *const R)
+            .cast::<u8>()
+            .add(offset)
+            .cast::<E>()
+            .read_unaligned()
+    };
+    result.from_stored_in_place();
+
+    Ok(result)
+}
+
+/// Write `E` at byte `offset` of a device register holding `value`, returning
+/// the updated register value. The access must lie within the register.
+fn write_register<E: backend::Elem, R: backend::Elem>(
+    value: R,
+    offset: Address,
+    update: E,
+) -> Result<R, OutOfBounds> {
+    let offset = offset as usize;
+    if offset + mem::size_of::<E>() > mem::size_of::<R>() {
+        return Err(OutOfBounds);
+    }
+
+    let mut stored = value;
+    stored.to_stored_in_place();
+
+    let mut update = update;
+    update.to_stored_in_place();
+
+    // SAFETY: The bytes written lie within `stored` as checked above.
+    unsafe {
+        (&mut stored as *mut R)
+            .cast::<u8>()
+            .add(offset)
+            .cast::<E>()
+            .write_unaligned(update)
+    };
+    stored.from_stored_in_place();
+
+    Ok(stored)
+}
+
+/// Offset of `addr` within the device occupying `length` bytes at `start`
+#[inline(always)]
+fn device_offset(addr: Address, start: Address, length: Address) -> Option<Address> {
+    addr.checked_sub(start).filter(|offset| *offset < length)
 }
 
 impl<E: backend::Elem, M: backend::Manager> Addressable<E> for Devices<M> {
-    fn read(&self, _addr: super::Address) -> Result<E, super::OutOfBounds> {
-        Err(super::OutOfBounds)
+    fn read(&self, addr: Address) -> Result<E, OutOfBounds> {
+        if let Some(offset) = device_offset(addr, clint::CLINT_START, clint::CLINT_LENGTH) {
+            return self.clint.read(offset);
+        }
+
+        if let Some(offset) = device_offset(addr, uart::UART_START, uart::UART_LENGTH) {
+            return self.uart.read(offset);
+        }
+
+        Err(OutOfBounds)
     }
 
-    fn write(&mut self, _addr: super::Address, _value: E) -> Result<(), super::OutOfBounds> {
-        Err(super::OutOfBounds)
+    fn write(&mut self, addr: Address, value: E) -> Result<(), OutOfBounds> {
+        if let Some(offset) = device_offset(addr, clint::CLINT_START, clint::CLINT_LENGTH) {
+            return self.clint.write(offset, value);
+        }
+
+        if let Some(offset) = device_offset(addr, uart::UART_START, uart::UART_LENGTH) {
+            return self.uart.write(offset, value);
+        }
+
+        Err(OutOfBounds)
     }
 
-    fn write_all(
-        &mut self,
-        _addr: super::Address,
-        _values: &[E],
-    ) -> Result<(), super::OutOfBounds> {
-        Err(super::OutOfBounds)
+    fn write_all(&mut self, addr: Address, values: &[E]) -> Result<(), OutOfBounds> {
+        values.iter().enumerate().try_for_each(|(i, value)| {
+            let offset = (i * mem::size_of::<E>()) as Address;
+            self.write(addr + offset, *value)
+        })
     }
 }
 
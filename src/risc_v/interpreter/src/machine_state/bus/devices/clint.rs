@@ -0,0 +1,203 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Core-local interruptor (CLINT)
+//!
+//! The CLINT provides the machine timer and software interrupts of the hart.
+//! Its registers follow the memory map of the SiFive CLINT:
+//!
+//! | Register   | Offset   | Size    |
+//! |------------|----------|---------|
+//! | `msip`     | `0x0000` | 4 bytes |
+//! | `mtimecmp` | `0x4000` | 8 bytes |
+//! | `mtime`    | `0xBFF8` | 8 bytes |
+//!
+//! To keep execution deterministic, `mtime` doesn't follow the wall clock.
+//! It advances by one for every instruction retired by the machine instead.
+//! Steps whose instruction raises an exception, e.g. a page fault or an
+//! environment call, leave it unchanged.
+
+use super::{read_register, write_register};
+use crate::{
+    machine_state::bus::{Address, OutOfBounds},
+    state_backend::{self as backend, Atom, Cell},
+    traps::Interrupt,
+};
+
+/// Start of the CLINT in the devices address space
+pub const CLINT_START: Address = 0x0200_0000;
+
+/// Length of the CLINT address space
+pub const CLINT_LENGTH: Address = 0x1_0000;
+
+/// Offset of the `msip` register
+const MSIP_OFFSET: Address = 0x0;
+
+/// Offset of the `mtimecmp` register
+const MTIMECMP_OFFSET: Address = 0x4000;
+
+/// Offset of the `mtime` register
+const MTIME_OFFSET: Address = 0xBFF8;
+
+/// Layout of the CLINT state
+pub type ClintLayout = (Atom<u32>, Atom<u64>, Atom<u64>);
+
+/// CLINT state
+pub struct Clint<M: backend::Manager> {
+    msip: Cell<u32, M>,
+    // `mtimecmp` is stored inverted, such that a zeroed state has the timer
+    // interrupt disabled.
+    not_mtimecmp: Cell<u64, M>,
+    mtime: Cell<u64, M>,
+}
+
+impl<M: backend::Manager> Clint<M> {
+    /// Bind the CLINT state to the allocated space.
+    pub fn bind(space: backend::AllocatedOf<ClintLayout, M>) -> Self {
+        Self {
+            msip: Cell::bind(space.0),
+            not_mtimecmp: Cell::bind(space.1),
+            mtime: Cell::bind(space.2),
+        }
+    }
+
+    /// Reset the CLINT state. The timer interrupt is disabled until
+    /// `mtimecmp` is written.
+    pub fn reset(&mut self) {
+        self.msip.write(0);
+        self.set_mtimecmp(u64::MAX);
+        self.mtime.write(0);
+    }
+
+    /// Current value of `mtimecmp`
+    fn mtimecmp(&self) -> u64 {
+        !self.not_mtimecmp.read()
+    }
+
+    fn set_mtimecmp(&mut self, mtimecmp: u64) {
+        self.not_mtimecmp.write(!mtimecmp);
+    }
+
+    /// Current value of `mtime`
+    pub fn mtime(&self) -> u64 {
+        self.mtime.read()
+    }
+
+    /// Advance `mtime` by one tick.
+    #[inline(always)]
+    pub fn tick(&mut self) {
+        self.mtime.write(self.mtime.read().wrapping_add(1));
+    }
+
+    /// `mip` bits of the pending machine timer and software interrupts
+    #[inline(always)]
+    pub fn pending_interrupts(&self) -> u64 {
+        let mut pending = 0;
+
+        if self.mtime.read() >= self.mtimecmp() {
+            pending |= 1 << Interrupt::MachineTimer.exception_code_const();
+        }
+
+        if self.msip.read() & 1 != 0 {
+            pending |= 1 << Interrupt::MachineSoftware.exception_code_const();
+        }
+
+        pending
+    }
+
+    /// Read `E` from the CLINT at the given offset.
+    pub fn read<E: backend::Elem>(&self, offset: Address) -> Result<E, OutOfBounds> {
+        match offset {
+            MSIP_OFFSET..=0x3 => read_register(self.msip.read(), offset - MSIP_OFFSET),
+            MTIMECMP_OFFSET..=0x4007 => read_register(self.mtimecmp(), offset - MTIMECMP_OFFSET),
+            MTIME_OFFSET..=0xBFFF => read_register(self.mtime.read(), offset - MTIME_OFFSET),
+            _ => Err(OutOfBounds),
+        }
+    }
+
+    /// Write `E` to the CLINT at the given offset.
+    pub fn write<E: backend::Elem>(
+        &mut self,
+        offset: Address,
+        value: E,
+    ) -> Result<(), OutOfBounds> {
+        match offset {
+            MSIP_OFFSET..=0x3 => {
+                // Only the lowest bit of `msip` is writable.
+                let msip = write_register(self.msip.read(), offset - MSIP_OFFSET, value)?;
+                self.msip.write(msip & 1);
+            }
+            MTIMECMP_OFFSET..=0x4007 => {
+                let mtimecmp = write_register(self.mtimecmp(), offset - MTIMECMP_OFFSET, value)?;
+                self.set_mtimecmp(mtimecmp);
+            }
+            MTIME_OFFSET..=0xBFFF => {
+                let mtime = write_register(self.mtime.read(), offset - MTIME_OFFSET, value)?;
+                self.mtime.write(mtime);
+            }
+            _ => return Err(OutOfBounds),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend_test,
+        machine_state::backend::{
+            tests::{test_determinism, ManagerFor},
+            Backend, BackendManagement, Layout,
+        },
+    };
+
+    backend_test!(test_reset, F, {
+        test_determinism::<F, ClintLayout, _>(|space| {
+            let mut clint: Clint<ManagerFor<'_, F, ClintLayout>> = Clint::bind(space);
+            clint.reset();
+        });
+    });
+
+    backend_test!(test_registers, F, {
+        let mut backend = F::new::<ClintLayout>();
+        let placed = ClintLayout::placed().into_location();
+        let mut clint: Clint<<F::Backend<ClintLayout> as BackendManagement>::Manager<'_>> =
+            Clint::bind(backend.allocate(placed));
+
+        // A zeroed state has the timer interrupt disabled
+        assert_eq!(clint.read::<u64>(MTIMECMP_OFFSET), Ok(u64::MAX));
+        assert_eq!(clint.pending_interrupts(), 0);
+        clint.reset();
+
+        let mtip = 1 << Interrupt::MachineTimer.exception_code_const();
+        let msip = 1 << Interrupt::MachineSoftware.exception_code_const();
+        assert_eq!(clint.pending_interrupts(), 0);
+
+        // The timer interrupt is pending once mtime reaches mtimecmp
+        clint.write(MTIMECMP_OFFSET, 2u64).unwrap();
+        clint.tick();
+        assert_eq!(clint.pending_interrupts(), 0);
+        clint.tick();
+        assert_eq!(clint.read::<u64>(MTIME_OFFSET), Ok(2));
+        assert_eq!(clint.pending_interrupts(), mtip);
+
+        // Registers can be accessed in parts
+        clint.write(MTIMECMP_OFFSET + 4, 1u32).unwrap();
+        assert_eq!(clint.read::<u64>(MTIMECMP_OFFSET), Ok((1 << 32) | 2));
+        assert_eq!(clint.read::<u32>(MTIMECMP_OFFSET + 4), Ok(1));
+        assert_eq!(clint.pending_interrupts(), 0);
+
+        // Only the lowest bit of msip is writable
+        clint.write(MSIP_OFFSET, 0xFFu8).unwrap();
+        assert_eq!(clint.read::<u32>(MSIP_OFFSET), Ok(1));
+        assert_eq!(clint.pending_interrupts(), msip);
+
+        // Accesses must lie within a register
+        assert_eq!(clint.read::<u64>(MSIP_OFFSET), Err(OutOfBounds));
+        assert_eq!(clint.write(MTIME_OFFSET + 4, 0u64), Err(OutOfBounds));
+        assert_eq!(clint.read::<u8>(0x8000), Err(OutOfBounds));
+    });
+}
@@ -0,0 +1,215 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! 16550-compatible UART
+//!
+//! The UART lets kernels print to a console before an execution environment
+//! is set up. Its registers are one byte wide and placed one byte apart.
+//!
+//! Transmitted bytes are collected in a host-side buffer, see
+//! [`Uart::output`]. There is no input: the receiver is always empty. The
+//! UART never raises interrupts, as there is no interrupt controller for
+//! external interrupts.
+
+use super::{read_register, write_register};
+use crate::{
+    machine_state::bus::{Address, OutOfBounds},
+    state_backend::{self as backend, Array, Region},
+};
+
+/// Start of the UART in the devices address space
+pub const UART_START: Address = 0x1000_0000;
+
+/// Length of the UART address space
+pub const UART_LENGTH: Address = 0x100;
+
+/// Input clock frequency of the UART as advertised in the device tree
+pub const UART_CLOCK_FREQUENCY: u32 = 3686400;
+
+/// Register offsets, see the 16550 data sheet
+const RBR_THR_DLL: Address = 0;
+const IER_DLM: Address = 1;
+const IIR_FCR: Address = 2;
+const LCR: Address = 3;
+const MCR: Address = 4;
+const LSR: Address = 5;
+const MSR: Address = 6;
+const SCR: Address = 7;
+
+/// Divisor latch access bit of the line control register
+const LCR_DLAB: u8 = 1 << 7;
+
+/// Transmitter holding register empty and transmitter empty bits of the line
+/// status register. Transmission is instantaneous.
+const LSR_TRANSMITTER_EMPTY: u8 = 1 << 5 | 1 << 6;
+
+/// Interrupt identification value signalling that no interrupt is pending
+const IIR_NO_INTERRUPT: u8 = 1;
+
+/// FIFOs enabled bits of the interrupt identification register
+const IIR_FIFO_ENABLED: u8 = 0b11 << 6;
+
+/// Indices of the stored registers
+const IER: usize = 0;
+const LCR_INDEX: usize = 1;
+const MCR_INDEX: usize = 2;
+const SCR_INDEX: usize = 3;
+const DLL: usize = 4;
+const DLM: usize = 5;
+const FCR: usize = 6;
+
+/// Layout of the UART state
+pub type UartLayout = Array<u8, 8>;
+
+/// UART state
+pub struct Uart<M: backend::Manager> {
+    registers: M::Region<u8, 8>,
+
+    /// Bytes transmitted by the UART. This is not part of the machine state.
+    output: Vec<u8>,
+}
+
+impl<M: backend::Manager> Uart<M> {
+    /// Bind the UART state to the allocated space.
+    pub fn bind(space: backend::AllocatedOf<UartLayout, M>) -> Self {
+        Self {
+            registers: space,
+            output: Vec::new(),
+        }
+    }
+
+    /// Reset the UART state.
+    pub fn reset(&mut self) {
+        for index in 0..8 {
+            self.registers.write(index, 0);
+        }
+    }
+
+    /// Bytes transmitted by the UART
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Take the bytes transmitted by the UART, leaving the output empty.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    fn dlab(&self) -> bool {
+        self.registers.read(LCR_INDEX) & LCR_DLAB != 0
+    }
+
+    /// Value of the register at the given offset as seen by a read
+    fn read_byte(&self, offset: Address) -> Option<u8> {
+        let value = match offset {
+            RBR_THR_DLL if self.dlab() => self.registers.read(DLL),
+            // The receiver is always empty.
+            RBR_THR_DLL => 0,
+            IER_DLM if self.dlab() => self.registers.read(DLM),
+            IER_DLM => self.registers.read(IER),
+            IIR_FCR => {
+                let fifo = match self.registers.read(FCR) & 1 {
+                    0 => 0,
+                    _ => IIR_FIFO_ENABLED,
+                };
+                fifo | IIR_NO_INTERRUPT
+            }
+            LCR => self.registers.read(LCR_INDEX),
+            MCR => self.registers.read(MCR_INDEX),
+            LSR => LSR_TRANSMITTER_EMPTY,
+            MSR => 0,
+            SCR => self.registers.read(SCR_INDEX),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Update the register at the given offset as done by a write.
+    fn write_byte(&mut self, offset: Address, value: u8) -> Option<()> {
+        match offset {
+            RBR_THR_DLL if self.dlab() => self.registers.write(DLL, value),
+            RBR_THR_DLL => self.output.push(value),
+            IER_DLM if self.dlab() => self.registers.write(DLM, value),
+            // Only the four interrupt enable bits are writable.
+            IER_DLM => self.registers.write(IER, value & 0xF),
+            IIR_FCR => self.registers.write(FCR, value),
+            LCR => self.registers.write(LCR_INDEX, value),
+            MCR => self.registers.write(MCR_INDEX, value & 0x1F),
+            // The status registers are read-only.
+            LSR | MSR => {}
+            SCR => self.registers.write(SCR_INDEX, value),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Read `E` from the UART at the given offset. Only single byte accesses
+    /// are supported.
+    pub fn read<E: backend::Elem>(&self, offset: Address) -> Result<E, OutOfBounds> {
+        let value = self.read_byte(offset).ok_or(OutOfBounds)?;
+        read_register(value, 0)
+    }
+
+    /// Write `E` to the UART at the given offset. Only single byte accesses
+    /// are supported.
+    pub fn write<E: backend::Elem>(
+        &mut self,
+        offset: Address,
+        value: E,
+    ) -> Result<(), OutOfBounds> {
+        let byte = write_register(0u8, 0, value)?;
+        self.write_byte(offset, byte).ok_or(OutOfBounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend_test,
+        machine_state::backend::{
+            tests::{test_determinism, ManagerFor},
+            Backend, BackendManagement, Layout,
+        },
+    };
+
+    backend_test!(test_reset, F, {
+        test_determinism::<F, UartLayout, _>(|space| {
+            let mut uart: Uart<ManagerFor<'_, F, UartLayout>> = Uart::bind(space);
+            uart.reset();
+        });
+    });
+
+    backend_test!(test_transmit, F, {
+        let mut backend = F::new::<UartLayout>();
+        let placed = UartLayout::placed().into_location();
+        let mut uart: Uart<<F::Backend<UartLayout> as BackendManagement>::Manager<'_>> =
+            Uart::bind(backend.allocate(placed));
+        uart.reset();
+
+        // The transmitter is always ready
+        assert_eq!(uart.read::<u8>(LSR), Ok(LSR_TRANSMITTER_EMPTY));
+
+        for byte in b"hi\n" {
+            uart.write(RBR_THR_DLL, *byte).unwrap();
+        }
+        assert_eq!(uart.output(), b"hi\n");
+
+        // Writes with the divisor latch enabled set the baud rate instead
+        uart.write(LCR, LCR_DLAB | 0b11).unwrap();
+        uart.write(RBR_THR_DLL, 0x03u8).unwrap();
+        uart.write(IER_DLM, 0x01u8).unwrap();
+        assert_eq!(uart.read::<u8>(RBR_THR_DLL), Ok(0x03));
+        assert_eq!(uart.read::<u8>(IER_DLM), Ok(0x01));
+        uart.write(LCR, 0b11u8).unwrap();
+        assert_eq!(uart.read::<u8>(IER_DLM), Ok(0));
+
+        assert_eq!(uart.take_output(), b"hi\n");
+        assert_eq!(uart.output(), b"");
+
+        // Only byte accesses within the registers are supported
+        assert_eq!(uart.write(RBR_THR_DLL, 0u32), Err(OutOfBounds));
+        assert_eq!(uart.read::<u8>(8), Err(OutOfBounds));
+    });
+}
@@ -0,0 +1,198 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Cache of decoded instructions
+//!
+//! Decoded instructions are cached by physical address, grouped into pages of
+//! [`CACHE_PAGE_SIZE`] bytes. Writes to a page drop all of its cached
+//! instructions. Instructions which cross a page boundary are never cached,
+//! so that a write only ever affects the instructions of the pages it touches.
+//!
+//! The cache is not part of the machine state. Whether an instruction is
+//! served from the cache or decoded from memory has no observable effect.
+
+use super::Address;
+use crate::parser::instruction::Instr;
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    hash::{BuildHasherDefault, Hasher},
+};
+
+/// Size of a cached page in bytes
+pub const CACHE_PAGE_SIZE: Address = 4096;
+
+/// Instructions are at least 2 bytes wide and 2-byte aligned.
+const ENTRIES_PER_PAGE: usize = CACHE_PAGE_SIZE as usize / 2;
+
+/// Decoded instructions of a page, indexed by half-word offset
+type CachedPage = Box<[Option<Instr>; ENTRIES_PER_PAGE]>;
+
+/// Hasher for page numbers. Every instruction fetch hashes a page number,
+/// which is too costly with the default hasher. A multiplicative hash spreads
+/// the consecutive page numbers of a program well enough.
+#[derive(Default)]
+struct PageHasher(u64);
+
+impl Hasher for PageHasher {
+    #[inline(always)]
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_u64(self.0.rotate_left(8) ^ *byte as u64);
+        }
+    }
+
+    #[inline(always)]
+    fn write_u64(&mut self, page: u64) {
+        self.0 = page.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    }
+}
+
+/// Cache of decoded instructions keyed by physical address
+#[derive(Default)]
+pub struct InstructionCache {
+    /// Cached pages with their page numbers
+    pages: Vec<(Address, CachedPage)>,
+
+    /// Position of each cached page in `pages`
+    slots: HashMap<Address, usize, BuildHasherDefault<PageHasher>>,
+
+    /// Position of the most recently used page, which serves most fetches
+    /// without a lookup in `slots`. It may be stale, hence the page number
+    /// found there must be checked.
+    last: Cell<usize>,
+}
+
+impl InstructionCache {
+    /// Split a physical address into its page number and entry index.
+    #[inline(always)]
+    fn locate(addr: Address) -> (Address, usize) {
+        let page = addr / CACHE_PAGE_SIZE;
+        let index = (addr % CACHE_PAGE_SIZE) as usize / 2;
+        (page, index)
+    }
+
+    /// Look up the decoded instruction at the given physical address.
+    #[inline(always)]
+    pub fn get(&self, addr: Address) -> Option<Instr> {
+        if addr & 1 != 0 {
+            return None;
+        }
+
+        let (page, index) = Self::locate(addr);
+        let slot = match self.pages.get(self.last.get()) {
+            Some((cached, _)) if *cached == page => self.last.get(),
+            _ => {
+                let slot = *self.slots.get(&page)?;
+                self.last.set(slot);
+                slot
+            }
+        };
+
+        self.pages[slot].1[index]
+    }
+
+    /// Cache the decoded instruction at the given physical address.
+    pub fn insert(&mut self, addr: Address, instr: Instr) {
+        let offset = addr % CACHE_PAGE_SIZE;
+        if addr & 1 != 0 || offset + instr.width() > CACHE_PAGE_SIZE {
+            return;
+        }
+
+        let (page, index) = Self::locate(addr);
+        let pages = &mut self.pages;
+        let slot = *self.slots.entry(page).or_insert_with(|| {
+            pages.push((page, Box::new([None; ENTRIES_PER_PAGE])));
+            pages.len() - 1
+        });
+        pages[slot].1[index] = Some(instr);
+    }
+
+    /// Drop the cached instructions of all pages overlapping the `len` bytes
+    /// starting at the given physical address.
+    #[inline(always)]
+    pub fn invalidate(&mut self, addr: Address, len: usize) {
+        if self.pages.is_empty() || len == 0 {
+            return;
+        }
+
+        let first = addr / CACHE_PAGE_SIZE;
+        let last = addr.saturating_add(len as Address - 1) / CACHE_PAGE_SIZE;
+        for page in first..=last {
+            self.remove_page(page);
+        }
+    }
+
+    /// Drop the cached instructions of the given page.
+    fn remove_page(&mut self, page: Address) {
+        let Some(slot) = self.slots.remove(&page) else {
+            return;
+        };
+
+        // The last page takes the place of the removed one.
+        self.pages.swap_remove(slot);
+        if let Some((moved, _)) = self.pages.get(slot) {
+            self.slots.insert(*moved, slot);
+        }
+    }
+
+    /// Drop all cached instructions.
+    pub fn invalidate_all(&mut self) {
+        self.pages.clear();
+        self.slots.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        machine_state::registers::t0,
+        parser::instruction::{Instr, UJTypeArgs},
+    };
+
+    #[test]
+    fn test_invalidate() {
+        let instr = Instr::Jal(UJTypeArgs { rd: t0, imm: 8 });
+        let mut cache = InstructionCache::default();
+
+        cache.insert(0x1000, instr);
+        cache.insert(0x2004, instr);
+        assert_eq!(cache.get(0x1000), Some(instr));
+        assert_eq!(cache.get(0x2004), Some(instr));
+        assert_eq!(cache.get(0x1002), None);
+
+        // Instructions crossing a page boundary aren't cached
+        cache.insert(0x1FFE, instr);
+        assert_eq!(cache.get(0x1FFE), None);
+
+        // Writes to other pages don't affect the cached instructions
+        cache.invalidate(0x3000, 8);
+        cache.invalidate(0x0FF8, 8);
+        assert_eq!(cache.get(0x1000), Some(instr));
+
+        // Writes spanning several pages invalidate all of them
+        cache.invalidate(0x1FFC, 8);
+        assert_eq!(cache.get(0x1000), None);
+        assert_eq!(cache.get(0x2004), None);
+
+        // Removing a page keeps the other pages
+        cache.insert(0x1000, instr);
+        cache.insert(0x5000, instr);
+        cache.insert(0x6000, instr);
+        assert_eq!(cache.get(0x6000), Some(instr));
+        cache.invalidate(0x1000, 1);
+        assert_eq!(cache.get(0x1000), None);
+        assert_eq!(cache.get(0x5000), Some(instr));
+        assert_eq!(cache.get(0x6000), Some(instr));
+
+        cache.insert(0x1000, instr);
+        cache.invalidate_all();
+        assert_eq!(cache.get(0x1000), None);
+    }
+}
@@ -0,0 +1,336 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Cost accounting of steps
+//!
+//! Each step records the events that affect its cost: the executed
+//! instruction, the memory accesses, the address translations and the traps
+//! taken. A [`CostModel`] turns these events into ticks, which
+//! [`MachineState::step_many_with_budget`] compares against a budget.
+//!
+//! Steps raising an environment call also record the bytes copied between
+//! memory and the execution environment while serving the call. As the call
+//! is served after the step has been charged, these copies are charged
+//! separately using [`CostModel::environ_call`].
+//!
+//! Costs only depend on the machine state, not on host-side caches such as
+//! the TLB or the instruction cache, so that they are deterministic.
+
+use super::{bus::main_memory::MainMemoryLayout, MachineState};
+use crate::{parser::instruction::Instr, state_backend as backend};
+
+/// Events of a single step that are charged by a [`CostModel`]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StepEvents {
+    /// Instruction executed by the step, if it could be fetched
+    pub instr: Option<Instr>,
+
+    /// Number of data loads
+    pub loads: u64,
+
+    /// Number of bytes read by data loads
+    pub loaded_bytes: u64,
+
+    /// Number of data stores
+    pub stores: u64,
+
+    /// Number of bytes written by data stores
+    pub stored_bytes: u64,
+
+    /// Number of page table levels of the address translations, including
+    /// instruction fetches. Translations served by the TLB are charged as if
+    /// they walked the page table.
+    pub page_table_levels: u64,
+
+    /// Number of traps taken by the machine, excluding those handled by the
+    /// execution environment
+    pub traps: u64,
+
+    /// Number of bytes copied between memory and the execution environment
+    /// while serving the environment call raised by the step
+    pub environ_bytes: u64,
+}
+
+/// Class of an instruction with respect to its cost
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstrClass {
+    /// Integer arithmetic and logic
+    Integer,
+    /// Branches and jumps
+    Branch,
+    /// Integer multiplication
+    Multiply,
+    /// Integer division and remainder
+    Divide,
+    /// Integer and floating-point loads
+    Load,
+    /// Integer and floating-point stores
+    Store,
+    /// Atomic memory operations
+    Atomic,
+    /// Floating-point arithmetic, comparisons, conversions and moves
+    Float,
+    /// Floating-point division and square root
+    FloatDivSqrt,
+    /// Accesses to control and status registers
+    Csr,
+    /// Environment calls, trap returns, fences and other system instructions
+    System,
+    /// Instructions which cannot be executed
+    Illegal,
+}
+
+impl InstrClass {
+    /// Class of the instruction `instr`
+    pub fn of(instr: &Instr) -> Self {
+        use Instr::*;
+
+        match instr {
+            Beq(_) | Bne(_) | Blt(_) | Bge(_) | Bltu(_) | Bgeu(_) | Jal(_) | Jalr(_) => {
+                InstrClass::Branch
+            }
+
+            Mul(_) | Mulh(_) | Mulhsu(_) | Mulhu(_) | Mulw(_) => InstrClass::Multiply,
+
+            Div(_) | Divu(_) | Divw(_) | Divuw(_) | Rem(_) | Remu(_) | Remw(_) | Remuw(_) => {
+                InstrClass::Divide
+            }
+
+            Lb(_) | Lh(_) | Lw(_) | Lbu(_) | Lhu(_) | Lwu(_) | Ld(_) | Flw(_) | Fld(_) => {
+                InstrClass::Load
+            }
+
+            Sb(_) | Sh(_) | Sw(_) | Sd(_) | Fsw(_) | Fsd(_) => InstrClass::Store,
+
+            Amoswapw(_) | Amoaddw(_) | Amoxorw(_) | Amoandw(_) | Amoorw(_) | Amominw(_)
+            | Amomaxw(_) | Amominuw(_) | Amomaxuw(_) => InstrClass::Atomic,
+
+            Fdivs(_) | Fsqrts(_) | Fdivd(_) | Fsqrtd(_) => InstrClass::FloatDivSqrt,
+
+            FclassS(_) | Feqs(_) | Fles(_) | Flts(_) | Fadds(_) | Fsubs(_) | Fmuls(_)
+            | Fmins(_) | Fmaxs(_) | Fmadds(_) | Fmsubs(_) | Fnmsubs(_) | Fnmadds(_) | Fsgnjs(_)
+            | Fsgnjns(_) | Fsgnjxs(_) | FmvXW(_) | FmvWX(_) | FclassD(_) | Feqd(_) | Fled(_)
+            | Fltd(_) | Faddd(_) | Fsubd(_) | Fmuld(_) | Fmind(_) | Fmaxd(_) | Fmaddd(_)
+            | Fmsubd(_) | Fnmsubd(_) | Fnmaddd(_) | Fsgnjd(_) | Fsgnjnd(_) | Fsgnjxd(_)
+            | FmvXD(_) | FmvDX(_) => InstrClass::Float,
+
+            Csrrw(_) | Csrrs(_) | Csrrc(_) | Csrrwi(_) | Csrrsi(_) | Csrrci(_) => InstrClass::Csr,
+
+            Fence(_)
+            | FenceTso(_)
+            | FenceI
+            | Ecall
+            | Ebreak
+            | Mret
+            | Sret
+            | Mnret
+            | Wfi
+            | SFenceVma { .. } => InstrClass::System,
+
+            Unknown { .. } | UnknownCompressed { .. } => InstrClass::Illegal,
+
+            Add(_) | Sub(_) | Xor(_) | Or(_) | And(_) | Sll(_) | Srl(_) | Sra(_) | Slt(_)
+            | Sltu(_) | Addw(_) | Subw(_) | Sllw(_) | Srlw(_) | Sraw(_) | Addi(_) | Addiw(_)
+            | Xori(_) | Ori(_) | Andi(_) | Slli(_) | Srli(_) | Srai(_) | Slliw(_) | Srliw(_)
+            | Sraiw(_) | Slti(_) | Sltiu(_) | Lui(_) | Auipc(_) => InstrClass::Integer,
+        }
+    }
+}
+
+/// Model assigning a cost in ticks to the steps of the machine
+pub trait CostModel {
+    /// Cost of executing `instr`, excluding the costs of its events
+    fn instr(&self, instr: &Instr) -> u64;
+
+    /// Cost of the memory accesses, address translations and traps of a step
+    fn events(&self, events: &StepEvents) -> u64 {
+        let _ = events;
+        0
+    }
+
+    /// Cost of a step
+    fn step(&self, events: &StepEvents) -> u64 {
+        let instr = events.instr.as_ref().map_or(0, |instr| self.instr(instr));
+        instr.saturating_add(self.events(events))
+    }
+
+    /// Cost of serving the environment call raised by a step, charged in
+    /// addition to the cost of the step itself
+    fn environ_call(&self, events: &StepEvents) -> u64 {
+        let _ = events;
+        0
+    }
+}
+
+/// Cost model charging one tick per step, such that ticks and steps coincide.
+/// Environment calls are not charged beyond the step raising them.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UniformCost;
+
+impl CostModel for UniformCost {
+    fn instr(&self, _instr: &Instr) -> u64 {
+        1
+    }
+
+    fn step(&self, _events: &StepEvents) -> u64 {
+        1
+    }
+}
+
+/// Cost model charging instructions by [`InstrClass`], plus the memory
+/// accesses, page table levels and traps of each step, and the bytes copied
+/// by environment calls
+#[derive(Debug, Clone, Copy)]
+pub struct ClassCost {
+    pub integer: u64,
+    pub branch: u64,
+    pub multiply: u64,
+    pub divide: u64,
+    pub load: u64,
+    pub store: u64,
+    pub atomic: u64,
+    pub float: u64,
+    pub float_div_sqrt: u64,
+    pub csr: u64,
+    pub system: u64,
+    pub illegal: u64,
+
+    /// Cost per byte loaded or stored
+    pub memory_byte: u64,
+
+    /// Cost per page table level of an address translation
+    pub page_table_level: u64,
+
+    /// Cost per trap taken
+    pub trap: u64,
+
+    /// Cost per byte copied by the execution environment
+    pub environ_byte: u64,
+}
+
+impl Default for ClassCost {
+    fn default() -> Self {
+        Self {
+            integer: 1,
+            branch: 1,
+            multiply: 2,
+            divide: 8,
+            load: 2,
+            store: 2,
+            atomic: 4,
+            float: 4,
+            float_div_sqrt: 16,
+            csr: 2,
+            system: 4,
+            illegal: 1,
+            memory_byte: 0,
+            page_table_level: 2,
+            trap: 8,
+            environ_byte: 1,
+        }
+    }
+}
+
+impl CostModel for ClassCost {
+    fn instr(&self, instr: &Instr) -> u64 {
+        match InstrClass::of(instr) {
+            InstrClass::Integer => self.integer,
+            InstrClass::Branch => self.branch,
+            InstrClass::Multiply => self.multiply,
+            InstrClass::Divide => self.divide,
+            InstrClass::Load => self.load,
+            InstrClass::Store => self.store,
+            InstrClass::Atomic => self.atomic,
+            InstrClass::Float => self.float,
+            InstrClass::FloatDivSqrt => self.float_div_sqrt,
+            InstrClass::Csr => self.csr,
+            InstrClass::System => self.system,
+            InstrClass::Illegal => self.illegal,
+        }
+    }
+
+    fn events(&self, events: &StepEvents) -> u64 {
+        let bytes = events.loaded_bytes.saturating_add(events.stored_bytes);
+        bytes
+            .saturating_mul(self.memory_byte)
+            .saturating_add(
+                events
+                    .page_table_levels
+                    .saturating_mul(self.page_table_level),
+            )
+            .saturating_add(events.traps.saturating_mul(self.trap))
+    }
+
+    fn environ_call(&self, events: &StepEvents) -> u64 {
+        events.environ_bytes.saturating_mul(self.environ_byte)
+    }
+}
+
+impl<ML: MainMemoryLayout, M: backend::Manager> MachineState<ML, M> {
+    /// Events recorded by the last step
+    pub fn step_events(&self) -> StepEvents {
+        self.events.get()
+    }
+
+    /// Record an event of the current step.
+    #[inline(always)]
+    pub(crate) fn record_event(&self, f: impl FnOnce(&mut StepEvents)) {
+        let mut events = self.events.get();
+        f(&mut events);
+        self.events.set(events);
+    }
+
+    /// Record that the execution environment copied `bytes` bytes between
+    /// memory and itself while serving the environment call raised by the
+    /// last step.
+    pub fn record_environ_bytes(&self, bytes: u64) {
+        self.record_event(|events| {
+            events.environ_bytes = events.environ_bytes.saturating_add(bytes)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine_state::registers::{t0, t1, t2};
+    use crate::parser::instruction::RTypeArgs;
+
+    #[test]
+    fn test_class_cost() {
+        let args = RTypeArgs {
+            rd: t0,
+            rs1: t1,
+            rs2: t2,
+        };
+        let model = ClassCost::default();
+
+        let events = StepEvents {
+            instr: Some(Instr::Div(args)),
+            page_table_levels: 3,
+            ..StepEvents::default()
+        };
+        assert_eq!(model.step(&events), 8 + 3 * 2);
+
+        let events = StepEvents {
+            instr: None,
+            traps: 1,
+            ..StepEvents::default()
+        };
+        assert_eq!(model.step(&events), 8);
+
+        // Bytes copied by environment calls are charged separately.
+        let events = StepEvents {
+            instr: Some(Instr::Ecall),
+            environ_bytes: 100,
+            ..StepEvents::default()
+        };
+        assert_eq!(model.step(&events), 4);
+        assert_eq!(model.environ_call(&events), 100);
+
+        // The uniform model charges every step the same.
+        assert_eq!(UniformCost.step(&events), 1);
+        assert_eq!(UniformCost.environ_call(&events), 0);
+    }
+}
@@ -5,6 +5,7 @@
 #![allow(non_upper_case_globals)]
 
 pub mod fields;
+pub mod pmp;
 pub mod satp;
 pub mod xstatus;
 
@@ -632,6 +633,8 @@ impl CSRegister {
             CSRegister::mstatus => xstatus::apply_warl_mstatus(new_value),
             CSRegister::sstatus => xstatus::apply_warl_sstatus(new_value),
             CSRegister::mnstatus => xstatus::apply_warl_mnstatus(new_value),
+            reg if pmp::is_pmpcfg(reg) => new_value & pmp::WARL_MASK_PMPCFG,
+            reg if pmp::is_pmpaddr(reg) => new_value & pmp::WARL_MASK_PMPADDR,
             _ => new_value,
         };
         Some(write_value)
@@ -1143,6 +1146,7 @@ impl<M: backend::Manager> CSRegisters<M> {
                 let fcsr = fcsr & !CSRegister::FFLAGS_MASK;
                 (CSRegister::fcsr, (value & CSRegister::FFLAGS_MASK) | fcsr)
             }
+            reg if pmp::is_pmp_register(reg) => (reg, self.pmp_transform_write(reg, value)),
             _ => (reg, value),
         }
     }
@@ -1256,21 +1260,34 @@ impl<M: backend::Manager> CSRegisters<M> {
         // regardless of the setting of the global yIE
         // bit for the higher-privilege mode."
 
+        //
+        // Section 3.1.9: "An interrupt i will trap to M-mode [...] if all of the following
+        // are true: (a) either the current privilege mode is M and the MIE bit in the
+        // mstatus register is set, or the current privilege mode has less privilege
+        // than M-mode; (b) bit i is set in both mip and mie; and (c) if register mideleg
+        // exists, bit i is not set in mideleg."
+        // Delegated interrupts trap to S-mode under the same conditions using SIE.
         let mstatus = self.read(CSRegister::mstatus);
-        let ie_machine = match xstatus::get_MIE(mstatus) {
-            true => self.read(CSRegister::mie),
-            false => 0,
+        let mie = self.read(CSRegister::mie);
+        let delegated = self.read(CSRegister::mideleg) & Interrupt::SUPERVISOR_BIT_MASK;
+
+        let machine_enabled = match current_mode {
+            Mode::Machine => xstatus::get_MIE(mstatus),
+            Mode::Supervisor | Mode::User => true,
         };
-        let ie_supervisor = match xstatus::get_SIE(mstatus) {
-            true => self.read(CSRegister::sie),
-            false => 0,
+        let supervisor_enabled = match current_mode {
+            Mode::Machine => false,
+            Mode::Supervisor => xstatus::get_SIE(mstatus),
+            Mode::User => true,
         };
 
-        match current_mode {
-            Mode::User => Interrupt::SUPERVISOR_BIT_MASK | Interrupt::MACHINE_BIT_MASK,
-            Mode::Supervisor => ie_supervisor | Interrupt::MACHINE_BIT_MASK,
-            Mode::Machine => ie_machine,
-        }
+        let machine = if machine_enabled { mie & !delegated } else { 0 };
+        let supervisor = if supervisor_enabled {
+            mie & delegated
+        } else {
+            0
+        };
+        machine | supervisor
     }
 
     /// Determine the mode where this trap would go to.
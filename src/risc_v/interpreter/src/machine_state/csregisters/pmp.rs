@@ -0,0 +1,320 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Physical memory protection
+//!
+//! Section 3.7: the `pmpcfg*` and `pmpaddr*` registers describe up to 64
+//! regions of physical memory together with the access permissions for
+//! S-mode and U-mode. Locked entries also apply to M-mode and can only be
+//! modified by resetting the hart.
+//!
+//! While all entries are off, PMP is considered absent and all accesses
+//! succeed. This keeps programs which never configure PMP running in S-mode
+//! and U-mode.
+
+use super::{ones, CSRValue, CSRegister, CSRegisters};
+use crate::{
+    machine_state::{bus::Address, mode::Mode, AccessType},
+    state_backend::{self as backend, Region},
+};
+
+/// Number of PMP entries
+pub const PMP_ENTRIES: usize = 64;
+
+/// Number of entries configured by each `pmpcfg` register
+const ENTRIES_PER_CFG: usize = 8;
+
+/// Permission and lock bits of an entry configuration
+pub const CFG_R: u8 = 1 << 0;
+pub const CFG_W: u8 = 1 << 1;
+pub const CFG_X: u8 = 1 << 2;
+pub const CFG_L: u8 = 1 << 7;
+
+/// Offset of the address-matching mode `A` in an entry configuration
+const CFG_A_OFFSET: u8 = 3;
+
+/// Bits 5 and 6 of each entry configuration are WARL zero.
+pub const WARL_MASK_PMPCFG: CSRValue = 0x9F9F_9F9F_9F9F_9F9F;
+
+/// `pmpaddr` registers hold bits 55 to 2 of a physical address.
+pub const WARL_MASK_PMPADDR: CSRValue = ones(54);
+
+/// Address-matching mode of a PMP entry, see table 3.9
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AddressMatching {
+    Off,
+    Tor,
+    Na4,
+    Napot,
+}
+
+impl AddressMatching {
+    /// Address-matching mode of an entry configuration
+    pub const fn from_cfg(cfg: u8) -> Self {
+        match (cfg >> CFG_A_OFFSET) & 0b11 {
+            0 => AddressMatching::Off,
+            1 => AddressMatching::Tor,
+            2 => AddressMatching::Na4,
+            _ => AddressMatching::Napot,
+        }
+    }
+}
+
+/// Configuration of entry `index` given the value of its `pmpcfg` register
+#[inline(always)]
+const fn entry_cfg(pmpcfg: CSRValue, index: usize) -> u8 {
+    (pmpcfg >> (8 * (index % ENTRIES_PER_CFG))) as u8
+}
+
+/// `pmpcfg` register holding the configuration of entry `index`
+#[inline(always)]
+const fn cfg_register(index: usize) -> usize {
+    // Only the even numbered pmpcfg registers exist for RV64.
+    CSRegister::pmpcfg0 as usize + 2 * (index / ENTRIES_PER_CFG)
+}
+
+/// Whether the access type is permitted by an entry configuration
+#[inline(always)]
+fn permits(cfg: u8, access_type: &AccessType) -> bool {
+    let bit = match access_type {
+        AccessType::Instruction => CFG_X,
+        AccessType::Load => CFG_R,
+        AccessType::Store => CFG_W,
+    };
+    cfg & bit != 0
+}
+
+impl<M: backend::Manager> CSRegisters<M> {
+    /// Configuration of PMP entry `index`
+    #[inline(always)]
+    fn pmp_cfg(&self, index: usize) -> u8 {
+        entry_cfg(self.registers.read(cfg_register(index)), index)
+    }
+
+    /// Value of the `pmpaddr` register of entry `index`
+    #[inline(always)]
+    fn pmp_addr(&self, index: usize) -> CSRValue {
+        self.registers.read(CSRegister::pmpaddr0 as usize + index)
+    }
+
+    /// Range of physical addresses `[start, end)` matched by entry `index`,
+    /// see section 3.7.1.
+    fn pmp_range(&self, index: usize, matching: AddressMatching) -> Option<(Address, Address)> {
+        let pmpaddr = self.pmp_addr(index);
+        match matching {
+            AddressMatching::Off => None,
+            AddressMatching::Tor => {
+                let start = match index {
+                    0 => 0,
+                    _ => self.pmp_addr(index - 1) << 2,
+                };
+                Some((start, pmpaddr << 2))
+            }
+            AddressMatching::Na4 => Some((pmpaddr << 2, (pmpaddr << 2) + 4)),
+            AddressMatching::Napot => {
+                // The number of trailing ones encodes the size of the region.
+                let trailing_ones = pmpaddr.trailing_ones() as u64;
+                let start = (pmpaddr & !ones(trailing_ones)) << 2;
+                Some((start, start + (1 << (trailing_ones + 3))))
+            }
+        }
+    }
+
+    /// Check whether PMP allows an access of `len` bytes to the physical
+    /// address `addr` in `mode`. Section 3.7.1
+    ///
+    /// The lowest-numbered entry matching any byte of the access determines
+    /// whether it succeeds. Accesses which only partially match this entry fail.
+    pub fn pmp_check(&self, mode: Mode, addr: Address, len: u64, access_type: &AccessType) -> bool {
+        let end = addr.saturating_add(len);
+        let mut any_active = false;
+
+        for cfg_index in 0..PMP_ENTRIES / ENTRIES_PER_CFG {
+            let first = cfg_index * ENTRIES_PER_CFG;
+            let pmpcfg = self.registers.read(cfg_register(first));
+            if pmpcfg == 0 {
+                continue;
+            }
+
+            for index in first..first + ENTRIES_PER_CFG {
+                let cfg = entry_cfg(pmpcfg, index);
+                let Some((start, stop)) = self.pmp_range(index, AddressMatching::from_cfg(cfg))
+                else {
+                    continue;
+                };
+                any_active = true;
+
+                if start >= stop || addr >= stop || end <= start {
+                    continue;
+                }
+
+                if addr < start || end > stop {
+                    return false;
+                }
+
+                return (mode == Mode::Machine && cfg & CFG_L == 0) || permits(cfg, access_type);
+            }
+        }
+
+        mode == Mode::Machine || !any_active
+    }
+
+    /// Account for locked PMP entries when writing a `pmpcfg` or `pmpaddr`
+    /// register. Section 3.7.1: writes to the configuration and address of a
+    /// locked entry are ignored, as are writes to the address of the entry
+    /// preceding a locked TOR entry.
+    pub(super) fn pmp_transform_write(&self, reg: CSRegister, value: CSRValue) -> CSRValue {
+        let index = reg as usize;
+        let pmpaddr0 = CSRegister::pmpaddr0 as usize;
+
+        if is_pmpcfg(reg) {
+            let old_value = self.registers.read(index);
+            return (0..ENTRIES_PER_CFG).fold(value, |value, entry| {
+                let shift = 8 * entry;
+                if entry_cfg(old_value, entry) & CFG_L == 0 {
+                    value
+                } else {
+                    (value & !(0xFF << shift)) | (old_value & (0xFF << shift))
+                }
+            });
+        }
+
+        let entry = index - pmpaddr0;
+        let locked = self.pmp_cfg(entry) & CFG_L != 0;
+        let next_locked_tor = entry + 1 < PMP_ENTRIES && {
+            let next = self.pmp_cfg(entry + 1);
+            next & CFG_L != 0 && AddressMatching::from_cfg(next) == AddressMatching::Tor
+        };
+
+        if locked || next_locked_tor {
+            self.registers.read(index)
+        } else {
+            value
+        }
+    }
+}
+
+/// Whether `reg` is one of the `pmpcfg` registers
+pub const fn is_pmpcfg(reg: CSRegister) -> bool {
+    let index = reg as usize;
+    index >= CSRegister::pmpcfg0 as usize && index <= CSRegister::pmpcfg14 as usize
+}
+
+/// Whether `reg` is one of the `pmpaddr` registers
+pub const fn is_pmpaddr(reg: CSRegister) -> bool {
+    let index = reg as usize;
+    index >= CSRegister::pmpaddr0 as usize && index <= CSRegister::pmpaddr63 as usize
+}
+
+/// Whether `reg` is one of the PMP registers
+pub const fn is_pmp_register(reg: CSRegister) -> bool {
+    is_pmpcfg(reg) || is_pmpaddr(reg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CFG_L, CFG_R, CFG_W, CFG_X};
+    use crate::{
+        backend_test,
+        machine_state::{
+            csregisters::{CSRValue, CSRegister, CSRegisters, CSRegistersLayout},
+            mode::Mode,
+            AccessType,
+        },
+        state_backend::{Backend, BackendManagement, Layout},
+    };
+
+    const TOR: u8 = 1 << 3;
+    const NA4: u8 = 2 << 3;
+    const NAPOT: u8 = 3 << 3;
+
+    backend_test!(test_pmp_check, F, {
+        let mut backend = F::new::<CSRegistersLayout>();
+        let placed = CSRegistersLayout::placed().into_location();
+        let mut csrs: CSRegisters<
+            <F::Backend<CSRegistersLayout> as BackendManagement>::Manager<'_>,
+        > = CSRegisters::bind(backend.allocate(placed));
+
+        // Without active entries every access succeeds
+        assert!(csrs.pmp_check(Mode::User, 0x1000, 8, &AccessType::Store));
+
+        // Entry 0 is off, pmpaddr0 is only the base of entry 1
+        // Entry 1: TOR [0x1000, 0x2000) read-only
+        // Entry 2: NA4 [0x2000, 0x2004) executable
+        // Entry 3: NAPOT [0x3000, 0x4000) read-write, locked
+        csrs.write(CSRegister::pmpaddr0, 0x1000 >> 2);
+        csrs.write(CSRegister::pmpaddr1, 0x2000 >> 2);
+        csrs.write(CSRegister::pmpaddr2, 0x2000 >> 2);
+        csrs.write(CSRegister::pmpaddr3, (0x3000 | 0x7FF) >> 2);
+        let cfgs = [0, TOR | CFG_R, NA4 | CFG_X, NAPOT | CFG_R | CFG_W | CFG_L];
+        let pmpcfg0 = CSRValue::from_le_bytes([cfgs[0], cfgs[1], cfgs[2], cfgs[3], 0, 0, 0, 0]);
+        csrs.write(CSRegister::pmpcfg0, pmpcfg0);
+
+        let check = |mode, addr, len, access_type| csrs.pmp_check(mode, addr, len, &access_type);
+
+        // TOR
+        assert!(check(Mode::User, 0x1000, 8, AccessType::Load));
+        assert!(check(Mode::Supervisor, 0x1FF8, 8, AccessType::Load));
+        assert!(!check(Mode::Supervisor, 0x1000, 8, AccessType::Store));
+        assert!(!check(Mode::User, 0x1000, 2, AccessType::Instruction));
+
+        // NA4, accesses matching only partially fail
+        assert!(check(Mode::User, 0x2000, 4, AccessType::Instruction));
+        assert!(!check(Mode::User, 0x2000, 8, AccessType::Instruction));
+        assert!(!check(Mode::User, 0x1FFC, 8, AccessType::Load));
+
+        // NAPOT
+        assert!(check(Mode::User, 0x3FF8, 8, AccessType::Store));
+        assert!(!check(Mode::User, 0x3000, 4, AccessType::Instruction));
+
+        // S-mode and U-mode accesses not matching any entry fail
+        assert!(!check(Mode::Supervisor, 0x800, 8, AccessType::Load));
+        assert!(!check(Mode::User, 0x4000, 8, AccessType::Load));
+
+        // M-mode is only restricted by locked entries
+        assert!(check(Mode::Machine, 0x800, 8, AccessType::Store));
+        assert!(check(Mode::Machine, 0x1000, 8, AccessType::Store));
+        assert!(!check(Mode::Machine, 0x3000, 4, AccessType::Instruction));
+        assert!(check(Mode::Machine, 0x3000, 4, AccessType::Load));
+    });
+
+    backend_test!(test_pmp_lock, F, {
+        let mut backend = F::new::<CSRegistersLayout>();
+        let placed = CSRegistersLayout::placed().into_location();
+        let mut csrs: CSRegisters<
+            <F::Backend<CSRegistersLayout> as BackendManagement>::Manager<'_>,
+        > = CSRegisters::bind(backend.allocate(placed));
+
+        // Entry 1 is a locked TOR entry, entry 2 is unlocked
+        csrs.write(CSRegister::pmpaddr0, 0x100);
+        csrs.write(CSRegister::pmpaddr1, 0x200);
+        csrs.write(CSRegister::pmpaddr2, 0x300);
+        let pmpcfg0 = CSRValue::from_le_bytes([0, TOR | CFG_R | CFG_L, CFG_R, 0, 0, 0, 0, 0]);
+        csrs.write(CSRegister::pmpcfg0, pmpcfg0);
+        assert_eq!(csrs.read(CSRegister::pmpcfg0), pmpcfg0);
+
+        // The configuration of locked entries can't be changed. Bits 5 and 6
+        // of the configurations are always zero.
+        csrs.write(CSRegister::pmpcfg0, CSRValue::from_le_bytes([0x7F; 8]));
+        let mut expected = [0x1F; 8];
+        expected[1] = TOR | CFG_R | CFG_L;
+        assert_eq!(
+            csrs.read(CSRegister::pmpcfg0),
+            CSRValue::from_le_bytes(expected)
+        );
+
+        // Neither can the address of the entry and its TOR base
+        csrs.write(CSRegister::pmpaddr0, 0x111);
+        csrs.write(CSRegister::pmpaddr1, 0x222);
+        csrs.write(CSRegister::pmpaddr2, 0x333);
+        assert_eq!(csrs.read(CSRegister::pmpaddr0), 0x100);
+        assert_eq!(csrs.read(CSRegister::pmpaddr1), 0x200);
+        assert_eq!(csrs.read(CSRegister::pmpaddr2), 0x333);
+
+        // Resetting the hart unlocks all entries
+        csrs.reset();
+        csrs.write(CSRegister::pmpaddr1, 0x222);
+        assert_eq!(csrs.read(CSRegister::pmpaddr1), 0x222);
+    });
+}
@@ -6,7 +6,8 @@ use crate::{
     machine_state::bus::{self, main_memory::MainMemoryLayout, Address},
     parser::parse_block,
 };
-use std::{borrow::Cow, collections::BTreeMap, marker::PhantomData};
+use kernel_loader::ProgramHeaders;
+use std::{borrow::Cow, collections::BTreeMap, marker::PhantomData, ops::Range};
 
 /// RISC-V program
 pub struct Program<'a, ML> {
@@ -23,9 +24,12 @@ pub struct Program<'a, ML> {
     // representing bytes at `index..index+length` and
     // all the arrays are non-overlapping
     pub segments: BTreeMap<Address, Cow<'a, [u8]>>,
+
+    /// Location of the ELF program headers in main memory, if they are loaded
+    pub program_headers: Option<ProgramHeaders>,
 }
 
-impl<'a, ML> kernel_loader::Memory for Program<'a, ML> {
+impl<'a, ML: MainMemoryLayout> kernel_loader::Memory for Program<'a, ML> {
     fn write_bytes(
         &mut self,
         mut paddr: u64,
@@ -91,6 +95,11 @@ impl<'a, ML> kernel_loader::Memory for Program<'a, ML> {
 
         Ok(())
     }
+
+    fn bounds(&self) -> Option<Range<u64>> {
+        let start = bus::start_of_main_memory::<ML>();
+        Some(start..start + ML::BYTES as u64)
+    }
 }
 
 impl<'a, ML: MainMemoryLayout> Program<'a, ML> {
@@ -104,9 +113,12 @@ impl<'a, ML: MainMemoryLayout> Program<'a, ML> {
             _pd: PhantomData,
             entrypoint: start_if_reloc,
             segments: BTreeMap::new(),
+            program_headers: None,
         };
 
-        myself.entrypoint = kernel_loader::load_elf(&mut myself, start_if_reloc, elf)?.entry;
+        let loaded = kernel_loader::load_elf(&mut myself, start_if_reloc, elf)?;
+        myself.entrypoint = loaded.entry;
+        myself.program_headers = loaded.program_headers;
 
         Ok(myself)
     }
@@ -118,6 +130,7 @@ impl<'a, ML: MainMemoryLayout> Program<'a, ML> {
             _pd: PhantomData,
             entrypoint,
             segments: BTreeMap::from_iter([(entrypoint, Cow::Borrowed(code))]),
+            program_headers: None,
         }
     }
 
@@ -150,6 +163,7 @@ mod tests {
             _pd: PhantomData,
             entrypoint: 0,
             segments: BTreeMap::new(),
+            program_headers: None,
         };
         let mut buffer = Cursor::new(vec![0; 2048]);
 
@@ -55,7 +55,11 @@
 //! [Layouts]: Layout
 //! [Locations]: Location
 
+pub mod hash;
 pub mod memory_backend;
+pub mod merkle;
+pub mod proof_backend;
+pub mod snapshot;
 
 mod layout;
 pub use layout::*;
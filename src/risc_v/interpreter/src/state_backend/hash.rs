@@ -0,0 +1,62 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+use mavryk_crypto_rs::blake2b::digest_256;
+use std::fmt;
+
+/// Size of a [`Hash`] in bytes
+pub const DIGEST_SIZE: usize = 32;
+
+/// BLAKE2b-256 digest of (a part of) the state
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hash {
+    digest: [u8; DIGEST_SIZE],
+}
+
+impl Hash {
+    /// Hash a slice of bytes.
+    pub fn blake2b_hash_bytes(bytes: &[u8]) -> Self {
+        let digest = digest_256(bytes)
+            .expect("BLAKE2b-256 hashing failed")
+            .try_into()
+            .expect("BLAKE2b-256 digest has unexpected size");
+        Self { digest }
+    }
+
+    /// Combine the hashes of the children of a Merkle tree node.
+    pub fn combine(hashes: &[Hash]) -> Self {
+        let bytes = hashes
+            .iter()
+            .flat_map(|hash| hash.digest)
+            .collect::<Vec<_>>();
+        Self::blake2b_hash_bytes(&bytes)
+    }
+
+    /// Raw digest bytes
+    pub fn as_bytes(&self) -> &[u8; DIGEST_SIZE] {
+        &self.digest
+    }
+}
+
+impl From<[u8; DIGEST_SIZE]> for Hash {
+    fn from(digest: [u8; DIGEST_SIZE]) -> Self {
+        Self { digest }
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hash(")?;
+        fmt::Display::fmt(self, f)?;
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.digest
+            .iter()
+            .try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
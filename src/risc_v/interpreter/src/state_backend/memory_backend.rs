@@ -2,7 +2,12 @@
 //
 // SPDX-License-Identifier: MIT
 
-use crate::state_backend::{self as backend, Layout};
+use crate::state_backend::{
+    self as backend,
+    hash::Hash,
+    merkle::{merkle_leaves, merkle_root},
+    Layout,
+};
 use std::{alloc, marker::PhantomData, ptr, slice};
 
 /// In-memory state backend
@@ -47,6 +52,11 @@ where
         // [self.layout] (u8 makes this easy). This slice is lifetime safe because of `'backend`.
         unsafe { slice::from_raw_parts_mut(self.backing_storage, self.layout.size()) }
     }
+
+    /// Compute the root hash of the Merkle tree over the state.
+    pub fn root_hash(&self) -> Hash {
+        merkle_root(&merkle_leaves::<L>(), self.borrow())
+    }
 }
 
 impl<T> Drop for InMemoryBackend<T> {
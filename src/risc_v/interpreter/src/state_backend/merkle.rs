@@ -0,0 +1,416 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Merkle trees over the state backend storage
+//!
+//! The leaves of the Merkle tree are the locations of a [`Layout`] in the
+//! order in which the layout allocates them. Locations larger than
+//! [`MERKLE_LEAF_SIZE`] are split into several leaves. Bytes that don't belong
+//! to any location, e.g. alignment padding, are not part of the tree.
+//!
+//! The leaves are arranged in a balanced binary tree. A leaf is hashed from
+//! its bytes and an inner node from the hashes of its two children.
+
+use super::{
+    hash::{Hash, DIGEST_SIZE},
+    DynRegion, Elem, Layout, Location, Manager, Region,
+};
+use std::{marker::PhantomData, ops::Range};
+
+/// Maximum size of a Merkle tree leaf in bytes
+pub const MERKLE_LEAF_SIZE: usize = 4096;
+
+/// Byte ranges of the leaves of the Merkle tree for layout `L`
+pub fn merkle_leaves<L: Layout>() -> Vec<Range<usize>> {
+    let mut leaves = Vec::new();
+    for location in traced_locations::<L>() {
+        let mut start = location.offset;
+        let end = location.offset + location.size;
+        while start < end {
+            let leaf_end = end.min(start + MERKLE_LEAF_SIZE);
+            leaves.push(start..leaf_end);
+            start = leaf_end;
+        }
+    }
+    leaves
+}
+
+/// Location allocated by a layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TracedLocation {
+    pub offset: usize,
+    pub size: usize,
+    pub elem_size: usize,
+}
+
+/// Locations of layout `L` in the order in which it allocates them
+pub(crate) fn traced_locations<L: Layout>() -> Vec<TracedLocation> {
+    let mut tracer = LocationTracer {
+        locations: Vec::new(),
+    };
+    L::allocate(&mut tracer, L::placed().into_location());
+    tracer.locations
+}
+
+/// Compute the root hash of the Merkle tree with the given `leaves` over
+/// `data`.
+pub fn merkle_root(leaves: &[Range<usize>], data: &[u8]) -> Hash {
+    fold_tree(0..leaves.len(), &mut |index| {
+        Hash::blake2b_hash_bytes(&data[leaves[index].clone()])
+    })
+}
+
+/// Compute the root hash of a balanced binary tree over the leaves with the
+/// given `indices`, given a function to hash a single leaf. The tree without
+/// leaves, e.g. that of an empty layout, has the hash of no bytes.
+pub(crate) fn fold_tree(indices: Range<usize>, hash_leaf: &mut impl FnMut(usize) -> Hash) -> Hash {
+    match indices.len() {
+        0 => return Hash::blake2b_hash_bytes(&[]),
+        1 => return hash_leaf(indices.start),
+        _ => {}
+    }
+
+    let (left, right) = split_range(indices);
+    let left = fold_tree(left, hash_leaf);
+    let right = fold_tree(right, hash_leaf);
+    Hash::combine(&[left, right])
+}
+
+/// Split the leaf indices of an inner node, which has at least two leaves,
+/// into those of its children.
+pub(crate) fn split_range(indices: Range<usize>) -> (Range<usize>, Range<usize>) {
+    let mid = indices.start + indices.len() / 2;
+    (indices.start..mid, mid..indices.end)
+}
+
+/// Pruned Merkle tree of a state which only reveals some leaves
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MerkleProof {
+    /// Subtree whose contents are hidden
+    Blinded(Hash),
+
+    /// Leaf whose contents are revealed
+    Leaf(Vec<u8>),
+
+    /// Inner node
+    Node(Box<MerkleProof>, Box<MerkleProof>),
+}
+
+/// Encoding tags of [`MerkleProof`] constructors
+const TAG_BLINDED: u8 = 0;
+const TAG_LEAF: u8 = 1;
+const TAG_NODE: u8 = 2;
+
+impl MerkleProof {
+    /// Build a proof for the Merkle tree with the given `leaves` over `data`
+    /// which reveals the leaves for which `reveal` holds.
+    pub fn build(leaves: &[Range<usize>], data: &[u8], reveal: &impl Fn(usize) -> bool) -> Self {
+        Self::build_with(leaves.len(), &|index| &data[leaves[index].clone()], reveal)
+    }
+
+    /// Build a proof for the Merkle tree with `num_leaves` leaves whose
+    /// contents are given by `leaf_data`, which reveals the leaves for which
+    /// `reveal` holds.
+    pub fn build_with<'a>(
+        num_leaves: usize,
+        leaf_data: &impl Fn(usize) -> &'a [u8],
+        reveal: &impl Fn(usize) -> bool,
+    ) -> Self {
+        Self::build_range(0..num_leaves, leaf_data, reveal)
+    }
+
+    fn build_range<'a>(
+        indices: Range<usize>,
+        leaf_data: &impl Fn(usize) -> &'a [u8],
+        reveal: &impl Fn(usize) -> bool,
+    ) -> Self {
+        if !indices.clone().any(reveal) {
+            let hash = fold_tree(indices, &mut |index| {
+                Hash::blake2b_hash_bytes(leaf_data(index))
+            });
+            return Self::Blinded(hash);
+        }
+
+        if indices.len() == 1 {
+            return Self::Leaf(leaf_data(indices.start).to_vec());
+        }
+
+        let (left, right) = split_range(indices);
+        Self::Node(
+            Box::new(Self::build_range(left, leaf_data, reveal)),
+            Box::new(Self::build_range(right, leaf_data, reveal)),
+        )
+    }
+
+    /// Root hash of the tree
+    pub fn root_hash(&self) -> Hash {
+        match self {
+            Self::Blinded(hash) => *hash,
+            Self::Leaf(data) => Hash::blake2b_hash_bytes(data),
+            Self::Node(left, right) => Hash::combine(&[left.root_hash(), right.root_hash()]),
+        }
+    }
+
+    /// Revealed leaves with their indices, provided the proof has the shape
+    /// of the Merkle tree with the given `leaves`
+    pub fn revealed_leaves(&self, leaves: &[Range<usize>]) -> Option<Vec<(usize, &[u8])>> {
+        let mut revealed = Vec::new();
+        self.collect_revealed(0..leaves.len(), leaves, &mut revealed)
+            .then_some(revealed)
+    }
+
+    fn collect_revealed<'a>(
+        &'a self,
+        indices: Range<usize>,
+        leaves: &[Range<usize>],
+        revealed: &mut Vec<(usize, &'a [u8])>,
+    ) -> bool {
+        match self {
+            Self::Blinded(_) => true,
+
+            Self::Leaf(data) => {
+                indices.len() == 1 && leaves[indices.start].len() == data.len() && {
+                    revealed.push((indices.start, data.as_slice()));
+                    true
+                }
+            }
+
+            Self::Node(left, right) => {
+                indices.len() > 1 && {
+                    let (left_indices, right_indices) = split_range(indices);
+                    left.collect_revealed(left_indices, leaves, revealed)
+                        && right.collect_revealed(right_indices, leaves, revealed)
+                }
+            }
+        }
+    }
+
+    /// Compute the root hash of the tree after the revealed leaves have been
+    /// updated. `hash_leaf` is called with the index of each revealed leaf.
+    pub fn updated_root_hash(
+        &self,
+        leaves: &[Range<usize>],
+        hash_leaf: &mut impl FnMut(usize) -> Hash,
+    ) -> Hash {
+        self.updated_root_hash_range(0..leaves.len(), hash_leaf)
+    }
+
+    fn updated_root_hash_range(
+        &self,
+        indices: Range<usize>,
+        hash_leaf: &mut impl FnMut(usize) -> Hash,
+    ) -> Hash {
+        match self {
+            Self::Blinded(hash) => *hash,
+            Self::Leaf(_) => hash_leaf(indices.start),
+            Self::Node(left, right) => {
+                let (left_indices, right_indices) = split_range(indices);
+                let left = left.updated_root_hash_range(left_indices, hash_leaf);
+                let right = right.updated_root_hash_range(right_indices, hash_leaf);
+                Hash::combine(&[left, right])
+            }
+        }
+    }
+
+    /// Serialise the proof.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Blinded(hash) => {
+                out.push(TAG_BLINDED);
+                out.extend_from_slice(hash.as_bytes());
+            }
+
+            Self::Leaf(data) => {
+                out.push(TAG_LEAF);
+                out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+                out.extend_from_slice(data);
+            }
+
+            Self::Node(left, right) => {
+                out.push(TAG_NODE);
+                left.encode(out);
+                right.encode(out);
+            }
+        }
+    }
+
+    /// Deserialise a proof produced by [`MerkleProof::encode`]. Returns the
+    /// proof and the remaining input.
+    pub fn decode(input: &[u8]) -> Option<(Self, &[u8])> {
+        let (tag, input) = input.split_first()?;
+        match *tag {
+            TAG_BLINDED => {
+                let digest: [u8; DIGEST_SIZE] = input.get(..DIGEST_SIZE)?.try_into().ok()?;
+                Some((Self::Blinded(Hash::from(digest)), &input[DIGEST_SIZE..]))
+            }
+
+            TAG_LEAF => {
+                let len = u64::from_le_bytes(input.get(..8)?.try_into().ok()?);
+                let input = &input[8..];
+                let len = usize::try_from(len)
+                    .ok()
+                    .filter(|len| *len <= input.len())?;
+                Some((Self::Leaf(input[..len].to_vec()), &input[len..]))
+            }
+
+            TAG_NODE => {
+                let (left, input) = Self::decode(input)?;
+                let (right, input) = Self::decode(input)?;
+                Some((Self::Node(Box::new(left), Box::new(right)), input))
+            }
+
+            _ => None,
+        }
+    }
+}
+
+/// [`Manager`] which only keeps track of the locations of a layout
+struct LocationTracer {
+    locations: Vec<TracedLocation>,
+}
+
+impl LocationTracer {
+    fn trace<E: Elem, const LEN: usize>(&mut self, loc: Location<[E; LEN]>) {
+        self.locations.push(TracedLocation {
+            offset: loc.offset(),
+            size: loc.size(),
+            elem_size: std::mem::size_of::<E>(),
+        });
+    }
+}
+
+/// Region of a [`LocationTracer`] that can't be accessed
+struct TracedRegion<E>(PhantomData<E>);
+
+impl<E: Elem> Region for TracedRegion<E> {
+    type Elem = E;
+
+    const LEN: usize = 0;
+
+    fn read(&self, _index: usize) -> E {
+        unreachable!("Traced regions can't be accessed")
+    }
+
+    fn read_all(&self) -> Vec<E> {
+        unreachable!("Traced regions can't be accessed")
+    }
+
+    fn read_some(&self, _offset: usize, _buffer: &mut [E]) {
+        unreachable!("Traced regions can't be accessed")
+    }
+
+    fn write(&mut self, _index: usize, _value: E) {
+        unreachable!("Traced regions can't be accessed")
+    }
+
+    fn write_all(&mut self, _value: &[E]) {
+        unreachable!("Traced regions can't be accessed")
+    }
+
+    fn write_some(&mut self, _index: usize, _buffer: &[E]) {
+        unreachable!("Traced regions can't be accessed")
+    }
+
+    fn replace(&mut self, _index: usize, _value: E) -> E {
+        unreachable!("Traced regions can't be accessed")
+    }
+}
+
+impl DynRegion for TracedRegion<u8> {
+    const LEN: usize = 0;
+
+    fn read<E: Elem>(&self, _address: usize) -> E {
+        unreachable!("Traced regions can't be accessed")
+    }
+
+    fn write<E: Elem>(&mut self, _address: usize, _value: E) {
+        unreachable!("Traced regions can't be accessed")
+    }
+
+    fn write_all<E: Elem>(&mut self, _address: usize, _values: &[E]) {
+        unreachable!("Traced regions can't be accessed")
+    }
+}
+
+impl Manager for LocationTracer {
+    type Region<E: Elem, const LEN: usize> = TracedRegion<E>;
+
+    fn allocate_region<E: Elem, const LEN: usize>(
+        &mut self,
+        loc: Location<[E; LEN]>,
+    ) -> Self::Region<E, LEN> {
+        self.trace(loc);
+        TracedRegion(PhantomData)
+    }
+
+    type DynRegion<const LEN: usize> = TracedRegion<u8>;
+
+    fn allocate_dyn_region<const LEN: usize>(
+        &mut self,
+        loc: Location<[u8; LEN]>,
+    ) -> Self::DynRegion<LEN> {
+        self.trace(loc);
+        TracedRegion(PhantomData)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_backend::{Array, Atom};
+
+    #[test]
+    fn test_leaves() {
+        type L = (Atom<u8>, Array<u64, 1024>, Atom<u16>);
+
+        // The array is split into two leaves, the last atom is aligned.
+        assert_eq!(
+            merkle_leaves::<L>(),
+            vec![0..1, 8..4104, 4104..8200, 8200..8202]
+        );
+    }
+
+    #[test]
+    fn test_proof_roundtrip() {
+        let leaves = (0..5).map(|i| i * 4..i * 4 + 4).collect::<Vec<_>>();
+        let data = (0..20).collect::<Vec<u8>>();
+        let root = merkle_root(&leaves, &data);
+
+        let proof = MerkleProof::build(&leaves, &data, &|index| index == 1 || index == 4);
+        assert_eq!(proof.root_hash(), root);
+        assert_eq!(
+            proof.revealed_leaves(&leaves),
+            Some(vec![(1, &data[4..8]), (4, &data[16..20])])
+        );
+
+        let mut encoded = Vec::new();
+        proof.encode(&mut encoded);
+        assert_eq!(
+            MerkleProof::decode(&encoded),
+            Some((proof.clone(), &[][..]))
+        );
+        assert_eq!(MerkleProof::decode(&encoded[..encoded.len() - 1]), None);
+
+        // A proof of a different shape is rejected.
+        assert_eq!(proof.revealed_leaves(&leaves[..4]), None);
+
+        // Updating revealed leaves changes the root hash.
+        let mut updated = data.clone();
+        updated[5] = 0xFF;
+        let updated_root = proof.updated_root_hash(&leaves, &mut |index| {
+            Hash::blake2b_hash_bytes(&updated[leaves[index].clone()])
+        });
+        assert_eq!(updated_root, merkle_root(&leaves, &updated));
+    }
+
+    #[test]
+    fn test_empty_tree() {
+        let root = merkle_root(&[], &[]);
+        assert_eq!(root, Hash::blake2b_hash_bytes(&[]));
+
+        let proof = MerkleProof::build(&[], &[], &|_| true);
+        assert_eq!(proof, MerkleProof::Blinded(root));
+        assert_eq!(proof.revealed_leaves(&[]), Some(vec![]));
+    }
+}
@@ -0,0 +1,363 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Proofs of state transitions
+//!
+//! A [`Proof`] of a state transition, e.g. a single [`MachineState::step`],
+//! is the Merkle tree of the state before the transition which reveals the
+//! leaves that the transition accesses. The [`ProofManager`] records these
+//! accesses while the transition runs, keeping the contents that each leaf
+//! had before its first access. The proof is built from those contents and
+//! from the untouched leaves, hence the state doesn't need to be copied.
+//!
+//! The verifier rebuilds a partial state from the revealed leaves, replays
+//! the transition against it and computes the root hash of the resulting
+//! state. Verification fails if the transition accesses any leaf that the
+//! proof doesn't reveal.
+//!
+//! [`MachineState::step`]: crate::machine_state::MachineState::step
+
+use super::{
+    hash::Hash,
+    memory_backend::InMemoryBackend,
+    merkle::{merkle_leaves, MerkleProof},
+    AllocatedOf, Backend, DynRegion, Elem, Layout, Location, Manager, Region,
+};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    marker::PhantomData,
+    mem,
+    ops::Range,
+    rc::Rc,
+    slice,
+};
+
+/// Proof of a state transition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proof {
+    tree: MerkleProof,
+}
+
+impl Proof {
+    /// Root hash of the state before the transition
+    pub fn initial_root_hash(&self) -> Hash {
+        self.tree.root_hash()
+    }
+
+    /// Merkle tree of the state before the transition
+    pub fn tree(&self) -> &MerkleProof {
+        &self.tree
+    }
+
+    /// Serialise the proof.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.tree.encode(&mut bytes);
+        bytes
+    }
+
+    /// Deserialise a proof produced by [`Proof::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match MerkleProof::decode(bytes)? {
+            (tree, []) => Some(Self { tree }),
+            _ => None,
+        }
+    }
+}
+
+/// Run the state transition `f` against the state in `backend` and produce a
+/// proof for it.
+pub fn prove<L: Layout, T>(
+    backend: &mut InMemoryBackend<L>,
+    f: impl FnOnce(AllocatedOf<L, ProofManager<'_>>) -> T,
+) -> (T, Proof) {
+    let leaves = merkle_leaves::<L>();
+    let (result, accessed) = run_recorded(backend, &leaves, f);
+
+    // Leaves which haven't been accessed still hold their initial contents.
+    let data = backend.borrow();
+    let tree = MerkleProof::build_with(
+        leaves.len(),
+        &|index| match accessed.get(&index) {
+            Some(initial) => initial.as_slice(),
+            None => &data[leaves[index].clone()],
+        },
+        &|index| accessed.contains_key(&index),
+    );
+
+    (result, Proof { tree })
+}
+
+/// Replay the state transition `f` against the partial state revealed by
+/// `proof`. Returns the result of `f` and the root hash of the state after the
+/// transition, or `None` if the proof is invalid for `f`.
+pub fn verify<L: Layout, T>(
+    proof: &Proof,
+    f: impl FnOnce(AllocatedOf<L, ProofManager<'_>>) -> T,
+) -> Option<(T, Hash)> {
+    let leaves = merkle_leaves::<L>();
+    let revealed = proof.tree.revealed_leaves(&leaves)?;
+
+    let (mut backend, _) = InMemoryBackend::<L>::new();
+    for (index, data) in revealed.iter() {
+        backend.write(leaves[*index].start, data);
+    }
+
+    let (result, accessed) = run_recorded(&mut backend, &leaves, f);
+
+    let revealed = revealed
+        .into_iter()
+        .map(|(index, _)| index)
+        .collect::<BTreeSet<_>>();
+    if !accessed.keys().all(|index| revealed.contains(index)) {
+        return None;
+    }
+
+    let data = backend.borrow();
+    let final_hash = proof.tree.updated_root_hash(&leaves, &mut |index| {
+        Hash::blake2b_hash_bytes(&data[leaves[index].clone()])
+    });
+
+    Some((result, final_hash))
+}
+
+/// Run `f` against the state in `backend` and return the indices of the
+/// Merkle tree leaves that it accessed, with their contents before the first
+/// access.
+fn run_recorded<L: Layout, T>(
+    backend: &mut InMemoryBackend<L>,
+    leaves: &[Range<usize>],
+    f: impl FnOnce(AllocatedOf<L, ProofManager<'_>>) -> T,
+) -> (T, BTreeMap<usize, Vec<u8>>) {
+    let log = Rc::new(RefCell::new(AccessLog {
+        leaves: leaves.to_vec(),
+        accessed: BTreeMap::new(),
+    }));
+
+    let mut manager = ProofManager::new(backend.borrow_mut(), log.clone());
+    let result = f(L::allocate(&mut manager, L::placed().into_location()));
+
+    let accessed = mem::take(&mut log.borrow_mut().accessed);
+    (result, accessed)
+}
+
+/// Merkle tree leaves accessed through a [`ProofManager`], with their
+/// contents before the first access
+struct AccessLog {
+    leaves: Vec<Range<usize>>,
+    accessed: BTreeMap<usize, Vec<u8>>,
+}
+
+impl AccessLog {
+    /// Record an access of `len` bytes at `offset` into the region at
+    /// `region_offset` whose current contents are `region`. Leaves never
+    /// span several regions.
+    fn record(&mut self, region_offset: usize, region: &[u8], offset: usize, len: usize) {
+        let offset = region_offset + offset;
+        let first = self.leaves.partition_point(|leaf| leaf.end <= offset);
+        for (index, leaf) in self.leaves.iter().enumerate().skip(first) {
+            if leaf.start >= offset + len {
+                break;
+            }
+
+            self.accessed.entry(index).or_insert_with(|| {
+                region[leaf.start - region_offset..leaf.end - region_offset].to_vec()
+            });
+        }
+    }
+}
+
+/// Manager for in-memory backing storage which records accessed locations
+pub struct ProofManager<'backend> {
+    backing_storage: usize,
+    log: Rc<RefCell<AccessLog>>,
+    _lifetime: PhantomData<&'backend mut [u8]>,
+}
+
+impl<'backend> ProofManager<'backend> {
+    fn new(backing_storage: &'backend mut [u8], log: Rc<RefCell<AccessLog>>) -> Self {
+        Self {
+            backing_storage: backing_storage.as_mut_ptr() as usize,
+            log,
+            _lifetime: PhantomData,
+        }
+    }
+}
+
+impl<'backend> Manager for ProofManager<'backend> {
+    type Region<E: Elem, const LEN: usize> = ProofRegion<'backend, E, LEN>;
+
+    fn allocate_region<E: Elem, const LEN: usize>(
+        &mut self,
+        loc: Location<[E; LEN]>,
+    ) -> Self::Region<E, LEN> {
+        let inner = unsafe {
+            let ptr = self.backing_storage + loc.offset();
+            &mut *(ptr as *mut [E; LEN])
+        };
+
+        ProofRegion {
+            inner,
+            offset: loc.offset(),
+            log: self.log.clone(),
+        }
+    }
+
+    type DynRegion<const LEN: usize> = ProofRegion<'backend, u8, LEN>;
+
+    fn allocate_dyn_region<const LEN: usize>(
+        &mut self,
+        loc: Location<[u8; LEN]>,
+    ) -> Self::DynRegion<LEN> {
+        self.allocate_region::<u8, LEN>(loc)
+    }
+}
+
+/// Region of a [`ProofManager`]
+pub struct ProofRegion<'backend, E, const LEN: usize> {
+    inner: &'backend mut [E; LEN],
+    offset: usize,
+    log: Rc<RefCell<AccessLog>>,
+}
+
+impl<'backend, E, const LEN: usize> ProofRegion<'backend, E, LEN> {
+    /// Record an access of `count` elements starting at element `index`.
+    fn record(&self, index: usize, count: usize) {
+        let size = mem::size_of::<E>();
+        self.record_bytes(index * size, count * size);
+    }
+
+    /// Record an access of `len` bytes starting at byte `address`. This must
+    /// happen before the access so the log sees the contents prior to it.
+    fn record_bytes(&self, address: usize, len: usize) {
+        // SAFETY: Elements are plain data, hence any of their bytes may be
+        // read. The slice doesn't outlive the shared borrow of `self.inner`.
+        let region = unsafe {
+            slice::from_raw_parts(
+                self.inner.as_ptr() as *const u8,
+                mem::size_of_val(self.inner),
+            )
+        };
+        self.log
+            .borrow_mut()
+            .record(self.offset, region, address, len);
+    }
+}
+
+impl<'backend, E: Elem, const LEN: usize> Region for ProofRegion<'backend, E, LEN> {
+    type Elem = E;
+
+    const LEN: usize = LEN;
+
+    fn read(&self, index: usize) -> E {
+        self.record(index, 1);
+        Region::read(&*self.inner, index)
+    }
+
+    fn read_all(&self) -> Vec<E> {
+        self.record(0, LEN);
+        self.inner.read_all()
+    }
+
+    fn read_some(&self, offset: usize, buffer: &mut [E]) {
+        self.record(offset, buffer.len());
+        self.inner.read_some(offset, buffer)
+    }
+
+    fn write(&mut self, index: usize, value: E) {
+        self.record(index, 1);
+        Region::write(self.inner, index, value)
+    }
+
+    fn write_all(&mut self, value: &[E]) {
+        self.record(0, LEN);
+        Region::write_all(self.inner, value)
+    }
+
+    fn write_some(&mut self, index: usize, buffer: &[E]) {
+        self.record(index, buffer.len());
+        self.inner.write_some(index, buffer)
+    }
+
+    fn replace(&mut self, index: usize, value: E) -> E {
+        self.record(index, 1);
+        self.inner.replace(index, value)
+    }
+}
+
+impl<'backend, const LEN: usize> DynRegion for ProofRegion<'backend, u8, LEN> {
+    const LEN: usize = LEN;
+
+    fn read<E: Elem>(&self, address: usize) -> E {
+        self.record_bytes(address, mem::size_of::<E>());
+        DynRegion::read(&*self.inner, address)
+    }
+
+    fn write<E: Elem>(&mut self, address: usize, value: E) {
+        self.record_bytes(address, mem::size_of::<E>());
+        DynRegion::write(self.inner, address, value)
+    }
+
+    fn write_all<E: Elem>(&mut self, address: usize, values: &[E]) {
+        self.record_bytes(address, mem::size_of_val(values));
+        DynRegion::write_all(self.inner, address, values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_backend::{Array, Atom, Cell};
+
+    type L = (Atom<u64>, Array<u8, 10000>, Atom<u64>);
+
+    /// Add the first atom to the last one and write the sum to the array.
+    fn transition<M: Manager>(space: AllocatedOf<L, M>) -> u64 {
+        let (first, mut array, mut last): (Cell<u64, M>, _, Cell<u64, M>) = space;
+        let sum = first.read() + last.read();
+        last.write(sum);
+        array.write(9000, sum as u8);
+        sum
+    }
+
+    #[test]
+    fn test_prove_verify() {
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        {
+            let mut space = backend.allocate(placed);
+            space.0.write(20);
+            space.2.write(22);
+        }
+
+        let initial_hash = backend.root_hash();
+        let (result, proof) = prove(&mut backend, |space| transition(space));
+        let final_hash = backend.root_hash();
+
+        assert_eq!(result, 42);
+        assert_ne!(initial_hash, final_hash);
+        assert_eq!(proof.initial_root_hash(), initial_hash);
+
+        // Only the atoms and the last leaf of the array are revealed.
+        let leaves = merkle_leaves::<L>();
+        let revealed = proof.tree().revealed_leaves(&leaves).unwrap();
+        assert_eq!(
+            revealed.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![0, 3, 4]
+        );
+
+        let proof = Proof::from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(
+            verify::<L, _>(&proof, |space| transition(space)),
+            Some((42, final_hash))
+        );
+
+        // A proof that doesn't reveal all accessed leaves is rejected.
+        let (_, partial_proof) = prove(&mut backend, |space: AllocatedOf<L, _>| space.0.read());
+        assert_eq!(
+            verify::<L, _>(&partial_proof, |space| transition(space)),
+            None
+        );
+    }
+}
@@ -0,0 +1,242 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Snapshots of the state backend storage
+//!
+//! A snapshot consists of a header followed by the non-zero pages of the
+//! backing storage in ascending order:
+//!
+//! | Field          | Size                    |
+//! |----------------|-------------------------|
+//! | Magic          | 8 bytes                 |
+//! | Version        | 4 bytes, little-endian  |
+//! | Layout hash    | 32 bytes                |
+//! | Storage size   | 8 bytes, little-endian  |
+//! | Page count     | 8 bytes, little-endian  |
+//! | Pages          | Page count × (8 + 4096) |
+//!
+//! Each page is its index as a little-endian 8 byte integer followed by its
+//! contents. The last page of the storage may be shorter than
+//! [`SNAPSHOT_PAGE_SIZE`]. The same state always yields the same snapshot.
+//!
+//! The layout hash identifies the shape of the [`Layout`]. Snapshots taken
+//! with a different layout are rejected.
+
+use super::{
+    hash::{Hash, DIGEST_SIZE},
+    memory_backend::InMemoryBackend,
+    merkle::traced_locations,
+    Layout,
+};
+
+/// Magic bytes at the start of every snapshot
+const SNAPSHOT_MAGIC: &[u8; 8] = b"RVSNAPSH";
+
+/// Version of the snapshot format
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Size of the pages of a snapshot in bytes
+pub const SNAPSHOT_PAGE_SIZE: usize = 4096;
+
+/// Errors when restoring a snapshot
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("Not a snapshot")]
+    InvalidMagic,
+
+    #[error("Unsupported snapshot version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Snapshot was taken with layout {found}, expected {expected}")]
+    LayoutMismatch { expected: Hash, found: Hash },
+
+    #[error("Malformed snapshot")]
+    Malformed,
+}
+
+/// Hash identifying the shape of layout `L`
+///
+/// The hash covers the offset, size and element size of every location as
+/// well as the total size and alignment of the layout.
+pub fn layout_hash<L: Layout>() -> Hash {
+    let placed = L::placed();
+    let mut bytes = Vec::new();
+
+    for value in [placed.size(), placed.align()] {
+        bytes.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+
+    for location in traced_locations::<L>() {
+        for value in [location.offset, location.size, location.elem_size] {
+            bytes.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+    }
+
+    Hash::blake2b_hash_bytes(&bytes)
+}
+
+impl<L: Layout> InMemoryBackend<L> {
+    /// Serialise the backing storage into a snapshot.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let data = self.borrow();
+        let pages = data
+            .chunks(SNAPSHOT_PAGE_SIZE)
+            .enumerate()
+            .filter(|(_, page)| page.iter().any(|byte| *byte != 0))
+            .collect::<Vec<_>>();
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(SNAPSHOT_MAGIC);
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(layout_hash::<L>().as_bytes());
+        bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&(pages.len() as u64).to_le_bytes());
+
+        for (index, page) in pages {
+            bytes.extend_from_slice(&(index as u64).to_le_bytes());
+            bytes.extend_from_slice(page);
+        }
+
+        bytes
+    }
+
+    /// Restore a backend from a snapshot produced by [`InMemoryBackend::snapshot`].
+    pub fn from_snapshot(snapshot: &[u8]) -> Result<Self, SnapshotError> {
+        let mut input = Input(snapshot);
+
+        if input.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::InvalidMagic);
+        }
+
+        let version = u32::from_le_bytes(input.take_array()?);
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let expected = layout_hash::<L>();
+        let found = Hash::from(input.take_array::<DIGEST_SIZE>()?);
+        if found != expected {
+            return Err(SnapshotError::LayoutMismatch { expected, found });
+        }
+
+        let (mut backend, _) = Self::new();
+        let data = backend.borrow_mut();
+
+        if input.take_u64()? != data.len() as u64 {
+            return Err(SnapshotError::Malformed);
+        }
+
+        let page_count = input.take_u64()?;
+        let mut next_index = 0;
+
+        for _ in 0..page_count {
+            let index = usize::try_from(input.take_u64()?).map_err(|_| SnapshotError::Malformed)?;
+
+            // Pages are stored in ascending order without duplicates.
+            if index < next_index {
+                return Err(SnapshotError::Malformed);
+            }
+            next_index = index.checked_add(1).ok_or(SnapshotError::Malformed)?;
+
+            let page = data
+                .chunks_mut(SNAPSHOT_PAGE_SIZE)
+                .nth(index)
+                .ok_or(SnapshotError::Malformed)?;
+            page.copy_from_slice(input.take(page.len())?);
+        }
+
+        if !input.0.is_empty() {
+            return Err(SnapshotError::Malformed);
+        }
+
+        Ok(backend)
+    }
+}
+
+/// Remaining input of a snapshot
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.0.len() < len {
+            return Err(SnapshotError::Malformed);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn take_array<const LEN: usize>(&mut self) -> Result<[u8; LEN], SnapshotError> {
+        Ok(self.take(LEN)?.try_into().unwrap())
+    }
+
+    fn take_u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.take_array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_backend::{Array, Atom, Backend, Region};
+
+    type L = (Atom<u64>, Array<u8, 10000>, Atom<u16>);
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        {
+            let mut space = backend.allocate(placed);
+            space.0.write(42);
+            space.1.write(9000, 1);
+            space.2.write(7);
+        }
+
+        let snapshot = backend.snapshot();
+        let restored = InMemoryBackend::<L>::from_snapshot(&snapshot).unwrap();
+        assert_eq!(restored.borrow(), backend.borrow());
+        assert_eq!(restored.snapshot(), snapshot);
+
+        // Only the non-zero pages are stored
+        assert!(snapshot.len() < 2 * (8 + SNAPSHOT_PAGE_SIZE) + 100);
+    }
+
+    #[test]
+    fn test_snapshot_rejected() {
+        let (backend, _) = InMemoryBackend::<L>::new();
+        let snapshot = backend.snapshot();
+
+        assert_eq!(
+            InMemoryBackend::<L>::from_snapshot(&snapshot[1..]).err(),
+            Some(SnapshotError::InvalidMagic)
+        );
+
+        let mut future = snapshot.clone();
+        future[8..12].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            InMemoryBackend::<L>::from_snapshot(&future).err(),
+            Some(SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1))
+        );
+
+        type Other = (Atom<u64>, Array<u16, 5000>, Atom<u16>);
+        assert!(matches!(
+            InMemoryBackend::<Other>::from_snapshot(&snapshot),
+            Err(SnapshotError::LayoutMismatch { .. })
+        ));
+
+        assert_eq!(
+            InMemoryBackend::<L>::from_snapshot(&snapshot[..snapshot.len() - 1]).err(),
+            Some(SnapshotError::Malformed)
+        );
+
+        // The empty state has no pages, its snapshot ends with the page count.
+        let mut out_of_range = snapshot[..snapshot.len() - 8].to_vec();
+        out_of_range.extend_from_slice(&1u64.to_le_bytes());
+        out_of_range.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(
+            InMemoryBackend::<L>::from_snapshot(&out_of_range).err(),
+            Some(SnapshotError::Malformed)
+        );
+    }
+}
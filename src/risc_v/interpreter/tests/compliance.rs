@@ -0,0 +1,293 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Compliance report of the interpreter
+//!
+//! Discovers compiled tests in a directory, runs each through the interpreter
+//! and prints a table of the results per ISA extension.
+//!
+//! Two kinds of tests are recognised:
+//! - riscv-tests binaries named `rv64{u,s,m}<ext>-{p,v}-<name>`, which pass
+//!   when they exit with code 0,
+//! - riscv-arch-test binaries with a `<name>.reference_output` file either
+//!   next to them or in a sibling `references` directory. They pass when the
+//!   memory between the `begin_signature` and `end_signature` symbols matches
+//!   the reference. They must be built with a model whose halt macro uses the
+//!   exit system call of the POSIX execution environment.
+//!
+//! Usage: `cargo test --release --test compliance -- [--strict] DIR`, e.g.
+//! with the generated riscv-tests in `tezt/tests/riscv-tests/generated`.
+//! Without a directory, the report is skipped. With `--strict`, failing tests
+//! make the runner fail.
+
+use risc_v_interpreter::{machine_state::mode::Mode, Interpreter, InterpreterResult};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Read,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+const MAX_STEPS: usize = 1_000_000;
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+
+/// riscv-tests exiting from another mode than the one their name suggests
+const EXIT_MODE_OVERRIDES: &[(&str, Mode)] = &[("rv64mi-p-csr", Mode::User)];
+
+/// Compiled test found in the tests directory
+struct TestCase {
+    name: String,
+    path: PathBuf,
+    extension: String,
+    exit_mode: Mode,
+    /// Expected signature of riscv-arch-test tests
+    reference: Option<Vec<u8>>,
+}
+
+/// Extension covered by a riscv-tests binary, e.g. `rv64ui-p-add` covers I.
+fn riscv_tests_extension(name: &str) -> Option<(String, Mode)> {
+    let (isa, _) = name.strip_prefix("rv64")?.split_once('-')?;
+    let mode = match isa.chars().next()? {
+        'u' => Mode::User,
+        's' => Mode::Supervisor,
+        'm' => Mode::Machine,
+        _ => return None,
+    };
+    let exit_mode = EXIT_MODE_OVERRIDES
+        .iter()
+        .find(|(test, _)| *test == name)
+        .map_or(mode, |(_, mode)| *mode);
+
+    let extension = match &isa[1..] {
+        "i" if mode == Mode::Machine => "Machine".to_owned(),
+        "i" if mode == Mode::Supervisor => "Supervisor".to_owned(),
+        ext if ext.len() == 1 => ext.to_uppercase(),
+        ext => {
+            let mut chars = ext.chars();
+            chars
+                .next()
+                .map_or(String::new(), |c| c.to_uppercase().collect())
+                + chars.as_str()
+        }
+    };
+    Some((extension, exit_mode))
+}
+
+/// Does the file at `path` start with the ELF magic number?
+fn is_elf(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && &magic == ELF_MAGIC
+}
+
+/// Parse a reference signature: one little-endian word in hexadecimal per
+/// line. The width of the words is given by the number of digits.
+fn parse_reference(contents: &str) -> Option<Vec<u8>> {
+    let mut signature = Vec::new();
+    for line in contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let word = u64::from_str_radix(line, 16).ok()?;
+        let width = line.len().div_ceil(2);
+        signature.extend_from_slice(&word.to_le_bytes()[..width.min(8)]);
+    }
+    Some(signature)
+}
+
+/// Reference signature of the riscv-arch-test binary at `path`, if any
+fn find_reference(path: &Path) -> Option<Vec<u8>> {
+    let stem = path.file_stem()?.to_str()?;
+    let file_name = format!("{stem}.reference_output");
+    let dir = path.parent()?;
+
+    [
+        dir.join(&file_name),
+        dir.join("..").join("references").join(&file_name),
+    ]
+    .iter()
+    .find_map(|candidate| fs::read_to_string(candidate).ok())
+    .and_then(|contents| parse_reference(&contents))
+}
+
+/// Extension covered by a riscv-arch-test binary. The tests are laid out as
+/// `<isa>/<extension>/src/<test>`.
+fn arch_test_extension(path: &Path) -> String {
+    let mut components = path
+        .parent()
+        .into_iter()
+        .flat_map(Path::ancestors)
+        .filter_map(|dir| dir.file_name()?.to_str());
+
+    let parent = components.next().unwrap_or_default();
+    match parent {
+        "src" => components.next().unwrap_or_default(),
+        _ => parent,
+    }
+    .to_owned()
+}
+
+/// Recursively collect the tests in `dir`.
+fn discover(dir: &Path, tests: &mut Vec<TestCase>) -> std::io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            discover(&path, tests)?;
+            continue;
+        }
+
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !is_elf(&path) {
+            continue;
+        }
+
+        if let Some((extension, exit_mode)) = riscv_tests_extension(name) {
+            tests.push(TestCase {
+                name: name.to_owned(),
+                extension,
+                exit_mode,
+                reference: None,
+                path,
+            });
+        } else if let Some(reference) = find_reference(&path) {
+            tests.push(TestCase {
+                name: name.to_owned(),
+                extension: arch_test_extension(&path),
+                exit_mode: Mode::Machine,
+                reference: Some(reference),
+                path,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a test, returning why it failed if it did.
+fn run_test(test: &TestCase) -> Result<(), String> {
+    let contents = fs::read(&test.path).map_err(|err| err.to_string())?;
+    let symbols = kernel_loader::get_elf_symbols(&contents).map_err(|err| err.to_string())?;
+    let symbol = |name: &str| {
+        symbols
+            .iter()
+            .find(|(_, sym)| **sym == name)
+            .map(|(addr, _)| *addr)
+    };
+
+    let mut backend = Interpreter::create_backend();
+    let mut interpreter = Interpreter::new(&mut backend, &contents, None, test.exit_mode)
+        .map_err(|err| format!("Boot failed: {err}"))?;
+
+    let code = match interpreter.run(MAX_STEPS) {
+        InterpreterResult::Exit { code, .. } => code,
+        InterpreterResult::Running(_) => return Err("Timeout".to_owned()),
+        InterpreterResult::Exception(exc, _) => return Err(format!("Exception: {exc:?}")),
+    };
+
+    let Some(reference) = &test.reference else {
+        return match code {
+            0 => Ok(()),
+            code => Err(format!("Failed at test case {}", code >> 1)),
+        };
+    };
+
+    let (Some(begin), Some(end)) = (symbol("begin_signature"), symbol("end_signature")) else {
+        return Err("Missing signature symbols".to_owned());
+    };
+    let signature = interpreter
+        .read_memory(begin, (end - begin) as usize)
+        .map_err(|err| err.to_string())?;
+
+    // The signature region may be padded past the reference.
+    match signature.get(..reference.len()) {
+        Some(signature) if signature == reference.as_slice() => Ok(()),
+        _ => {
+            let offset = signature
+                .iter()
+                .zip(reference.iter())
+                .position(|(ours, theirs)| ours != theirs)
+                .unwrap_or(signature.len().min(reference.len()));
+            Err(format!("Signature differs at offset {offset:#x}"))
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let mut strict = false;
+    let mut dir = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--strict" => strict = true,
+            arg if arg.starts_with('-') => {}
+            arg => dir = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let Some(dir) = dir else {
+        eprintln!("Skipping compliance report: no tests directory given");
+        return ExitCode::SUCCESS;
+    };
+
+    let mut tests = Vec::new();
+    if let Err(err) = discover(&dir, &mut tests) {
+        eprintln!(
+            "Skipping compliance report: failed to read {} ({err})",
+            dir.display()
+        );
+        return ExitCode::SUCCESS;
+    }
+
+    // Silence the panic messages of the tests, they are reported as failures.
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut table: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    let mut failures = Vec::new();
+    for test in tests.iter() {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| run_test(test)))
+            .unwrap_or_else(|_| Err("Panicked".to_owned()));
+
+        let (passed, total) = table.entry(&test.extension).or_default();
+        *total += 1;
+        match result {
+            Ok(()) => *passed += 1,
+            Err(reason) => failures.push((test.name.as_str(), reason)),
+        }
+    }
+
+    let _ = panic::take_hook();
+
+    for (name, reason) in failures.iter() {
+        println!("FAILED {name}: {reason}");
+    }
+
+    println!();
+    println!(
+        "{:<12} {:>7} {:>7} {:>7}",
+        "Extension", "Passed", "Total", "%"
+    );
+    for (extension, (passed, total)) in table.iter() {
+        let percentage = 100.0 * *passed as f64 / *total as f64;
+        println!("{extension:<12} {passed:>7} {total:>7} {percentage:>6.1}%");
+    }
+
+    let passed: usize = table.values().map(|(passed, _)| passed).sum();
+    println!("{passed} of {} tests passed", tests.len());
+
+    if strict && !failures.is_empty() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,28 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+use risc_v_interpreter::{machine_state::mode::Mode, Interpreter, InterpreterResult::*};
+use std::fs;
+
+const HELLO_STD: &str = "../tests/hello_std/rv64-hello-std";
+const MAX_STEPS: usize = 10_000_000;
+
+#[test]
+fn test_hello_std() {
+    let contents = fs::read(HELLO_STD)
+        .expect("Failed to read binary, try building it with: make -C src/risc_v/tests build");
+
+    let mut backend = Interpreter::create_backend();
+    let mut interpreter =
+        Interpreter::new(&mut backend, &contents, None, Mode::Machine).expect("Boot failed");
+    match interpreter.run(MAX_STEPS) {
+        Exit { code: 0, .. } => (),
+        Exit { code, steps } => panic!("Exited with code {} after {} steps", code, steps),
+        Running(_) => panic!("Timeout"),
+        Exception(exc, _) => panic!("Unexpected exception: {:?}", exc),
+    }
+
+    assert_eq!(interpreter.stdout(), b"Hello from program: 328350\n");
+    assert_eq!(interpreter.stderr(), b"done\n");
+}
@@ -332,3 +332,32 @@ test_case!(#[ignore], test_suite_rv64uzfh_v_fmin, "rv64uzfh-v-fmin");
 test_case!(#[ignore], test_suite_rv64uzfh_v_ldst, "rv64uzfh-v-ldst");
 test_case!(#[ignore], test_suite_rv64uzfh_v_move, "rv64uzfh-v-move");
 test_case!(#[ignore], test_suite_rv64uzfh_v_recoding, "rv64uzfh-v-recoding");
+
+#[test]
+fn test_checkpoint_restore() {
+    let contents = fs::read(format!("{}/rv64ui-v-add", TESTS_DIR)).expect("Failed to read binary");
+    let mut backend = Interpreter::create_backend();
+    let pc = {
+        let mut interpreter =
+            Interpreter::new(&mut backend, &contents, None, Mode::User).expect("Boot failed");
+        assert!(matches!(interpreter.run(2000), Running(2000)));
+        interpreter.read_pc()
+    };
+
+    // Take the checkpoint once virtual memory is enabled, while no
+    // interpreter is bound to the backend.
+    let checkpoint = backend.clone();
+
+    let Exit { code: 0, steps } = Interpreter::resume(&mut backend).run(MAX_STEPS) else {
+        panic!("Unexpected result")
+    };
+
+    // Re-executing from the checkpoint is deterministic.
+    backend.borrow_mut().copy_from_slice(checkpoint.borrow());
+    let mut interpreter = Interpreter::resume(&mut backend);
+    assert_eq!(interpreter.read_pc(), pc);
+    assert!(matches!(
+        interpreter.run(MAX_STEPS),
+        Exit { code: 0, steps: replayed } if replayed == steps
+    ));
+}
@@ -2,11 +2,14 @@ use derive_more::{Error, From};
 use goblin::{
     elf,
     elf::header::{ET_DYN, ET_EXEC},
-    elf::program_header::{ProgramHeader, PT_LOAD},
+    elf::program_header::{ProgramHeader, PT_LOAD, PT_PHDR},
+    elf::reloc::Reloc,
     elf::Elf,
+    elf64::reloc::{R_RISCV_32, R_RISCV_64, R_RISCV_JUMP_SLOT, R_RISCV_NONE, R_RISCV_RELATIVE},
 };
 use std::collections::HashMap;
 use std::io::{Cursor, Seek, SeekFrom, Write};
+use std::ops::Range;
 
 #[derive(Debug, From, Error, derive_more::Display)]
 pub enum Error {
@@ -16,6 +19,65 @@ pub enum Error {
         addr: u64,
     },
     Goblin(goblin::error::Error),
+    #[from(ignore)]
+    #[display(fmt = "ELF type {} is not supported", elf_type)]
+    UnsupportedElfType {
+        elf_type: u16,
+    },
+    #[from(ignore)]
+    #[display(fmt = "Unsupported relocation type {} at offset {:#x}", r_type, offset)]
+    UnsupportedRelocation {
+        r_type: u32,
+        offset: u64,
+    },
+    #[from(ignore)]
+    #[display(fmt = "Relocation at offset {:#x} refers to an invalid symbol", offset)]
+    InvalidSymbol {
+        offset: u64,
+    },
+    #[from(ignore)]
+    #[display(
+        fmt = "Undefined symbol {} in relocation at offset {:#x}",
+        name,
+        offset
+    )]
+    UndefinedSymbol {
+        name: String,
+        offset: u64,
+    },
+    #[from(ignore)]
+    #[display(fmt = "Segment at {:#x} exceeds the ELF file", addr)]
+    SegmentOutOfFile {
+        addr: u64,
+    },
+    #[from(ignore)]
+    #[display(
+        fmt = "Segment at {:#x} is not aligned to its alignment {:#x}",
+        addr,
+        align
+    )]
+    MisalignedSegment {
+        addr: u64,
+        align: u64,
+    },
+    #[from(ignore)]
+    #[display(fmt = "Segments at {:#x} and {:#x} overlap", first, second)]
+    OverlappingSegments {
+        first: u64,
+        second: u64,
+    },
+    #[from(ignore)]
+    #[display(
+        fmt = "Range {:#x}..{:#x} is outside of the memory {:#x}..{:#x}",
+        "range.start",
+        "range.end",
+        "bounds.start",
+        "bounds.end"
+    )]
+    OutOfBounds {
+        range: Range<u64>,
+        bounds: Range<u64>,
+    },
 }
 
 /// [LoadResult] is the outcome of loading an ELF file
@@ -24,6 +86,18 @@ pub struct LoadResult {
     pub entry: u64,
     /// index of the last written byte in memory after loading the ELF
     pub last_written: u64,
+    /// location of the program headers in memory, if they are loaded
+    pub program_headers: Option<ProgramHeaders>,
+}
+
+/// [ProgramHeaders] locates the program headers of a loaded ELF file, which
+/// the C runtime of a static executable inspects to set up thread-local storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeaders {
+    /// the address of the first program header
+    pub addr: u64,
+    /// the number of program headers
+    pub count: u64,
 }
 
 /// [Memory] is an interface to the linear array of bytes of the RISC-V virtual machine
@@ -36,39 +110,166 @@ pub trait Memory {
         let bytes = vec![0u8; len as usize];
         self.write_bytes(paddr, &bytes)
     }
+
+    /// Range of physical addresses that can be written to, if it is bounded.
+    /// Loading fails if a segment or a relocation falls outside of it.
+    fn bounds(&self) -> Option<Range<u64>> {
+        None
+    }
 }
 
-/// Loads an executable ELF file in the memory of the virtual machine
-pub fn load_elf_nonreloc<'a>(
-    mem: &mut impl Memory,
-    elf: &Elf<'a>,
-    contents: &'a [u8],
-) -> Result<LoadResult, Error> {
-    let loadable_segments = elf
-        .program_headers
+/// Check that `range` lies within the bounds of `mem`.
+fn check_bounds(mem: &impl Memory, range: Range<u64>) -> Result<(), Error> {
+    match mem.bounds() {
+        Some(bounds) if range.start < bounds.start || range.end > bounds.end => {
+            Err(Error::OutOfBounds { range, bounds })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Validate the loadable segments among `headers` before writing anything to
+/// memory.
+/// Segments are loaded at `base` plus their physical address if `physical` is
+/// set, plus their virtual address otherwise. Returns the loadable segments
+/// together with their load address.
+fn validate_segments<'a>(
+    mem: &impl Memory,
+    headers: &'a [ProgramHeader],
+    contents: &[u8],
+    base: u64,
+    physical: bool,
+) -> Result<Vec<(u64, &'a ProgramHeader)>, Error> {
+    let mut segments = Vec::new();
+
+    for segment in headers {
+        if segment.p_type != PT_LOAD {
+            continue;
+        }
+
+        let addr = base.wrapping_add(if physical {
+            segment.p_paddr
+        } else {
+            segment.p_vaddr
+        });
+
+        let file_end = segment.p_offset.checked_add(segment.p_filesz);
+        if !matches!(file_end, Some(end) if end <= contents.len() as u64)
+            || segment.p_filesz > segment.p_memsz
+        {
+            return Err(Error::SegmentOutOfFile { addr });
+        }
+
+        // The address and the offset in the file must be congruent modulo the
+        // alignment, which is a power of 2.
+        let align = segment.p_align;
+        if align > 1
+            && (!align.is_power_of_two() || segment.p_vaddr % align != segment.p_offset % align)
+        {
+            return Err(Error::MisalignedSegment { addr, align });
+        }
+
+        let end = addr
+            .checked_add(segment.p_memsz)
+            .ok_or(Error::OutOfBounds {
+                range: addr..u64::MAX,
+                bounds: mem.bounds().unwrap_or(0..u64::MAX),
+            })?;
+        check_bounds(mem, addr..end)?;
+
+        segments.push((addr, segment));
+    }
+
+    let mut ranges: Vec<Range<u64>> = segments
         .iter()
-        .filter(|header| header.p_type == PT_LOAD);
+        .map(|(addr, segment)| *addr..addr + segment.p_memsz)
+        .collect();
+    ranges.sort_by_key(|range| range.start);
+    for pair in ranges.windows(2) {
+        if pair[0].end > pair[1].start {
+            return Err(Error::OverlappingSegments {
+                first: pair[0].start,
+                second: pair[1].start,
+            });
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Address of the program headers once the segments among `headers` are
+/// loaded, see [validate_segments] for `base` and `physical`. The headers are
+/// either described by a `PT_PHDR` segment or part of a loadable segment.
+fn program_headers(
+    headers: &[ProgramHeader],
+    phoff: u64,
+    base: u64,
+    physical: bool,
+) -> Option<ProgramHeaders> {
+    let load_addr = |segment: &ProgramHeader| {
+        base.wrapping_add(if physical {
+            segment.p_paddr
+        } else {
+            segment.p_vaddr
+        })
+    };
 
-    let mut last_written = 0;
+    let addr = match headers.iter().find(|segment| segment.p_type == PT_PHDR) {
+        Some(segment) => load_addr(segment),
+        None => headers
+            .iter()
+            .filter(|segment| segment.p_type == PT_LOAD)
+            .find(|segment| {
+                segment.p_offset <= phoff && phoff - segment.p_offset < segment.p_filesz
+            })
+            .map(|segment| load_addr(segment).wrapping_add(phoff - segment.p_offset))?,
+    };
 
-    for segment in loadable_segments {
+    Some(ProgramHeaders {
+        addr,
+        count: headers.len() as u64,
+    })
+}
+
+/// Copy the validated segments to memory. Returns the address past the last
+/// written byte.
+fn write_segments(
+    mem: &mut impl Memory,
+    segments: &[(u64, &ProgramHeader)],
+    contents: &[u8],
+    mut last_written: u64,
+) -> Result<u64, Error> {
+    for (addr, segment) in segments {
         // Copy the region from the file to memory.
-        mem.write_bytes(segment.p_paddr, &contents[segment.file_range()])?;
+        mem.write_bytes(*addr, &contents[segment.file_range()])?;
 
         // If the target memory region is larger than the source region,
         // we must fill the gap with 0s.
         if segment.p_memsz > segment.p_filesz {
-            let first_zero = segment.p_paddr + segment.p_filesz;
+            let first_zero = addr + segment.p_filesz;
             let num_zeroes = segment.p_memsz - segment.p_filesz;
             mem.set_zero(first_zero, num_zeroes)?;
         }
 
-        last_written = segment.p_paddr + segment.p_memsz;
+        last_written = addr + segment.p_memsz;
     }
 
+    Ok(last_written)
+}
+
+/// Loads an executable ELF file in the memory of the virtual machine
+pub fn load_elf_nonreloc<'a>(
+    mem: &mut impl Memory,
+    elf: &Elf<'a>,
+    contents: &'a [u8],
+) -> Result<LoadResult, Error> {
+    let segments = validate_segments(mem, &elf.program_headers, contents, 0, true)?;
+    let last_written = write_segments(mem, &segments, contents, 0)?;
+
     Ok(LoadResult {
         entry: elf.entry,
         last_written,
+        program_headers: program_headers(&elf.program_headers, elf.header.e_phoff, 0, true),
     })
 }
 
@@ -90,51 +291,84 @@ pub fn load_elf_reloc<'a>(
     elf: &Elf<'a>,
     contents: &'a [u8],
 ) -> Result<LoadResult, Error> {
-    let loadable_segments = elf
-        .program_headers
-        .iter()
-        .filter(|header| header.p_type == PT_LOAD);
-
-    let mut last_written = start;
-
-    for segment in loadable_segments {
-        // Copy the region from the file to memory.
-        mem.write_bytes(start + segment.p_vaddr, &contents[segment.file_range()])?;
-
-        // If the target memory region is larger than the source region,
-        // we must fill the gap with 0s.
-        if segment.p_memsz > segment.p_filesz {
-            let first_zero = start + segment.p_vaddr + segment.p_filesz;
-            let num_zeroes = segment.p_memsz - segment.p_filesz;
-            mem.set_zero(first_zero, num_zeroes)?;
-        }
+    let segments = validate_segments(mem, &elf.program_headers, contents, start, false)?;
+    let last_written = write_segments(mem, &segments, contents, start)?;
 
-        last_written = start + segment.p_vaddr + segment.p_memsz;
+    // The RISC-V psABI only uses relocations with explicit addends.
+    if let Some(reloc) = elf.dynrels.iter().next() {
+        return Err(Error::UnsupportedRelocation {
+            r_type: reloc.r_type,
+            offset: reloc.r_offset,
+        });
     }
 
-    for r in elf.dynrelas.iter() {
-        let goblin::elf::reloc::Reloc {
-            r_offset,
-            r_addend,
-            r_sym: _,
-            r_type,
-        } = r;
-        match r_type {
-            goblin::elf64::reloc::R_RISCV_RELATIVE => {
-                let relocated = (start as i64 + r_addend.unwrap_or_default()).to_ne_bytes();
-                let buf = &relocated[..];
-                mem.write_bytes(start + r_offset, buf)?;
-            }
-            t => unimplemented!("Unsupported relocation directive {t}"),
+    for reloc in elf.dynrelas.iter().chain(elf.pltrelocs.iter()) {
+        let symbol = match reloc.r_type {
+            R_RISCV_32 | R_RISCV_64 | R_RISCV_JUMP_SLOT => resolve_symbol(elf, start, &reloc)?,
+            _ => 0,
+        };
+
+        if let Some((value, width)) = relocation_value(&reloc, start, symbol)? {
+            let addr = start.wrapping_add(reloc.r_offset);
+            check_bounds(mem, addr..addr.saturating_add(width as u64))?;
+            mem.write_bytes(addr, &value.to_le_bytes()[..width])?;
         }
     }
 
     Ok(LoadResult {
         entry: elf.header.e_entry + start,
         last_written,
+        program_headers: program_headers(&elf.program_headers, elf.header.e_phoff, start, false),
     })
 }
 
+/// Address of the symbol referenced by `reloc` once the ELF file is loaded at
+/// `start`. Undefined weak symbols resolve to 0, other undefined symbols
+/// cannot be resolved as there is no dynamic linking.
+fn resolve_symbol(elf: &Elf, start: u64, reloc: &Reloc) -> Result<u64, Error> {
+    let offset = reloc.r_offset;
+    if reloc.r_sym == 0 {
+        return Ok(0);
+    }
+
+    let symbol = elf
+        .dynsyms
+        .get(reloc.r_sym)
+        .ok_or(Error::InvalidSymbol { offset })?;
+
+    if symbol.st_shndx as u32 != elf::section_header::SHN_UNDEF {
+        Ok(start.wrapping_add(symbol.st_value))
+    } else if symbol.st_bind() == elf::sym::STB_WEAK {
+        Ok(0)
+    } else {
+        let name = elf.dynstrtab.get_at(symbol.st_name).unwrap_or_default();
+        Err(Error::UndefinedSymbol {
+            name: name.to_owned(),
+            offset,
+        })
+    }
+}
+
+/// Value to write for the dynamic relocation `reloc` and its width in bytes,
+/// given the load address `start` and the address of the referenced symbol.
+fn relocation_value(reloc: &Reloc, start: u64, symbol: u64) -> Result<Option<(u64, usize)>, Error> {
+    let addend = reloc.r_addend.unwrap_or_default() as u64;
+    let value = match reloc.r_type {
+        R_RISCV_NONE => return Ok(None),
+        R_RISCV_RELATIVE => (start.wrapping_add(addend), 8),
+        R_RISCV_64 => (symbol.wrapping_add(addend), 8),
+        R_RISCV_32 => (symbol.wrapping_add(addend), 4),
+        R_RISCV_JUMP_SLOT => (symbol, 8),
+        r_type => {
+            return Err(Error::UnsupportedRelocation {
+                r_type,
+                offset: reloc.r_offset,
+            })
+        }
+    };
+    Ok(Some(value))
+}
+
 /// Loads an ELF file. If the file is relocatable, loads it at the given [start] address in the memory of the VM.
 /// If it is not relocatable, [start] is ignored.
 pub fn load_elf(mem: &mut impl Memory, start: u64, contents: &[u8]) -> Result<LoadResult, Error> {
@@ -142,7 +376,7 @@ pub fn load_elf(mem: &mut impl Memory, start: u64, contents: &[u8]) -> Result<Lo
     match elf.header.e_type {
         ET_EXEC => load_elf_nonreloc(mem, &elf, contents),
         ET_DYN => load_elf_reloc(mem, start, &elf, contents),
-        t => todo!("ELF type {t} is not yet supported"),
+        elf_type => Err(Error::UnsupportedElfType { elf_type }),
     }
 }
 
@@ -150,7 +384,9 @@ pub fn get_elf_symbols(contents: &[u8]) -> Result<HashMap<u64, &str>, Error> {
     let mut symbols = HashMap::new();
     let elf = Elf::parse(contents)?;
     for symbol in elf.syms.iter() {
-        let name = elf.strtab.get_at(symbol.st_name).expect("Symbol not found");
+        let Some(name) = elf.strtab.get_at(symbol.st_name) else {
+            continue;
+        };
         if !name.is_empty()
             && u32::try_from(symbol.st_shndx).expect("Symbol not valid address")
                 != elf::section_header::SHN_UNDEF
@@ -202,3 +438,162 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Memory backed by a vector, covering addresses `start..start + len`
+    struct TestMemory {
+        start: u64,
+        bytes: Vec<u8>,
+    }
+
+    impl Memory for TestMemory {
+        fn write_bytes(&mut self, paddr: u64, bytes: &[u8]) -> Result<(), Error> {
+            let offset = (paddr - self.start) as usize;
+            self.bytes[offset..offset + bytes.len()].copy_from_slice(bytes);
+            Ok(())
+        }
+
+        fn bounds(&self) -> Option<Range<u64>> {
+            Some(self.start..self.start + self.bytes.len() as u64)
+        }
+    }
+
+    fn segment(vaddr: u64, offset: u64, filesz: u64, memsz: u64, align: u64) -> ProgramHeader {
+        ProgramHeader {
+            p_type: PT_LOAD,
+            p_vaddr: vaddr,
+            p_paddr: vaddr,
+            p_offset: offset,
+            p_filesz: filesz,
+            p_memsz: memsz,
+            p_align: align,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_segments() {
+        let mem = TestMemory {
+            start: 0x1000,
+            bytes: vec![0; 0x1000],
+        };
+        let contents = [0u8; 0x100];
+
+        let headers = [
+            segment(0, 0, 0x80, 0x100, 0x10),
+            segment(0x100, 0x80, 0x80, 0x800, 0x10),
+        ];
+        let segments = validate_segments(&mem, &headers, &contents, 0x1000, false).unwrap();
+        assert_eq!(
+            segments.iter().map(|(addr, _)| *addr).collect::<Vec<_>>(),
+            [0x1000, 0x1100]
+        );
+
+        let overlapping = [
+            segment(0, 0, 0x80, 0x200, 0x10),
+            segment(0x100, 0x80, 0x80, 0x80, 0x10),
+        ];
+        assert!(matches!(
+            validate_segments(&mem, &overlapping, &contents, 0x1000, false),
+            Err(Error::OverlappingSegments {
+                first: 0x1000,
+                second: 0x1100
+            })
+        ));
+
+        let misaligned = [segment(0x104, 0x80, 0x80, 0x80, 0x10)];
+        assert!(matches!(
+            validate_segments(&mem, &misaligned, &contents, 0x1000, false),
+            Err(Error::MisalignedSegment {
+                addr: 0x1104,
+                align: 0x10
+            })
+        ));
+
+        let too_large = [segment(0x800, 0, 0x80, 0x1000, 0x10)];
+        assert!(matches!(
+            validate_segments(&mem, &too_large, &contents, 0x1000, false),
+            Err(Error::OutOfBounds { .. })
+        ));
+
+        let outside_file = [segment(0, 0x80, 0x100, 0x100, 0x10)];
+        assert!(matches!(
+            validate_segments(&mem, &outside_file, &contents, 0x1000, false),
+            Err(Error::SegmentOutOfFile { addr: 0x1000 })
+        ));
+    }
+
+    #[test]
+    fn test_program_headers() {
+        let load = [
+            segment(0, 0, 0x200, 0x200, 0x10),
+            segment(0x400, 0x200, 0x80, 0x80, 0x10),
+        ];
+        assert_eq!(
+            program_headers(&load, 0x40, 0x1000, false),
+            Some(ProgramHeaders {
+                addr: 0x1040,
+                count: 2
+            })
+        );
+        assert_eq!(program_headers(&load, 0x300, 0x1000, false), None);
+
+        let phdr = [
+            ProgramHeader {
+                p_type: PT_PHDR,
+                p_vaddr: 0x40,
+                p_paddr: 0x40,
+                ..Default::default()
+            },
+            segment(0, 0, 0x200, 0x200, 0x10),
+        ];
+        assert_eq!(
+            program_headers(&phdr, 0x300, 0x1000, false),
+            Some(ProgramHeaders {
+                addr: 0x1040,
+                count: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_relocation_value() {
+        let reloc = |r_type, r_addend| Reloc {
+            r_offset: 0x10,
+            r_addend: Some(r_addend),
+            r_sym: 0,
+            r_type,
+        };
+
+        assert_eq!(
+            relocation_value(&reloc(R_RISCV_RELATIVE, 0x20), 0x1000, 0).unwrap(),
+            Some((0x1020, 8))
+        );
+        assert_eq!(
+            relocation_value(&reloc(R_RISCV_64, -0x8), 0x1000, 0x1400).unwrap(),
+            Some((0x13f8, 8))
+        );
+        assert_eq!(
+            relocation_value(&reloc(R_RISCV_32, 0x4), 0x1000, 0x1400).unwrap(),
+            Some((0x1404, 4))
+        );
+        assert_eq!(
+            relocation_value(&reloc(R_RISCV_JUMP_SLOT, 0), 0x1000, 0x1400).unwrap(),
+            Some((0x1400, 8))
+        );
+        assert_eq!(
+            relocation_value(&reloc(R_RISCV_NONE, 0), 0x1000, 0).unwrap(),
+            None
+        );
+        assert!(matches!(
+            relocation_value(&reloc(goblin::elf64::reloc::R_RISCV_COPY, 0), 0x1000, 0),
+            Err(Error::UnsupportedRelocation {
+                r_type: goblin::elf64::reloc::R_RISCV_COPY,
+                offset: 0x10
+            })
+        ));
+    }
+}
@@ -0,0 +1,1030 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Durable storage of the PVM
+//!
+//! The durable storage is a key-value store whose keys are paths. It follows
+//! the semantics of the WASM PVM's durable storage: a path is a node if there
+//! is a value at that path or anywhere below it, and the value of a node
+//! counts towards its number of children.
+//!
+//! The storage is kept in the state backend as a table of entries, one per
+//! value, sorted by path so that lookups are binary searches and the entries
+//! of a node are contiguous. Values live in a heap region which is compacted
+//! when it runs out of space. The number of entries and the size of the heap
+//! are given by the [`DurableStorageLayout`].
+
+use mavryk_smart_rollup_constants::riscv::{
+    SBI_FIRMWARE_MAVRYK, SBI_MAVRYK_STORE_COPY, SBI_MAVRYK_STORE_DELETE,
+    SBI_MAVRYK_STORE_DELETE_VALUE, SBI_MAVRYK_STORE_GET_NTH_KEY, SBI_MAVRYK_STORE_HAS,
+    SBI_MAVRYK_STORE_LIST_SIZE, SBI_MAVRYK_STORE_MOVE, SBI_MAVRYK_STORE_READ,
+    SBI_MAVRYK_STORE_VALUE_SIZE, SBI_MAVRYK_STORE_WRITE,
+};
+use mavryk_smart_rollup_core::{
+    INPUT_OUTPUT_TOO_LARGE, MAX_FILE_CHUNK_SIZE, MEMORY_INVALID_ACCESS, STORE_INVALID_ACCESS,
+    STORE_INVALID_KEY, STORE_INVALID_SUBKEY_INDEX, STORE_KEY_TOO_LARGE, STORE_NOT_A_NODE,
+    STORE_NOT_A_VALUE, STORE_VALUE_SIZE_EXCEEDED, VALUE_TYPE_NONE, VALUE_TYPE_SUBTREE,
+    VALUE_TYPE_VALUE, VALUE_TYPE_VALUE_WITH_SUBTREE,
+};
+use risc_v_interpreter::{
+    machine_state::{
+        bus::{main_memory::MainMemoryLayout, Address},
+        registers::{a0, a1, a2, a3, a4, a6, a7},
+        MachineState,
+    },
+    state_backend::{self as backend, Region},
+};
+use std::{cmp::Ordering, ops::Range};
+
+/// Maximum length of a path in bytes, matches
+/// `mavryk_smart_rollup_host::path::PATH_MAX_SIZE`
+pub const PATH_MAX_SIZE: usize = 242;
+
+/// Number of bytes reserved for the path of an entry
+const PATH_CAPACITY: usize = 256;
+
+/// Separator between the steps of a path
+const PATH_SEPARATOR: u8 = b'/';
+
+/// Paths below this prefix may be read but not written
+const READONLY_PREFIX: &[u8] = b"/readonly";
+
+/// Configuration object for the durable storage size: maximum number of
+/// values and number of bytes available to store them
+pub enum Sizes<const ENTRIES: usize, const HEAP_BYTES: usize> {}
+
+/// Durable storage for up to 4096 values, holding 16 MiB in total
+pub type D16M = Sizes<4096, { 16 * 1024 * 1024 }>;
+
+/// Durable storage for up to 64 values, holding 64 KiB in total
+pub type D64K = Sizes<64, { 64 * 1024 }>;
+
+/// Underlying layout of [`Sizes`]: number of entries, number of heap bytes in
+/// use (including holes), number of bytes held by values, entry table and
+/// heap
+pub type SizesLayout<const ENTRIES: usize, const HEAP_BYTES: usize> = (
+    backend::Atom<u64>,
+    backend::Atom<u64>,
+    backend::Atom<u64>,
+    EntriesLayout<ENTRIES>,
+    backend::Array<u8, HEAP_BYTES>,
+);
+
+/// Layout of the entry table: path, path length, value offset in the heap
+/// and value length
+pub type EntriesLayout<const ENTRIES: usize> = (
+    backend::Array<[u8; PATH_CAPACITY], ENTRIES>,
+    backend::Array<u64, ENTRIES>,
+    backend::Array<u64, ENTRIES>,
+    backend::Array<u64, ENTRIES>,
+);
+
+/// Durable storage layout, i.e. specifies how many values the storage holds
+// XXX: Like for the main memory, we go through a dummy trait to associate the
+// region types with [`Sizes`].
+pub trait DurableStorageLayout: backend::Layout {
+    type Paths<M: backend::Manager>: Region<Elem = [u8; PATH_CAPACITY]>;
+
+    type Words<M: backend::Manager>: Region<Elem = u64>;
+
+    type Heap<M: backend::Manager>: Region<Elem = u8>;
+
+    /// Maximum number of values
+    const ENTRIES: usize;
+
+    /// Number of bytes available to store values
+    const HEAP_BYTES: usize;
+
+    fn refl<M: backend::Manager>(space: backend::AllocatedOf<Self, M>) -> DurableStorage<Self, M>;
+}
+
+impl<const ENTRIES: usize, const HEAP_BYTES: usize> DurableStorageLayout
+    for Sizes<ENTRIES, HEAP_BYTES>
+{
+    type Paths<M: backend::Manager> = M::Region<[u8; PATH_CAPACITY], ENTRIES>;
+
+    type Words<M: backend::Manager> = M::Region<u64, ENTRIES>;
+
+    type Heap<M: backend::Manager> = M::Region<u8, HEAP_BYTES>;
+
+    const ENTRIES: usize = ENTRIES;
+
+    const HEAP_BYTES: usize = HEAP_BYTES;
+
+    fn refl<M: backend::Manager>(space: backend::AllocatedOf<Self, M>) -> DurableStorage<Self, M> {
+        space
+    }
+}
+
+impl<const ENTRIES: usize, const HEAP_BYTES: usize> backend::Layout for Sizes<ENTRIES, HEAP_BYTES> {
+    type Placed = backend::PlacedOf<SizesLayout<ENTRIES, HEAP_BYTES>>;
+
+    fn place_with(alloc: &mut backend::Choreographer) -> Self::Placed {
+        SizesLayout::<ENTRIES, HEAP_BYTES>::place_with(alloc)
+    }
+
+    type Allocated<M: backend::Manager> = DurableStorage<Self, M>;
+
+    fn allocate<M: backend::Manager>(backend: &mut M, placed: Self::Placed) -> Self::Allocated<M> {
+        let (len, heap_used, value_bytes, entries, heap) =
+            SizesLayout::<ENTRIES, HEAP_BYTES>::allocate(backend, placed);
+        let (paths, path_lengths, value_offsets, value_lengths) = entries;
+        DurableStorage {
+            len,
+            heap_used,
+            value_bytes,
+            paths,
+            path_lengths,
+            value_offsets,
+            value_lengths,
+            heap,
+        }
+    }
+}
+
+/// Errors that durable storage operations may produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DurableStorageError {
+    /// The path exceeds [`PATH_MAX_SIZE`]
+    KeyTooLarge,
+
+    /// The path is malformed or not writable
+    InvalidKey,
+
+    /// There is no value at the path
+    NotAValue,
+
+    /// The offset lies beyond the end of the value
+    InvalidAccess,
+
+    /// The storage has run out of entries or heap space
+    ValueSizeExceeded,
+
+    /// Guest memory could not be accessed
+    MemoryInvalidAccess,
+
+    /// More than [`MAX_FILE_CHUNK_SIZE`] bytes were written at once
+    InputOutputTooLarge,
+
+    /// There is no node at the path
+    NotANode,
+
+    /// The node has fewer children than the requested index
+    InvalidSubkeyIndex,
+}
+
+impl DurableStorageError {
+    /// Error code as returned by the host functions of the WASM PVM
+    pub fn code(self) -> i32 {
+        match self {
+            Self::KeyTooLarge => STORE_KEY_TOO_LARGE,
+            Self::InvalidKey => STORE_INVALID_KEY,
+            Self::NotAValue => STORE_NOT_A_VALUE,
+            Self::InvalidAccess => STORE_INVALID_ACCESS,
+            Self::ValueSizeExceeded => STORE_VALUE_SIZE_EXCEEDED,
+            Self::MemoryInvalidAccess => MEMORY_INVALID_ACCESS,
+            Self::InputOutputTooLarge => INPUT_OUTPUT_TOO_LARGE,
+            Self::NotANode => STORE_NOT_A_NODE,
+            Self::InvalidSubkeyIndex => STORE_INVALID_SUBKEY_INDEX,
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, DurableStorageError>;
+
+/// Check that `path` is well-formed, i.e. matches `(\/[A-Za-z0-9._\-]+)+`
+/// and is at most [`PATH_MAX_SIZE`] bytes long. Paths below
+/// [`READONLY_PREFIX`] are rejected.
+fn validate_path(path: &[u8]) -> Result<()> {
+    let well_formed = match path {
+        [] | [.., PATH_SEPARATOR] => false,
+        _ if path.len() > PATH_MAX_SIZE => return Err(DurableStorageError::KeyTooLarge),
+        [PATH_SEPARATOR, steps @ ..] => steps.split(|b| *b == PATH_SEPARATOR).all(|step| {
+            !step.is_empty()
+                && step
+                    .iter()
+                    .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+        }),
+        _ => false,
+    };
+
+    if well_formed && !is_below(path, READONLY_PREFIX) {
+        Ok(())
+    } else {
+        Err(DurableStorageError::InvalidKey)
+    }
+}
+
+/// Like [`validate_path`] but accepts paths below [`READONLY_PREFIX`].
+fn validate_path_maybe_readonly(path: &[u8]) -> Result<()> {
+    match path.strip_prefix(READONLY_PREFIX) {
+        Some([]) => Ok(()),
+        Some(rest @ [PATH_SEPARATOR, _, ..]) => validate_path(rest),
+        _ => validate_path(path),
+    }
+}
+
+/// Is `path` equal to `prefix` or below it?
+fn is_below(path: &[u8], prefix: &[u8]) -> bool {
+    strip_node_prefix(path, prefix).is_some()
+}
+
+/// Remove `prefix` from `path` if `path` is equal to `prefix` or below it.
+/// The remainder is either empty or starts with a [`PATH_SEPARATOR`].
+fn strip_node_prefix<'a>(path: &'a [u8], prefix: &[u8]) -> Option<&'a [u8]> {
+    match path.strip_prefix(prefix)? {
+        rest @ ([] | [PATH_SEPARATOR, ..]) => Some(rest),
+        _ => None,
+    }
+}
+
+/// Order in which the entries of the durable storage are kept: byte-wise on
+/// paths, except that the separator comes before any other byte. This keeps
+/// the entries of a node contiguous and grouped by child, in the order of the
+/// children's names.
+fn path_order(lhs: &[u8], rhs: &[u8]) -> Ordering {
+    let key = |byte: &u8| if *byte == PATH_SEPARATOR { 0 } else { *byte };
+    lhs.iter().map(key).cmp(rhs.iter().map(key))
+}
+
+/// Durable storage
+pub struct DurableStorage<L: DurableStorageLayout + ?Sized, M: backend::Manager> {
+    len: backend::Cell<u64, M>,
+    heap_used: backend::Cell<u64, M>,
+    value_bytes: backend::Cell<u64, M>,
+    paths: L::Paths<M>,
+    path_lengths: L::Words<M>,
+    value_offsets: L::Words<M>,
+    value_lengths: L::Words<M>,
+    heap: L::Heap<M>,
+}
+
+impl<L: DurableStorageLayout, M: backend::Manager> DurableStorage<L, M> {
+    /// Bind the durable storage to the given allocated space.
+    pub fn bind(space: backend::AllocatedOf<L, M>) -> Self {
+        L::refl(space)
+    }
+
+    /// Reset to an empty storage.
+    pub fn reset(&mut self) {
+        self.len.write(0);
+        self.heap_used.write(0);
+        self.value_bytes.write(0);
+    }
+
+    /// Check whether there is a value and/or a subtree at `path`. Returns one
+    /// of the `VALUE_TYPE_*` constants of `mavryk_smart_rollup_core`.
+    pub fn has(&self, path: &[u8]) -> Result<i32> {
+        validate_path_maybe_readonly(path)?;
+
+        let has_value = self.find(path).is_some();
+        let has_subtree = !self.entries_strictly_below(path).is_empty();
+
+        Ok(match (has_value, has_subtree) {
+            (false, false) => VALUE_TYPE_NONE,
+            (true, false) => VALUE_TYPE_VALUE,
+            (false, true) => VALUE_TYPE_SUBTREE,
+            (true, true) => VALUE_TYPE_VALUE_WITH_SUBTREE,
+        })
+    }
+
+    /// Read at most `max_bytes` bytes (and no more than [`MAX_FILE_CHUNK_SIZE`])
+    /// of the value at `path`, starting at `offset`.
+    pub fn read(&self, path: &[u8], offset: usize, max_bytes: usize) -> Result<Vec<u8>> {
+        validate_path_maybe_readonly(path)?;
+
+        let index = self.find(path).ok_or(DurableStorageError::NotAValue)?;
+        let length = self.value_lengths.read(index) as usize;
+
+        if offset > length {
+            return Err(DurableStorageError::InvalidAccess);
+        }
+
+        let num_bytes = MAX_FILE_CHUNK_SIZE.min(max_bytes).min(length - offset);
+        let mut buffer = vec![0u8; num_bytes];
+        let start = self.value_offsets.read(index) as usize + offset;
+        self.heap.read_some(start, &mut buffer);
+
+        Ok(buffer)
+    }
+
+    /// Write `bytes` to the value at `path`, starting at `offset`. The value
+    /// is created if it doesn't exist and extended if necessary.
+    pub fn write(&mut self, path: &[u8], offset: usize, bytes: &[u8]) -> Result<()> {
+        if bytes.len() > MAX_FILE_CHUNK_SIZE {
+            return Err(DurableStorageError::InputOutputTooLarge);
+        }
+
+        validate_path(path)?;
+
+        let existing = self.search(path);
+        let length = existing.map_or(0, |index| self.value_lengths.read(index) as usize);
+
+        // A missing value is empty, so the only valid offset is zero.
+        if offset > length {
+            return Err(DurableStorageError::InvalidAccess);
+        }
+
+        // Writing past the end of the value extends it.
+        let new_length = length.max(offset + bytes.len());
+        self.ensure_capacity(usize::from(existing.is_err()), &[], new_length - length)?;
+
+        let index = existing.unwrap_or_else(|index| {
+            self.open_gap(index, 1);
+            self.init_entry(index, path);
+            index
+        });
+        if new_length > length {
+            self.grow(index, new_length);
+        }
+
+        let start = self.value_offsets.read(index) as usize + offset;
+        self.heap.write_some(start, bytes);
+
+        Ok(())
+    }
+
+    /// Delete the node at `path`, i.e. its value and its subtree.
+    pub fn delete(&mut self, path: &[u8]) -> Result<()> {
+        validate_path(path)?;
+
+        let doomed = self.entries_below(path);
+        self.remove_entries(doomed);
+
+        Ok(())
+    }
+
+    /// Delete the value at `path` but leave its subtree intact.
+    pub fn delete_value(&mut self, path: &[u8]) -> Result<()> {
+        validate_path(path)?;
+
+        if let Some(index) = self.find(path) {
+            self.remove_entries(index..index + 1);
+        }
+
+        Ok(())
+    }
+
+    /// Number of children of the node at `path`. A value at `path` counts as
+    /// one child.
+    pub fn list_size(&self, path: &[u8]) -> Result<i64> {
+        Ok(self.children(path)?.len() as i64)
+    }
+
+    /// Name of the `index`-th child of the node at `path`, in lexicographic
+    /// order. A value at `path` is the child with an empty name.
+    pub fn get_nth_key(&self, path: &[u8], index: i64) -> Result<Vec<u8>> {
+        let mut children = self.children(path)?;
+
+        let index = usize::try_from(index)
+            .ok()
+            .filter(|index| *index < children.len())
+            .ok_or(DurableStorageError::InvalidSubkeyIndex)?;
+
+        Ok(children.swap_remove(index))
+    }
+
+    /// Move the node at `from_path` to `to_path`, replacing the node there.
+    pub fn move_node(&mut self, from_path: &[u8], to_path: &[u8]) -> Result<()> {
+        self.relocate(from_path, to_path, false)
+    }
+
+    /// Copy the node at `from_path` to `to_path`, replacing the node there.
+    pub fn copy_node(&mut self, from_path: &[u8], to_path: &[u8]) -> Result<()> {
+        self.relocate(from_path, to_path, true)
+    }
+
+    /// Size of the value at `path` in bytes
+    pub fn value_size(&self, path: &[u8]) -> Result<i32> {
+        validate_path_maybe_readonly(path)?;
+
+        let index = self.find(path).ok_or(DurableStorageError::NotAValue)?;
+        Ok(self.value_lengths.read(index) as i32)
+    }
+
+    /// Handle a durable storage SBI call. Arguments are taken from and the
+    /// result is placed in the registers of `machine`, following the
+    /// signatures of the host functions in `mavryk_smart_rollup_core`.
+    ///
+    /// Returns `false` if the call is not a durable storage call, in which
+    /// case the machine state is untouched.
+    pub fn handle_sbi_call<ML: MainMemoryLayout>(
+        &mut self,
+        machine: &mut MachineState<ML, M>,
+    ) -> bool {
+        if machine.hart.xregisters.read(a7) != SBI_FIRMWARE_MAVRYK {
+            return false;
+        }
+
+        let function = machine.hart.xregisters.read(a6);
+        let [arg0, arg1, arg2, arg3, arg4] =
+            [a0, a1, a2, a3, a4].map(|reg| machine.hart.xregisters.read(reg));
+
+        let result = match function {
+            SBI_MAVRYK_STORE_HAS => {
+                read_path(machine, arg0, arg1).and_then(|path| self.has(&path).map(i64::from))
+            }
+
+            SBI_MAVRYK_STORE_READ => read_path(machine, arg0, arg1).and_then(|path| {
+                let bytes = self.read(&path, arg2 as usize, arg4 as usize)?;
+                machine
+                    .write_guest_bytes(arg3, &bytes)
+                    .map_err(|_| DurableStorageError::MemoryInvalidAccess)?;
+                Ok(bytes.len() as i64)
+            }),
+
+            SBI_MAVRYK_STORE_WRITE => read_path(machine, arg0, arg1).and_then(|path| {
+                if arg4 > MAX_FILE_CHUNK_SIZE as u64 {
+                    return Err(DurableStorageError::InputOutputTooLarge);
+                }
+                let bytes = machine
+                    .read_guest_bytes(arg3, arg4)
+                    .map_err(|_| DurableStorageError::MemoryInvalidAccess)?;
+                self.write(&path, arg2 as usize, &bytes)?;
+                Ok(0)
+            }),
+
+            SBI_MAVRYK_STORE_DELETE => read_path(machine, arg0, arg1)
+                .and_then(|path| self.delete(&path))
+                .map(|()| 0),
+
+            SBI_MAVRYK_STORE_DELETE_VALUE => read_path(machine, arg0, arg1)
+                .and_then(|path| self.delete_value(&path))
+                .map(|()| 0),
+
+            SBI_MAVRYK_STORE_LIST_SIZE => {
+                read_path(machine, arg0, arg1).and_then(|path| self.list_size(&path))
+            }
+
+            SBI_MAVRYK_STORE_GET_NTH_KEY => read_path(machine, arg0, arg1).and_then(|path| {
+                let mut key = self.get_nth_key(&path, arg2 as i64)?;
+                key.truncate(arg4 as usize);
+                machine
+                    .write_guest_bytes(arg3, &key)
+                    .map_err(|_| DurableStorageError::MemoryInvalidAccess)?;
+                Ok(key.len() as i64)
+            }),
+
+            SBI_MAVRYK_STORE_MOVE => read_path(machine, arg0, arg1).and_then(|from_path| {
+                let to_path = read_path(machine, arg2, arg3)?;
+                self.move_node(&from_path, &to_path)?;
+                Ok(0)
+            }),
+
+            SBI_MAVRYK_STORE_COPY => read_path(machine, arg0, arg1).and_then(|from_path| {
+                let to_path = read_path(machine, arg2, arg3)?;
+                self.copy_node(&from_path, &to_path)?;
+                Ok(0)
+            }),
+
+            SBI_MAVRYK_STORE_VALUE_SIZE => read_path(machine, arg0, arg1)
+                .and_then(|path| self.value_size(&path).map(i64::from)),
+
+            _ => return false,
+        };
+
+        let result = result.unwrap_or_else(|err| err.code().into());
+        machine.hart.xregisters.write(a0, result as u64);
+
+        true
+    }
+
+    /// Sorted names of the children of the node at `path`
+    fn children(&self, path: &[u8]) -> Result<Vec<Vec<u8>>> {
+        validate_path_maybe_readonly(path)?;
+
+        // The entries of each child are contiguous and children are visited
+        // in order, hence comparing with the last child suffices to remove
+        // duplicates.
+        let mut children: Vec<Vec<u8>> = Vec::new();
+        for index in self.entries_below(path) {
+            let entry_path = self.path(index);
+            let rest = strip_node_prefix(&entry_path, path).unwrap_or_default();
+            let child = rest
+                .split(|b| *b == PATH_SEPARATOR)
+                .nth(1)
+                .unwrap_or_default();
+
+            if children.last().map(Vec::as_slice) != Some(child) {
+                children.push(child.to_vec());
+            }
+        }
+
+        if children.is_empty() {
+            return Err(DurableStorageError::NotANode);
+        }
+
+        Ok(children)
+    }
+
+    /// Number of entries in use
+    fn len(&self) -> usize {
+        self.len.read() as usize
+    }
+
+    /// Path of the entry at `index`
+    fn path(&self, index: usize) -> Vec<u8> {
+        let length = self.path_lengths.read(index) as usize;
+        self.paths.read(index)[..length].to_vec()
+    }
+
+    /// Value of the entry at `index`
+    fn value(&self, index: usize) -> Vec<u8> {
+        let mut buffer = vec![0u8; self.value_lengths.read(index) as usize];
+        self.heap
+            .read_some(self.value_offsets.read(index) as usize, &mut buffer);
+        buffer
+    }
+
+    /// First index in `range` whose path doesn't satisfy `pred`. The paths in
+    /// `range` satisfying `pred` must come before the ones that don't.
+    fn partition_point(&self, range: Range<usize>, pred: impl Fn(&[u8]) -> bool) -> usize {
+        let (mut low, mut high) = (range.start, range.end);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if pred(&self.path(mid)) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    /// Index of the entry holding the value at `path`, or the index at which
+    /// such an entry would have to be inserted
+    fn search(&self, path: &[u8]) -> std::result::Result<usize, usize> {
+        let index = self.partition_point(0..self.len(), |entry| path_order(entry, path).is_lt());
+        if index < self.len() && self.path(index) == path {
+            Ok(index)
+        } else {
+            Err(index)
+        }
+    }
+
+    /// Index of the entry holding the value at `path`
+    fn find(&self, path: &[u8]) -> Option<usize> {
+        self.search(path).ok()
+    }
+
+    /// Indices of the entries at or below `path`
+    fn entries_below(&self, path: &[u8]) -> Range<usize> {
+        let start = self.search(path).unwrap_or_else(|index| index);
+        let end = self.partition_point(start..self.len(), |entry| is_below(entry, path));
+        start..end
+    }
+
+    /// Indices of the entries below `path`, excluding the one at `path`
+    fn entries_strictly_below(&self, path: &[u8]) -> Range<usize> {
+        let Range { start, end } = self.entries_below(path);
+        // The entry at `path`, if any, comes first.
+        let at_path = start < end && self.path_lengths.read(start) as usize == path.len();
+        start + usize::from(at_path)..end
+    }
+
+    /// Copy the entry at index `from` to index `to`.
+    fn copy_entry(&mut self, from: usize, to: usize) {
+        self.paths.write(to, self.paths.read(from));
+        self.path_lengths.write(to, self.path_lengths.read(from));
+        self.value_offsets.write(to, self.value_offsets.read(from));
+        self.value_lengths.write(to, self.value_lengths.read(from));
+    }
+
+    /// Remove the entries in `range`, keeping the remaining ones in order.
+    fn remove_entries(&mut self, range: Range<usize>) {
+        let removed_bytes = range
+            .clone()
+            .map(|index| self.value_lengths.read(index))
+            .sum::<u64>();
+        self.value_bytes
+            .write(self.value_bytes.read() - removed_bytes);
+
+        let len = self.len();
+        for (from, to) in (range.end..len).zip(range.start..) {
+            self.copy_entry(from, to);
+        }
+        self.len.write((len - range.len()) as u64);
+    }
+
+    /// Shift the entries from `index` onwards to make room for `count`
+    /// entries. Callers must initialise the new entries using
+    /// [`Self::init_entry`].
+    fn open_gap(&mut self, index: usize, count: usize) {
+        let len = self.len();
+        for from in (index..len).rev() {
+            self.copy_entry(from, from + count);
+        }
+        self.len.write((len + count) as u64);
+    }
+
+    /// Check that `new_entries` entries holding `new_bytes` bytes in total can
+    /// be added, given that the entries in `removed` are removed beforehand.
+    fn ensure_capacity(
+        &self,
+        new_entries: usize,
+        removed: &[Range<usize>],
+        new_bytes: usize,
+    ) -> Result<()> {
+        let removed_entries = removed.iter().map(Range::len).sum::<usize>();
+        let removed_bytes = removed
+            .iter()
+            .flat_map(Range::clone)
+            .map(|index| self.value_lengths.read(index) as usize)
+            .sum::<usize>();
+
+        let entries = self.len() - removed_entries + new_entries;
+        let bytes = self.value_bytes.read() as usize - removed_bytes + new_bytes;
+
+        if entries > L::ENTRIES || bytes > L::HEAP_BYTES {
+            return Err(DurableStorageError::ValueSizeExceeded);
+        }
+
+        Ok(())
+    }
+
+    /// Initialise the entry at `index` for `path`, with an empty value.
+    fn init_entry(&mut self, index: usize, path: &[u8]) {
+        let mut stored_path = [0u8; PATH_CAPACITY];
+        stored_path[..path.len()].copy_from_slice(path);
+        self.paths.write(index, stored_path);
+        self.path_lengths.write(index, path.len() as u64);
+
+        // Placing the empty value at the end of the heap lets it grow in place.
+        self.value_offsets.write(index, self.heap_used.read());
+        self.value_lengths.write(index, 0);
+    }
+
+    /// Grow the value of the entry at `index` to `new_length` bytes, keeping
+    /// its contents. Callers must make sure there is enough capacity using
+    /// [`Self::ensure_capacity`].
+    fn grow(&mut self, index: usize, new_length: usize) {
+        let offset = self.value_offsets.read(index) as usize;
+        let length = self.value_lengths.read(index) as usize;
+        let heap_used = self.heap_used.read() as usize;
+
+        self.value_bytes
+            .write(self.value_bytes.read() + (new_length - length) as u64);
+
+        // The value at the end of the heap can grow in place.
+        if offset + length == heap_used && offset + new_length <= L::HEAP_BYTES {
+            self.heap_used.write((offset + new_length) as u64);
+            self.value_lengths.write(index, new_length as u64);
+            return;
+        }
+
+        // Other values are moved to the end of the heap, leaving a hole to be
+        // closed by compaction. The entry is emptied first so compaction
+        // doesn't preserve the old value.
+        let value = self.value(index);
+        self.value_lengths.write(index, 0);
+
+        if heap_used + new_length > L::HEAP_BYTES {
+            self.compact();
+        }
+
+        let offset = self.heap_used.read();
+        self.heap_used.write(offset + new_length as u64);
+        self.heap.write_some(offset as usize, &value);
+        self.value_offsets.write(index, offset);
+        self.value_lengths.write(index, new_length as u64);
+    }
+
+    /// Move all values to the start of the heap, closing holes left by
+    /// removed or relocated values.
+    fn compact(&mut self) {
+        let mut indices = (0..self.len()).collect::<Vec<_>>();
+        indices.sort_by_key(|&index| self.value_offsets.read(index));
+
+        let mut cursor = 0;
+        for index in indices {
+            // Values only ever move towards the start of the heap, therefore
+            // they never overwrite values which have not been moved yet.
+            let value = self.value(index);
+            self.heap.write_some(cursor, &value);
+            self.value_offsets.write(index, cursor as u64);
+            cursor += value.len();
+        }
+
+        self.heap_used.write(cursor as u64);
+    }
+
+    /// Copy the node at `from_path` to `to_path`, replacing the node there.
+    /// The node at `from_path` is removed unless `keep_source` is set.
+    fn relocate(&mut self, from_path: &[u8], to_path: &[u8], keep_source: bool) -> Result<()> {
+        validate_path(from_path)?;
+        validate_path(to_path)?;
+
+        let sources = self.entries_below(from_path);
+        if sources.is_empty() {
+            return Err(DurableStorageError::NotANode);
+        }
+
+        // Replacing the prefix of the paths keeps them in order.
+        let mut moved = Vec::with_capacity(sources.len());
+        let mut moved_bytes = 0;
+        for index in sources.clone() {
+            let path = self.path(index);
+            let rest = strip_node_prefix(&path, from_path).unwrap_or_default();
+
+            let new_path = [to_path, rest].concat();
+            if new_path.len() > PATH_MAX_SIZE {
+                return Err(DurableStorageError::KeyTooLarge);
+            }
+
+            let value = self.value(index);
+            moved_bytes += value.len();
+            moved.push((new_path, value));
+        }
+
+        // The entries of two nodes are either disjoint or one contains the
+        // other. Ranges are removed back to front so their indices stay valid.
+        let target = self.entries_below(to_path);
+        let doomed = if keep_source {
+            vec![target]
+        } else if sources.start < target.end && target.start < sources.end {
+            let union = sources.start.min(target.start)..sources.end.max(target.end);
+            vec![union]
+        } else if sources.start < target.start {
+            vec![target, sources]
+        } else {
+            vec![sources, target]
+        };
+
+        self.ensure_capacity(moved.len(), &doomed, moved_bytes)?;
+        for range in doomed {
+            self.remove_entries(range);
+        }
+
+        // None of the new paths exist as they are all at or below `to_path`,
+        // whose entries are contiguous.
+        let start = self.search(to_path).unwrap_or_else(|index| index);
+        self.open_gap(start, moved.len());
+        for (index, (path, _)) in (start..).zip(moved.iter()) {
+            self.init_entry(index, path);
+        }
+
+        for (index, (_, value)) in (start..).zip(moved) {
+            self.grow(index, value.len());
+            let offset = self.value_offsets.read(index) as usize;
+            self.heap.write_some(offset, &value);
+        }
+
+        Ok(())
+    }
+}
+
+/// Read a path of `len` bytes from guest memory.
+fn read_path<ML: MainMemoryLayout, M: backend::Manager>(
+    machine: &MachineState<ML, M>,
+    addr: Address,
+    len: u64,
+) -> Result<Vec<u8>> {
+    // Read-only paths are longer than regular ones.
+    if len > (READONLY_PREFIX.len() + PATH_MAX_SIZE) as u64 {
+        return Err(DurableStorageError::KeyTooLarge);
+    }
+
+    machine
+        .read_guest_bytes(addr, len)
+        .map_err(|_| DurableStorageError::MemoryInvalidAccess)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use risc_v_interpreter::state_backend::{memory_backend::InMemoryBackend, Backend};
+
+    macro_rules! create_storage {
+        ($backend:ident) => {{
+            let (backend, placed) = InMemoryBackend::<D64K>::new();
+            $backend = backend;
+            let mut storage = DurableStorage::<D64K, _>::bind($backend.allocate(placed));
+            storage.reset();
+            storage
+        }};
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut backend;
+        let mut storage = create_storage!(backend);
+
+        assert_eq!(storage.has(b"/a"), Ok(VALUE_TYPE_NONE));
+        assert_eq!(
+            storage.read(b"/a", 0, 10),
+            Err(DurableStorageError::NotAValue)
+        );
+        assert_eq!(
+            storage.write(b"/a", 1, b"x"),
+            Err(DurableStorageError::InvalidAccess)
+        );
+
+        storage.write(b"/a", 0, b"hello").unwrap();
+        assert_eq!(storage.read(b"/a", 0, 10), Ok(b"hello".to_vec()));
+        assert_eq!(storage.read(b"/a", 2, 2), Ok(b"ll".to_vec()));
+        assert_eq!(storage.read(b"/a", 5, 2), Ok(vec![]));
+        assert_eq!(
+            storage.read(b"/a", 6, 2),
+            Err(DurableStorageError::InvalidAccess)
+        );
+
+        // Splicing within the value
+        storage.write(b"/a", 1, b"EL").unwrap();
+        assert_eq!(storage.read(b"/a", 0, 10), Ok(b"hELlo".to_vec()));
+
+        // Writing past the end overwrites the tail and extends the value
+        storage.write(b"/a", 3, b"p me").unwrap();
+        assert_eq!(storage.read(b"/a", 0, 10), Ok(b"hELp me".to_vec()));
+        assert_eq!(storage.value_size(b"/a"), Ok(7));
+
+        assert_eq!(
+            storage.write(b"/a", 0, &[0; MAX_FILE_CHUNK_SIZE + 1]),
+            Err(DurableStorageError::InputOutputTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_paths() {
+        let mut backend;
+        let mut storage = create_storage!(backend);
+
+        for path in [
+            &b""[..],
+            b"/",
+            b"a",
+            b"/a/",
+            b"/a//b",
+            b"/a b",
+            b"/readonly/a",
+        ] {
+            assert_eq!(
+                storage.write(path, 0, b"x"),
+                Err(DurableStorageError::InvalidKey)
+            );
+        }
+
+        let long_path = [b"/".as_slice(), &[b'a'; PATH_MAX_SIZE]].concat();
+        assert_eq!(
+            storage.write(&long_path, 0, b"x"),
+            Err(DurableStorageError::KeyTooLarge)
+        );
+
+        // Read-only paths may be read
+        assert_eq!(
+            storage.read(b"/readonly/a", 0, 1),
+            Err(DurableStorageError::NotAValue)
+        );
+        assert_eq!(storage.has(b"/readonly/a"), Ok(VALUE_TYPE_NONE));
+        assert_eq!(
+            storage.value_size(b"/readonly/a"),
+            Err(DurableStorageError::NotAValue)
+        );
+        assert_eq!(
+            storage.list_size(b"/readonly/a"),
+            Err(DurableStorageError::NotANode)
+        );
+        assert_eq!(
+            storage.delete(b"/readonly/a"),
+            Err(DurableStorageError::InvalidKey)
+        );
+    }
+
+    #[test]
+    fn test_tree_structure() {
+        let mut backend;
+        let mut storage = create_storage!(backend);
+
+        storage.write(b"/a", 0, b"1").unwrap();
+        storage.write(b"/a/b", 0, b"2").unwrap();
+        storage.write(b"/a/b/c", 0, b"3").unwrap();
+        storage.write(b"/a/d", 0, b"4").unwrap();
+        storage.write(b"/ab", 0, b"5").unwrap();
+        storage.write(b"/a-b", 0, b"6").unwrap();
+        storage.write(b"/a/b-c", 0, b"7").unwrap();
+
+        assert_eq!(storage.has(b"/a"), Ok(VALUE_TYPE_VALUE_WITH_SUBTREE));
+        assert_eq!(storage.has(b"/a/d"), Ok(VALUE_TYPE_VALUE));
+        assert_eq!(storage.has(b"/a/e"), Ok(VALUE_TYPE_NONE));
+
+        // The value of "/a" counts as a child
+        assert_eq!(storage.list_size(b"/a"), Ok(4));
+        assert_eq!(storage.list_size(b"/a/d"), Ok(1));
+        assert_eq!(storage.list_size(b"/e"), Err(DurableStorageError::NotANode));
+
+        // Children are sorted, the value of "/a" has an empty name
+        assert_eq!(storage.get_nth_key(b"/a", 0), Ok(b"".to_vec()));
+        assert_eq!(storage.get_nth_key(b"/a", 1), Ok(b"b".to_vec()));
+        assert_eq!(storage.get_nth_key(b"/a", 2), Ok(b"b-c".to_vec()));
+        assert_eq!(storage.get_nth_key(b"/a", 3), Ok(b"d".to_vec()));
+        assert_eq!(
+            storage.get_nth_key(b"/a", 4),
+            Err(DurableStorageError::InvalidSubkeyIndex)
+        );
+
+        storage.delete_value(b"/a").unwrap();
+        assert_eq!(storage.has(b"/a"), Ok(VALUE_TYPE_SUBTREE));
+        assert_eq!(storage.list_size(b"/a"), Ok(3));
+
+        storage.delete(b"/a").unwrap();
+        assert_eq!(storage.has(b"/a"), Ok(VALUE_TYPE_NONE));
+        assert_eq!(storage.has(b"/a/b/c"), Ok(VALUE_TYPE_NONE));
+        assert_eq!(storage.read(b"/ab", 0, 10), Ok(b"5".to_vec()));
+        assert_eq!(storage.read(b"/a-b", 0, 10), Ok(b"6".to_vec()));
+    }
+
+    #[test]
+    fn test_move_copy() {
+        let mut backend;
+        let mut storage = create_storage!(backend);
+
+        storage.write(b"/a", 0, b"1").unwrap();
+        storage.write(b"/a/b", 0, b"2").unwrap();
+        storage.write(b"/c/d", 0, b"3").unwrap();
+
+        assert_eq!(
+            storage.move_node(b"/x", b"/y"),
+            Err(DurableStorageError::NotANode)
+        );
+
+        // Copying replaces the target node
+        storage.copy_node(b"/a", b"/c").unwrap();
+        assert_eq!(storage.read(b"/c", 0, 10), Ok(b"1".to_vec()));
+        assert_eq!(storage.read(b"/c/b", 0, 10), Ok(b"2".to_vec()));
+        assert_eq!(storage.has(b"/c/d"), Ok(VALUE_TYPE_NONE));
+        assert_eq!(storage.read(b"/a/b", 0, 10), Ok(b"2".to_vec()));
+
+        // Moving into the subtree of the source
+        storage.move_node(b"/a", b"/a/e").unwrap();
+        assert_eq!(storage.has(b"/a"), Ok(VALUE_TYPE_SUBTREE));
+        assert_eq!(storage.read(b"/a/e", 0, 10), Ok(b"1".to_vec()));
+        assert_eq!(storage.read(b"/a/e/b", 0, 10), Ok(b"2".to_vec()));
+        assert_eq!(storage.has(b"/a/b"), Ok(VALUE_TYPE_NONE));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut backend;
+        let mut storage = create_storage!(backend);
+
+        let chunks = D64K::HEAP_BYTES / MAX_FILE_CHUNK_SIZE;
+        let write_chunks = |storage: &mut DurableStorage<D64K, _>, path, fill, range| {
+            for i in range {
+                storage
+                    .write(path, i * MAX_FILE_CHUNK_SIZE, &[fill; MAX_FILE_CHUNK_SIZE])
+                    .unwrap();
+            }
+        };
+
+        // Fill up the heap, except for one chunk.
+        write_chunks(&mut storage, b"/a", 0xAA, 0..chunks / 2);
+        write_chunks(&mut storage, b"/b", 0xBB, 0..1);
+        write_chunks(&mut storage, b"/c", 0xCC, 0..chunks / 2 - 2);
+
+        // "/a" has to be moved to the end of the heap in order to grow, which
+        // requires closing the hole it leaves behind.
+        write_chunks(&mut storage, b"/a", 0xAA, chunks / 2..chunks / 2 + 1);
+
+        let full = |storage: &DurableStorage<D64K, _>, path, fill, len| {
+            (0..len).all(|i| {
+                storage.read(path, i * MAX_FILE_CHUNK_SIZE, MAX_FILE_CHUNK_SIZE)
+                    == Ok(vec![fill; MAX_FILE_CHUNK_SIZE])
+            })
+        };
+        assert!(full(&storage, b"/a", 0xAA, chunks / 2 + 1));
+        assert!(full(&storage, b"/b", 0xBB, 1));
+        assert!(full(&storage, b"/c", 0xCC, chunks / 2 - 2));
+
+        assert_eq!(
+            storage.write(b"/d", 0, &[0; MAX_FILE_CHUNK_SIZE]),
+            Err(DurableStorageError::ValueSizeExceeded)
+        );
+        assert_eq!(
+            storage.copy_node(b"/b", b"/d"),
+            Err(DurableStorageError::ValueSizeExceeded)
+        );
+
+        // Moving never requires more space.
+        storage.move_node(b"/b", b"/d").unwrap();
+        assert!(full(&storage, b"/d", 0xBB, 1));
+
+        storage.delete(b"/a").unwrap();
+        storage.copy_node(b"/d", b"/e").unwrap();
+        assert!(full(&storage, b"/e", 0xBB, 1));
+
+        // The number of values is limited as well.
+        for path in [&b"/c"[..], b"/d", b"/e"] {
+            storage.delete(path).unwrap();
+        }
+        for i in 0..D64K::ENTRIES {
+            storage
+                .write(format!("/f/{i}").as_bytes(), 0, b"x")
+                .unwrap();
+        }
+        assert_eq!(
+            storage.write(b"/g", 0, b"x"),
+            Err(DurableStorageError::ValueSizeExceeded)
+        );
+        assert_eq!(storage.list_size(b"/f"), Ok(D64K::ENTRIES as i64));
+    }
+}
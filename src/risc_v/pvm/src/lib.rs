@@ -1,3 +1,5 @@
+pub mod durable_storage;
+pub mod reveals;
 pub mod state;
 
 use risc_v_interpreter::add;
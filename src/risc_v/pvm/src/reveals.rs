@@ -0,0 +1,349 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Reveal requests served from a local directory
+//!
+//! Preimages are stored in files named after the hexadecimal encoding of
+//! their hash, which is the layout of the preimages directory written by the
+//! installer client. DAL slots are stored in files named
+//! `dal/<published level>/<slot index>` below the same directory. Slots
+//! without a file were not attested, their pages are revealed as empty.
+//!
+//! The revealed data is not part of the PVM state: preimages are addressed by
+//! their contents and DAL slots are fixed once attested.
+
+use mavryk_smart_rollup_constants::{
+    dal,
+    riscv::{
+        SBI_FIRMWARE_MAVRYK, SBI_MAVRYK_REVEAL_DAL_PAGE, SBI_MAVRYK_REVEAL_DAL_PARAMETERS,
+        SBI_MAVRYK_REVEAL_PREIMAGE,
+    },
+};
+use mavryk_smart_rollup_core::{GENERIC_INVALID_ACCESS, MEMORY_INVALID_ACCESS, PREIMAGE_HASH_SIZE};
+use risc_v_interpreter::{
+    machine_state::{
+        bus::main_memory::MainMemoryLayout,
+        registers::{a0, a1, a2, a3, a4, a6, a7},
+        MachineState,
+    },
+    state_backend as backend,
+};
+use std::{fs, io, path::PathBuf};
+
+/// Size of the encoded DAL parameters
+pub const DAL_PARAMETERS_SIZE: usize = 32;
+
+/// Errors that reveal requests may produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevealError {
+    /// The hash does not have [`PREIMAGE_HASH_SIZE`] bytes
+    InvalidHash,
+
+    /// There is no preimage for the hash
+    MissingPreimage,
+
+    /// The slot or page index is out of range
+    InvalidPage,
+
+    /// Accessing guest memory failed
+    MemoryInvalidAccess,
+}
+
+impl RevealError {
+    /// Error code returned to the kernel
+    pub fn code(self) -> i32 {
+        match self {
+            Self::InvalidHash | Self::MissingPreimage | Self::InvalidPage => GENERIC_INVALID_ACCESS,
+            Self::MemoryInvalidAccess => MEMORY_INVALID_ACCESS,
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, RevealError>;
+
+/// Parameters of the Data Availability Layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DalParameters {
+    pub number_of_slots: u64,
+    pub attestation_lag: u64,
+    pub slot_size: u64,
+    pub page_size: u64,
+}
+
+impl Default for DalParameters {
+    /// Parameters of the Mavryk mainnet
+    fn default() -> Self {
+        Self {
+            number_of_slots: dal::MAINNET_NUMBER_OF_SLOTS,
+            attestation_lag: dal::MAINNET_ATTESTATION_LAG,
+            slot_size: dal::MAINNET_SLOT_SIZE,
+            page_size: dal::MAINNET_PAGE_SIZE,
+        }
+    }
+}
+
+impl DalParameters {
+    /// Encoding as revealed to the kernel: the parameters as big-endian
+    /// 64-bit integers
+    pub fn to_bytes(&self) -> [u8; DAL_PARAMETERS_SIZE] {
+        let mut bytes = [0u8; DAL_PARAMETERS_SIZE];
+        let fields = [
+            self.number_of_slots,
+            self.attestation_lag,
+            self.slot_size,
+            self.page_size,
+        ];
+        for (chunk, field) in bytes.chunks_exact_mut(8).zip(fields) {
+            chunk.copy_from_slice(&field.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Number of pages per slot
+    pub fn pages_per_slot(&self) -> u64 {
+        self.slot_size.div_ceil(self.page_size.max(1))
+    }
+}
+
+/// Directory of preimages and DAL slots
+#[derive(Debug, Clone)]
+pub struct PreimageDirectory {
+    root: PathBuf,
+    dal_parameters: DalParameters,
+}
+
+impl PreimageDirectory {
+    /// Serve the reveals from `root`, using the default DAL parameters.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            dal_parameters: DalParameters::default(),
+        }
+    }
+
+    /// Use `dal_parameters` instead of the default ones.
+    pub fn with_dal_parameters(self, dal_parameters: DalParameters) -> Self {
+        Self {
+            dal_parameters,
+            ..self
+        }
+    }
+
+    /// Parameters of the Data Availability Layer
+    pub fn dal_parameters(&self) -> &DalParameters {
+        &self.dal_parameters
+    }
+
+    /// Preimage of `hash`
+    pub fn preimage(&self, hash: &[u8]) -> Result<Vec<u8>> {
+        if hash.len() != PREIMAGE_HASH_SIZE {
+            return Err(RevealError::InvalidHash);
+        }
+
+        let name: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+        fs::read(self.root.join(name)).map_err(|_| RevealError::MissingPreimage)
+    }
+
+    /// Page `page_index` of the slot `slot_index` published at
+    /// `published_level`. Pages of slots which were not attested are empty.
+    pub fn dal_page(
+        &self,
+        published_level: i32,
+        slot_index: u8,
+        page_index: i16,
+    ) -> Result<Vec<u8>> {
+        let params = &self.dal_parameters;
+        if published_level < 0
+            || u64::from(slot_index) >= params.number_of_slots
+            || page_index < 0
+            || page_index as u64 >= params.pages_per_slot()
+        {
+            return Err(RevealError::InvalidPage);
+        }
+
+        let path = self
+            .root
+            .join("dal")
+            .join(published_level.to_string())
+            .join(slot_index.to_string());
+        let slot = match fs::read(path) {
+            Ok(slot) => slot,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(_) => return Err(RevealError::InvalidPage),
+        };
+
+        // Slots are padded with zeros up to the slot size.
+        let start = page_index as u64 * params.page_size;
+        let end = (start + params.page_size).min(params.slot_size);
+        let mut page = vec![0u8; (end - start) as usize];
+        if let Some(contents) = slot.get(start as usize..) {
+            let len = contents.len().min(page.len());
+            page[..len].copy_from_slice(&contents[..len]);
+        }
+        Ok(page)
+    }
+
+    /// Handle a reveal SBI call. Arguments are taken from and the result is
+    /// placed in the registers of `machine`. The revealed data is trimmed to
+    /// the size of the destination buffer.
+    ///
+    /// Returns `false` if the call is not a reveal call, in which case the
+    /// machine state is untouched.
+    pub fn handle_sbi_call<ML: MainMemoryLayout, M: backend::Manager>(
+        &self,
+        machine: &mut MachineState<ML, M>,
+    ) -> bool {
+        if machine.hart.xregisters.read(a7) != SBI_FIRMWARE_MAVRYK {
+            return false;
+        }
+
+        let function = machine.hart.xregisters.read(a6);
+        let [arg0, arg1, arg2, arg3, arg4] =
+            [a0, a1, a2, a3, a4].map(|reg| machine.hart.xregisters.read(reg));
+
+        let result = match function {
+            SBI_MAVRYK_REVEAL_PREIMAGE => {
+                // Don't read arbitrarily large hashes from memory.
+                if arg1 > PREIMAGE_HASH_SIZE as u64 {
+                    Err(RevealError::InvalidHash)
+                } else {
+                    machine
+                        .read_guest_bytes(arg0, arg1)
+                        .map_err(|_| RevealError::MemoryInvalidAccess)
+                        .and_then(|hash| self.preimage(&hash))
+                        .and_then(|preimage| write_trimmed(machine, arg2, arg3, &preimage))
+                }
+            }
+
+            SBI_MAVRYK_REVEAL_DAL_PAGE => dal_page_args(arg0, arg1, arg2)
+                .and_then(|(level, slot, page)| self.dal_page(level, slot, page))
+                .and_then(|page| write_trimmed(machine, arg3, arg4, &page)),
+
+            SBI_MAVRYK_REVEAL_DAL_PARAMETERS => {
+                write_trimmed(machine, arg0, arg1, &self.dal_parameters.to_bytes())
+            }
+
+            _ => return false,
+        };
+
+        let result = result.unwrap_or_else(|err| err.code().into());
+        machine.hart.xregisters.write(a0, result as u64);
+
+        true
+    }
+}
+
+/// Published level, slot index and page index of a DAL page reveal, from
+/// the registers holding them. Signed arguments are sign-extended to 64 bits
+/// by the calling convention. Values out of range are rejected rather than
+/// truncated.
+pub fn dal_page_args(
+    published_level: u64,
+    slot_index: u64,
+    page_index: u64,
+) -> Result<(i32, u8, i16)> {
+    let published_level = i32::try_from(published_level as i64);
+    let slot_index = u8::try_from(slot_index);
+    let page_index = i16::try_from(page_index as i64);
+
+    match (published_level, slot_index, page_index) {
+        (Ok(level), Ok(slot), Ok(page)) => Ok((level, slot, page)),
+        _ => Err(RevealError::InvalidPage),
+    }
+}
+
+/// Write at most `max_bytes` of `data` to guest memory starting at virtual
+/// address `addr`. Returns the number of bytes written.
+fn write_trimmed<ML: MainMemoryLayout, M: backend::Manager>(
+    machine: &mut MachineState<ML, M>,
+    addr: u64,
+    max_bytes: u64,
+    data: &[u8],
+) -> Result<i64> {
+    let len = data.len().min(max_bytes as usize);
+    machine
+        .write_guest_bytes(addr, &data[..len])
+        .map_err(|_| RevealError::MemoryInvalidAccess)?;
+    Ok(len as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create an empty directory for the test `name`.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("mavkit-risc-v-pvm-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_preimage() {
+        let dir = test_dir("preimage");
+        let hash = [[0u8].as_slice(), &[0xab; 32]].concat();
+        fs::write(dir.join(format!("00{}", "ab".repeat(32))), b"preimage").unwrap();
+
+        let reveals = PreimageDirectory::new(&dir);
+        assert_eq!(reveals.preimage(&hash), Ok(b"preimage".to_vec()));
+        assert_eq!(
+            reveals.preimage(&[1; PREIMAGE_HASH_SIZE]),
+            Err(RevealError::MissingPreimage)
+        );
+        assert_eq!(reveals.preimage(&hash[1..]), Err(RevealError::InvalidHash));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_dal_page() {
+        let dir = test_dir("dal-page");
+        let params = DalParameters {
+            number_of_slots: 4,
+            attestation_lag: 1,
+            slot_size: 10,
+            page_size: 4,
+        };
+        fs::create_dir_all(dir.join("dal").join("7")).unwrap();
+        fs::write(dir.join("dal").join("7").join("1"), [1, 2, 3, 4, 5, 6]).unwrap();
+
+        let reveals = PreimageDirectory::new(&dir).with_dal_parameters(params);
+        assert_eq!(reveals.dal_page(7, 1, 0), Ok(vec![1, 2, 3, 4]));
+        // The slot is padded, the last page is shorter.
+        assert_eq!(reveals.dal_page(7, 1, 1), Ok(vec![5, 6, 0, 0]));
+        assert_eq!(reveals.dal_page(7, 1, 2), Ok(vec![0, 0]));
+        // Unattested slots have empty pages.
+        assert_eq!(reveals.dal_page(7, 2, 0), Ok(vec![]));
+        assert_eq!(reveals.dal_page(8, 1, 0), Ok(vec![]));
+
+        assert_eq!(reveals.dal_page(7, 1, 3), Err(RevealError::InvalidPage));
+        assert_eq!(reveals.dal_page(7, 4, 0), Err(RevealError::InvalidPage));
+        assert_eq!(reveals.dal_page(7, 1, -1), Err(RevealError::InvalidPage));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_dal_page_args() {
+        assert_eq!(dal_page_args(7, 1, 2), Ok((7, 1, 2)));
+        assert_eq!(
+            dal_page_args(-1i64 as u64, 1, -2i64 as u64),
+            Ok((-1, 1, -2))
+        );
+
+        // Out of range arguments are not truncated
+        assert_eq!(dal_page_args(1 << 32, 1, 0), Err(RevealError::InvalidPage));
+        assert_eq!(dal_page_args(7, 257, 0), Err(RevealError::InvalidPage));
+        assert_eq!(dal_page_args(7, 1, 1 << 16), Err(RevealError::InvalidPage));
+    }
+
+    #[test]
+    fn test_dal_parameters_encoding() {
+        let bytes = DalParameters::default().to_bytes();
+        assert_eq!(bytes[..8], 32u64.to_be_bytes());
+        assert_eq!(bytes[24..], 3967u64.to_be_bytes());
+    }
+}
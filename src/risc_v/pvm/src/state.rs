@@ -5,20 +5,193 @@
 // Allow dead code while this module contains stubs.
 #![allow(dead_code)]
 
+use crate::{
+    durable_storage::{DurableStorage, DurableStorageLayout},
+    reveals::PreimageDirectory,
+};
+use mavryk_smart_rollup_constants::riscv::{SBI_FIRMWARE_MAVRYK, SBI_MAVRYK_INBOX_NEXT};
+use mavryk_smart_rollup_core::MAX_INPUT_MESSAGE_SIZE;
 use risc_v_interpreter::{
     exec_env::{self, ExecutionEnvironment, ExecutionEnvironmentState},
-    machine_state::{self, bus::main_memory, StepManyResult},
-    state_backend,
-    traps::EnvironException,
+    machine_state::{
+        self,
+        bus::{main_memory, Address},
+        cost::{CostModel, UniformCost},
+        registers::{a0, a1, a2, a6, a7},
+        StepManyResult,
+    },
+    state_backend::{self, Region},
+    traps::{EnvironException, TrapContext},
 };
 
 /// PVM state layout
-pub type PvmLayout<EE, ML> = (
+pub type PvmLayout<EE, ML, DL> = (
     state_backend::Atom<u64>,
     machine_state::MachineStateLayout<ML>,
     <EE as ExecutionEnvironment>::Layout,
+    (PvmStatusLayout, PvmInputLayout),
+    DL,
+);
+
+/// Layout of the PVM status: status tag, stuck reason exception code and
+/// stuck reason program counter
+pub type PvmStatusLayout = (
+    state_backend::Atom<u8>,
+    state_backend::Atom<u64>,
+    state_backend::Atom<Address>,
+);
+
+/// Encoding of [`Status::Eval`] in the status cell
+const STATUS_EVAL: u8 = 0;
+
+/// Encoding of [`Status::Input`] in the status cell
+const STATUS_INPUT: u8 = 1;
+
+/// Encoding of [`Status::Stuck`] in the status cell
+const STATUS_STUCK: u8 = 2;
+
+/// Encoding of [`StuckReason::MalformedStatus`] in the status cell
+const STATUS_MALFORMED: u8 = 3;
+
+/// Persisted PVM status
+struct PvmStatus<M: state_backend::Manager> {
+    tag: state_backend::Cell<u8, M>,
+    stuck_exception: state_backend::Cell<u64, M>,
+    stuck_pc: state_backend::Cell<Address, M>,
+}
+
+impl<M: state_backend::Manager> PvmStatus<M> {
+    /// Bind the status to the given allocated space.
+    fn bind(space: state_backend::AllocatedOf<PvmStatusLayout, M>) -> Self {
+        Self {
+            tag: space.0,
+            stuck_exception: space.1,
+            stuck_pc: space.2,
+        }
+    }
+
+    /// Reset to the initial state.
+    fn reset(&mut self) {
+        self.tag.write(STATUS_EVAL);
+        self.stuck_exception.write(0);
+        self.stuck_pc.write(0);
+    }
+
+    /// Read the current status. A status which can't be decoded, e.g. from a
+    /// corrupted state, reads as [`StuckReason::MalformedStatus`].
+    fn read(&self) -> Status {
+        const MALFORMED: Status = Status::Stuck(StuckReason::MalformedStatus);
+
+        match self.tag.read() {
+            STATUS_EVAL => Status::Eval,
+            STATUS_INPUT => Status::Input,
+            STATUS_STUCK => match environ_exception_from_code(self.stuck_exception.read()) {
+                Some(exception) => Status::Stuck(StuckReason::FatalEnvironCall {
+                    exception,
+                    pc: self.stuck_pc.read(),
+                }),
+                None => MALFORMED,
+            },
+            _ => MALFORMED,
+        }
+    }
+
+    /// Update the current status.
+    fn write(&mut self, status: Status) {
+        match status {
+            Status::Eval => self.tag.write(STATUS_EVAL),
+            Status::Input => self.tag.write(STATUS_INPUT),
+            Status::Stuck(StuckReason::FatalEnvironCall { exception, pc }) => {
+                self.tag.write(STATUS_STUCK);
+                self.stuck_exception
+                    .write(exception.as_exception().exception_code());
+                self.stuck_pc.write(pc);
+            }
+            Status::Stuck(StuckReason::MalformedStatus) => self.tag.write(STATUS_MALFORMED),
+        }
+    }
+}
+
+/// Layout of the pending input: presence flag, level, message counter,
+/// payload length and payload
+pub type PvmInputLayout = (
+    state_backend::Atom<u8>,
+    state_backend::Atom<u64>,
+    state_backend::Atom<u64>,
+    state_backend::Atom<u64>,
+    state_backend::Array<u8, MAX_INPUT_MESSAGE_SIZE>,
 );
 
+/// Input provided to the PVM which the kernel hasn't read yet
+struct PvmInput<M: state_backend::Manager> {
+    present: state_backend::Cell<u8, M>,
+    level: state_backend::Cell<u64, M>,
+    counter: state_backend::Cell<u64, M>,
+    length: state_backend::Cell<u64, M>,
+    payload: M::Region<u8, MAX_INPUT_MESSAGE_SIZE>,
+}
+
+impl<M: state_backend::Manager> PvmInput<M> {
+    /// Bind the pending input to the given allocated space.
+    fn bind(space: state_backend::AllocatedOf<PvmInputLayout, M>) -> Self {
+        Self {
+            present: space.0,
+            level: space.1,
+            counter: space.2,
+            length: space.3,
+            payload: space.4,
+        }
+    }
+
+    /// Reset to no pending input.
+    fn reset(&mut self) {
+        self.present.write(0);
+        self.level.write(0);
+        self.counter.write(0);
+        self.length.write(0);
+    }
+
+    /// Record an input. Returns `false` if the payload is too large.
+    fn write(&mut self, level: u64, counter: u64, payload: &[u8]) -> bool {
+        if payload.len() > MAX_INPUT_MESSAGE_SIZE {
+            return false;
+        }
+
+        self.present.write(1);
+        self.level.write(level);
+        self.counter.write(counter);
+        self.length.write(payload.len() as u64);
+        self.payload.write_some(0, payload);
+        true
+    }
+
+    /// Remove the pending input, if any, returning its level, counter and
+    /// payload.
+    fn take(&mut self) -> Option<(u64, u64, Vec<u8>)> {
+        if self.present.read() == 0 {
+            return None;
+        }
+
+        let length = (self.length.read() as usize).min(MAX_INPUT_MESSAGE_SIZE);
+        let mut payload = vec![0u8; length];
+        self.payload.read_some(0, &mut payload);
+        self.present.write(0);
+
+        Some((self.level.read(), self.counter.read(), payload))
+    }
+}
+
+/// Inverse of `exception.as_exception().exception_code()`
+fn environ_exception_from_code(code: u64) -> Option<EnvironException> {
+    [
+        EnvironException::EnvCallFromUMode,
+        EnvironException::EnvCallFromSMode,
+        EnvironException::EnvCallFromMMode,
+    ]
+    .into_iter()
+    .find(|exc| exc.as_exception().exception_code() == code)
+}
+
 /// Value for the initial version
 const INITIAL_VERSION: u64 = 0;
 
@@ -26,6 +199,7 @@ const INITIAL_VERSION: u64 = 0;
 pub struct Pvm<
     EE: ExecutionEnvironment,
     ML: main_memory::MainMemoryLayout,
+    DL: DurableStorageLayout,
     M: state_backend::Manager,
 > {
     version: state_backend::Cell<u64, M>,
@@ -33,41 +207,115 @@ pub struct Pvm<
 
     /// Execution environment state
     pub syscall_state: EE::State<M>,
+
+    /// Current machine status
+    status: PvmStatus<M>,
+
+    /// Durable storage
+    pub durable_storage: DurableStorage<DL, M>,
+
+    /// Input not read by the kernel yet
+    input: PvmInput<M>,
+
+    /// Source of the data revealed to the kernel, which is not part of the
+    /// state. Reveal calls are fatal without it.
+    reveals: Option<PreimageDirectory>,
 }
 
-impl<EE: ExecutionEnvironment, ML: main_memory::MainMemoryLayout, M: state_backend::Manager>
-    Pvm<EE, ML, M>
+impl<
+        EE: ExecutionEnvironment,
+        ML: main_memory::MainMemoryLayout,
+        DL: DurableStorageLayout,
+        M: state_backend::Manager,
+    > Pvm<EE, ML, DL, M>
 {
     /// Bind the PVM to the given allocated region.
-    pub fn bind(space: state_backend::AllocatedOf<PvmLayout<EE, ML>, M>) -> Self {
+    pub fn bind(space: state_backend::AllocatedOf<PvmLayout<EE, ML, DL>, M>) -> Self {
         // Ensure we're binding a version we can deal with
         assert_eq!(space.0.read(), INITIAL_VERSION);
 
+        let (status, input) = space.3;
         Self {
             version: space.0,
             machine_state: machine_state::MachineState::bind(space.1),
             syscall_state: EE::State::<M>::bind(space.2),
+            status: PvmStatus::bind(status),
+            durable_storage: DurableStorage::bind(space.4),
+            input: PvmInput::bind(input),
+            reveals: None,
         }
     }
 
+    /// Serve the reveal calls of the kernel from `reveals`.
+    pub fn set_preimage_directory(&mut self, reveals: PreimageDirectory) {
+        self.reveals = Some(reveals);
+    }
+
     /// Reset the PVM state.
     pub fn reset(&mut self) {
         self.version.write(INITIAL_VERSION);
         self.machine_state.reset();
         self.syscall_state.reset();
+        self.status.reset();
+        self.durable_storage.reset();
+        self.input.reset();
     }
 
-    /// Provide input. Returns `false` if the machine state is not in
-    /// `Status::Input` status.
-    pub fn provide_input(&mut self, _level: u64, _counter: u64, _payload: &[u8]) -> bool {
-        // TODO: https://gitlab.com/tezos/tezos/-/issues/6945
-        // Implement input provider
-        todo!("Input function is not implemented")
+    /// Provide input. Returns `false` if the machine state is neither in
+    /// [`Status::Input`] nor in [`Status::Stuck`] status, or if the payload
+    /// exceeds [`MAX_INPUT_MESSAGE_SIZE`]. The input is then not consumed.
+    ///
+    /// The input is recorded in the state until the kernel reads it with the
+    /// `inbox_next` SBI call. Like the WASM PVM, a stuck machine is recovered
+    /// by the next input: the environment call that could not be handled is
+    /// skipped and evaluation resumes right after it.
+    pub fn provide_input(&mut self, level: u64, counter: u64, payload: &[u8]) -> bool {
+        let status = self.status();
+        if status == Status::Eval || !self.input.write(level, counter, payload) {
+            return false;
+        }
+
+        if let Status::Stuck(StuckReason::FatalEnvironCall { pc, .. }) = status {
+            // ECALL instructions are never compressed.
+            self.machine_state.hart.pc.write(pc + 4);
+        }
+
+        self.status.write(Status::Eval);
+        true
+    }
+
+    /// Serve the `inbox_next` SBI call from the pending input. Returns `false`
+    /// if the call is not an `inbox_next` call or if there is no pending
+    /// input, in which case the call is left to the execution environment.
+    fn handle_inbox_next(&mut self) -> bool {
+        let xregisters = &self.machine_state.hart.xregisters;
+        if xregisters.read(a7) != SBI_FIRMWARE_MAVRYK
+            || xregisters.read(a6) != SBI_MAVRYK_INBOX_NEXT
+        {
+            return false;
+        }
+
+        let (buffer, max_len) = (xregisters.read(a0), xregisters.read(a1));
+        let Some((level, counter, mut payload)) = self.input.take() else {
+            return false;
+        };
+
+        payload.truncate(max_len as usize);
+        let length = match self.machine_state.write_guest_bytes(buffer, &payload) {
+            Ok(()) => payload.len() as u64,
+            Err(_) => 0,
+        };
+
+        let xregisters = &mut self.machine_state.hart.xregisters;
+        xregisters.write(a0, level);
+        xregisters.write(a1, counter);
+        xregisters.write(a2, length);
+        true
     }
 
     /// Get the current machine status.
     pub fn status(&self) -> Status {
-        Status::Eval
+        self.status.read()
     }
 
     /// Defines how to handle exceptions in the PVM execution environment.
@@ -77,13 +325,36 @@ impl<EE: ExecutionEnvironment, ML: main_memory::MainMemoryLayout, M: state_backe
             EnvironException::EnvCallFromUMode
             | EnvironException::EnvCallFromSMode
             | EnvironException::EnvCallFromMMode => {
+                // The durable storage is part of the PVM state, therefore the
+                // PVM serves its SBI calls itself, as well as reveal calls.
+                let handled = self.handle_inbox_next()
+                    || self
+                        .durable_storage
+                        .handle_sbi_call(&mut self.machine_state)
+                    || self
+                        .reveals
+                        .as_ref()
+                        .is_some_and(|reveals| reveals.handle_sbi_call(&mut self.machine_state));
+                if handled {
+                    // ECALL instructions are never compressed.
+                    let pc = self.machine_state.hart.pc.read();
+                    self.machine_state.hart.pc.write(pc + 4);
+                    return true;
+                }
+
                 match self
                     .syscall_state
                     .handle_call(&mut self.machine_state, exception)
                 {
                     exec_env::EcallOutcome::Fatal => {
-                        // TODO: https://app.asana.com/0/1206655199123740/1206682246825814/f
-                        unimplemented!("Fatal exceptions aren't implemented yet")
+                        // The PC still points at the faulting ECALL.
+                        let pc = self.machine_state.hart.pc.read();
+                        self.status
+                            .write(Status::Stuck(StuckReason::FatalEnvironCall {
+                                exception,
+                                pc,
+                            }));
+                        false
                     }
                     exec_env::EcallOutcome::Handled { continue_eval } => continue_eval,
                 }
@@ -93,6 +364,10 @@ impl<EE: ExecutionEnvironment, ML: main_memory::MainMemoryLayout, M: state_backe
 
     /// Perform one step. Returns `false` if the PVM is not in [`Status::Eval`] status.
     pub fn step(&mut self) -> bool {
+        if self.status() != Status::Eval {
+            return false;
+        }
+
         if let Err(exc) = self.machine_state.step() {
             self.handle_exception(exc);
         }
@@ -114,19 +389,53 @@ impl<EE: ExecutionEnvironment, ML: main_memory::MainMemoryLayout, M: state_backe
     /// the execution environment will still retire an instruction, just not itself.
     /// (a possible case: the privilege mode access violation is treated in EE,
     /// but a page fault is not)
+    ///
+    /// No steps are performed if the PVM is not in [`Status::Eval`] status.
     pub fn step_many(&mut self, max_steps: usize) -> usize {
-        self.step_many_accum(max_steps, 0)
+        self.step_many_with_budget(max_steps, &UniformCost, u64::MAX)
+            .0
+    }
+
+    /// Perform at most `max_steps` steps like [`Self::step_many`], also
+    /// stopping once `max_ticks` ticks have been spent according to
+    /// `cost_model`. Returns the number of steps performed and the ticks
+    /// spent, which may exceed `max_ticks` by the cost of the last step.
+    ///
+    /// Serving an environment call is charged on top of the step raising it,
+    /// see [`CostModel::environ_call`].
+    pub fn step_many_with_budget<C: CostModel + ?Sized>(
+        &mut self,
+        max_steps: usize,
+        cost_model: &C,
+        max_ticks: u64,
+    ) -> (usize, u64) {
+        if self.status() != Status::Eval {
+            return (0, 0);
+        }
+
+        self.step_many_accum(max_steps, cost_model, max_ticks, 0, 0)
     }
 
-    // Tail-recursive helper function for [step_many]
-    fn step_many_accum(&mut self, max_steps: usize, accum: usize) -> usize {
+    // Tail-recursive helper function for [step_many_with_budget]
+    fn step_many_accum<C: CostModel + ?Sized>(
+        &mut self,
+        max_steps: usize,
+        cost_model: &C,
+        max_ticks: u64,
+        accum: usize,
+        accum_ticks: u64,
+    ) -> (usize, u64) {
         let StepManyResult {
             mut steps,
             exception,
-        } = self.machine_state.step_many(max_steps, |_| true);
+            ticks,
+        } = self
+            .machine_state
+            .step_many_with_budget(max_steps, cost_model, max_ticks, |_| true);
 
-        // Total steps done
+        // Total steps done and ticks spent
         let mut total_steps = accum.saturating_add(steps);
+        let mut total_ticks = accum_ticks.saturating_add(ticks);
 
         if let Some(exc) = exception {
             // Raising the exception is not a completed step. Trying to handle it is.
@@ -135,22 +444,444 @@ impl<EE: ExecutionEnvironment, ML: main_memory::MainMemoryLayout, M: state_backe
             total_steps = total_steps.saturating_add(1);
             steps = steps.saturating_add(1);
 
+            let continue_eval = self.handle_exception(exc);
+
+            // The bytes copied by the handler are recorded in the events of
+            // the step raising the exception.
+            let environ_ticks = cost_model.environ_call(&self.machine_state.step_events());
+            total_ticks = total_ticks.saturating_add(environ_ticks);
+            let ticks = ticks.saturating_add(environ_ticks);
+
             // Exception was handled in a way that allows us to evaluate more.
-            if self.handle_exception(exc) {
+            if continue_eval {
                 let steps_left = max_steps.saturating_sub(steps);
-                return self.step_many_accum(steps_left, total_steps);
+                let ticks_left = max_ticks.saturating_sub(ticks);
+                return self.step_many_accum(
+                    steps_left,
+                    cost_model,
+                    ticks_left,
+                    total_steps,
+                    total_ticks,
+                );
             }
         }
 
-        total_steps
+        (total_steps, total_ticks)
     }
 }
 
 /// Machine status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Status {
     /// Evaluating normally
     Eval,
 
     /// Input has been requested by the PVM
     Input,
+
+    /// Evaluation cannot continue until the next input is provided
+    Stuck(StuckReason),
+}
+
+/// Reason for the PVM being in [`Status::Stuck`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StuckReason {
+    /// The execution environment failed to handle the environment call raised
+    /// by the instruction at address `pc`.
+    FatalEnvironCall {
+        exception: EnvironException,
+        pc: Address,
+    },
+
+    /// The status stored in the state could not be decoded. Evaluation
+    /// resumes at the current instruction with the next input.
+    MalformedStatus,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::durable_storage::D64K;
+    use risc_v_interpreter::{
+        exec_env::posix::Posix,
+        machine_state::{
+            bus::{main_memory::M1K, start_of_main_memory, Addressable},
+            registers::{a0, a1, a2, a3, a4, a6, a7},
+        },
+        state_backend::{memory_backend::InMemoryBackend, Backend, Layout},
+    };
+
+    #[test]
+    fn test_fatal_ecall_gets_stuck_until_input() {
+        type L = PvmLayout<Posix, M1K, D64K>;
+
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        let mut pvm = Pvm::<Posix, M1K, D64K, _>::bind(backend.allocate(placed));
+        pvm.reset();
+
+        // ECALL with an unknown system call number
+        const ECALL: u32 = 0b111_0011;
+        let ecall_pc = start_of_main_memory::<M1K>();
+        pvm.machine_state.bus.write(ecall_pc, ECALL).unwrap();
+        pvm.machine_state.hart.xregisters.write(a7, 1234);
+
+        assert_eq!(pvm.status(), Status::Eval);
+        assert!(pvm.step());
+
+        let stuck = Status::Stuck(StuckReason::FatalEnvironCall {
+            exception: EnvironException::EnvCallFromMMode,
+            pc: ecall_pc,
+        });
+        assert_eq!(pvm.status(), stuck);
+
+        // A stuck PVM refuses to evaluate further.
+        assert!(!pvm.step());
+        assert_eq!(pvm.step_many(10), 0);
+        assert_eq!(pvm.machine_state.hart.pc.read(), ecall_pc);
+
+        // The status is persisted in the state.
+        let mut pvm =
+            Pvm::<Posix, M1K, D64K, _>::bind(backend.allocate(L::placed().into_location()));
+        assert_eq!(pvm.status(), stuck);
+
+        // The next input recovers the PVM.
+        assert!(pvm.provide_input(0, 0, &[]));
+        assert_eq!(pvm.status(), Status::Eval);
+        assert_eq!(pvm.machine_state.hart.pc.read(), ecall_pc + 4);
+        assert!(!pvm.provide_input(0, 1, &[]));
+    }
+
+    #[test]
+    fn test_input_is_read_with_inbox_next() {
+        use mavryk_smart_rollup_constants::riscv::{SBI_FIRMWARE_MAVRYK, SBI_MAVRYK_INBOX_NEXT};
+
+        type L = PvmLayout<Posix, M1K, D64K>;
+
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        let mut pvm = Pvm::<Posix, M1K, D64K, _>::bind(backend.allocate(placed));
+        pvm.reset();
+
+        const ECALL: u32 = 0b111_0011;
+        let ecall_pc = start_of_main_memory::<M1K>();
+        let buffer_addr = ecall_pc + 0x100;
+        pvm.machine_state.bus.write(ecall_pc, ECALL).unwrap();
+
+        // Get stuck on an unknown system call, then recover with an input.
+        pvm.machine_state.hart.xregisters.write(a7, 1234);
+        assert!(pvm.step());
+        assert!(matches!(pvm.status(), Status::Stuck(_)));
+        assert!(!pvm.provide_input(5, 1, &[0; MAX_INPUT_MESSAGE_SIZE + 1]));
+        assert!(pvm.provide_input(5, 1, b"hello"));
+
+        let inbox_next = |pvm: &mut Pvm<Posix, M1K, D64K, _>| {
+            let xregisters = &mut pvm.machine_state.hart.xregisters;
+            xregisters.write(a0, buffer_addr);
+            xregisters.write(a1, 4);
+            xregisters.write(a6, SBI_MAVRYK_INBOX_NEXT);
+            xregisters.write(a7, SBI_FIRMWARE_MAVRYK);
+            pvm.machine_state.hart.pc.write(ecall_pc);
+            assert!(pvm.step());
+        };
+
+        // The recorded input is delivered once, trimmed to the buffer.
+        inbox_next(&mut pvm);
+        assert_eq!(pvm.status(), Status::Eval);
+        let xregisters = &pvm.machine_state.hart.xregisters;
+        assert_eq!([a0, a1, a2].map(|reg| xregisters.read(reg)), [5, 1, 4]);
+        assert_eq!(pvm.machine_state.bus.read(buffer_addr), Ok(*b"hell"));
+
+        // Without a pending input, the call is left to the environment.
+        inbox_next(&mut pvm);
+        assert!(matches!(pvm.status(), Status::Stuck(_)));
+    }
+
+    #[test]
+    fn test_malformed_status() {
+        type L = PvmLayout<Posix, M1K, D64K>;
+
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        let mut pvm = Pvm::<Posix, M1K, D64K, _>::bind(backend.allocate(placed));
+        pvm.reset();
+
+        // An unknown tag, or a stuck status with an unknown exception code,
+        // doesn't crash the PVM.
+        let malformed = Status::Stuck(StuckReason::MalformedStatus);
+        pvm.status.tag.write(42);
+        assert_eq!(pvm.status(), malformed);
+
+        pvm.status.tag.write(STATUS_STUCK);
+        pvm.status.stuck_exception.write(u64::MAX);
+        assert_eq!(pvm.status(), malformed);
+        assert!(!pvm.step());
+
+        // The next input resumes evaluation at the current instruction.
+        let pc = pvm.machine_state.hart.pc.read();
+        assert!(pvm.provide_input(0, 0, &[]));
+        assert_eq!(pvm.status(), Status::Eval);
+        assert_eq!(pvm.machine_state.hart.pc.read(), pc);
+    }
+
+    #[test]
+    fn test_step_many_with_budget() {
+        use risc_v_interpreter::{machine_state::cost::StepEvents, parser::instruction::Instr};
+
+        /// Charges 2 ticks per instruction
+        struct Double;
+
+        impl CostModel for Double {
+            fn instr(&self, _instr: &Instr) -> u64 {
+                2
+            }
+        }
+
+        type L = PvmLayout<Posix, M1K, D64K>;
+
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        let mut pvm = Pvm::<Posix, M1K, D64K, _>::bind(backend.allocate(placed));
+        pvm.reset();
+
+        // ADDI x0, x0, 0
+        const NOP: u32 = 0b001_0011;
+        let start = start_of_main_memory::<M1K>();
+        for index in 0..8 {
+            pvm.machine_state.bus.write(start + index * 4, NOP).unwrap();
+        }
+
+        // The step exhausting the budget is completed.
+        assert_eq!(pvm.step_many_with_budget(10, &Double, 5), (3, 6));
+        assert_eq!(pvm.machine_state.hart.pc.read(), start + 12);
+        assert!(matches!(
+            pvm.machine_state.step_events(),
+            StepEvents {
+                instr: Some(Instr::Addi(_)),
+                loads: 0,
+                stores: 0,
+                ..
+            }
+        ));
+
+        // The step limit still applies, and uniform costs count steps.
+        assert_eq!(pvm.step_many_with_budget(2, &UniformCost, 100), (2, 2));
+        assert_eq!(pvm.step_many(1), 1);
+    }
+
+    #[test]
+    fn test_durable_storage_sbi_calls() {
+        use mavryk_smart_rollup_constants::riscv::{
+            SBI_FIRMWARE_MAVRYK, SBI_MAVRYK_STORE_READ, SBI_MAVRYK_STORE_WRITE,
+        };
+
+        type L = PvmLayout<Posix, M1K, D64K>;
+
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        let mut pvm = Pvm::<Posix, M1K, D64K, _>::bind(backend.allocate(placed));
+        pvm.reset();
+
+        const ECALL: u32 = 0b111_0011;
+        let ecall_pc = start_of_main_memory::<M1K>();
+        let path_addr = ecall_pc + 0x100;
+        let value_addr = ecall_pc + 0x200;
+        let buffer_addr = ecall_pc + 0x300;
+
+        pvm.machine_state.bus.write(ecall_pc, ECALL).unwrap();
+        pvm.machine_state
+            .bus
+            .write_all(path_addr, b"/foo".as_slice())
+            .unwrap();
+        pvm.machine_state
+            .bus
+            .write_all(value_addr, b"bar".as_slice())
+            .unwrap();
+
+        let ecall = |pvm: &mut Pvm<Posix, M1K, D64K, _>, function, args: [u64; 5]| {
+            let xregisters = &mut pvm.machine_state.hart.xregisters;
+            for (reg, arg) in [a0, a1, a2, a3, a4].into_iter().zip(args) {
+                xregisters.write(reg, arg);
+            }
+            xregisters.write(a6, function);
+            xregisters.write(a7, SBI_FIRMWARE_MAVRYK);
+
+            pvm.machine_state.hart.pc.write(ecall_pc);
+            assert!(pvm.step());
+            assert_eq!(pvm.status(), Status::Eval);
+            assert_eq!(pvm.machine_state.hart.pc.read(), ecall_pc + 4);
+            pvm.machine_state.hart.xregisters.read(a0) as i64
+        };
+
+        let result = ecall(
+            &mut pvm,
+            SBI_MAVRYK_STORE_WRITE,
+            [path_addr, 4, 0, value_addr, 3],
+        );
+        assert_eq!(result, 0);
+        assert_eq!(
+            pvm.durable_storage.read(b"/foo", 0, 10),
+            Ok(b"bar".to_vec())
+        );
+
+        let result = ecall(
+            &mut pvm,
+            SBI_MAVRYK_STORE_READ,
+            [path_addr, 4, 1, buffer_addr, 10],
+        );
+        assert_eq!(result, 2);
+        assert_eq!(pvm.machine_state.bus.read(buffer_addr), Ok(*b"ar"));
+
+        // Errors are reported to the caller.
+        let result = ecall(
+            &mut pvm,
+            SBI_MAVRYK_STORE_READ,
+            [path_addr, 3, 0, buffer_addr, 10],
+        );
+        assert_eq!(result, mavryk_smart_rollup_core::STORE_NOT_A_VALUE as i64);
+    }
+
+    #[test]
+    fn test_environ_call_cost() {
+        use mavryk_smart_rollup_constants::riscv::{
+            SBI_FIRMWARE_MAVRYK, SBI_MAVRYK_STORE_READ, SBI_MAVRYK_STORE_WRITE,
+        };
+        use risc_v_interpreter::{machine_state::cost::StepEvents, parser::instruction::Instr};
+
+        /// Charges the bytes copied by environment calls only
+        struct Bytes;
+
+        impl CostModel for Bytes {
+            fn instr(&self, _instr: &Instr) -> u64 {
+                0
+            }
+
+            fn environ_call(&self, events: &StepEvents) -> u64 {
+                events.environ_bytes
+            }
+        }
+
+        type L = PvmLayout<Posix, M1K, D64K>;
+
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        let mut pvm = Pvm::<Posix, M1K, D64K, _>::bind(backend.allocate(placed));
+        pvm.reset();
+
+        const ECALL: u32 = 0b111_0011;
+        let ecall_pc = start_of_main_memory::<M1K>();
+        let path_addr = ecall_pc + 0x100;
+        let value_addr = ecall_pc + 0x200;
+        let buffer_addr = ecall_pc + 0x300;
+
+        pvm.machine_state.bus.write(ecall_pc, ECALL).unwrap();
+        pvm.machine_state
+            .bus
+            .write_all(path_addr, b"/foo".as_slice())
+            .unwrap();
+        pvm.machine_state
+            .bus
+            .write_all(value_addr, b"bar".as_slice())
+            .unwrap();
+
+        let prepare_ecall = |pvm: &mut Pvm<Posix, M1K, D64K, _>, function, args: [u64; 5]| {
+            let xregisters = &mut pvm.machine_state.hart.xregisters;
+            for (reg, arg) in [a0, a1, a2, a3, a4].into_iter().zip(args) {
+                xregisters.write(reg, arg);
+            }
+            xregisters.write(a6, function);
+            xregisters.write(a7, SBI_FIRMWARE_MAVRYK);
+            pvm.machine_state.hart.pc.write(ecall_pc);
+        };
+
+        // Writing reads the path and the value from memory.
+        prepare_ecall(
+            &mut pvm,
+            SBI_MAVRYK_STORE_WRITE,
+            [path_addr, 4, 0, value_addr, 3],
+        );
+        assert_eq!(pvm.step_many_with_budget(1, &Bytes, u64::MAX), (1, 7));
+        assert_eq!(pvm.machine_state.step_events().environ_bytes, 7);
+
+        // Reading reads the path and writes the value to memory. The copies
+        // count against the budget.
+        prepare_ecall(
+            &mut pvm,
+            SBI_MAVRYK_STORE_READ,
+            [path_addr, 4, 1, buffer_addr, 10],
+        );
+        assert_eq!(pvm.step_many_with_budget(10, &Bytes, 1), (1, 6));
+        assert_eq!(pvm.machine_state.bus.read(buffer_addr), Ok(*b"ar"));
+
+        // Uniform costs still count steps.
+        prepare_ecall(
+            &mut pvm,
+            SBI_MAVRYK_STORE_WRITE,
+            [path_addr, 4, 0, value_addr, 3],
+        );
+        assert_eq!(pvm.step_many_with_budget(1, &UniformCost, u64::MAX), (1, 1));
+    }
+
+    #[test]
+    fn test_reveal_sbi_calls() {
+        use crate::reveals::{DalParameters, DAL_PARAMETERS_SIZE};
+        use mavryk_smart_rollup_constants::riscv::{
+            SBI_FIRMWARE_MAVRYK, SBI_MAVRYK_REVEAL_DAL_PARAMETERS, SBI_MAVRYK_REVEAL_PREIMAGE,
+        };
+
+        type L = PvmLayout<Posix, M1K, D64K>;
+
+        let (mut backend, placed) = InMemoryBackend::<L>::new();
+        let mut pvm = Pvm::<Posix, M1K, D64K, _>::bind(backend.allocate(placed));
+        pvm.reset();
+
+        const ECALL: u32 = 0b111_0011;
+        let ecall_pc = start_of_main_memory::<M1K>();
+        let buffer_addr = ecall_pc + 0x100;
+        pvm.machine_state.bus.write(ecall_pc, ECALL).unwrap();
+
+        let prepare_ecall = |pvm: &mut Pvm<Posix, M1K, D64K, _>, function, args: [u64; 4]| {
+            let xregisters = &mut pvm.machine_state.hart.xregisters;
+            for (reg, arg) in [a0, a1, a2, a3].into_iter().zip(args) {
+                xregisters.write(reg, arg);
+            }
+            xregisters.write(a6, function);
+            xregisters.write(a7, SBI_FIRMWARE_MAVRYK);
+            pvm.machine_state.hart.pc.write(ecall_pc);
+        };
+
+        // Without a preimage directory, reveals are fatal.
+        prepare_ecall(
+            &mut pvm,
+            SBI_MAVRYK_REVEAL_DAL_PARAMETERS,
+            [buffer_addr, 32, 0, 0],
+        );
+        assert!(pvm.step());
+        assert!(matches!(pvm.status(), Status::Stuck(_)));
+        assert!(pvm.provide_input(0, 0, &[]));
+
+        let dir = std::env::temp_dir().join("mavkit-risc-v-pvm-missing-preimages");
+        pvm.set_preimage_directory(PreimageDirectory::new(dir));
+
+        // The revealed data is trimmed to the buffer.
+        prepare_ecall(
+            &mut pvm,
+            SBI_MAVRYK_REVEAL_DAL_PARAMETERS,
+            [buffer_addr, 8, 0, 0],
+        );
+        assert!(pvm.step());
+        assert_eq!(pvm.status(), Status::Eval);
+        assert_eq!(pvm.machine_state.hart.pc.read(), ecall_pc + 4);
+        assert_eq!(pvm.machine_state.hart.xregisters.read(a0), 8);
+        let expected: [u8; DAL_PARAMETERS_SIZE] = DalParameters::default().to_bytes();
+        let revealed: Result<[u8; 8], _> = pvm.machine_state.bus.read(buffer_addr);
+        assert_eq!(revealed, Ok(expected[..8].try_into().unwrap()));
+
+        // Missing preimages are reported to the caller.
+        let hash_addr = buffer_addr + 0x40;
+        prepare_ecall(
+            &mut pvm,
+            SBI_MAVRYK_REVEAL_PREIMAGE,
+            [hash_addr, 33, buffer_addr, 64],
+        );
+        assert!(pvm.step());
+        assert_eq!(
+            pvm.machine_state.hart.xregisters.read(a0) as i64,
+            mavryk_smart_rollup_core::GENERIC_INVALID_ACCESS as i64
+        );
+    }
 }
@@ -13,6 +13,14 @@ pub enum Mode {
     Debug(Options),
     /// Run a program using rvemu
     Rvemu(Options),
+    /// Run a program using the RISC-V interpreter and rvemu in lock-step,
+    /// stopping at the first divergence
+    Diff(Options),
+    /// Serve a program running in the RISC-V interpreter to GDB
+    Gdbserver(GdbServerOptions),
+    /// Count the instructions retired by a program running in the RISC-V
+    /// interpreter per symbol and call stack
+    Profile(ProfileOptions),
 }
 
 #[derive(Clone, ValueEnum, Debug)]
@@ -32,21 +40,37 @@ pub struct Cli {
 #[derive(Debug, Clone, Parser)]
 pub struct Options {
     /// Path to the input ELF executable
-    #[arg(short, long)]
-    pub input: String,
+    #[arg(short, long, required_unless_present = "load_snapshot")]
+    pub input: Option<String>,
 
     /// Path to the initrd
     #[arg(long)]
     pub initrd: Option<String>,
 
+    /// Path to a JSON or YAML file describing the inbox levels (requires the
+    /// supervisor binary interface)
+    #[arg(long, conflicts_with = "posix")]
+    pub inbox: Option<String>,
+
+    /// Path to a directory of preimages, as written by the installer client,
+    /// from which reveal calls are served
+    #[arg(long)]
+    pub preimages_dir: Option<String>,
+
     /// Keep going after the inbox has been drained.
     #[arg(short, long)]
     pub keep_going: bool,
 
-    /// Support some POSIX-style system calls
+    /// Support some POSIX-style system calls instead of the supervisor binary
+    /// interface in rvemu
     #[arg(long)]
     pub posix: bool,
 
+    /// Serve the supervisor binary interface instead of the POSIX-style
+    /// system calls in the RISC-V interpreter
+    #[arg(long, conflicts_with = "posix")]
+    pub sbi: bool,
+
     /// Rollup address
     #[arg(short, long, default_value = "sr1UNDWPUYVeomgG15wn5jSw689EJ4RNnVQa")]
     pub address: String,
@@ -57,6 +81,52 @@ pub struct Options {
 
     #[arg(short = 'm', long, value_enum, default_value_t = ExitMode::User)]
     pub posix_exit_mode: ExitMode,
+
+    /// Maximum number of steps to run
+    #[arg(long, default_value_t = 1000000)]
+    pub max_steps: usize,
+
+    /// Resume from a snapshot instead of booting the input program
+    #[arg(long, conflicts_with = "input")]
+    pub load_snapshot: Option<String>,
+
+    /// Save a snapshot of the state once the interpreter stops
+    #[arg(long)]
+    pub save_snapshot: Option<String>,
+}
+
+/// Options of the GDB server
+#[derive(Debug, Clone, Parser)]
+pub struct GdbServerOptions {
+    #[command(flatten)]
+    pub common: Options,
+
+    /// Address to listen on for the GDB connection
+    #[arg(long, default_value = "127.0.0.1:1234")]
+    pub listen: String,
+}
+
+/// Options of the profiler
+#[derive(Debug, Clone, Parser)]
+pub struct ProfileOptions {
+    #[command(flatten)]
+    pub common: Options,
+
+    /// Path to write the call stacks to, in the folded format of flamegraph
+    /// tools
+    #[arg(long)]
+    pub folded: Option<String>,
+
+    /// Number of symbols listed in the summary
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+impl Options {
+    /// Path to the input ELF executable
+    pub fn input(&self) -> Result<&str, &'static str> {
+        self.input.as_deref().ok_or("Missing input program")
+    }
 }
 
 /// Parse the command-line arguments.
@@ -12,12 +12,13 @@ use ratatui::{
     widgets::{block::*, *},
 };
 use risc_v_interpreter::{
-    machine_state::{csregisters, mode::Mode, registers},
-    Interpreter, InterpreterResult,
+    machine_state::{csregisters, mode::Mode, registers, AccessType},
+    Interpreter, InterpreterBackend, InterpreterResult,
 };
 use std::collections::{BTreeMap, HashMap, HashSet};
 
 mod errors;
+mod history;
 mod tui;
 
 const GREEN: Color = tailwind::GREEN.c400;
@@ -29,6 +30,10 @@ const GRAY: Color = tailwind::GRAY.c500;
 const SELECTED_STYLE_FG: Color = BLUE;
 const NEXT_STYLE_FG: Color = GREEN;
 const MAX_STEPS: usize = 1_000_000;
+const MEMORY_ROW_BYTES: u64 = 16;
+const MEMORY_PAGE_ROWS: u64 = 8;
+const WATCHPOINT_BYTES: u64 = 8;
+const MAX_CALL_STACK_DEPTH: usize = 32;
 
 #[derive(Debug)]
 struct Instruction<'a> {
@@ -45,33 +50,93 @@ struct ProgramView<'a> {
     symbols: HashMap<u64, &'a str>,
 }
 
+/// Hex view of the memory
+struct MemoryView {
+    address: u64,
+    physical: bool,
+}
+
+/// Watchpoint stopping execution when the watched memory changes
+struct Watchpoint {
+    address: u64,
+    physical: bool,
+    contents: Vec<Option<u8>>,
+}
+
+/// Input requested from the user in the bottom bar
+#[derive(Clone, Copy)]
+enum PromptKind {
+    Memory,
+    Watchpoint,
+    Symbol,
+    StepBack,
+}
+
+struct Prompt {
+    kind: PromptKind,
+    input: String,
+}
+
 pub struct DebuggerApp<'a> {
     title: &'a str,
-    interpreter: &'a mut Interpreter<'a>,
+
+    /// State of the program. An interpreter is only bound to it while the
+    /// state is inspected or run, such that the history can take and restore
+    /// checkpoints of the backend in between.
+    backend: &'a mut InterpreterBackend,
+
     program: ProgramView<'a>,
     status: InterpreterResult,
+    memory: MemoryView,
+    watchpoints: Vec<Watchpoint>,
+    hit_watchpoint: Option<u64>,
+    prompt: Option<Prompt>,
+    message: Option<String>,
+    sorted_symbols: BTreeMap<u64, &'a str>,
+    history: history::History,
 }
 
 macro_rules! xregister_line {
-    ($self: ident, $reg: ident) => {
+    ($interpreter: ident, $reg: ident) => {
         Line::from(vec![
             format!("   {0} ({0:?}): ", $reg).into(),
-            format!("{} ", $self.interpreter.read_xregister($reg)).fg(YELLOW),
-            format!("0x{:x}", $self.interpreter.read_xregister($reg)).fg(ORANGE),
+            format!("{} ", $interpreter.read_xregister($reg)).fg(YELLOW),
+            format!("0x{:x}", $interpreter.read_xregister($reg)).fg(ORANGE),
         ])
     };
 }
 
 macro_rules! fregister_line {
-    ($self: ident, $reg: ident) => {
+    ($interpreter: ident, $reg: ident) => {
         Line::from(vec![
             format!("   {0} ({0:?}): ", $reg).into(),
-            format!("{} ", u64::from($self.interpreter.read_fregister($reg))).fg(YELLOW),
-            format!("0x{:x}", u64::from($self.interpreter.read_fregister($reg))).fg(ORANGE),
+            format!("{} ", u64::from($interpreter.read_fregister($reg))).fg(YELLOW),
+            format!("0x{:x}", u64::from($interpreter.read_fregister($reg))).fg(ORANGE),
         ])
     };
 }
 
+/// Read the byte at `address`, which is translated unless `physical` is set.
+fn read_byte(interpreter: &Interpreter, address: u64, physical: bool) -> Option<u8> {
+    let address = if physical {
+        address
+    } else {
+        interpreter.translate(address, AccessType::Load).ok()?
+    };
+    interpreter.read_memory(address, 1).ok()?.first().copied()
+}
+
+fn read_bytes(
+    interpreter: &Interpreter,
+    address: u64,
+    len: u64,
+    physical: bool,
+) -> Vec<Option<u8>> {
+    (0..len)
+        .map(|offset| read_byte(interpreter, address.wrapping_add(offset), physical))
+        .collect()
+}
+
 impl<'a> Instruction<'a> {
     fn new(address: u64, text: &'a str, symbols: &HashMap<u64, &'a str>) -> Self {
         let jump = match text
@@ -98,25 +163,39 @@ impl<'a> Instruction<'a> {
 impl<'a> DebuggerApp<'a> {
     pub fn launch(fname: &str, contents: &[u8], exit_mode: Mode) -> Result<()> {
         let mut backend = Interpreter::create_backend();
-        let (mut interpreter, prog) =
+        let (_, prog) =
             Interpreter::new_with_parsed_program(&mut backend, contents, None, exit_mode)?;
         let symbols = kernel_loader::get_elf_symbols(contents)?;
         errors::install_hooks()?;
         let terminal = tui::init()?;
-        DebuggerApp::new(&mut interpreter, fname, &prog, symbols).run_debugger(terminal)?;
+        DebuggerApp::new(&mut backend, fname, &prog, symbols).run_debugger(terminal)?;
         tui::restore()?;
         Ok(())
     }
 
     fn new(
-        interpreter: &'a mut Interpreter<'a>,
+        backend: &'a mut InterpreterBackend,
         title: &'a str,
         program: &'a BTreeMap<u64, String>,
         symbols: HashMap<u64, &'a str>,
     ) -> Self {
+        let sorted_symbols = symbols.iter().map(|(addr, name)| (*addr, *name)).collect();
+        let sp = Interpreter::resume(backend).read_xregister(registers::sp);
+        let memory = MemoryView {
+            address: sp & !(MEMORY_ROW_BYTES - 1),
+            physical: false,
+        };
+        let history = history::History::new(backend);
         Self {
             title,
-            interpreter,
+            backend,
+            memory,
+            watchpoints: Vec::new(),
+            hit_watchpoint: None,
+            prompt: None,
+            message: None,
+            sorted_symbols,
+            history,
             program: ProgramView::with_items(
                 program
                     .iter()
@@ -134,15 +213,45 @@ impl<'a> DebuggerApp<'a> {
             if let Event::Key(key) = event::read()? {
                 if key.kind == KeyEventKind::Press {
                     use KeyCode::*;
+
+                    if let Some(prompt) = &mut self.prompt {
+                        match key.code {
+                            Esc => self.prompt = None,
+                            Enter => {
+                                if let Some(prompt) = self.prompt.take() {
+                                    self.submit_prompt(prompt)
+                                }
+                            }
+                            Backspace => {
+                                prompt.input.pop();
+                            }
+                            Char(c) => prompt.input.push(c),
+                            _ => {}
+                        }
+                        continue;
+                    }
+
+                    self.message = None;
                     match key.code {
                         Char('q') | Esc => return Ok(()),
                         Char('s') => self.step(1),
                         Char('b') => self.program.set_breakpoint(),
                         Char('r') => self.step_until_breakpoint(),
+                        Char('S') => self.step_back(1),
+                        Char('B') => self.open_prompt(PromptKind::StepBack),
+                        Char('R') => self.step_back_until_breakpoint(),
                         Char('j') | Down => self.program.next(),
                         Char('k') | Up => self.program.previous(),
                         Char('g') | Home => self.program.go_top(),
                         Char('G') | End => self.program.go_bottom(),
+                        Char('m') => self.open_prompt(PromptKind::Memory),
+                        Char('w') => self.open_prompt(PromptKind::Watchpoint),
+                        Char('/') => self.open_prompt(PromptKind::Symbol),
+                        Char('p') => self.memory.physical = !self.memory.physical,
+                        Char(']') => self.scroll_memory(1),
+                        Char('[') => self.scroll_memory(-1),
+                        Char('}') | PageDown => self.scroll_memory(MEMORY_PAGE_ROWS as i64),
+                        Char('{') | PageUp => self.scroll_memory(-(MEMORY_PAGE_ROWS as i64)),
                         _ => {}
                     }
                 }
@@ -156,7 +265,7 @@ impl<'a> DebuggerApp<'a> {
     }
 
     fn update_after_step(&mut self, result: InterpreterResult) {
-        let pc = self.interpreter.read_pc();
+        let pc = Interpreter::resume(self.backend).read_pc();
         self.program.next_instr = self
             .program
             .instructions
@@ -173,18 +282,206 @@ impl<'a> DebuggerApp<'a> {
     }
 
     fn step(&mut self, max_steps: usize) {
-        let result = self.interpreter.run(max_steps);
+        let result = self.history.run(self.backend, max_steps, |_| true);
         self.update_after_step(result);
     }
 
+    /// Go back `steps` steps, re-executing from the last checkpoint.
+    fn step_back(&mut self, steps: usize) {
+        let target = self.history.steps().saturating_sub(steps);
+        self.history.rewind(self.backend, target);
+        self.message = Some(format!("Went back to step {target}"));
+        self.update_after_rewind();
+    }
+
+    /// Go back to the last time a breakpoint was hit.
+    fn step_back_until_breakpoint(&mut self) {
+        let breakpoints = &self.program.breakpoints;
+        self.message = Some(
+            match self
+                .history
+                .rewind_until(self.backend, |pc| breakpoints.contains(&pc))
+            {
+                Some(steps) => format!("Went back to step {steps}"),
+                None => "No earlier breakpoint hit, went back to the start".to_owned(),
+            },
+        );
+        self.update_after_rewind();
+    }
+
+    fn update_after_rewind(&mut self) {
+        // The watched memory has changed since it was last checked.
+        self.watchpoint_triggered();
+        self.hit_watchpoint = None;
+        self.update_after_step(InterpreterResult::Running(0));
+    }
+
     fn step_until_breakpoint(&mut self) {
         self.step(1);
-        let result = self.interpreter.step_many(MAX_STEPS, |m| {
-            !self.program.breakpoints.contains(&m.hart.pc.read())
-        });
+        if self.watchpoint_triggered() {
+            return;
+        }
+
+        let result = if self.watchpoints.is_empty() {
+            let breakpoints = &self.program.breakpoints;
+            self.history
+                .run(self.backend, MAX_STEPS, |pc| !breakpoints.contains(&pc))
+        } else {
+            self.step_until_watchpoint()
+        };
         self.update_after_step(result);
     }
 
+    /// Step one instruction at a time until a breakpoint or a watchpoint is
+    /// hit.
+    fn step_until_watchpoint(&mut self) -> InterpreterResult {
+        let mut steps = 0;
+        while steps < MAX_STEPS
+            && !self
+                .program
+                .breakpoints
+                .contains(&Interpreter::resume(self.backend).read_pc())
+        {
+            match self.history.run(self.backend, 1, |_| true) {
+                InterpreterResult::Running(done) => steps += done,
+                result => return result,
+            }
+
+            if self.watchpoint_triggered() {
+                break;
+            }
+        }
+        InterpreterResult::Running(steps)
+    }
+
+    /// Check whether the memory of any watchpoint has changed, recording the
+    /// new contents.
+    fn watchpoint_triggered(&mut self) -> bool {
+        let interpreter = Interpreter::resume(self.backend);
+        self.hit_watchpoint = None;
+
+        for watchpoint in self.watchpoints.iter_mut() {
+            let contents = read_bytes(
+                &interpreter,
+                watchpoint.address,
+                WATCHPOINT_BYTES,
+                watchpoint.physical,
+            );
+            if contents != watchpoint.contents {
+                watchpoint.contents = contents;
+                self.hit_watchpoint = self.hit_watchpoint.or(Some(watchpoint.address));
+            }
+        }
+
+        self.hit_watchpoint.is_some()
+    }
+
+    fn scroll_memory(&mut self, rows: i64) {
+        let offset = rows.wrapping_mul(MEMORY_ROW_BYTES as i64);
+        self.memory.address = self.memory.address.wrapping_add(offset as u64);
+    }
+
+    fn open_prompt(&mut self, kind: PromptKind) {
+        self.prompt = Some(Prompt {
+            kind,
+            input: String::new(),
+        });
+    }
+
+    /// Resolve user input to an address. The input is either a symbol name or
+    /// a hexadecimal address.
+    fn resolve_address(&self, input: &str) -> Option<u64> {
+        let input = input.trim();
+        if let Some((addr, _)) = self.sorted_symbols.iter().find(|(_, name)| **name == input) {
+            return Some(*addr);
+        }
+        u64::from_str_radix(input.trim_start_matches("0x"), 16).ok()
+    }
+
+    fn submit_prompt(&mut self, prompt: Prompt) {
+        if let PromptKind::StepBack = prompt.kind {
+            match prompt.input.trim().parse() {
+                Ok(steps) => self.step_back(steps),
+                Err(_) => self.message = Some(format!("Invalid number of steps: {}", prompt.input)),
+            }
+            return;
+        }
+
+        let Some(address) = self.resolve_address(&prompt.input) else {
+            self.message = Some(format!("Unknown symbol or address: {}", prompt.input));
+            return;
+        };
+
+        match prompt.kind {
+            PromptKind::Memory => self.memory.address = address & !(MEMORY_ROW_BYTES - 1),
+            PromptKind::Watchpoint => {
+                if let Some(index) = self.watchpoints.iter().position(|w| w.address == address) {
+                    self.watchpoints.remove(index);
+                    self.message = Some(format!("Removed watchpoint at {address:x}"));
+                } else {
+                    let physical = self.memory.physical;
+                    let interpreter = Interpreter::resume(self.backend);
+                    self.watchpoints.push(Watchpoint {
+                        address,
+                        physical,
+                        contents: read_bytes(&interpreter, address, WATCHPOINT_BYTES, physical),
+                    });
+                    self.message = Some(format!("Added watchpoint at {address:x}"));
+                }
+            }
+            PromptKind::Symbol => {
+                if !self.program.go_to_address(address) {
+                    self.message = Some(format!("No instruction at {address:x}"));
+                }
+            }
+            // Handled above
+            PromptKind::StepBack => {}
+        }
+    }
+
+    /// Symbolic description of `address`
+    fn describe_address(&self, address: u64) -> String {
+        match self.sorted_symbols.range(..=address).next_back() {
+            Some((start, name)) if *start == address => name.to_string(),
+            Some((start, name)) => format!("{name}+{:#x}", address - start),
+            None => "??".to_owned(),
+        }
+    }
+
+    /// Return addresses of the active frames, found by following the frame
+    /// pointers. The frame pointer points past the saved return address and
+    /// the saved frame pointer of the caller.
+    fn call_stack(&mut self) -> Vec<u64> {
+        let interpreter = Interpreter::resume(self.backend);
+        let read_u64 = |address: u64| {
+            let bytes: Option<Vec<u8>> = read_bytes(&interpreter, address, 8, false)
+                .into_iter()
+                .collect();
+            Some(u64::from_le_bytes(bytes?.try_into().ok()?))
+        };
+
+        let mut frames = vec![interpreter.read_pc()];
+        let mut fp = interpreter.read_xregister(registers::fp);
+        while fp != 0 && frames.len() < MAX_CALL_STACK_DEPTH {
+            let (Some(ra), Some(prev_fp)) =
+                (read_u64(fp.wrapping_sub(8)), read_u64(fp.wrapping_sub(16)))
+            else {
+                break;
+            };
+            if ra == 0 {
+                break;
+            }
+            frames.push(ra);
+
+            // Frames grow towards lower addresses.
+            if prev_fp <= fp {
+                break;
+            }
+            fp = prev_fp;
+        }
+        frames
+    }
+
     fn render_program_pane(&mut self, area: Rect, buf: &mut Buffer) {
         let title = Title::from(format!(" {} ", self.title).bold());
         let block = Block::default()
@@ -221,39 +518,40 @@ impl<'a> DebuggerApp<'a> {
             .borders(Borders::ALL)
             .border_set(border::THICK);
 
+        let interpreter = Interpreter::resume(self.backend);
         use registers::*;
         let registers_text = Text::from(vec![
-            xregister_line!(self, ra),
-            xregister_line!(self, sp),
-            xregister_line!(self, gp),
-            xregister_line!(self, tp),
-            xregister_line!(self, t0),
-            xregister_line!(self, t1),
-            xregister_line!(self, t2),
-            xregister_line!(self, s0),
-            xregister_line!(self, s1),
-            xregister_line!(self, a0),
-            xregister_line!(self, a1),
-            xregister_line!(self, a2),
-            xregister_line!(self, a3),
-            xregister_line!(self, a4),
-            xregister_line!(self, a5),
-            xregister_line!(self, a6),
-            xregister_line!(self, a7),
-            xregister_line!(self, s2),
-            xregister_line!(self, s3),
-            xregister_line!(self, s4),
-            xregister_line!(self, s5),
-            xregister_line!(self, s6),
-            xregister_line!(self, s7),
-            xregister_line!(self, s8),
-            xregister_line!(self, s9),
-            xregister_line!(self, s10),
-            xregister_line!(self, s11),
-            xregister_line!(self, t3),
-            xregister_line!(self, t4),
-            xregister_line!(self, t5),
-            xregister_line!(self, t6),
+            xregister_line!(interpreter, ra),
+            xregister_line!(interpreter, sp),
+            xregister_line!(interpreter, gp),
+            xregister_line!(interpreter, tp),
+            xregister_line!(interpreter, t0),
+            xregister_line!(interpreter, t1),
+            xregister_line!(interpreter, t2),
+            xregister_line!(interpreter, s0),
+            xregister_line!(interpreter, s1),
+            xregister_line!(interpreter, a0),
+            xregister_line!(interpreter, a1),
+            xregister_line!(interpreter, a2),
+            xregister_line!(interpreter, a3),
+            xregister_line!(interpreter, a4),
+            xregister_line!(interpreter, a5),
+            xregister_line!(interpreter, a6),
+            xregister_line!(interpreter, a7),
+            xregister_line!(interpreter, s2),
+            xregister_line!(interpreter, s3),
+            xregister_line!(interpreter, s4),
+            xregister_line!(interpreter, s5),
+            xregister_line!(interpreter, s6),
+            xregister_line!(interpreter, s7),
+            xregister_line!(interpreter, s8),
+            xregister_line!(interpreter, s9),
+            xregister_line!(interpreter, s10),
+            xregister_line!(interpreter, s11),
+            xregister_line!(interpreter, t3),
+            xregister_line!(interpreter, t4),
+            xregister_line!(interpreter, t5),
+            xregister_line!(interpreter, t6),
         ]);
 
         Paragraph::new(registers_text)
@@ -269,39 +567,40 @@ impl<'a> DebuggerApp<'a> {
             .borders(Borders::ALL)
             .border_set(border::THICK);
 
+        let interpreter = Interpreter::resume(self.backend);
         use registers::*;
         let registers_text = Text::from(vec![
-            fregister_line!(self, ft0),
-            fregister_line!(self, ft1),
-            fregister_line!(self, ft2),
-            fregister_line!(self, ft3),
-            fregister_line!(self, ft4),
-            fregister_line!(self, ft5),
-            fregister_line!(self, ft6),
-            fregister_line!(self, ft7),
-            fregister_line!(self, fs0),
-            fregister_line!(self, fs1),
-            fregister_line!(self, fa0),
-            fregister_line!(self, fa1),
-            fregister_line!(self, fa2),
-            fregister_line!(self, fa3),
-            fregister_line!(self, fa4),
-            fregister_line!(self, fa5),
-            fregister_line!(self, fa6),
-            fregister_line!(self, fa7),
-            fregister_line!(self, fs2),
-            fregister_line!(self, fs3),
-            fregister_line!(self, fs4),
-            fregister_line!(self, fs5),
-            fregister_line!(self, fs6),
-            fregister_line!(self, fs7),
-            fregister_line!(self, fs8),
-            fregister_line!(self, fs9),
-            fregister_line!(self, fs10),
-            fregister_line!(self, ft8),
-            fregister_line!(self, ft9),
-            fregister_line!(self, ft10),
-            fregister_line!(self, ft11),
+            fregister_line!(interpreter, ft0),
+            fregister_line!(interpreter, ft1),
+            fregister_line!(interpreter, ft2),
+            fregister_line!(interpreter, ft3),
+            fregister_line!(interpreter, ft4),
+            fregister_line!(interpreter, ft5),
+            fregister_line!(interpreter, ft6),
+            fregister_line!(interpreter, ft7),
+            fregister_line!(interpreter, fs0),
+            fregister_line!(interpreter, fs1),
+            fregister_line!(interpreter, fa0),
+            fregister_line!(interpreter, fa1),
+            fregister_line!(interpreter, fa2),
+            fregister_line!(interpreter, fa3),
+            fregister_line!(interpreter, fa4),
+            fregister_line!(interpreter, fa5),
+            fregister_line!(interpreter, fa6),
+            fregister_line!(interpreter, fa7),
+            fregister_line!(interpreter, fs2),
+            fregister_line!(interpreter, fs3),
+            fregister_line!(interpreter, fs4),
+            fregister_line!(interpreter, fs5),
+            fregister_line!(interpreter, fs6),
+            fregister_line!(interpreter, fs7),
+            fregister_line!(interpreter, fs8),
+            fregister_line!(interpreter, fs9),
+            fregister_line!(interpreter, fs10),
+            fregister_line!(interpreter, ft8),
+            fregister_line!(interpreter, ft9),
+            fregister_line!(interpreter, ft10),
+            fregister_line!(interpreter, ft11),
         ]);
 
         Paragraph::new(registers_text)
@@ -319,8 +618,10 @@ impl<'a> DebuggerApp<'a> {
 
         use csregisters::*;
 
-        let frm = self.interpreter.read_csregister(CSRegister::frm);
-        let fflags = self.interpreter.read_csregister(CSRegister::fflags);
+        let interpreter = Interpreter::resume(self.backend);
+        let frm = interpreter.read_csregister(CSRegister::frm);
+        let fflags = interpreter.read_csregister(CSRegister::fflags);
+        let fcsr = interpreter.read_csregister(CSRegister::fcsr);
 
         fn rounding_mode(rm: u64) -> &'static str {
             match rm {
@@ -345,11 +646,7 @@ impl<'a> DebuggerApp<'a> {
         let registers_text = Text::from(vec![
             Line::from(vec![
                 format!(" {0:>6}: ", CSRegister::fcsr).into(),
-                format!(
-                    "0x{:02x}",
-                    self.interpreter.read_csregister(CSRegister::fcsr)
-                )
-                .fg(ORANGE),
+                format!("0x{:02x}", fcsr).fg(ORANGE),
             ]),
             Line::from(vec![
                 format!(" {0:>6}: ", CSRegister::frm).into(),
@@ -375,24 +672,125 @@ impl<'a> DebuggerApp<'a> {
             .render(area, buf)
     }
 
+    fn render_memory_pane(&mut self, area: Rect, buf: &mut Buffer) {
+        let kind = if self.memory.physical {
+            "physical"
+        } else {
+            "virtual"
+        };
+        let title = Title::from(format!(" Memory ({kind}) ").bold());
+        let block = Block::default()
+            .title(title.alignment(Alignment::Left))
+            .borders(Borders::ALL)
+            .border_set(border::THICK);
+
+        let rows = area.height.saturating_sub(2) as u64;
+        let interpreter = Interpreter::resume(self.backend);
+        let watched = |address: u64| {
+            self.watchpoints.iter().any(|w| {
+                w.physical == self.memory.physical
+                    && (w.address..w.address.saturating_add(WATCHPOINT_BYTES)).contains(&address)
+            })
+        };
+
+        let lines: Vec<Line> = (0..rows)
+            .map(|row| {
+                let address = self.memory.address.wrapping_add(row * MEMORY_ROW_BYTES);
+                let bytes = read_bytes(
+                    &interpreter,
+                    address,
+                    MEMORY_ROW_BYTES,
+                    self.memory.physical,
+                );
+
+                let mut line = vec![format!(" {address:016x}: ").fg(ORANGE)];
+                for (offset, byte) in bytes.iter().enumerate() {
+                    let text = match byte {
+                        Some(byte) => format!("{byte:02x} "),
+                        None => "?? ".to_owned(),
+                    };
+                    if watched(address.wrapping_add(offset as u64)) {
+                        line.push(text.fg(RED).bold());
+                    } else {
+                        line.push(text.into());
+                    }
+                }
+
+                let ascii: String = bytes
+                    .iter()
+                    .map(|byte| match byte {
+                        Some(byte) if byte.is_ascii_graphic() => *byte as char,
+                        _ => '.',
+                    })
+                    .collect();
+                line.push(format!(" {ascii}").fg(YELLOW));
+                Line::from(line)
+            })
+            .collect();
+
+        Paragraph::new(Text::from(lines))
+            .left_aligned()
+            .block(block)
+            .render(area, buf)
+    }
+
+    fn render_call_stack_pane(&mut self, area: Rect, buf: &mut Buffer) {
+        let title = Title::from(" Call Stack ".bold());
+        let block = Block::default()
+            .title(title.alignment(Alignment::Left))
+            .borders(Borders::ALL)
+            .border_set(border::THICK);
+
+        let lines: Vec<Line> = self
+            .call_stack()
+            .into_iter()
+            .enumerate()
+            .map(|(depth, address)| {
+                Line::from(vec![
+                    format!("   #{depth:<2} ").into(),
+                    format!("{address:x} ").fg(ORANGE),
+                    self.describe_address(address).fg(BLUE),
+                ])
+            })
+            .collect();
+
+        Paragraph::new(Text::from(lines))
+            .left_aligned()
+            .block(block)
+            .render(area, buf)
+    }
+
     fn render_status_pane(&mut self, area: Rect, buf: &mut Buffer) {
         let title = Title::from(" Status ".bold());
         let block = Block::default()
             .title(title.alignment(Alignment::Left))
             .borders(Borders::ALL)
             .border_set(border::THICK);
+        let interpreter = Interpreter::resume(self.backend);
         let pc_line = Line::from(vec![
             "   PC: ".into(),
-            format!("{:x}", self.interpreter.read_pc()).fg(ORANGE),
+            format!("{:x}", interpreter.read_pc()).fg(ORANGE),
         ]);
         let mode_line = Line::from(vec![
             "   Mode: ".into(),
-            format!("{:?}", self.interpreter.read_mode()).fg(BLUE),
+            format!("{:?}", interpreter.read_mode()).fg(BLUE),
         ]);
         let status_text = match &self.status {
             InterpreterResult::Running(steps) => vec![
-                Line::from(vec!["   Running".bold().fg(GREEN)]),
-                Line::from(vec![format!("   Steps executed: {}", steps).into()]),
+                match self.hit_watchpoint {
+                    Some(address) => {
+                        Line::from(vec![format!("   Running, watchpoint at {address:x} hit")
+                            .bold()
+                            .fg(GREEN)])
+                    }
+                    None => Line::from(vec!["   Running".bold().fg(GREEN)]),
+                },
+                Line::from(vec![format!(
+                    "   Steps executed: {} (at step {})",
+                    steps,
+                    self.history.steps()
+                )
+                .into()]),
                 pc_line,
                 mode_line,
             ],
@@ -400,13 +798,23 @@ impl<'a> DebuggerApp<'a> {
                 let color = if *code == 0 { YELLOW } else { RED };
                 vec![
                     Line::from(vec![format!("   Exit with code {}", code).bold().fg(color)]),
-                    Line::from(vec![format!("   Steps executed: {}", steps).into()]),
+                    Line::from(vec![format!(
+                        "   Steps executed: {} (at step {})",
+                        steps,
+                        self.history.steps()
+                    )
+                    .into()]),
                     pc_line,
                 ]
             }
             InterpreterResult::Exception(exc, steps) => vec![
                 Line::from(vec![format!("   Exception: {:?}", exc).bold().fg(RED)]),
-                Line::from(vec![format!("   Steps executed: {}", steps).into()]),
+                Line::from(vec![format!(
+                    "   Steps executed: {} (at step {})",
+                    steps,
+                    self.history.steps()
+                )
+                .into()]),
                 pc_line,
             ],
         };
@@ -417,6 +825,26 @@ impl<'a> DebuggerApp<'a> {
     }
 
     fn render_bottom_bar(&mut self, area: Rect, buf: &mut Buffer) {
+        if let Some(prompt) = &self.prompt {
+            let label = match prompt.kind {
+                PromptKind::Memory => " Show memory at (symbol or hex address): ",
+                PromptKind::Watchpoint => " Toggle watchpoint at (symbol or hex address): ",
+                PromptKind::Symbol => " Go to (symbol or hex address): ",
+                PromptKind::StepBack => " Steps to go back: ",
+            };
+            Line::from(vec![
+                label.fg(BLUE).bold(),
+                format!("{}_", prompt.input).into(),
+            ])
+            .render(area, buf);
+            return;
+        }
+
+        if let Some(message) = &self.message {
+            Line::from(vec![format!(" {message}").fg(YELLOW).bold()]).render(area, buf);
+            return;
+        }
+
         Line::from(vec![
             " Step ".into(),
             "<s>  ".fg(BLUE).bold(),
@@ -426,6 +854,20 @@ impl<'a> DebuggerApp<'a> {
             "<b>  ".fg(BLUE).bold(),
             " Run ".into(),
             "<r>  ".fg(BLUE).bold(),
+            " Step back ".into(),
+            "<S/B>  ".fg(BLUE).bold(),
+            " Run back ".into(),
+            "<R>  ".fg(BLUE).bold(),
+            " Go to ".into(),
+            "</>  ".fg(BLUE).bold(),
+            " Memory ".into(),
+            "<m>  ".fg(BLUE).bold(),
+            " Scroll ".into(),
+            "<[/]>  ".fg(BLUE).bold(),
+            " Virtual/Physical ".into(),
+            "<p>  ".fg(BLUE).bold(),
+            " Watchpoint ".into(),
+            "<w>  ".fg(BLUE).bold(),
             " Quit ".into(),
             "<q> ".fg(BLUE).bold(),
         ])
@@ -444,12 +886,21 @@ impl Widget for &mut DebuggerApp<'_> {
         let main_layout = Layout::default()
             .direction(Direction::Horizontal)
             .constraints(vec![Constraint::Percentage(50), Constraint::Percentage(50)]);
-        let [program_area, rhs_area] = main_layout.areas(outer_layout[0]);
+        let [lhs_area, rhs_area] = main_layout.areas(outer_layout[0]);
+
+        let lhs_layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Fill(1), Constraint::Length(10)]);
+        let [program_area, memory_area] = lhs_layout.areas(lhs_area);
 
         let rhs_layout = Layout::default()
             .direction(Direction::Vertical)
-            .constraints(vec![Constraint::Fill(1), Constraint::Length(6)]);
-        let [registers_area, status_area] = rhs_layout.areas(rhs_area);
+            .constraints(vec![
+                Constraint::Fill(1),
+                Constraint::Length(8),
+                Constraint::Length(6),
+            ]);
+        let [registers_area, call_stack_area, status_area] = rhs_layout.areas(rhs_area);
 
         let registers_layout = Layout::default()
             .direction(Direction::Horizontal)
@@ -462,6 +913,8 @@ impl Widget for &mut DebuggerApp<'_> {
         let [fregisters_area, fcsr_area] = f_layout.areas(f_area);
 
         self.render_program_pane(program_area, buf);
+        self.render_memory_pane(memory_area, buf);
+        self.render_call_stack_pane(call_stack_area, buf);
         self.render_xregisters_pane(xregisters_area, buf);
         self.render_fregisters_pane(fregisters_area, buf);
         self.render_fcsr_pane(fcsr_area, buf);
@@ -582,6 +1035,21 @@ impl<'a> ProgramView<'a> {
         self.state.select(Some(i));
     }
 
+    /// Select the instruction at `address`. Returns whether there is one.
+    fn go_to_address(&mut self, address: u64) -> bool {
+        match self
+            .instructions
+            .iter()
+            .position(|instr| instr.address == address)
+        {
+            Some(index) => {
+                self.state.select(Some(index));
+                true
+            }
+            None => false,
+        }
+    }
+
     fn go_top(&mut self) {
         self.state.select(Some(0));
     }
@@ -0,0 +1,316 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Execution history for reverse execution
+//!
+//! The interpreter is deterministic, hence any earlier state can be recovered
+//! by restoring the last checkpoint taken before it and executing the steps
+//! in between again. Only a few checkpoints are kept: once there are too
+//! many, every other one is dropped and the interval between them doubles.
+//!
+//! Checkpoints are taken and restored on the backend, in between runs of an
+//! interpreter bound to it. They only hold the pages of the backend which
+//! aren't zero, and share the pages which haven't changed with the previous
+//! checkpoint.
+
+use risc_v_interpreter::{Interpreter, InterpreterBackend, InterpreterResult};
+use std::{collections::BTreeMap, rc::Rc};
+
+/// Initial number of steps between checkpoints
+const CHECKPOINT_INTERVAL: usize = 100_000;
+
+/// Maximum number of checkpoints kept, including the initial one
+const MAX_CHECKPOINTS: usize = 4;
+
+/// Size of the pages of the backend stored by checkpoints
+const PAGE_SIZE: usize = 4096;
+
+const ZERO_PAGE: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
+
+/// Pages of the backend which aren't zero, by page number
+struct Checkpoint(BTreeMap<usize, Rc<[u8]>>);
+
+impl Checkpoint {
+    /// Take a checkpoint of `backend`, sharing the pages which are unchanged
+    /// since `previous`.
+    fn take(backend: &InterpreterBackend, previous: Option<&Checkpoint>) -> Self {
+        let pages = backend
+            .borrow()
+            .chunks(PAGE_SIZE)
+            .enumerate()
+            .filter(|(_, page)| *page != &ZERO_PAGE[..page.len()])
+            .map(|(number, page)| {
+                let unchanged = previous
+                    .and_then(|previous| previous.0.get(&number))
+                    .filter(|previous| previous[..] == *page);
+                let page = match unchanged {
+                    Some(previous) => previous.clone(),
+                    None => Rc::from(page),
+                };
+                (number, page)
+            })
+            .collect();
+        Self(pages)
+    }
+
+    /// Restore the state of `backend`. Only the pages which differ are
+    /// written.
+    fn restore(&self, backend: &mut InterpreterBackend) {
+        for (number, page) in backend.borrow_mut().chunks_mut(PAGE_SIZE).enumerate() {
+            let saved = match self.0.get(&number) {
+                Some(saved) => &saved[..],
+                None => &ZERO_PAGE[..page.len()],
+            };
+            if page != saved {
+                page.copy_from_slice(saved);
+            }
+        }
+    }
+}
+
+pub struct History {
+    /// Checkpoints by the number of steps executed before they were taken
+    checkpoints: BTreeMap<usize, Checkpoint>,
+
+    /// Number of steps between checkpoints
+    interval: usize,
+
+    /// Number of steps executed since the program started
+    steps: usize,
+}
+
+impl History {
+    /// Start the history at the current state in `backend`.
+    pub fn new(backend: &InterpreterBackend) -> Self {
+        Self {
+            checkpoints: BTreeMap::from([(0, Checkpoint::take(backend, None))]),
+            interval: CHECKPOINT_INTERVAL,
+            steps: 0,
+        }
+    }
+
+    /// Number of steps executed since the program started
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Take a checkpoint of the current state, unless one exists already.
+    fn checkpoint(&mut self, backend: &InterpreterBackend) {
+        if self.checkpoints.contains_key(&self.steps) {
+            return;
+        }
+
+        let previous = self.checkpoints.values().next_back();
+        let checkpoint = Checkpoint::take(backend, previous);
+        self.checkpoints.insert(self.steps, checkpoint);
+        if self.checkpoints.len() > MAX_CHECKPOINTS {
+            self.interval = self.interval.saturating_mul(2);
+            let interval = self.interval;
+            self.checkpoints.retain(|steps, _| steps % interval == 0);
+        }
+    }
+
+    /// Run for at most `max_steps` steps while `should_continue` holds for
+    /// the program counter, taking checkpoints along the way. The state in
+    /// `backend` is run by an interpreter bound to it until the next
+    /// checkpoint.
+    pub fn run(
+        &mut self,
+        backend: &mut InterpreterBackend,
+        max_steps: usize,
+        mut should_continue: impl FnMut(u64) -> bool,
+    ) -> InterpreterResult {
+        let mut done = 0;
+        loop {
+            let next_checkpoint = (self.steps / self.interval + 1).saturating_mul(self.interval);
+            let chunk = max_steps
+                .saturating_sub(done)
+                .min(next_checkpoint - self.steps);
+
+            let result = Interpreter::resume(backend)
+                .step_many(chunk, |m| should_continue(m.hart.pc.read()));
+            let steps = match result {
+                InterpreterResult::Running(steps)
+                | InterpreterResult::Exit { steps, .. }
+                | InterpreterResult::Exception(_, steps) => steps,
+            };
+            self.steps += steps;
+            done += steps;
+
+            if self.steps == next_checkpoint {
+                self.checkpoint(backend);
+            }
+
+            match result {
+                InterpreterResult::Running(steps) if steps == chunk && done < max_steps => {}
+                InterpreterResult::Running(_) => return InterpreterResult::Running(done),
+                InterpreterResult::Exit { code, .. } => {
+                    return InterpreterResult::Exit { code, steps: done }
+                }
+                InterpreterResult::Exception(exc, _) => {
+                    return InterpreterResult::Exception(exc, done)
+                }
+            }
+        }
+    }
+
+    /// Go back to the state after `target` steps, which must not lie in the
+    /// future.
+    pub fn rewind(&mut self, backend: &mut InterpreterBackend, target: usize) {
+        let (start, checkpoint) = self
+            .checkpoints
+            .range(..=target)
+            .next_back()
+            .expect("The initial checkpoint is always kept");
+        checkpoint.restore(backend);
+        self.steps = *start;
+
+        // Execution may stop early, e.g. after system calls which pause the
+        // evaluation.
+        while self.steps < target {
+            match self.run(backend, target - self.steps, |_| true) {
+                InterpreterResult::Running(steps) if steps > 0 => {}
+                _ => break,
+            }
+        }
+    }
+
+    /// Go back to the last state before the current one whose program
+    /// counter satisfies `stop`. Returns the number of steps executed until
+    /// that state, or [`None`] after going back to the start of the program
+    /// when there is no such state.
+    pub fn rewind_until(
+        &mut self,
+        backend: &mut InterpreterBackend,
+        mut stop: impl FnMut(u64) -> bool,
+    ) -> Option<usize> {
+        let mut end = self.steps;
+        let starts: Vec<usize> = self.checkpoints.range(..end).map(|(s, _)| *s).collect();
+
+        // Search the intervals between checkpoints, latest first.
+        for start in starts.into_iter().rev() {
+            self.rewind(backend, start);
+
+            let mut found = None;
+            while self.steps < end {
+                let result = if stop(Interpreter::resume(backend).read_pc()) {
+                    found = Some(self.steps);
+                    self.run(backend, 1, |_| true)
+                } else {
+                    self.run(backend, end - self.steps, |pc| !stop(pc))
+                };
+
+                match result {
+                    InterpreterResult::Running(steps) if steps > 0 => {}
+                    _ => break,
+                }
+            }
+
+            if let Some(target) = found {
+                self.rewind(backend, target);
+                return Some(target);
+            }
+            end = start;
+        }
+
+        self.rewind(backend, 0);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use risc_v_interpreter::machine_state::{
+        bus::{main_memory::M1G, start_of_main_memory},
+        registers::ra,
+    };
+
+    // ADDI ra, ra, 1
+    const INCREMENT_RA: u32 = 0x0010_8093;
+
+    #[test]
+    fn test_checkpoint_pages() {
+        let mut backend = Interpreter::create_backend();
+        let start = start_of_main_memory::<M1G>();
+
+        let first = Checkpoint::take(&backend, None);
+        assert!(first.0.is_empty());
+
+        let write = |backend: &mut InterpreterBackend, addr, data: &[u8]| {
+            Interpreter::resume(backend)
+                .write_memory(addr, data)
+                .unwrap()
+        };
+        let read = |backend: &mut InterpreterBackend, addr, len| {
+            Interpreter::resume(backend).read_memory(addr, len).unwrap()
+        };
+
+        write(&mut backend, start, &[1; 8]);
+        write(&mut backend, start + 0x10_0000, &[2]);
+        let second = Checkpoint::take(&backend, Some(&first));
+        assert_eq!(second.0.len(), 2);
+
+        // Unchanged pages are shared with the previous checkpoint.
+        write(&mut backend, start + 0x10_0000, &[3]);
+        let third = Checkpoint::take(&backend, Some(&second));
+        let shared = (second.0.values().zip(third.0.values()))
+            .filter(|(a, b)| Rc::ptr_eq(a, b))
+            .count();
+        assert_eq!(shared, 1);
+
+        // Restoring clears the pages which were zero.
+        first.restore(&mut backend);
+        assert_eq!(read(&mut backend, start, 8), [0; 8]);
+        assert_eq!(read(&mut backend, start + 0x10_0000, 1), [0]);
+
+        second.restore(&mut backend);
+        assert_eq!(read(&mut backend, start, 8), [1; 8]);
+        assert_eq!(read(&mut backend, start + 0x10_0000, 1), [2]);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut backend = Interpreter::create_backend();
+        let start = start_of_main_memory::<M1G>();
+
+        {
+            let mut interpreter = Interpreter::resume(&mut backend);
+            for index in 0..16 {
+                interpreter
+                    .write_memory(start + index * 4, &INCREMENT_RA.to_le_bytes())
+                    .unwrap();
+            }
+            interpreter.write_pc(start);
+        }
+
+        let mut history = History::new(&backend);
+        assert!(matches!(
+            history.run(&mut backend, 10, |_| true),
+            InterpreterResult::Running(10)
+        ));
+        assert_eq!(history.steps(), 10);
+
+        history.rewind(&mut backend, 3);
+        assert_eq!(history.steps(), 3);
+        {
+            let interpreter = Interpreter::resume(&mut backend);
+            assert_eq!(interpreter.read_xregister(ra), 3);
+            assert_eq!(interpreter.read_pc(), start + 12);
+        }
+
+        // Going back to the last step at a given address
+        history.run(&mut backend, 5, |_| true);
+        assert_eq!(
+            history.rewind_until(&mut backend, |pc| pc == start + 4 * 6),
+            Some(6)
+        );
+        assert_eq!(Interpreter::resume(&mut backend).read_xregister(ra), 6);
+        assert_eq!(
+            history.rewind_until(&mut backend, |pc| pc == start + 4 * 20),
+            None
+        );
+        assert_eq!(history.steps(), 0);
+    }
+}
@@ -0,0 +1,301 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Lock-step differential execution of the interpreter and rvemu
+//!
+//! Both emulators start from the state the interpreter boots into. After each
+//! step, the program counter, the privilege mode, the X and F registers and
+//! the memory written by the step are compared. Execution stops at the first
+//! divergence.
+//!
+//! System calls handled by the POSIX execution environment of the
+//! interpreter are not emulated in rvemu. Instead rvemu adopts their result.
+
+use crate::rvemu_boot::A0;
+use kernel_loader::Memory;
+use risc_v_interpreter::{
+    machine_state::{
+        bus,
+        mode::Mode,
+        registers::{self, parse_fregister, parse_xregister},
+    },
+    parser::{self, instruction::Instr},
+    Interpreter, InterpreterResult,
+};
+use rvemu::{
+    cpu::{self, AccessType, BYTE},
+    emulator::Emulator,
+    exception::Exception,
+};
+use std::error::Error;
+
+/// Result of stepping both emulators
+type DiffResult<T> = Result<T, Box<dyn Error>>;
+
+/// Convert an rvemu exception into an error.
+fn rvemu_error(exc: Exception) -> Box<dyn Error> {
+    format!("rvemu: {:?}", exc).into()
+}
+
+/// Convert the privilege mode of rvemu into the one of the interpreter.
+fn rvemu_mode(mode: cpu::Mode) -> DiffResult<Mode> {
+    match mode {
+        cpu::Mode::User => Ok(Mode::User),
+        cpu::Mode::Supervisor => Ok(Mode::Supervisor),
+        cpu::Mode::Machine => Ok(Mode::Machine),
+        mode => Err(format!("rvemu: unsupported mode {:?}", mode).into()),
+    }
+}
+
+/// Convert the privilege mode of the interpreter into the one of rvemu.
+fn interpreter_mode(mode: Mode) -> cpu::Mode {
+    match mode {
+        Mode::User => cpu::Mode::User,
+        Mode::Supervisor => cpu::Mode::Supervisor,
+        Mode::Machine => cpu::Mode::Machine,
+    }
+}
+
+/// Read a series of bytes from the physical memory of rvemu.
+fn rvemu_read_memory(emu: &mut Emulator, addr: u64, len: usize) -> DiffResult<Vec<u8>> {
+    (0..len as u64)
+        .map(|offset| {
+            emu.cpu
+                .bus
+                .read(addr.wrapping_add(offset), BYTE)
+                .map(|byte| byte as u8)
+                .map_err(rvemu_error)
+        })
+        .collect()
+}
+
+/// Boot rvemu into the state the interpreter has booted into. This copies the
+/// memory holding the program, the initial ramdisk and the device tree.
+fn sync_rvemu(emu: &mut Emulator, interpreter: &Interpreter) -> DiffResult<()> {
+    let start = bus::start_of_main_memory::<bus::main_memory::M1G>();
+
+    // The device tree is placed last. Its total size is a big-endian integer
+    // at offset 4.
+    let dtb_addr = interpreter.read_xregister(registers::a1);
+    let dtb_size = interpreter.read_memory(dtb_addr + 4, 4)?;
+    let dtb_size = u32::from_be_bytes(dtb_size.as_slice().try_into()?);
+    let end = dtb_addr + dtb_size as u64;
+
+    let contents = interpreter.read_memory(start, (end - start) as usize)?;
+    emu.cpu.bus.write_bytes(start, &contents)?;
+
+    for index in 1..32 {
+        let value = interpreter.read_xregister(parse_xregister(index));
+        emu.cpu.xregs.write(index as u64, value);
+    }
+
+    emu.initialize_pc(interpreter.read_pc());
+    emu.cpu.mode = interpreter_mode(interpreter.read_mode());
+
+    Ok(())
+}
+
+/// Decode the instruction rvemu is about to execute.
+fn next_instr(emu: &mut Emulator, interpreter: &Interpreter) -> DiffResult<Instr> {
+    let mut read_half = |addr: u64| -> DiffResult<u16> {
+        let addr = emu
+            .cpu
+            .translate(addr, AccessType::Instruction)
+            .map_err(rvemu_error)?;
+        let bytes = interpreter.read_memory(addr, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+
+    let pc = interpreter.read_pc();
+    let lower = read_half(pc)?;
+    parser::parse(lower, || read_half(pc + 2))
+}
+
+/// Virtual address and width of the memory written by `instr`
+fn store_target(instr: &Instr, interpreter: &Interpreter) -> Option<(u64, usize)> {
+    let target = |rs1, imm: i64, width| {
+        let base = interpreter.read_xregister(rs1);
+        Some((base.wrapping_add(imm as u64), width))
+    };
+
+    match instr {
+        Instr::Sb(args) => target(args.rs1, args.imm, 1),
+        Instr::Sh(args) => target(args.rs1, args.imm, 2),
+        Instr::Sw(args) => target(args.rs1, args.imm, 4),
+        Instr::Sd(args) => target(args.rs1, args.imm, 8),
+        Instr::Fsw(args) => target(args.rs1, args.imm, 4),
+        Instr::Fsd(args) => target(args.rs1, args.imm, 8),
+        Instr::Amoswapw(args)
+        | Instr::Amoaddw(args)
+        | Instr::Amoxorw(args)
+        | Instr::Amoandw(args)
+        | Instr::Amoorw(args)
+        | Instr::Amominw(args)
+        | Instr::Amomaxw(args)
+        | Instr::Amominuw(args)
+        | Instr::Amomaxuw(args) => target(args.rs1, 0, 4),
+        _ => None,
+    }
+}
+
+/// Describe the differences between the states of both emulators.
+fn compare(
+    emu: &mut Emulator,
+    interpreter: &Interpreter,
+    store: Option<(u64, usize)>,
+) -> DiffResult<Vec<String>> {
+    let mut diffs = Vec::new();
+
+    let mut diff = |name: String, ours: u64, theirs: u64| {
+        if ours != theirs {
+            diffs.push(format!("{name}: interpreter {ours:#x}, rvemu {theirs:#x}"));
+        }
+    };
+
+    diff("pc".to_owned(), interpreter.read_pc(), emu.cpu.pc);
+
+    let mode = rvemu_mode(emu.cpu.mode)?;
+    diff(
+        "mode".to_owned(),
+        interpreter.read_mode() as u64,
+        mode as u64,
+    );
+
+    for index in 1..32 {
+        let reg = parse_xregister(index);
+        let ours = interpreter.read_xregister(reg);
+        let theirs = emu.cpu.xregs.read(index as u64);
+        diff(reg.to_string(), ours, theirs);
+    }
+
+    for index in 0..32 {
+        let reg = parse_fregister(index);
+        let ours = u64::from(interpreter.read_fregister(reg));
+        let theirs = emu.cpu.fregs.read(index as u64).to_bits();
+        diff(format!("{reg:?}"), ours, theirs);
+    }
+
+    if let Some((v_addr, width)) = store {
+        // A store that faulted on both sides leaves the memory untouched.
+        if let Ok(p_addr) = emu.cpu.translate(v_addr, AccessType::Store) {
+            let ours = interpreter.read_memory(p_addr, width)?;
+            let theirs = rvemu_read_memory(emu, p_addr, width)?;
+            if ours != theirs {
+                diffs.push(format!(
+                    "memory at {p_addr:#x}: interpreter {ours:02x?}, rvemu {theirs:02x?}"
+                ));
+            }
+        }
+    }
+
+    Ok(diffs)
+}
+
+/// Step rvemu once like the interpreter steps once. `result` is the result of
+/// the interpreter's step. Returns whether the program exited.
+fn step_rvemu(
+    emu: &mut Emulator,
+    interpreter: &Interpreter,
+    result: &InterpreterResult,
+    exit_mode: Mode,
+) -> DiffResult<bool> {
+    emu.cpu.devices_increment();
+
+    if let Some(interrupt) = emu.cpu.check_pending_interrupt() {
+        interrupt.take_trap(&mut emu.cpu);
+    }
+
+    let mode = rvemu_mode(emu.cpu.mode)?;
+    match emu.cpu.execute() {
+        Ok(_) => Ok(false),
+
+        Err(
+            Exception::EnvironmentCallFromUMode
+            | Exception::EnvironmentCallFromSMode
+            | Exception::EnvironmentCallFromMMode,
+        ) if mode == exit_mode => match result {
+            InterpreterResult::Exit { .. } => Ok(true),
+
+            // Adopt the result of the system call handled by the interpreter.
+            _ => {
+                let a0 = interpreter.read_xregister(registers::a0);
+                emu.cpu.xregs.write(A0, a0);
+                emu.cpu.pc += 4;
+                Ok(false)
+            }
+        },
+
+        Err(exception) => {
+            exception.take_trap(&mut emu.cpu);
+            Ok(false)
+        }
+    }
+}
+
+/// Run `program` with the interpreter and rvemu in lock-step for at most
+/// `max_steps` steps.
+pub fn diff(
+    program: &[u8],
+    initrd: Option<&[u8]>,
+    exit_mode: Mode,
+    max_steps: usize,
+) -> DiffResult<()> {
+    let mut backend = Interpreter::create_backend();
+    let mut interpreter = Interpreter::new(&mut backend, program, initrd, exit_mode)?;
+
+    let mut emu = Emulator::new();
+    sync_rvemu(&mut emu, &interpreter)?;
+
+    let initial = compare(&mut emu, &interpreter, None)?;
+    if !initial.is_empty() {
+        return Err(format!("Initial states differ:\n  {}", initial.join("\n  ")).into());
+    }
+
+    for step in 1..=max_steps {
+        let pc = interpreter.read_pc();
+        let instr = next_instr(&mut emu, &interpreter).ok();
+        let store = instr
+            .as_ref()
+            .and_then(|instr| store_target(instr, &interpreter));
+
+        let result = interpreter.step_many(1, |_| true);
+        if let InterpreterResult::Exception(exc, _) = &result {
+            return Err(format!("Interpreter: unhandled {exc:?} at {pc:#x}").into());
+        }
+
+        let exited = step_rvemu(&mut emu, &interpreter, &result, exit_mode)?;
+        match (&result, exited) {
+            (InterpreterResult::Exit { code, .. }, true) => {
+                println!("Both exited with code {code} after {step} steps without divergence");
+                return Ok(());
+            }
+            (InterpreterResult::Exit { code, .. }, false) => {
+                return Err(format!(
+                    "Divergence at step {step}: the interpreter exited with code {code} at {pc:#x}, rvemu did not"
+                )
+                .into());
+            }
+            (_, true) => {
+                return Err(format!(
+                    "Divergence at step {step}: rvemu exited at {pc:#x}, the interpreter did not"
+                )
+                .into());
+            }
+            _ => {}
+        }
+
+        let diffs = compare(&mut emu, &interpreter, store)?;
+        if !diffs.is_empty() {
+            let instr = instr.map_or("<unknown>".to_owned(), |instr| instr.to_string());
+            return Err(format!(
+                "Divergence at step {step} after executing {instr} at {pc:#x}:\n  {}",
+                diffs.join("\n  ")
+            )
+            .into());
+        }
+    }
+
+    println!("No divergence in {max_steps} steps");
+    Ok(())
+}
@@ -0,0 +1,667 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! GDB remote serial protocol server
+//!
+//! The server lets GDB debug a program running in the interpreter. It accepts
+//! a single connection and supports:
+//!
+//! - reading and writing registers and memory,
+//! - software and hardware breakpoints,
+//! - single-stepping and continuing, which can be interrupted with Ctrl-C,
+//! - write watchpoints.
+//!
+//! Memory addresses are virtual addresses, translated in the current
+//! privilege mode of the hart. Breakpoints are kept by the server rather than
+//! patched into memory. Watchpoints are software watchpoints: they trigger
+//! when a step changes the watched memory.
+//!
+//! See <https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html>.
+
+use risc_v_interpreter::{
+    machine_state::{
+        csregisters::CSRegister,
+        registers::{parse_fregister, parse_xregister, FValue},
+        AccessType,
+    },
+    Interpreter, InterpreterResult,
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+/// Number of steps to run between checks for an interrupt from GDB
+const CONTINUE_CHUNK: usize = 10_000;
+
+/// Register number of the program counter
+const PC_REGNUM: usize = 32;
+
+/// Register number of `f0`
+const FIRST_FREG_REGNUM: usize = 33;
+
+/// Register numbers of the floating-point CSRs, which GDB places after the
+/// CSR base register number 65
+const FFLAGS_REGNUM: usize = 66;
+const FRM_REGNUM: usize = 67;
+const FCSR_REGNUM: usize = 68;
+
+/// Error reply for requests that fail
+const REPLY_ERROR: &str = "E01";
+
+/// Byte sent by GDB to interrupt the target
+const INTERRUPT: u8 = 0x03;
+
+/// Target description advertising the registers of an RV64 hart with the F
+/// and D extensions
+fn target_description() -> String {
+    let xregs: String = (0..32)
+        .map(|i| format!(r#"<reg name="x{i}" bitsize="64" type="int" regnum="{i}"/>"#))
+        .collect();
+    let fregs: String = (0..32)
+        .map(|i| {
+            let regnum = FIRST_FREG_REGNUM + i;
+            format!(r#"<reg name="f{i}" bitsize="64" type="ieee_double" regnum="{regnum}"/>"#)
+        })
+        .collect();
+
+    format!(
+        r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+<architecture>riscv:rv64</architecture>
+<feature name="org.gnu.gdb.riscv.cpu">
+{xregs}
+<reg name="pc" bitsize="64" type="code_ptr" regnum="{PC_REGNUM}"/>
+</feature>
+<feature name="org.gnu.gdb.riscv.fpu">
+{fregs}
+<reg name="fflags" bitsize="32" type="int" regnum="{FFLAGS_REGNUM}"/>
+<reg name="frm" bitsize="32" type="int" regnum="{FRM_REGNUM}"/>
+<reg name="fcsr" bitsize="32" type="int" regnum="{FCSR_REGNUM}"/>
+</feature>
+</target>
+"#
+    )
+}
+
+/// Message received from GDB
+enum Message {
+    /// Packet with the given contents
+    Packet(String),
+
+    /// Request to interrupt the running target
+    Interrupt,
+}
+
+/// Connection to GDB
+struct Connection {
+    stream: TcpStream,
+    no_ack: bool,
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0u8];
+        self.stream.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    /// Receive the next message. Returns `None` once GDB has disconnected.
+    fn receive(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            let byte = match self.read_byte() {
+                Ok(byte) => byte,
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err),
+            };
+
+            match byte {
+                b'$' => {}
+                INTERRUPT => return Ok(Some(Message::Interrupt)),
+                // Acknowledgements and stray bytes are ignored.
+                _ => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let expected = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                == Some(expected);
+
+            if !self.no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+
+            if valid {
+                let packet = String::from_utf8_lossy(&data).into_owned();
+                return Ok(Some(Message::Packet(packet)));
+            }
+        }
+    }
+
+    /// Send a packet with the given contents.
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${data}#{checksum:02x}")?;
+        self.stream.flush()
+    }
+
+    /// Check whether GDB has requested an interrupt without blocking.
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0u8];
+        let result = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(1) => Ok(byte[0] == INTERRUPT),
+            Ok(_) => Ok(false),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Parse a hexadecimal number.
+fn parse_hex(data: &str) -> Option<u64> {
+    u64::from_str_radix(data, 16).ok()
+}
+
+/// Parse `addr,len` arguments.
+fn parse_range(data: &str) -> Option<(u64, u64)> {
+    let (addr, len) = data.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+/// Encode a register value in target byte order.
+fn encode_register(value: u64, bytes: usize) -> String {
+    hex::encode(&value.to_le_bytes()[..bytes])
+}
+
+/// Decode a register value given in target byte order.
+fn decode_register(data: &str) -> Option<u64> {
+    let bytes = hex::decode(data).ok()?;
+    let mut value = [0u8; 8];
+    value.get_mut(..bytes.len())?.copy_from_slice(&bytes);
+    Some(u64::from_le_bytes(value))
+}
+
+/// GDB server for a program running in the interpreter
+pub struct GdbServer<'a, 'b> {
+    interpreter: &'b mut Interpreter<'a>,
+    breakpoints: HashSet<u64>,
+
+    /// Write watchpoints with the length and the last seen contents of the
+    /// watched memory
+    watchpoints: BTreeMap<u64, (u64, Option<Vec<u8>>)>,
+
+    /// Exit code of the program once it has exited
+    exit_code: Option<usize>,
+
+    /// Number of bytes of the program output already forwarded
+    stdout_len: usize,
+    stderr_len: usize,
+}
+
+impl<'a, 'b> GdbServer<'a, 'b> {
+    pub fn new(interpreter: &'b mut Interpreter<'a>) -> Self {
+        Self {
+            interpreter,
+            breakpoints: HashSet::new(),
+            watchpoints: BTreeMap::new(),
+            exit_code: None,
+            stdout_len: 0,
+            stderr_len: 0,
+        }
+    }
+
+    /// Wait for GDB to connect on `address` and serve its requests until it
+    /// disconnects.
+    pub fn serve(&mut self, address: &str) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind(address)?;
+        eprintln!("Waiting for GDB to connect on {}", listener.local_addr()?);
+
+        let (stream, peer) = listener.accept()?;
+        eprintln!("GDB connected from {peer}");
+        stream.set_nodelay(true)?;
+
+        let mut conn = Connection {
+            stream,
+            no_ack: false,
+        };
+
+        while let Some(message) = conn.receive()? {
+            let packet = match message {
+                Message::Packet(packet) => packet,
+                // The target is already stopped.
+                Message::Interrupt => continue,
+            };
+
+            let reply = match packet.as_bytes().first() {
+                Some(b'k') => return Ok(()),
+                Some(b'D') => {
+                    conn.send("OK")?;
+                    return Ok(());
+                }
+                Some(b'c') => self.resume(&mut conn, &packet[1..], false)?,
+                Some(b's') => self.resume(&mut conn, &packet[1..], true)?,
+                _ => self.handle_query(&packet),
+            };
+
+            conn.send(&reply)?;
+
+            if packet == "QStartNoAckMode" {
+                conn.no_ack = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reply to a packet which doesn't resume execution.
+    fn handle_query(&mut self, packet: &str) -> String {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => Some(self.stop_reply("S05")),
+            Some(b'g') => Some(self.read_registers()),
+            Some(b'G') => self.write_registers(&packet[1..]),
+            Some(b'p') => self.read_register(&packet[1..]),
+            Some(b'P') => self.write_register(&packet[1..]),
+            Some(b'm') => self.read_memory(&packet[1..]),
+            Some(b'M') => self.write_memory(&packet[1..]),
+            Some(b'Z') => self.insert_point(&packet[1..]),
+            Some(b'z') => self.remove_point(&packet[1..]),
+            Some(b'H') => Some("OK".to_owned()),
+            Some(b'T') => Some("OK".to_owned()),
+            Some(b'q') | Some(b'Q') => self.handle_general_query(packet),
+            _ => Some(String::new()),
+        };
+
+        reply.unwrap_or_else(|| REPLY_ERROR.to_owned())
+    }
+
+    /// Reply to general queries and settings.
+    fn handle_general_query(&mut self, packet: &str) -> Option<String> {
+        if packet.starts_with("qSupported") {
+            return Some(
+                "PacketSize=4000;QStartNoAckMode+;swbreak+;hwbreak+;qXfer:features:read+"
+                    .to_owned(),
+            );
+        }
+
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = parse_range(args)?;
+            let description = target_description();
+            let start = (offset as usize).min(description.len());
+            let end = start.saturating_add(length as usize).min(description.len());
+            let marker = if end == description.len() { 'l' } else { 'm' };
+            return Some(format!("{marker}{}", &description[start..end]));
+        }
+
+        let reply = match packet {
+            "QStartNoAckMode" => "OK",
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _ => "",
+        };
+        Some(reply.to_owned())
+    }
+
+    /// Reply for a stop, or the exit reply if the program has exited
+    fn stop_reply(&self, reply: &str) -> String {
+        match self.exit_code {
+            Some(code) => format!("W{:02x}", code as u8),
+            None => reply.to_owned(),
+        }
+    }
+
+    /// Forward the output of the program to the standard output and error of
+    /// the server.
+    fn forward_output(&mut self) -> io::Result<()> {
+        io::stdout().write_all(&self.interpreter.take_console())?;
+
+        let stdout = &self.interpreter.stdout()[self.stdout_len..];
+        io::stdout().write_all(stdout)?;
+        self.stdout_len += stdout.len();
+
+        let stderr = &self.interpreter.stderr()[self.stderr_len..];
+        io::stderr().write_all(stderr)?;
+        self.stderr_len += stderr.len();
+
+        Ok(())
+    }
+
+    /// Resume execution until a breakpoint or watchpoint is hit, GDB
+    /// interrupts, or after one step if `single_step` is set. `addr`
+    /// optionally gives the address to resume at.
+    fn resume(
+        &mut self,
+        conn: &mut Connection,
+        addr: &str,
+        single_step: bool,
+    ) -> io::Result<String> {
+        if self.exit_code.is_some() {
+            return Ok(self.stop_reply("S05"));
+        }
+
+        if let Some(addr) = parse_hex(addr) {
+            self.interpreter.write_pc(addr);
+        }
+
+        // Breakpoints at the resumption address are only hit once the
+        // program gets back to it.
+        let mut leaving = true;
+
+        let reply = loop {
+            let max = if single_step || !self.watchpoints.is_empty() {
+                1
+            } else {
+                CONTINUE_CHUNK
+            };
+
+            let breakpoints = &self.breakpoints;
+            let result = self.interpreter.step_many(max, |machine| {
+                std::mem::take(&mut leaving) || !breakpoints.contains(&machine.hart.pc.read())
+            });
+
+            match result {
+                InterpreterResult::Exit { code, .. } => {
+                    self.exit_code = Some(code);
+                    break self.stop_reply("S05");
+                }
+                InterpreterResult::Exception(exc, _) => {
+                    eprintln!("Unhandled {exc:?} at {:#x}", self.interpreter.read_pc());
+                    break "S05".to_owned();
+                }
+                InterpreterResult::Running(_) => {}
+            }
+
+            if let Some(addr) = self.triggered_watchpoint() {
+                break format!("T05watch:{addr:x};");
+            }
+
+            if single_step {
+                break "S05".to_owned();
+            }
+
+            if self.breakpoints.contains(&self.interpreter.read_pc()) {
+                break "T05swbreak:;".to_owned();
+            }
+
+            if conn.interrupt_requested()? {
+                break "S02".to_owned();
+            }
+        };
+
+        self.forward_output()?;
+        Ok(reply)
+    }
+
+    /// Contents of `len` bytes of memory at virtual address `addr`
+    fn memory(&self, addr: u64, len: u64) -> Option<Vec<u8>> {
+        // The length comes from GDB: memory is only allocated for the bytes
+        // that could actually be read.
+        let mut contents = Vec::new();
+        for offset in 0..len {
+            let v_addr = addr.wrapping_add(offset);
+            let p_addr = self.interpreter.translate(v_addr, AccessType::Load).ok()?;
+            let byte = self.interpreter.read_memory(p_addr, 1).ok()?;
+            contents.extend(byte);
+        }
+        Some(contents)
+    }
+
+    /// Find a watchpoint whose memory changed since it was last checked.
+    fn triggered_watchpoint(&mut self) -> Option<u64> {
+        let mut triggered = None;
+        let ranges: Vec<(u64, u64)> = self
+            .watchpoints
+            .iter()
+            .map(|(addr, (len, _))| (*addr, *len))
+            .collect();
+
+        for (addr, len) in ranges {
+            let contents = self.memory(addr, len);
+            if let Some((_, last)) = self.watchpoints.get_mut(&addr) {
+                if *last != contents {
+                    *last = contents;
+                    triggered = triggered.or(Some(addr));
+                }
+            }
+        }
+
+        triggered
+    }
+
+    fn read_registers(&self) -> String {
+        let mut reply = encode_register(0, 8);
+        for index in 1..32 {
+            let value = self.interpreter.read_xregister(parse_xregister(index));
+            reply.push_str(&encode_register(value, 8));
+        }
+        reply.push_str(&encode_register(self.interpreter.read_pc(), 8));
+        reply
+    }
+
+    fn write_registers(&mut self, data: &str) -> Option<String> {
+        // Only the registers read by `g` are written.
+        for (index, value) in data.as_bytes().chunks(16).enumerate().take(PC_REGNUM + 1) {
+            let value = decode_register(std::str::from_utf8(value).ok()?)?;
+            self.set_register(index, value)?;
+        }
+        Some("OK".to_owned())
+    }
+
+    fn read_register(&self, data: &str) -> Option<String> {
+        let regnum = parse_hex(data)? as usize;
+        let reply = match regnum {
+            0 => encode_register(0, 8),
+            1..=31 => {
+                let value = self
+                    .interpreter
+                    .read_xregister(parse_xregister(regnum as u32));
+                encode_register(value, 8)
+            }
+            PC_REGNUM => encode_register(self.interpreter.read_pc(), 8),
+            FIRST_FREG_REGNUM..=64 => {
+                let reg = parse_fregister((regnum - FIRST_FREG_REGNUM) as u32);
+                encode_register(self.interpreter.read_fregister(reg).into(), 8)
+            }
+            FFLAGS_REGNUM => {
+                encode_register(self.interpreter.read_csregister(CSRegister::fflags), 4)
+            }
+            FRM_REGNUM => encode_register(self.interpreter.read_csregister(CSRegister::frm), 4),
+            FCSR_REGNUM => encode_register(self.interpreter.read_csregister(CSRegister::fcsr), 4),
+            // Unknown registers are unavailable.
+            _ => "xxxxxxxxxxxxxxxx".to_owned(),
+        };
+        Some(reply)
+    }
+
+    fn write_register(&mut self, data: &str) -> Option<String> {
+        let (regnum, value) = data.split_once('=')?;
+        self.set_register(parse_hex(regnum)? as usize, decode_register(value)?)?;
+        Some("OK".to_owned())
+    }
+
+    /// Write to the register with number `regnum`. Only X and F registers
+    /// and the program counter can be written.
+    fn set_register(&mut self, regnum: usize, value: u64) -> Option<()> {
+        match regnum {
+            // Writes to x0 are ignored.
+            0 => {}
+            1..=31 => self
+                .interpreter
+                .write_xregister(parse_xregister(regnum as u32), value),
+            PC_REGNUM => self.interpreter.write_pc(value),
+            FIRST_FREG_REGNUM..=64 => {
+                let reg = parse_fregister((regnum - FIRST_FREG_REGNUM) as u32);
+                self.interpreter.write_fregister(reg, FValue::from(value))
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn read_memory(&self, data: &str) -> Option<String> {
+        let (addr, len) = parse_range(data)?;
+        self.memory(addr, len).map(hex::encode)
+    }
+
+    fn write_memory(&mut self, data: &str) -> Option<String> {
+        let (range, contents) = data.split_once(':')?;
+        let (addr, len) = parse_range(range)?;
+        let contents = hex::decode(contents).ok()?;
+        if contents.len() as u64 != len {
+            return None;
+        }
+
+        for (offset, byte) in contents.iter().enumerate() {
+            let v_addr = addr.wrapping_add(offset as u64);
+            let p_addr = self.interpreter.translate(v_addr, AccessType::Store).ok()?;
+            self.interpreter.write_memory(p_addr, &[*byte]).ok()?;
+        }
+        Some("OK".to_owned())
+    }
+
+    /// Insert a breakpoint or watchpoint given as `type,addr,kind`.
+    fn insert_point(&mut self, data: &str) -> Option<String> {
+        let (kind, range) = data.split_once(',')?;
+        let (addr, len) = parse_range(range)?;
+        match kind {
+            // Software and hardware breakpoints
+            "0" | "1" => {
+                self.breakpoints.insert(addr);
+            }
+            // Write watchpoints
+            "2" => {
+                let contents = self.memory(addr, len);
+                self.watchpoints.insert(addr, (len, contents));
+            }
+            // Read and access watchpoints are not supported.
+            _ => return Some(String::new()),
+        }
+        Some("OK".to_owned())
+    }
+
+    /// Remove a breakpoint or watchpoint given as `type,addr,kind`.
+    fn remove_point(&mut self, data: &str) -> Option<String> {
+        let (kind, range) = data.split_once(',')?;
+        let (addr, _) = parse_range(range)?;
+        match kind {
+            "0" | "1" => {
+                self.breakpoints.remove(&addr);
+            }
+            "2" => {
+                self.watchpoints.remove(&addr);
+            }
+            _ => return Some(String::new()),
+        }
+        Some("OK".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use risc_v_interpreter::machine_state::bus::{main_memory::M1G, start_of_main_memory};
+
+    #[test]
+    fn test_read_memory() {
+        let mut backend = Interpreter::create_backend();
+        let mut interpreter = Interpreter::resume(&mut backend);
+        let mut server = GdbServer::new(&mut interpreter);
+        let addr = start_of_main_memory::<M1G>();
+
+        assert_eq!(server.handle_query(&format!("M{addr:x},4:01020304")), "OK");
+        assert_eq!(server.handle_query(&format!("m{addr:x},4")), "01020304");
+        assert_eq!(server.handle_query(&format!("m{addr:x},0")), "");
+
+        // Huge lengths requested by GDB fail once memory runs out.
+        assert_eq!(
+            server.handle_query("mffffffffffffff00,ffffffffffffffff"),
+            REPLY_ERROR
+        );
+        assert_eq!(server.handle_query("m0,"), REPLY_ERROR);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut backend = Interpreter::create_backend();
+        let mut interpreter = Interpreter::resume(&mut backend);
+        let mut server = GdbServer::new(&mut interpreter);
+        let addr = start_of_main_memory::<M1G>();
+
+        assert_eq!(server.handle_query(&format!("Z2,{addr:x},8")), "OK");
+        assert_eq!(server.triggered_watchpoint(), None);
+
+        // Writes within the watched range trigger the watchpoint once.
+        server.interpreter.write_memory(addr + 4, &[1]).unwrap();
+        assert_eq!(server.triggered_watchpoint(), Some(addr));
+        assert_eq!(server.triggered_watchpoint(), None);
+
+        // Writes outside of the watched range are ignored.
+        server.interpreter.write_memory(addr + 8, &[1]).unwrap();
+        assert_eq!(server.triggered_watchpoint(), None);
+
+        assert_eq!(server.handle_query(&format!("z2,{addr:x},8")), "OK");
+        server.interpreter.write_memory(addr, &[1]).unwrap();
+        assert_eq!(server.triggered_watchpoint(), None);
+
+        // Watchpoints over unmapped memory can be set, but never trigger.
+        assert_eq!(
+            server.handle_query("Z2,ffffffffffffff00,ffffffffffffffff"),
+            "OK"
+        );
+        assert_eq!(server.triggered_watchpoint(), None);
+
+        // Read and access watchpoints are not supported.
+        assert_eq!(server.handle_query(&format!("Z3,{addr:x},8")), "");
+        assert_eq!(server.handle_query(&format!("Z4,{addr:x},8")), "");
+    }
+
+    #[test]
+    fn test_target_description() {
+        let mut backend = Interpreter::create_backend();
+        let mut interpreter = Interpreter::resume(&mut backend);
+        let mut server = GdbServer::new(&mut interpreter);
+        let description = target_description();
+
+        assert_eq!(
+            server.handle_query("qXfer:features:read:target.xml:0,10"),
+            format!("m{}", &description[..0x10])
+        );
+
+        // Reads reaching past the end are truncated, even when the length
+        // would overflow.
+        let offset = description.len() - 4;
+        assert_eq!(
+            server.handle_query(&format!(
+                "qXfer:features:read:target.xml:{offset:x},ffffffffffffffff"
+            )),
+            format!("l{}", &description[offset..])
+        );
+        assert_eq!(
+            server.handle_query("qXfer:features:read:target.xml:ffffffffffffffff,10"),
+            "l"
+        );
+        assert_eq!(
+            server.handle_query("qXfer:features:read:target.xml:0"),
+            REPLY_ERROR
+        );
+    }
+}
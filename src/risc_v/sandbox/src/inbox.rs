@@ -1,12 +1,55 @@
 use mavryk_crypto_rs::hash::{BlockHash, ContractKt1Hash};
+use mavryk_data_encoding::nom::NomReader;
 use mavryk_smart_rollup_encoding::{
     inbox::{InboxMessage, InfoPerLevel, InternalInboxMessage, Transfer},
-    michelson,
+    michelson::{self, MichelsonExpr},
     public_key_hash::PublicKeyHash,
     smart_rollup::SmartRollupAddress,
     timestamp::Timestamp,
 };
-use std::collections::LinkedList;
+use serde::Deserialize;
+use std::{collections::LinkedList, error::Error, path::Path};
+
+/// Message of an inbox file
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FileMessage {
+    /// External message with hex-encoded contents
+    External { external: String },
+
+    /// Transfer from a smart contract
+    Transfer { transfer: FileTransfer },
+}
+
+/// Transfer of an inbox file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileTransfer {
+    /// Hex-encoded binary Micheline payload
+    payload: String,
+
+    /// Sending smart contract
+    sender: String,
+
+    /// Implicit account originating the transfer
+    source: String,
+
+    /// Destination rollup, defaults to the rollup being run
+    destination: Option<String>,
+}
+
+/// Decode a hex string found in an inbox file.
+fn decode_hex(data: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    hex::decode(data).map_err(|err| format!("Invalid hex string {data:?}: {err}").into())
+}
+
+/// Decode a hex-encoded binary Micheline expression found in an inbox file.
+fn decode_micheline(data: &str) -> Result<MichelsonExpr, Box<dyn Error>> {
+    match MichelsonExpr::nom_read(&decode_hex(data)?) {
+        Ok(([], expr)) => Ok(expr),
+        _ => Err(format!("Invalid Micheline expression {data:?}").into()),
+    }
+}
 
 /// Inbox builder
 pub struct InboxBuilder {
@@ -84,12 +127,12 @@ impl InboxBuilder {
     }
 
     /// Inject a transfer notification.
-    pub fn insert_transfer<E: michelson::Michelson>(
+    pub fn insert_transfer(
         &mut self,
         sender: ContractKt1Hash,
         source: PublicKeyHash,
         destination: SmartRollupAddress,
-        payload: E,
+        payload: MichelsonExpr,
     ) -> &mut Self {
         let msg = InboxMessage::Internal(InternalInboxMessage::Transfer(Transfer {
             payload,
@@ -97,7 +140,6 @@ impl InboxBuilder {
             source,
             destination,
         }));
-
         let mut data = Vec::new();
         msg.serialize(&mut data)
             .expect("Failed to serialise transfer");
@@ -105,6 +147,62 @@ impl InboxBuilder {
         self.insert_raw(data)
     }
 
+    /// Inject the levels of an inbox file. The file contains a list of
+    /// levels, each of which is a list of messages. It is parsed as JSON if
+    /// its extension is `.json` and as YAML otherwise. Transfers without a
+    /// destination are addressed to `rollup`.
+    ///
+    /// ```yaml
+    /// - - external: "01020304"
+    ///   - transfer:
+    ///       payload: "030b" # Unit
+    ///       sender: KT1EfTusMLoeCAAGd9MZJn5yKzFr6kJU5U91
+    ///       source: mv1Q4RbUk6tX1J43AGg4ScnisAEhLnpHNyux
+    /// - - external: "0101"
+    /// ```
+    pub fn load_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        rollup: &SmartRollupAddress,
+    ) -> Result<&mut Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = std::fs::read(path)?;
+        let levels: Vec<Vec<FileMessage>> = match path.extension() {
+            Some(ext) if ext == "json" => serde_json::from_slice(&contents)?,
+            _ => serde_yaml::from_slice(&contents)?,
+        };
+
+        for (index, level) in levels.into_iter().enumerate() {
+            // The builder starts with an open level.
+            if index > 0 {
+                self.next_level();
+            }
+
+            for message in level {
+                match message {
+                    FileMessage::External { external } => {
+                        self.insert_external(decode_hex(&external)?);
+                    }
+
+                    FileMessage::Transfer { transfer } => {
+                        let destination = match transfer.destination {
+                            Some(destination) => SmartRollupAddress::from_b58check(&destination)?,
+                            None => rollup.clone(),
+                        };
+                        self.insert_transfer(
+                            ContractKt1Hash::from_base58_check(&transfer.sender)?,
+                            PublicKeyHash::from_b58check(&transfer.source)?,
+                            destination,
+                            decode_micheline(&transfer.payload)?,
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(self)
+    }
+
     /// Inject a raw message.
     fn insert_raw(&mut self, data: Vec<u8>) -> &mut Self {
         self.levels
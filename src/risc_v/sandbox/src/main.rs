@@ -3,21 +3,26 @@
 //
 // SPDX-License-Identifier: MIT
 
-use cli::Options;
-use mavryk_crypto_rs::hash::ContractKt1Hash;
-use mavryk_smart_rollup_encoding::{
-    michelson::MichelsonUnit, public_key_hash::PublicKeyHash, smart_rollup::SmartRollupAddress,
-};
+use cli::{GdbServerOptions, Options, ProfileOptions};
+use mavkit_risc_v_pvm::reveals::PreimageDirectory;
+use mavryk_smart_rollup_encoding::smart_rollup::SmartRollupAddress;
 use risc_v_interpreter::{
-    machine_state::mode::Mode, traps::EnvironException, Interpreter, InterpreterResult::*,
+    exec_env::sbi::{InboxMessage, RollupMetadata},
+    machine_state::mode::Mode,
+    traps::EnvironException,
+    ExecEnv, Interpreter,
+    InterpreterResult::*,
 };
 use rvemu::emulator::Emulator;
-use std::{error::Error, path::Path};
+use std::{error::Error, io::Write, path::Path};
 
 mod cli;
 mod debugger;
 mod devicetree;
+mod diff;
+mod gdbserver;
 mod inbox;
+mod profile;
 mod rvemu_boot;
 mod rvemu_syscall;
 
@@ -27,22 +32,99 @@ pub fn exception_to_error(exc: EnvironException) -> Box<dyn Error> {
 }
 
 fn run(opts: Options) -> Result<(), Box<dyn Error>> {
-    let contents = std::fs::read(&opts.input)?;
-    let mut backend = Interpreter::create_backend();
-    let mut interpreter = Interpreter::new(&mut backend, &contents, None, posix_exit_mode(opts))?;
+    // Reveal requests are served by the PVM, not by the execution
+    // environments of the interpreter.
+    if opts.preimages_dir.is_some() {
+        return Err("Reveals are not supported by the interpreter yet, use rvemu instead".into());
+    }
+
+    // Only the supervisor binary interface reads from the inbox.
+    if opts.inbox.is_some() && !opts.sbi {
+        return Err("An inbox can only be used with --sbi".into());
+    }
+
+    let address = SmartRollupAddress::from_b58check(opts.address.as_str())?;
+    let mut inbox = inbox::InboxBuilder::new();
+    if let Some(path) = &opts.inbox {
+        inbox.load_file(path, &address)?;
+    }
+    let mut inbox = inbox.build();
+
+    let mut backend = match &opts.load_snapshot {
+        Some(path) => Interpreter::load_snapshot(&std::fs::read(path)?)?,
+        None => Interpreter::create_backend(),
+    };
+
+    let (result, inbox_drained) = {
+        let mut interpreter = match &opts.load_snapshot {
+            Some(_) => Interpreter::resume(&mut backend),
+            None => {
+                let contents = std::fs::read(opts.input()?)?;
+                let initrd = opts.initrd.as_ref().map(std::fs::read).transpose()?;
+                let exec_env = if opts.sbi {
+                    ExecEnv::Sbi
+                } else {
+                    ExecEnv::Posix(posix_exit_mode(&opts))
+                };
+                Interpreter::new_with_exec_env(
+                    &mut backend,
+                    &contents,
+                    initrd.as_deref(),
+                    exec_env,
+                )?
+            }
+        };
+
+        interpreter.set_inbox(
+            std::iter::from_fn(|| inbox.next())
+                .map(|(level, id, payload)| InboxMessage { level, id, payload })
+                .collect(),
+        );
+        interpreter.set_rollup_metadata(RollupMetadata {
+            origination_level: opts.origination_level,
+            address: address.hash().as_ref().to_vec(),
+        });
+
+        // Reading from a drained inbox pauses the evaluation. Like in rvemu
+        // mode, stop once the inbox has been found drained twice.
+        let inbox_drained =
+            |interpreter: &Interpreter| !opts.keep_going && interpreter.inbox_none_count() >= 2;
+
+        let mut steps = 0;
+        let result = loop {
+            match interpreter.run(opts.max_steps - steps) {
+                Running(done) => {
+                    steps += done;
+                    if steps >= opts.max_steps || inbox_drained(&interpreter) {
+                        break Running(steps);
+                    }
+                }
+                result => break result,
+            }
+        };
+
+        std::io::stdout().write_all(interpreter.console())?;
+        std::io::stdout().write_all(interpreter.stdout())?;
+        std::io::stderr().write_all(interpreter.stderr())?;
+        (result, inbox_drained(&interpreter))
+    };
 
-    const MAX_STEPS: usize = 1000000;
+    if let Some(path) = &opts.save_snapshot {
+        std::fs::write(path, backend.snapshot())?;
+    }
 
-    match interpreter.run(MAX_STEPS) {
+    match result {
         Exit { code: 0, .. } => Ok(()),
         Exit { code, .. } => Err(format!("Failed with exit code {}", code).into()),
+        // Running out of steps is expected when pausing execution for a snapshot
+        Running(_) if opts.save_snapshot.is_some() || inbox_drained => Ok(()),
         Running(_) => Err("Timeout".into()),
         Exception(exc, _) => Err(exception_to_error(exc)),
     }
 }
 
 fn debug(opts: Options) -> Result<(), Box<dyn Error>> {
-    let path = Path::new(&opts.input);
+    let path = Path::new(opts.input()?);
     let fname = path
         .file_name()
         .ok_or("Invalid program path")?
@@ -60,11 +142,54 @@ fn debug(opts: Options) -> Result<(), Box<dyn Error>> {
     )?)
 }
 
+fn diff(opts: Options) -> Result<(), Box<dyn Error>> {
+    let contents = std::fs::read(opts.input()?)?;
+    let initrd = opts.initrd.as_ref().map(std::fs::read).transpose()?;
+    diff::diff(
+        &contents,
+        initrd.as_deref(),
+        posix_exit_mode(&opts),
+        opts.max_steps,
+    )
+}
+
+fn gdbserver(opts: GdbServerOptions) -> Result<(), Box<dyn Error>> {
+    let mut backend = match &opts.common.load_snapshot {
+        Some(path) => Interpreter::load_snapshot(&std::fs::read(path)?)?,
+        None => Interpreter::create_backend(),
+    };
+
+    let mut interpreter = match &opts.common.load_snapshot {
+        Some(_) => Interpreter::resume(&mut backend),
+        None => {
+            let contents = std::fs::read(opts.common.input()?)?;
+            let initrd = opts.common.initrd.as_ref().map(std::fs::read).transpose()?;
+            let mode = posix_exit_mode(&opts.common);
+            Interpreter::new(&mut backend, &contents, initrd.as_deref(), mode)?
+        }
+    };
+
+    gdbserver::GdbServer::new(&mut interpreter).serve(&opts.listen)
+}
+
+fn profile(opts: ProfileOptions) -> Result<(), Box<dyn Error>> {
+    let contents = std::fs::read(opts.common.input()?)?;
+    let initrd = opts.common.initrd.as_ref().map(std::fs::read).transpose()?;
+    profile::profile(
+        &contents,
+        initrd.as_deref(),
+        posix_exit_mode(&opts.common),
+        opts.common.max_steps,
+        opts.folded.as_deref(),
+        opts.top,
+    )
+}
+
 fn rvemu(opts: Options) -> Result<(), Box<dyn Error>> {
     let mut emu = Emulator::new();
 
     // Load the ELF binary into the emulator.
-    let contents = std::fs::read(&opts.input)?;
+    let contents = std::fs::read(opts.input()?)?;
 
     rvemu_boot::setup_boot(&mut emu, &contents, opts.initrd)?;
 
@@ -76,27 +201,19 @@ fn rvemu(opts: Options) -> Result<(), Box<dyn Error>> {
 
     // Prepare inbox
     let mut inbox = inbox::InboxBuilder::new();
-    inbox
-        .insert_external(vec![1, 2, 3, 4])
-        .insert_external(vec![1, 4, 3, 2])
-        .next_level()
-        .insert_external(vec![1, 1])
-        .next_level()
-        .insert_external(vec![1, 2])
-        .next_level()
-        .insert_transfer(
-            ContractKt1Hash::from_base58_check("KT1EfTusMLoeCAAGd9MZJn5yKzFr6kJU5U91").unwrap(),
-            PublicKeyHash::from_b58check("mv1Q4RbUk6tX1J43AGg4ScnisAEhLnpHNyux").unwrap(),
-            meta.address.clone(),
-            MichelsonUnit,
-        );
+    if let Some(path) = &opts.inbox {
+        inbox.load_file(path, &meta.address)?;
+    }
     let mut inbox = inbox.build();
 
+    let reveals = opts.preimages_dir.as_ref().map(PreimageDirectory::new);
+
     let handle_syscall = if opts.posix {
         fn dummy(
             emu: &mut Emulator,
             _: &rvemu_syscall::RollupMetadata,
             _: &mut inbox::Inbox,
+            _: Option<&PreimageDirectory>,
         ) -> Result<(), Box<dyn Error>> {
             rvemu_syscall::handle_posix(emu)
         }
@@ -125,7 +242,7 @@ fn rvemu(opts: Options) -> Result<(), Box<dyn Error>> {
                 match exception {
                     rvemu::exception::Exception::EnvironmentCallFromSMode
                     | rvemu::exception::Exception::EnvironmentCallFromUMode => {
-                        handle_syscall(&mut emu, &meta, &mut inbox).map_err(
+                        handle_syscall(&mut emu, &meta, &mut inbox, reveals.as_ref()).map_err(
                             |err| -> Box<dyn Error> {
                                 format!("Failed to handle environment call at {prev_pc:x}: {}", err)
                                     .as_str()
@@ -161,7 +278,7 @@ fn rvemu(opts: Options) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn posix_exit_mode(opts: Options) -> Mode {
+fn posix_exit_mode(opts: &Options) -> Mode {
     match opts.posix_exit_mode {
         cli::ExitMode::User => Mode::User,
         cli::ExitMode::Supervisor => Mode::Supervisor,
@@ -175,5 +292,8 @@ fn main() -> Result<(), Box<dyn Error>> {
         cli::Mode::Rvemu(opts) => rvemu(opts),
         cli::Mode::Run(opts) => run(opts),
         cli::Mode::Debug(opts) => debug(opts),
+        cli::Mode::Diff(opts) => diff(opts),
+        cli::Mode::Gdbserver(opts) => gdbserver(opts),
+        cli::Mode::Profile(opts) => profile(opts),
     }
 }
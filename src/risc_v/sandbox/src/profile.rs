@@ -0,0 +1,349 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Instruction-level profiler
+//!
+//! Every retired instruction is counted by its program counter. Counts are
+//! attributed to the ELF symbol containing the program counter.
+//!
+//! Call stacks are reconstructed from the executed instructions: `jal` and
+//! `jalr` linking into `ra` push a frame, `ret` pops one. Traps push a frame
+//! as well, which `mret` or `sret` pop. The stacks are written in the folded
+//! format understood by flamegraph tools, one line per stack:
+//!
+//! ```text
+//! _start;main;compute 1234
+//! ```
+//!
+//! Instructions are decoded once per address, self-modifying code is not
+//! supported.
+
+use risc_v_interpreter::{
+    machine_state::{mode::Mode, registers, AccessType},
+    parser::{self, instruction::Instr},
+    Interpreter, InterpreterResult,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    io::Write,
+};
+
+/// Name given to addresses not covered by any symbol
+const UNKNOWN_SYMBOL: &str = "[unknown]";
+
+/// Maximum depth of the reconstructed call stacks. Calls past this depth are
+/// attributed to the deepest frame.
+const MAX_STACK_DEPTH: usize = 1024;
+
+/// Effect of an instruction on the control flow
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flow {
+    /// Function call, linking into `ra`
+    Call,
+    /// Return from a function or from a trap handler
+    Return,
+    /// Other jump or branch
+    Jump,
+    /// Instruction continuing with the next one, unless it traps
+    Sequential,
+}
+
+/// Classify a compressed instruction. The parser does not decode compressed
+/// instructions yet, hence the control flow instructions are recognised
+/// from their encoding.
+fn classify_compressed(instr: u16) -> Flow {
+    let op = instr & 0b11;
+    let funct3 = instr >> 13;
+    let bit12 = (instr >> 12) & 1;
+    let rs1 = (instr >> 7) & 0x1F;
+    let rs2 = (instr >> 2) & 0x1F;
+
+    match (op, funct3) {
+        // c.j, c.beqz and c.bnez
+        (0b01, 0b101..=0b111) => Flow::Jump,
+        // c.jr and c.jalr
+        (0b10, 0b100) if rs1 != 0 && rs2 == 0 => match (bit12, rs1) {
+            (0, 1) => Flow::Return,
+            (0, _) => Flow::Jump,
+            _ => Flow::Call,
+        },
+        _ => Flow::Sequential,
+    }
+}
+
+/// Classify a decoded instruction.
+fn classify(instr: &Instr) -> Flow {
+    match instr {
+        Instr::Jal(args) if args.rd == registers::ra => Flow::Call,
+        Instr::Jalr(args) if args.rd == registers::ra => Flow::Call,
+        Instr::Jalr(args) if args.rd == registers::zero && args.rs1 == registers::ra => {
+            Flow::Return
+        }
+        Instr::Mret | Instr::Sret | Instr::Mnret => Flow::Return,
+        Instr::Jal(_)
+        | Instr::Jalr(_)
+        | Instr::Beq(_)
+        | Instr::Bne(_)
+        | Instr::Blt(_)
+        | Instr::Bge(_)
+        | Instr::Bltu(_)
+        | Instr::Bgeu(_) => Flow::Jump,
+        Instr::UnknownCompressed { instr } => classify_compressed(*instr),
+        _ => Flow::Sequential,
+    }
+}
+
+/// Decode the instruction at `pc`, returning its control flow effect and its
+/// width.
+fn decode(interpreter: &Interpreter, pc: u64) -> Option<(Flow, u64)> {
+    let read_half = |addr: u64| -> Result<u16, Box<dyn Error>> {
+        let addr = interpreter.translate(addr, AccessType::Instruction)?;
+        let bytes = interpreter.read_memory(addr, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+
+    let lower = read_half(pc).ok()?;
+    let instr = parser::parse(lower, || read_half(pc + 2)).ok()?;
+    Some((classify(&instr), instr.width()))
+}
+
+/// Symbol lookup by address
+struct Symbols<'a> {
+    symbols: BTreeMap<u64, &'a str>,
+}
+
+impl<'a> Symbols<'a> {
+    fn new(symbols: HashMap<u64, &'a str>) -> Self {
+        Self {
+            symbols: symbols.into_iter().collect(),
+        }
+    }
+
+    /// Start address of the symbol containing `address`
+    fn start_of(&self, address: u64) -> Option<u64> {
+        self.symbols
+            .range(..=address)
+            .next_back()
+            .map(|(start, _)| *start)
+    }
+
+    /// Name of the symbol starting at `start`
+    fn name(&self, start: Option<u64>) -> &'a str {
+        start
+            .and_then(|start| self.symbols.get(&start).copied())
+            .unwrap_or(UNKNOWN_SYMBOL)
+    }
+}
+
+/// Counts collected while profiling
+struct Profile {
+    /// Retired instructions by program counter
+    by_pc: HashMap<u64, u64>,
+
+    /// Retired instructions by call stack. Frames are identified by the start
+    /// of their symbol, the innermost frame comes last.
+    by_stack: HashMap<Vec<Option<u64>>, u64>,
+
+    /// Total number of retired instructions
+    total: u64,
+}
+
+/// Profiler driving the interpreter one instruction at a time
+struct Profiler<'a, 'b> {
+    interpreter: &'b mut Interpreter<'a>,
+    symbols: Symbols<'b>,
+    decoded: HashMap<u64, Option<(Flow, u64)>>,
+
+    /// Symbols of the callers of the current function
+    callers: Vec<Option<u64>>,
+
+    /// Calls made beyond [`MAX_STACK_DEPTH`], which are not in `callers`
+    overflowed: usize,
+
+    profile: Profile,
+}
+
+impl<'a, 'b> Profiler<'a, 'b> {
+    /// Account for the instruction at `pc` being retired.
+    fn record(&mut self, pc: u64) {
+        *self.profile.by_pc.entry(pc).or_insert(0) += 1;
+        self.profile.total += 1;
+
+        self.callers.push(self.symbols.start_of(pc));
+        match self.profile.by_stack.get_mut(self.callers.as_slice()) {
+            Some(count) => *count += 1,
+            None => {
+                self.profile.by_stack.insert(self.callers.clone(), 1);
+            }
+        }
+        self.callers.pop();
+    }
+
+    /// Update the call stack after the instruction at `pc` has been executed.
+    fn update_stack(&mut self, pc: u64, flow: Option<(Flow, u64)>) {
+        let next_pc = self.interpreter.read_pc();
+        let caller = self.symbols.start_of(pc);
+
+        match flow {
+            Some((Flow::Call, _)) => self.push(caller),
+            Some((Flow::Return, _)) => self.pop(),
+            Some((Flow::Jump, _)) => {}
+            // Sequential instructions only change the control flow by trapping.
+            Some((Flow::Sequential, width)) if next_pc == pc.wrapping_add(width) => {}
+            Some((Flow::Sequential, _)) | None => self.push(caller),
+        }
+    }
+
+    fn push(&mut self, caller: Option<u64>) {
+        if self.callers.len() < MAX_STACK_DEPTH {
+            self.callers.push(caller);
+        } else {
+            self.overflowed += 1;
+        }
+    }
+
+    /// Update the call stack on a return. Returns from calls beyond
+    /// [`MAX_STACK_DEPTH`] don't pop a caller, as their call wasn't pushed.
+    fn pop(&mut self) {
+        if self.overflowed > 0 {
+            self.overflowed -= 1;
+        } else {
+            self.callers.pop();
+        }
+    }
+
+    /// Run for at most `max_steps` steps.
+    fn run(&mut self, max_steps: usize) -> InterpreterResult {
+        for _ in 0..max_steps {
+            let pc = self.interpreter.read_pc();
+            let flow = match self.decoded.get(&pc) {
+                Some(flow) => *flow,
+                None => {
+                    let flow = decode(self.interpreter, pc);
+                    self.decoded.insert(pc, flow);
+                    flow
+                }
+            };
+
+            let result = self.interpreter.step_many(1, |_| true);
+            self.record(pc);
+
+            match result {
+                InterpreterResult::Running(_) => self.update_stack(pc, flow),
+                result => return result,
+            }
+        }
+
+        InterpreterResult::Running(max_steps)
+    }
+}
+
+impl Profile {
+    /// Write the call stacks in the folded format.
+    fn write_folded(&self, symbols: &Symbols, out: &mut impl Write) -> std::io::Result<()> {
+        let mut lines: Vec<(String, u64)> = self
+            .by_stack
+            .iter()
+            .map(|(stack, count)| {
+                let frames: Vec<&str> = stack.iter().map(|start| symbols.name(*start)).collect();
+                (frames.join(";"), *count)
+            })
+            .collect();
+        lines.sort();
+
+        for (stack, count) in lines {
+            writeln!(out, "{stack} {count}")?;
+        }
+        Ok(())
+    }
+
+    /// Print the `top` symbols with the most retired instructions.
+    fn print_summary(&self, symbols: &Symbols, top: usize) {
+        let mut own: HashMap<Option<u64>, u64> = HashMap::new();
+        for (pc, count) in self.by_pc.iter() {
+            *own.entry(symbols.start_of(*pc)).or_insert(0) += count;
+        }
+
+        // Instructions retired while a symbol is on the call stack, counting
+        // recursive calls once.
+        let mut total: HashMap<Option<u64>, u64> = HashMap::new();
+        for (stack, count) in self.by_stack.iter() {
+            let frames: HashSet<&Option<u64>> = stack.iter().collect();
+            for frame in frames {
+                *total.entry(*frame).or_insert(0) += count;
+            }
+        }
+
+        let mut rows: Vec<(Option<u64>, u64)> = own.into_iter().collect();
+        rows.sort_by(|(a_start, a), (b_start, b)| b.cmp(a).then(a_start.cmp(b_start)));
+
+        let percentage = |count: u64| 100.0 * count as f64 / self.total.max(1) as f64;
+        println!(
+            "{:>12} {:>7} {:>12} {:>7}  Symbol",
+            "Self", "%", "Total", "%"
+        );
+        for (start, count) in rows.into_iter().take(top) {
+            let total = total.get(&start).copied().unwrap_or(count);
+            println!(
+                "{:>12} {:>6.2}% {:>12} {:>6.2}%  {}",
+                count,
+                percentage(count),
+                total,
+                percentage(total),
+                symbols.name(start)
+            );
+        }
+        println!("{} instructions retired", self.total);
+    }
+}
+
+/// Profile `program` for at most `max_steps` steps. The folded call stacks
+/// are written to `folded` if given, and the `top` symbols with the most
+/// retired instructions are summarised on the standard output.
+pub fn profile(
+    program: &[u8],
+    initrd: Option<&[u8]>,
+    exit_mode: Mode,
+    max_steps: usize,
+    folded: Option<&str>,
+    top: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mut backend = Interpreter::create_backend();
+    let mut interpreter = Interpreter::new(&mut backend, program, initrd, exit_mode)?;
+    let symbols = Symbols::new(kernel_loader::get_elf_symbols(program)?);
+
+    let mut profiler = Profiler {
+        interpreter: &mut interpreter,
+        symbols,
+        decoded: HashMap::new(),
+        callers: Vec::new(),
+        overflowed: 0,
+        profile: Profile {
+            by_pc: HashMap::new(),
+            by_stack: HashMap::new(),
+            total: 0,
+        },
+    };
+    let result = profiler.run(max_steps);
+    let Profiler {
+        symbols, profile, ..
+    } = profiler;
+
+    if let Some(path) = folded {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        profile.write_folded(&symbols, &mut file)?;
+        file.flush()?;
+    }
+    profile.print_summary(&symbols, top);
+
+    match result {
+        InterpreterResult::Exit { code, .. } => println!("Exited with code {code}"),
+        InterpreterResult::Running(_) => println!("Stopped after {max_steps} steps"),
+        InterpreterResult::Exception(exc, _) => {
+            return Err(format!("Unhandled {exc:?} at {:#x}", interpreter.read_pc()).into())
+        }
+    }
+    Ok(())
+}
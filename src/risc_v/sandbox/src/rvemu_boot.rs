@@ -32,6 +32,7 @@ pub fn setup_boot(
     let LoadResult {
         entry,
         last_written,
+        ..
     } = kernel_loader::load_elf(&mut emu.cpu.bus, rvemu::bus::DRAM_BASE, contents)?;
 
     let initrd_addr = last_written;
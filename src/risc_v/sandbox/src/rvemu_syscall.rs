@@ -6,14 +6,17 @@
 //!   - https://www.scs.stanford.edu/~zyedidia/docs/riscv/riscv-sbi.pdf
 
 use crate::inbox::Inbox;
-use crate::rvemu_boot::{A0, A1, A2, A3, A6, A7};
+use crate::rvemu_boot::{A0, A1, A2, A3, A4, A6, A7};
 use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
 use kernel_loader::Memory;
+use mavkit_risc_v_pvm::reveals::{dal_page_args, PreimageDirectory, RevealError};
 use mavryk_smart_rollup_constants::riscv::{
     SBI_CONSOLE_PUTCHAR, SBI_FIRMWARE_MAVRYK, SBI_MAVRYK_BLAKE2B_HASH256, SBI_MAVRYK_ED25519_SIGN,
     SBI_MAVRYK_ED25519_VERIFY, SBI_MAVRYK_INBOX_NEXT, SBI_MAVRYK_META_ADDRESS,
-    SBI_MAVRYK_META_ORIGINATION_LEVEL, SBI_SHUTDOWN,
+    SBI_MAVRYK_META_ORIGINATION_LEVEL, SBI_MAVRYK_REVEAL_DAL_PAGE,
+    SBI_MAVRYK_REVEAL_DAL_PARAMETERS, SBI_MAVRYK_REVEAL_PREIMAGE, SBI_SHUTDOWN,
 };
+use mavryk_smart_rollup_core::PREIMAGE_HASH_SIZE;
 use mavryk_smart_rollup_encoding::smart_rollup::SmartRollupAddress;
 use rvemu::cpu::{AccessType, BYTE};
 use rvemu::emulator::Emulator;
@@ -179,8 +182,88 @@ fn sbi_mavryk_blake2b_hash256(emu: &mut Emulator) -> SBIResult {
     Ok(())
 }
 
+/// Write at most `max_bytes` of the revealed data to the address in
+/// `dest_reg`. The number of bytes written, or the error code if the reveal
+/// failed, is placed in a0.
+fn write_revealed(
+    emu: &mut Emulator,
+    dest_reg: u64,
+    max_bytes: u64,
+    revealed: Result<Vec<u8>, RevealError>,
+) -> SBIResult {
+    let result = match revealed {
+        Ok(data) => {
+            let dest_addr = read_physical_address(emu, dest_reg)?;
+            let length = max_bytes.min(data.len() as u64);
+            emu.cpu
+                .bus
+                .write_bytes(dest_addr, &data[0..length as usize])?;
+            length
+        }
+        Err(err) => err.code() as u64,
+    };
+
+    emu.cpu.xregs.write(A0, result);
+    Ok(())
+}
+
+/// Obtain the directory reveals are served from.
+fn reveals_dir(reveals: Option<&PreimageDirectory>) -> Result<&PreimageDirectory, Box<dyn Error>> {
+    reveals.ok_or_else(|| "Reveal requests require a preimages directory".into())
+}
+
+/// Reveal the preimage of a hash.
+fn sbi_mavryk_reveal_preimage(
+    emu: &mut Emulator,
+    reveals: Option<&PreimageDirectory>,
+) -> SBIResult {
+    let reveals = reveals_dir(reveals)?;
+
+    let hash_len = emu.cpu.xregs.read(A1);
+    let preimage = if hash_len == PREIMAGE_HASH_SIZE as u64 {
+        let hash_addr = read_physical_address(emu, A0)?;
+        let hash = read_memory(emu, hash_addr, hash_len)?;
+        reveals.preimage(&hash)
+    } else {
+        Err(RevealError::InvalidHash)
+    };
+
+    let max_bytes = emu.cpu.xregs.read(A3);
+    write_revealed(emu, A2, max_bytes, preimage)
+}
+
+/// Reveal a page of a DAL slot.
+fn sbi_mavryk_reveal_dal_page(
+    emu: &mut Emulator,
+    reveals: Option<&PreimageDirectory>,
+) -> SBIResult {
+    let reveals = reveals_dir(reveals)?;
+
+    let [published_level, slot_index, page_index] = [A0, A1, A2].map(|reg| emu.cpu.xregs.read(reg));
+    let page = dal_page_args(published_level, slot_index, page_index)
+        .and_then(|(level, slot, page)| reveals.dal_page(level, slot, page));
+
+    let max_bytes = emu.cpu.xregs.read(A4);
+    write_revealed(emu, A3, max_bytes, page)
+}
+
+/// Reveal the parameters of the Data Availability Layer.
+fn sbi_mavryk_reveal_dal_parameters(
+    emu: &mut Emulator,
+    reveals: Option<&PreimageDirectory>,
+) -> SBIResult {
+    let params = reveals_dir(reveals)?.dal_parameters().to_bytes();
+    let max_bytes = emu.cpu.xregs.read(A1);
+    write_revealed(emu, A0, max_bytes, Ok(params.to_vec()))
+}
+
 /// Handle a system call originating from the user program.
-pub fn handle_sbi(emu: &mut Emulator, meta: &RollupMetadata, inbox: &mut Inbox) -> SBIResult {
+pub fn handle_sbi(
+    emu: &mut Emulator,
+    meta: &RollupMetadata,
+    inbox: &mut Inbox,
+    reveals: Option<&PreimageDirectory>,
+) -> SBIResult {
     // TODO: https://gitlab.com/tezos/tezos/-/issues/6767
     // Feed errors back to caller instead of raising them in the sandbox.
     // This means this function most likely should return unit.
@@ -199,6 +282,9 @@ pub fn handle_sbi(emu: &mut Emulator, meta: &RollupMetadata, inbox: &mut Inbox)
                 SBI_MAVRYK_ED25519_SIGN => sbi_mavryk_ed25519_sign(emu),
                 SBI_MAVRYK_ED25519_VERIFY => sbi_mavryk_ed25519_verify(emu),
                 SBI_MAVRYK_BLAKE2B_HASH256 => sbi_mavryk_blake2b_hash256(emu),
+                SBI_MAVRYK_REVEAL_PREIMAGE => sbi_mavryk_reveal_preimage(emu, reveals),
+                SBI_MAVRYK_REVEAL_DAL_PAGE => sbi_mavryk_reveal_dal_page(emu, reveals),
+                SBI_MAVRYK_REVEAL_DAL_PARAMETERS => sbi_mavryk_reveal_dal_parameters(emu, reveals),
                 _ => Err(format!(
                     "Unimplemented Tezos SBI extension ({sbi_extension}) function {sbi_function}"
                 )
@@ -0,0 +1,19 @@
+// SPDX-FileCopyrightText: 2024 TriliTech <contact@trili.tech>
+//
+// SPDX-License-Identifier: MIT
+
+//! Program using the Rust standard library, booted by the interpreter in its
+//! POSIX execution environment.
+
+use std::collections::HashMap;
+
+fn main() {
+    let name = std::env::args().next().unwrap_or_default();
+
+    // The hasher of the map is seeded using `getrandom`.
+    let squares: HashMap<u64, u64> = (0..100).map(|i| (i, i * i)).collect();
+    let sum: u64 = squares.values().sum();
+
+    println!("Hello from {name}: {sum}");
+    eprintln!("done");
+}